@@ -0,0 +1,35 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+@group(2) @binding(0) var<uniform> tint: vec4<f32>;
+// x: white flash from 0 to 1, y: dissolve progress from 0 to 1
+@group(2) @binding(1) var<uniform> effects: vec4<f32>;
+@group(2) @binding(2) var sprite_texture: texture_2d<f32>;
+@group(2) @binding(3) var sprite_sampler: sampler;
+
+// Size of the blocks the character breaks up into, in uv cells across the sprite
+const DISSOLVE_CELLS: f32 = 40.0;
+// Band just ahead of the dissolve front that glows before it disappears
+const EDGE_WIDTH: f32 = 0.08;
+const EDGE_COLOR: vec3<f32> = vec3(1.0, 0.6, 0.2);
+
+fn cell_noise(cell: vec2<f32>) -> f32 {
+    return fract(sin(dot(cell, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let sampled = textureSample(sprite_texture, sprite_sampler, in.uv) * tint;
+    var color = vec4(mix(sampled.rgb, vec3(1.0), effects.x), sampled.a);
+
+    let dissolve = effects.y;
+    if dissolve > 0.0 {
+        let noise = cell_noise(floor(in.uv * DISSOLVE_CELLS));
+        if noise < dissolve {
+            discard;
+        }
+        if noise < dissolve + EDGE_WIDTH {
+            color = vec4(EDGE_COLOR, color.a);
+        }
+    }
+    return color;
+}
@@ -0,0 +1,34 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+@group(2) @binding(0) var<uniform> color: vec4<f32>;
+// Outline width in uv units of the sprite in xy, how much larger the quad is than the
+// sprite it sits behind in zw
+@group(2) @binding(1) var<uniform> extent: vec4<f32>;
+@group(2) @binding(2) var sprite_texture: texture_2d<f32>;
+@group(2) @binding(3) var sprite_sampler: sampler;
+
+const SAMPLES: i32 = 16;
+const TAU: f32 = 6.2831853;
+
+fn sprite_alpha(uv: vec2<f32>) -> f32 {
+    if any(uv < vec2(0.0)) || any(uv > vec2(1.0)) {
+        return 0.0;
+    }
+    return textureSampleLevel(sprite_texture, sprite_sampler, uv, 0.0).a;
+}
+
+// Covers every pixel within `thickness` of an opaque sprite pixel. The sprite is drawn on
+// top, so only the rim around it stays visible
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = (in.uv - 0.5) * extent.zw + 0.5;
+    var coverage = sprite_alpha(uv);
+    for (var i = 0; i < SAMPLES; i++) {
+        let angle = f32(i) * TAU / f32(SAMPLES);
+        coverage = max(coverage, sprite_alpha(uv + vec2(cos(angle), sin(angle)) * extent.xy));
+    }
+    if coverage < 0.01 {
+        discard;
+    }
+    return vec4(color.rgb, color.a * coverage);
+}
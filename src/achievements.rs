@@ -0,0 +1,305 @@
+use bevy::prelude::*;
+use bevy_ggrs::LocalPlayers;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::game::{Player, SessionMode};
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated};
+use crate::notifications::Notify;
+use crate::scoring::{PointScored, Score};
+use crate::skins::Palette;
+use crate::strike::StrikeLanded;
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct AchievementPlugin;
+
+const PROFILE_PATH: &str = "profile.ron";
+// Strikes on the ball, by either player, between a serve and the point
+const LONG_RALLY_HITS: u32 = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Achievement {
+    FirstWin,
+    LongRally,
+    // A match won without ever using the second jump
+    GroundedWin,
+}
+
+// What this player has earned and wears, kept in profile.ron next to the settings
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Profile {
+    pub unlocked: Vec<Achievement>,
+    // Colors of this machine's own player, one of the unlocked ones. Only shown here, the
+    // opponent's screen keeps the usual colors
+    pub palette: Palette,
+}
+
+// Toward the achievements that take a whole rally or match, reset with every match
+#[derive(Resource, Default)]
+struct MatchProgress {
+    rally_hits: u32,
+    double_jumped: bool,
+    decided: bool,
+}
+
+#[derive(Component)]
+struct AchievementScreen;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum ScreenAction {
+    // Wear its colors, or take them off again
+    Wear(Achievement),
+    Back,
+}
+
+// Text of an achievement's button, relabelled whenever the profile changes
+#[derive(Component)]
+struct WearLabel(Achievement);
+
+impl Achievement {
+    pub const ALL: [Achievement; 3] = [Achievement::FirstWin, Achievement::LongRally, Achievement::GroundedWin];
+
+    fn label_key(self) -> &'static str {
+        match self {
+            Achievement::FirstWin => "achievement.first_win",
+            Achievement::LongRally => "achievement.long_rally",
+            Achievement::GroundedWin => "achievement.grounded_win",
+        }
+    }
+
+    fn description_key(self) -> &'static str {
+        match self {
+            Achievement::FirstWin => "achievement.first_win_description",
+            Achievement::LongRally => "achievement.long_rally_description",
+            Achievement::GroundedWin => "achievement.grounded_win_description",
+        }
+    }
+
+    pub fn unlocks(self) -> Palette {
+        match self {
+            Achievement::FirstWin => Palette::Gold,
+            Achievement::LongRally => Palette::Frost,
+            Achievement::GroundedWin => Palette::Ember,
+        }
+    }
+}
+
+impl Profile {
+    pub fn load() -> Self {
+        let Ok(text) = std::fs::read_to_string(PROFILE_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("ignoring broken {PROFILE_PATH}: {err}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("the profile always serializes");
+        std::fs::write(PROFILE_PATH, text)
+    }
+
+    fn has(&self, achievement: Achievement) -> bool {
+        self.unlocked.contains(&achievement)
+    }
+
+    // A palette whose achievement was lost with an edited file falls back to the usual one
+    pub fn worn_palette(&self) -> Palette {
+        let earned = Achievement::ALL.iter().any(|achievement| self.has(*achievement) && achievement.unlocks() == self.palette);
+        if earned { self.palette } else { Palette::Primary }
+    }
+}
+
+impl Plugin for AchievementPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Profile::load())
+            .add_systems(OnEnter(GameState::InGame), reset_progress)
+            .add_systems(Update, track_achievements.run_if(in_state(GameState::InGame)))
+            .add_systems(OnEnter(GameState::Achievements), setup_achievement_screen)
+            .add_systems(
+                Update,
+                (achievement_screen_action, update_wear_labels).chain().run_if(in_state(GameState::Achievements)),
+            )
+            .add_systems(OnExit(GameState::Achievements), cleanup_achievement_screen);
+    }
+}
+
+fn reset_progress(mut commands: Commands) {
+    commands.insert_resource(MatchProgress::default());
+}
+
+fn unlock(profile: &mut Profile, achievement: Achievement, locale: &Locale, notify: &mut EventWriter<Notify>) {
+    if profile.has(achievement) {
+        return;
+    }
+    info!("achievement unlocked: {achievement:?}");
+    profile.unlocked.push(achievement);
+    if let Err(err) = profile.save() {
+        warn!("can't save {PROFILE_PATH}: {err}");
+    }
+    notify.send(Notify::info(locale.format(
+        "achievement.unlocked",
+        &[("name", &locale.get(achievement.label_key())), ("palette", &locale.get(achievement.unlocks().label_key()))],
+    )));
+}
+
+// Counted from confirmed events like the stats, against someone who is trying: online
+// opponents and the daily challenge's bot. Points come first, so a strike confirmed with the
+// point that ends a rally counts toward the next
+fn track_achievements(
+    (mode, local_players): (Res<SessionMode>, Option<Res<LocalPlayers>>),
+    mut progress: ResMut<MatchProgress>,
+    (mut strikes, mut points): (EventReader<StrikeLanded>, EventReader<PointScored>),
+    players: Query<&Player>,
+    score: Option<Res<Score>>,
+    mut profile: ResMut<Profile>,
+    (locale, mut notify): (Res<Locale>, EventWriter<Notify>),
+) {
+    let own = mode.own_handle(local_players.as_deref());
+    let Some(own) = own.filter(|_| matches!(*mode, SessionMode::Online | SessionMode::Daily)) else {
+        strikes.clear();
+        points.clear();
+        return;
+    };
+    if points.read().count() > 0 {
+        progress.rally_hits = 0;
+    }
+    progress.rally_hits += strikes.read().filter(|strike| strike.ball).count() as u32;
+    if progress.rally_hits >= LONG_RALLY_HITS {
+        unlock(&mut profile, Achievement::LongRally, &locale, &mut notify);
+    }
+    // Both jumps spent means the second one was used
+    if players.iter().any(|player| player.handle == own && player.jumps_remaining == 0) {
+        progress.double_jumped = true;
+    }
+    let Some(winner) = score.and_then(|score| score.winner) else {
+        return;
+    };
+    if progress.decided {
+        return;
+    }
+    progress.decided = true;
+    if winner != own {
+        return;
+    }
+    unlock(&mut profile, Achievement::FirstWin, &locale, &mut notify);
+    if !progress.double_jumped {
+        unlock(&mut profile, Achievement::GroundedWin, &locale, &mut notify);
+    }
+}
+
+fn setup_achievement_screen(mut commands: Commands, profile: Res<Profile>, locale: Res<Locale>) {
+    commands.spawn((Camera2d, AchievementScreen));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            AchievementScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::default(),
+                Localized("achievements.title"),
+                TextFont {
+                    font_size: 36.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            for (order, achievement) in Achievement::ALL.into_iter().enumerate() {
+                parent
+                    .spawn(Node {
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(20.0),
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        row.spawn((menu_button(order as u32), ScreenAction::Wear(achievement)))
+                            .with_children(|button| {
+                                button.spawn((button_text(wear_label(&profile, achievement, &locale)), WearLabel(achievement)));
+                            });
+                        row.spawn((
+                            Text::new(format!(
+                                "{}\n{}",
+                                locale.get(achievement.label_key()),
+                                locale.get(achievement.description_key())
+                            )),
+                            TextFont {
+                                font_size: 20.0,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                            Node {
+                                width: Val::Px(420.0),
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+            parent
+                .spawn((menu_button(Achievement::ALL.len() as u32), ScreenAction::Back, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("settings.back")));
+                });
+        });
+}
+
+fn wear_label(profile: &Profile, achievement: Achievement, locale: &Locale) -> String {
+    let palette = locale.get(achievement.unlocks().label_key());
+    if !profile.has(achievement) {
+        locale.format("achievements.locked", &[("palette", &palette)])
+    } else if profile.worn_palette() == achievement.unlocks() {
+        locale.format("achievements.wearing", &[("palette", &palette)])
+    } else {
+        locale.format("achievements.wear", &[("palette", &palette)])
+    }
+}
+
+fn achievement_screen_action(
+    mut activated: EventReader<MenuActivated>,
+    actions: Query<&ScreenAction>,
+    mut profile: ResMut<Profile>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        match actions.get(*entity) {
+            Ok(ScreenAction::Wear(achievement)) if profile.has(*achievement) => {
+                let palette = achievement.unlocks();
+                profile.palette = if profile.worn_palette() == palette { Palette::Primary } else { palette };
+                if let Err(err) = profile.save() {
+                    warn!("can't save {PROFILE_PATH}: {err}");
+                }
+            }
+            Ok(ScreenAction::Back) => next_state.set(GameState::MainMenu),
+            _ => {}
+        }
+    }
+}
+
+fn update_wear_labels(
+    profile: Res<Profile>,
+    locale: Res<Locale>,
+    mut labels: Query<(&WearLabel, &mut Text)>,
+) {
+    if !profile.is_changed() && !locale.is_changed() {
+        return;
+    }
+    for (label, mut text) in labels.iter_mut() {
+        text.0 = wear_label(&profile, label.0, &locale);
+    }
+}
+
+fn cleanup_achievement_screen(mut commands: Commands, query: Query<Entity, With<AchievementScreen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
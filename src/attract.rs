@@ -0,0 +1,115 @@
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use crate::GameState;
+use crate::game::SessionMode;
+use crate::locale::Localized;
+use crate::ui_layout::SafeArea;
+
+pub struct AttractPlugin;
+
+// How long the main menu sits untouched before a match starts playing itself
+const IDLE_SECS: f32 = 30.0;
+
+// Seconds since anyone touched anything on the main menu
+#[derive(Resource, Default)]
+struct IdleTime(f32);
+
+// Tells whoever walks up that the match isn't theirs, and how to get the menu back
+#[derive(Component)]
+struct AttractBanner;
+
+// Anything at all done with a keyboard, mouse or pad this frame
+#[derive(SystemParam)]
+struct Activity<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    motion: EventReader<'w, 's, MouseMotion>,
+    gamepads: Query<'w, 's, &'static Gamepad>,
+}
+
+impl Activity<'_, '_> {
+    fn any(&mut self) -> bool {
+        // Read every time, so motion from before the menu came up isn't taken for a touch later
+        let moved = self.motion.read().count() > 0;
+        moved
+            || self.keys.get_pressed().next().is_some()
+            || self.mouse.get_pressed().next().is_some()
+            || self.gamepads.iter().any(|gamepad| gamepad.get_pressed().next().is_some())
+    }
+}
+
+impl Plugin for AttractPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<IdleTime>()
+            .add_systems(OnEnter(GameState::MainMenu), reset_idle)
+            .add_systems(Update, start_attract.run_if(in_state(GameState::MainMenu)))
+            .add_systems(OnEnter(GameState::InGame), spawn_banner.run_if(resource_equals(SessionMode::Attract)))
+            .add_systems(
+                Update,
+                leave_attract
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_equals(SessionMode::Attract)),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_banner);
+    }
+}
+
+fn reset_idle(mut idle: ResMut<IdleTime>) {
+    idle.0 = 0.0;
+}
+
+// Plays the level picked on the menu, both sides driven by the training dummy's ball chaser
+fn start_attract(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut idle: ResMut<IdleTime>,
+    mut activity: Activity,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if activity.any() {
+        idle.0 = 0.0;
+        return;
+    }
+    idle.0 += time.delta_secs();
+    if idle.0 < IDLE_SECS {
+        return;
+    }
+    info!("menu idle for {IDLE_SECS} s, starting the attract match");
+    commands.insert_resource(SessionMode::Attract);
+    next_state.set(GameState::InGame);
+}
+
+fn leave_attract(mut activity: Activity, mut next_state: ResMut<NextState<GameState>>) {
+    if activity.any() {
+        next_state.set(GameState::MainMenu);
+    }
+}
+
+fn spawn_banner(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(40.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::default(),
+        Localized("attract.banner"),
+        TextFont {
+            font_size: 28.0,
+            ..default()
+        },
+        TextColor(Color::srgba(1.0, 1.0, 1.0, 0.8)),
+        AttractBanner,
+    ));
+}
+
+fn cleanup_banner(mut commands: Commands, banners: Query<Entity, With<AttractBanner>>) {
+    for entity in banners.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,145 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::{Rollback, RollbackFrameCount, RollbackOrdered};
+use crate::GameState;
+use crate::determinism::sort_by_rollback;
+use crate::input::PlayerInput;
+use crate::rng::fnv1a;
+
+pub struct AuditPlugin;
+
+// Plays a recorded input file through a local session with nobody at the keyboard and writes
+// one checksum of the simulation per frame. Running the same file on a native and a wasm
+// build and diffing the outputs shows the first frame where floats came out differently.
+// Started with --audit <file> natively or ?audit=<url> on the web. The input file is the
+// replay.txt of a crash report, or anything else in its format
+#[derive(Resource)]
+pub struct AuditRun {
+    // Where the checksums go, the input's name with .checksums.txt on the end
+    output: String,
+    // One input per handle for every frame, in order
+    inputs: Vec<Vec<PlayerInput>>,
+    next: usize,
+    checksums: Vec<(i32, u64)>,
+}
+
+impl AuditRun {
+    fn parse(source: &str, text: &str) -> Result<Self, String> {
+        let mut inputs = Vec::new();
+        // Lines start with the frame they were recorded on, anything else is a header
+        for line in text.lines() {
+            let mut fields = line.split_whitespace();
+            if fields.next().and_then(|frame| frame.parse::<i32>().ok()).is_none() {
+                continue;
+            }
+            let frame = fields
+                .map(|field| PlayerInput::parse_field(field).map_err(|err| format!("{line:?}: {err}")))
+                .collect::<Result<Vec<PlayerInput>, String>>()?;
+            inputs.push(frame);
+        }
+        if inputs.is_empty() {
+            return Err(format!("{source} has no frames in it"));
+        }
+        Ok(Self { output: format!("{source}.checksums.txt"), inputs, next: 0, checksums: Vec::new() })
+    }
+
+    // What the handle presses on the next frame read, nothing once the file runs out
+    pub fn next_inputs(&mut self) -> Vec<PlayerInput> {
+        let inputs = self.inputs.get(self.next).cloned().unwrap_or_default();
+        self.next += 1;
+        inputs
+    }
+
+    fn finished(&self) -> bool {
+        self.checksums.len() >= self.inputs.len()
+    }
+
+    fn report(&self) -> String {
+        self.checksums.iter().map(|(frame, checksum)| format!("{frame} {checksum:016x}\n")).collect()
+    }
+}
+
+// The requested run, if this start is an audit. Bad input files are reported and the game
+// starts normally
+pub fn requested() -> Option<AuditRun> {
+    let (source, text) = read_source()?;
+    match AuditRun::parse(&source, &text) {
+        Ok(run) => {
+            info!("auditing {} frames from {source}", run.inputs.len());
+            Some(run)
+        }
+        Err(err) => {
+            error!("can't audit: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_source() -> Option<(String, String)> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--audit").skip(1);
+    let path = args.next()?;
+    match std::fs::read_to_string(&path) {
+        Ok(text) => Some((path, text)),
+        Err(err) => {
+            error!("can't audit {path}: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_source() -> Option<(String, String)> {
+    crate::web::audit_source()
+}
+
+impl Plugin for AuditPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, start_audit.run_if(resource_exists::<AuditRun>))
+            .add_systems(Update, finish_audit.run_if(resource_exists::<AuditRun>));
+    }
+}
+
+fn start_audit(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::InGame);
+}
+
+// Last in the rollback schedule. Bit patterns rather than values, so -0.0 and 0.0 or two
+// NaNs don't hide a difference
+pub fn record_checksum(
+    frame: Res<RollbackFrameCount>,
+    order: Res<RollbackOrdered>,
+    mut run: ResMut<AuditRun>,
+    bodies: Query<(&Rollback, &Transform, Option<&LinearVelocity>)>,
+) {
+    let mut bodies: Vec<_> = bodies.iter().collect();
+    sort_by_rollback(&mut bodies, &order, |(rollback, ..)| **rollback);
+
+    let mut bytes = Vec::with_capacity(bodies.len() * 24);
+    for (_, transform, velocity) in bodies {
+        let velocity = velocity.map(|velocity| velocity.0).unwrap_or_default();
+        let translation = transform.translation.to_array();
+        let values = translation.into_iter().chain(transform.rotation.to_array()).chain(velocity.to_array());
+        for value in values {
+            bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+    }
+    // A resimulated frame replaces the checksum it had before
+    run.checksums.retain(|(recorded, _)| *recorded < frame.0);
+    run.checksums.push((frame.0, fnv1a(&bytes)));
+}
+
+fn finish_audit(run: Res<AuditRun>, mut exit: EventWriter<AppExit>) {
+    if !run.finished() {
+        return;
+    }
+    let report = run.report();
+    #[cfg(not(target_arch = "wasm32"))]
+    match std::fs::write(&run.output, report) {
+        Ok(()) => info!("audit checksums written to {}", run.output),
+        Err(err) => error!("can't write audit checksums to {}: {err}", run.output),
+    }
+    #[cfg(target_arch = "wasm32")]
+    crate::web::save_file(&run.output, &report);
+    exit.send(AppExit::Success);
+}
@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+use crate::game::SessionMode;
+use crate::guardrails::SpawnRollbackExt;
+use crate::level::{BALL_LAYER, GROUND_LAYER, WALL_LAYER};
+use crate::presentation::{player_color, Appearance, Mirrors};
+use crate::speed_limit::SpeedLimit;
+
+pub struct BallPlugin;
+
+pub const BALL_RADIUS: f32 = 0.3;
+const BALL_SPAWN: Vec2 = Vec2::new(-4.0, 2.0);
+// Below this the ball moves less than half its radius per frame and regular contacts catch it
+const CCD_SPEED: f32 = BALL_RADIUS * 30.0;
+
+#[derive(Component, Clone, Debug, Default)]
+pub struct Ball {
+    // Who touched the ball last, None after a clash nobody won
+    pub last_touch: Option<usize>,
+    // Touches in a row by last_touch, for the touch-count rule
+    pub consecutive_touches: u8,
+}
+
+impl Ball {
+    pub fn touch(&mut self, handle: Option<usize>) {
+        if handle.is_some() && handle == self.last_touch {
+            self.consecutive_touches = self.consecutive_touches.saturating_add(1);
+        } else {
+            self.consecutive_touches = u8::from(handle.is_some());
+        }
+        self.last_touch = handle;
+    }
+}
+
+impl Plugin for BallPlugin {
+    fn build(&self, app: &mut App) {
+        app.rollback_component_with_clone::<Ball>()
+            .add_systems(OnEnter(GameState::InGame), spawn_ball.run_if(not(resource_equals(SessionMode::Race))))
+            .add_systems(Update, draw_possession_ring.run_if(in_state(GameState::InGame)))
+            .add_systems(OnExit(GameState::InGame), cleanup_ball);
+    }
+}
+
+fn spawn_ball(mut commands: Commands) {
+    commands.spawn_rollback("spawn_ball", (
+        Ball::default(),
+        Transform::from_translation(BALL_SPAWN.extend(0.)),
+        Appearance::new(Sprite {
+            color: Color::srgb(0.95, 0.95, 0.9),
+            custom_size: Some(Vec2::splat(BALL_RADIUS * 2.0)),
+            ..default()
+        }),
+        ball_body(),
+    ));
+}
+
+// How the ball moves and what it bounces off
+fn ball_body() -> impl Bundle {
+    (
+        RigidBody::Dynamic,
+        Collider::circle(BALL_RADIUS),
+        // The ball only bounces off the arena, players interact with it through strikes
+        CollisionLayers::new([BALL_LAYER], [WALL_LAYER, GROUND_LAYER]),
+        // Sweeps fast shots against the arena so a spike can't tunnel through the net or a
+        // thin wall. The arena is all static, so dynamic bodies are left out of the sweep
+        SweptCcd::LINEAR.with_velocity_threshold(CCD_SPEED, 0.0).include_dynamic(false),
+        LinearVelocity::default(),
+        Restitution::new(0.7),
+        Friction::new(0.2),
+        GravityScale(0.6),
+        SpeedLimit::BALL,
+    )
+}
+
+// Ring in the color of whoever touched the ball last, drawn around the display position
+fn draw_possession_ring(
+    mut gizmos: Gizmos,
+    balls: Query<(Entity, &Ball)>,
+    displays: Query<(&Mirrors, &Transform)>,
+) {
+    for (entity, ball) in balls.iter() {
+        let Some(handle) = ball.last_touch else {
+            continue;
+        };
+        let Some((_, transform)) = displays.iter().find(|(mirrors, _)| mirrors.0 == entity) else {
+            continue;
+        };
+        gizmos.circle_2d(
+            Isometry2d::from_translation(transform.translation.truncate()),
+            BALL_RADIUS + 0.1,
+            player_color(handle),
+        );
+    }
+}
+
+fn cleanup_ball(mut commands: Commands, balls: Query<Entity, With<Ball>>) {
+    for entity in balls.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::time::TimeUpdateStrategy;
+    use super::*;
+
+    const NET_THICKNESS: f32 = 0.1;
+
+    // Just the physics, stepped a frame at a time without gravity so the shot flies flat
+    fn physics_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            bevy::asset::AssetPlugin::default(),
+            bevy::scene::ScenePlugin,
+            PhysicsPlugins::default(),
+        ))
+        .init_resource::<Assets<Mesh>>()
+        .insert_resource(Gravity(Vec2::ZERO))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / 60.0)));
+        app
+    }
+
+    fn fire_at_net(speed: f32) -> Vec2 {
+        let mut app = physics_app();
+        app.world_mut().spawn((
+            Transform::default(),
+            RigidBody::Static,
+            Collider::rectangle(NET_THICKNESS, 5.0),
+            CollisionLayers::new([WALL_LAYER], !WALL_LAYER),
+        ));
+        let ball = app
+            .world_mut()
+            .spawn((Transform::from_xyz(-2.0, 0.0, 0.0), ball_body()))
+            .insert(LinearVelocity(Vec2::new(speed, 0.0)))
+            .id();
+        for _ in 0..120 {
+            app.update();
+        }
+        app.world().get::<Transform>(ball).expect("the ball is still there").translation.truncate()
+    }
+
+    #[test]
+    fn ball_at_top_speed_does_not_tunnel_through_the_net() {
+        let position = fire_at_net(SpeedLimit::BALL.max_speed);
+        assert!(position.x < -(NET_THICKNESS / 2.0 + BALL_RADIUS) + 0.01, "ball ended up at {position}");
+    }
+
+    #[test]
+    fn ball_far_past_top_speed_does_not_tunnel_through_the_net() {
+        let position = fire_at_net(SpeedLimit::BALL.max_speed * 4.0);
+        assert!(position.x < -(NET_THICKNESS / 2.0 + BALL_RADIUS) + 0.01, "ball ended up at {position}");
+    }
+}
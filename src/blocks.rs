@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+use crate::determinism::sort_by_rollback;
+use crate::game::Player;
+use crate::pool::ParticleBurst;
+use crate::strike::{strike_started, STRIKE_RANGE};
+
+pub struct BlocksPlugin;
+
+pub const BLOCK_COLOR: Color = Color::srgb(0.6, 0.45, 0.3);
+const DEBRIS_COUNT: u32 = 12;
+
+// HP of a breakable level block, rollback state so a mispredicted strike is undone with the
+// rest of the frame. A block at 0 keeps its entity and loses its collider through
+// ColliderDisabled, which is rollback state too
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Breakable {
+    pub hp: u8,
+    max_hp: u8,
+    half_size: Vec2,
+}
+
+impl Breakable {
+    pub fn new(hp: u8, size: Vec2) -> Self {
+        let hp = hp.max(1);
+        Self { hp, max_hp: hp, half_size: size / 2.0 }
+    }
+
+    pub fn broken(&self) -> bool {
+        self.hp == 0
+    }
+}
+
+impl Plugin for BlocksPlugin {
+    fn build(&self, app: &mut App) {
+        app.rollback_component_with_copy::<Breakable>()
+            .add_systems(Update, update_block_visuals.run_if(in_state(GameState::InGame)));
+    }
+}
+
+// Each strike that starts this frame takes one HP off the closest block in reach on the
+// side the striker faces. Players go in handle order and blocks in rollback order, so two
+// strikes on one block or one strike between two blocks resolve the same on every peer
+pub fn strike_blocks(
+    mut commands: Commands,
+    players: Query<(&Player, &Transform)>,
+    mut blocks: Query<(Entity, &Rollback, &mut Breakable, &Transform)>,
+    order: Res<RollbackOrdered>,
+) {
+    let mut players: Vec<_> = players.iter().filter(|(player, _)| strike_started(player)).collect();
+    if players.is_empty() {
+        return;
+    }
+    players.sort_by_key(|(player, _)| player.handle);
+
+    let mut blocks: Vec<_> = blocks.iter_mut().filter(|(_, _, block, ..)| !block.broken()).collect();
+    sort_by_rollback(&mut blocks, &order, |(_, rollback, ..)| **rollback);
+
+    for (player, transform) in players {
+        let position = transform.translation.truncate();
+        let facing = if player.facing_left { -1.0 } else { 1.0 };
+        let target = blocks
+            .iter_mut()
+            .filter(|(_, _, block, ..)| !block.broken())
+            .filter_map(|(entity, _, block, block_transform)| {
+                let center = block_transform.translation.truncate();
+                let closest = position.clamp(center - block.half_size, center + block.half_size);
+                let in_reach = closest.distance(position) <= STRIKE_RANGE && (closest.x - position.x) * facing >= 0.0;
+                in_reach.then(|| (closest.distance(position), entity, block))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        let Some((_, entity, block)) = target else {
+            continue;
+        };
+        block.hp -= 1;
+        if block.broken() {
+            commands.entity(*entity).insert(ColliderDisabled);
+        }
+    }
+}
+
+// Fades as it takes hits and bursts into debris when it goes. A rollback that brings it back
+// shows it again
+fn update_block_visuals(
+    mut blocks: Query<(Ref<Breakable>, &Transform, &mut Sprite, &mut Visibility), Changed<Breakable>>,
+    mut bursts: EventWriter<ParticleBurst>,
+) {
+    for (block, transform, mut sprite, mut visibility) in blocks.iter_mut() {
+        if !block.broken() {
+            sprite.color = BLOCK_COLOR.with_alpha(0.4 + 0.6 * block.hp as f32 / block.max_hp as f32);
+            *visibility = Visibility::Inherited;
+            continue;
+        }
+        if *visibility != Visibility::Hidden && !block.is_added() {
+            bursts.send(ParticleBurst {
+                position: transform.translation.truncate(),
+                color: BLOCK_COLOR,
+                count: DEBRIS_COUNT,
+            });
+        }
+        *visibility = Visibility::Hidden;
+    }
+}
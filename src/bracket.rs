@@ -0,0 +1,525 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::game::SessionMode;
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated, MenuAdjusted};
+use crate::notifications::Notify;
+use crate::scoring::{MatchRules, Score};
+use crate::text_input::{spawn_text_input, InputFilter, TextInput};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct BracketPlugin;
+
+const MIN_ENTRANTS: usize = 3;
+const MAX_ENTRANTS: usize = 8;
+const NAME_MAX_LEN: usize = 16;
+// Sets a match can be played over
+const BEST_OF: [u32; 3] = [1, 3, 5];
+// Real seconds the result of a match stays up before the bracket comes back
+const RESULT_SECS: f64 = 4.0;
+const PAIRING_BORDER: Color = Color::srgb(0.3, 0.3, 0.3);
+// Border of the pairing up next
+const NEXT_BORDER: Color = Color::srgb(1.0, 0.8, 0.2);
+const WINNER_COLOR: Color = Color::WHITE;
+const OTHER_COLOR: Color = Color::srgb(0.55, 0.55, 0.55);
+
+// What was entered on the setup screen, kept so a second tournament starts from the same names
+#[derive(Resource)]
+struct TournamentSetup {
+    names: [String; MAX_ENTRANTS],
+    best_of: u32,
+}
+
+// A single elimination couch tournament. Every match is a local session, the winner of each
+// pairing moves on to the next round until one is left
+#[derive(Resource, Debug)]
+pub struct Tournament {
+    names: Vec<String>,
+    // Pairings round by round, the first round first. Each round has half the pairings of the
+    // one before it, down to the final
+    rounds: Vec<Vec<Pairing>>,
+    best_of: u32,
+    // Round and pairing of the match being played
+    playing: Option<(usize, usize)>,
+    // Real seconds when the match being played was decided
+    decided_at: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Pairing {
+    // Entrants on the left and on the right. None is a bye in the first round and a winner
+    // still to be found after it
+    entrants: [Option<usize>; 2],
+    winner: Option<usize>,
+}
+
+#[derive(Component)]
+struct SetupScreen;
+
+#[derive(Component)]
+struct BracketScreen;
+
+// Names of the two playing, under the arena
+#[derive(Component)]
+struct VersusHud;
+
+// Which of the name fields, in entry order
+#[derive(Component)]
+struct EntrantField(usize);
+
+#[derive(Component)]
+struct BestOfLabel;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum SetupAction {
+    BestOf,
+    Start,
+    Back,
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum BracketAction {
+    PlayNext,
+    // Back to the main menu, with or without a champion
+    Leave,
+}
+
+impl Default for TournamentSetup {
+    fn default() -> Self {
+        Self { names: Default::default(), best_of: BEST_OF[1] }
+    }
+}
+
+// Positions of the seeds in the first round, so the best seeds only meet late. Seeds past the
+// entrants are byes, which this hands to the best seeds
+fn seed_order(size: usize) -> Vec<usize> {
+    let mut order = vec![0];
+    while order.len() < size {
+        let len = order.len();
+        order = order.iter().flat_map(|seed| [*seed, 2 * len - 1 - seed]).collect();
+    }
+    order
+}
+
+impl Tournament {
+    // Seeded in entry order
+    fn new(names: Vec<String>, best_of: u32) -> Self {
+        let size = names.len().next_power_of_two();
+        let first: Vec<Pairing> = seed_order(size)
+            .chunks(2)
+            .map(|seeds| Pairing {
+                entrants: [seeds[0], seeds[1]].map(|seed| (seed < names.len()).then_some(seed)),
+                winner: None,
+            })
+            .collect();
+        let mut rounds = vec![first];
+        while rounds.last().is_some_and(|round| round.len() > 1) {
+            let len = rounds.last().map_or(0, Vec::len);
+            rounds.push(vec![Pairing::default(); len / 2]);
+        }
+        let mut tournament = Self { names, rounds, best_of, playing: None, decided_at: None };
+        for index in 0..tournament.rounds[0].len() {
+            if let [Some(entrant), None] | [None, Some(entrant)] = tournament.rounds[0][index].entrants {
+                tournament.advance(0, index, entrant);
+            }
+        }
+        tournament
+    }
+
+    fn advance(&mut self, round: usize, index: usize, winner: usize) {
+        self.rounds[round][index].winner = Some(winner);
+        if let Some(next) = self.rounds.get_mut(round + 1) {
+            next[index / 2].entrants[index % 2] = Some(winner);
+        }
+    }
+
+    // The first pairing with both entrants known and no winner yet
+    fn next_pairing(&self) -> Option<(usize, usize)> {
+        self.rounds.iter().enumerate().find_map(|(round, pairings)| {
+            pairings
+                .iter()
+                .position(|pairing| pairing.winner.is_none() && pairing.entrants.iter().all(Option::is_some))
+                .map(|index| (round, index))
+        })
+    }
+
+    fn champion(&self) -> Option<&str> {
+        let winner = self.rounds.last()?.first()?.winner?;
+        Some(&self.names[winner])
+    }
+
+    fn entrant_name(&self, round: usize, entrant: Option<usize>, locale: &Locale) -> String {
+        match entrant {
+            Some(entrant) => self.names[entrant].clone(),
+            None if round == 0 => locale.get("bracket.bye").to_string(),
+            None => locale.get("bracket.undecided").to_string(),
+        }
+    }
+
+    fn round_label_key(&self, round: usize) -> &'static str {
+        match self.rounds.len() - 1 - round {
+            0 => "bracket.final",
+            1 => "bracket.semi_finals",
+            _ => "bracket.quarter_finals",
+        }
+    }
+
+    // Each match is over once a player has taken this many sets
+    fn rules(&self) -> MatchRules {
+        MatchRules { sets_to_win: self.best_of.div_ceil(2), ..default() }
+    }
+}
+
+impl Plugin for BracketPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TournamentSetup>()
+            .add_systems(OnEnter(GameState::TournamentSetup), setup_entry_screen)
+            .add_systems(
+                Update,
+                (setup_input, update_best_of_label).chain().run_if(in_state(GameState::TournamentSetup)),
+            )
+            .add_systems(OnExit(GameState::TournamentSetup), (keep_entries, cleanup::<SetupScreen>).chain())
+            .add_systems(OnEnter(GameState::Bracket), setup_bracket_screen)
+            .add_systems(Update, bracket_input.run_if(in_state(GameState::Bracket)))
+            .add_systems(OnExit(GameState::Bracket), cleanup::<BracketScreen>)
+            .add_systems(OnEnter(GameState::InGame), spawn_versus_hud.run_if(resource_exists::<Tournament>))
+            .add_systems(
+                Update,
+                finish_match.run_if(in_state(GameState::InGame)).run_if(resource_exists::<Tournament>),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup::<VersusHud>)
+            .add_systems(OnEnter(GameState::MainMenu), end_tournament.run_if(resource_exists::<Tournament>));
+    }
+}
+
+fn setup_entry_screen(mut commands: Commands, setup: Res<TournamentSetup>) {
+    commands.spawn((Camera2d, SetupScreen));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            SetupScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((button_text(""), Localized("bracket.setup_title")));
+            // Two columns of four, eight fields in one column don't fit a small window
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                })
+                .with_children(|row| {
+                    for column in 0..2 {
+                        row.spawn(Node {
+                            flex_direction: FlexDirection::Column,
+                            ..default()
+                        })
+                        .with_children(|column_node| {
+                            for slot in column * MAX_ENTRANTS / 2..(column + 1) * MAX_ENTRANTS / 2 {
+                                let input = TextInput::new(
+                                    setup.names[slot].clone(),
+                                    NAME_MAX_LEN,
+                                    InputFilter::Any,
+                                    "bracket.entrant",
+                                );
+                                spawn_text_input(column_node, slot as u32, input).insert(EntrantField(slot));
+                            }
+                        });
+                    }
+                });
+            let first = MAX_ENTRANTS as u32;
+            parent
+                .spawn((menu_button(first), SetupAction::BestOf))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), BestOfLabel));
+                });
+            parent
+                .spawn((menu_button(first + 1), SetupAction::Start))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("bracket.start")));
+                });
+            parent
+                .spawn((menu_button(first + 2), SetupAction::Back, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("settings.back")));
+                });
+        });
+}
+
+fn next_best_of(current: u32, by: i32) -> u32 {
+    let index = BEST_OF.iter().position(|best_of| *best_of == current).unwrap_or(0) as i32;
+    BEST_OF[(index + by).rem_euclid(BEST_OF.len() as i32) as usize]
+}
+
+// Empty fields are skipped, so players can be struck off without moving the others up
+fn setup_input(
+    mut commands: Commands,
+    (mut activated, mut adjusted): (EventReader<MenuActivated>, EventReader<MenuAdjusted>),
+    actions: Query<&SetupAction>,
+    fields: Query<(&EntrantField, &TextInput)>,
+    mut setup: ResMut<TournamentSetup>,
+    (locale, mut notify): (Res<Locale>, EventWriter<Notify>),
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for adjusted in adjusted.read() {
+        if matches!(actions.get(adjusted.entity), Ok(SetupAction::BestOf)) {
+            setup.best_of = next_best_of(setup.best_of, adjusted.step);
+        }
+    }
+    for MenuActivated(entity) in activated.read() {
+        match actions.get(*entity) {
+            Ok(SetupAction::BestOf) => setup.best_of = next_best_of(setup.best_of, 1),
+            Ok(SetupAction::Start) => {
+                let mut entries: Vec<_> = fields.iter().collect();
+                entries.sort_by_key(|(field, _)| field.0);
+                let names: Vec<String> = entries
+                    .iter()
+                    .map(|(_, input)| input.value.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect();
+                if names.len() < MIN_ENTRANTS {
+                    notify.send(Notify::warning(locale.format("toast.bracket_too_few", &[("min", &MIN_ENTRANTS)])));
+                    continue;
+                }
+                info!("tournament with {} players, best of {}: {}", names.len(), setup.best_of, names.join(", "));
+                commands.insert_resource(Tournament::new(names, setup.best_of));
+                next_state.set(GameState::Bracket);
+            }
+            Ok(SetupAction::Back) => next_state.set(GameState::MainMenu),
+            Err(_) => {}
+        }
+    }
+}
+
+fn update_best_of_label(
+    setup: Res<TournamentSetup>,
+    locale: Res<Locale>,
+    mut labels: Query<(&mut Text, Ref<BestOfLabel>)>,
+) {
+    for (mut text, label) in labels.iter_mut() {
+        if label.is_added() || setup.is_changed() || locale.is_changed() {
+            text.0 = locale.format("bracket.best_of", &[("sets", &setup.best_of)]);
+        }
+    }
+}
+
+fn keep_entries(fields: Query<(&EntrantField, &TextInput)>, mut setup: ResMut<TournamentSetup>) {
+    for (field, input) in fields.iter() {
+        setup.names[field.0] = input.value.clone();
+    }
+}
+
+fn setup_bracket_screen(mut commands: Commands, tournament: Res<Tournament>, locale: Res<Locale>) {
+    commands.spawn((Camera2d, BracketScreen));
+
+    let next = tournament.next_pairing();
+    let champion = tournament.champion();
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            BracketScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Stretch,
+                    margin: UiRect::bottom(Val::Px(24.0)),
+                    ..default()
+                })
+                .with_children(|row| {
+                    for (round, pairings) in tournament.rounds.iter().enumerate() {
+                        row.spawn(Node {
+                            flex_direction: FlexDirection::Column,
+                            justify_content: JustifyContent::SpaceAround,
+                            margin: UiRect::horizontal(Val::Px(12.0)),
+                            ..default()
+                        })
+                        .with_children(|column| {
+                            column.spawn((
+                                Text::new(locale.get(tournament.round_label_key(round))),
+                                TextFont {
+                                    font_size: 22.0,
+                                    ..default()
+                                },
+                                TextColor(Color::WHITE),
+                            ));
+                            for (index, pairing) in pairings.iter().enumerate() {
+                                let border = if next == Some((round, index)) { NEXT_BORDER } else { PAIRING_BORDER };
+                                column
+                                    .spawn((
+                                        Node {
+                                            width: Val::Px(200.0),
+                                            flex_direction: FlexDirection::Column,
+                                            padding: UiRect::all(Val::Px(6.0)),
+                                            margin: UiRect::vertical(Val::Px(6.0)),
+                                            border: UiRect::all(Val::Px(2.0)),
+                                            ..default()
+                                        },
+                                        BorderColor(border),
+                                    ))
+                                    .with_children(|card| {
+                                        for entrant in pairing.entrants {
+                                            let won = entrant.is_some() && entrant == pairing.winner;
+                                            card.spawn((
+                                                Text::new(tournament.entrant_name(round, entrant, &locale)),
+                                                TextFont {
+                                                    font_size: 20.0,
+                                                    ..default()
+                                                },
+                                                TextColor(if won || pairing.winner.is_none() { WINNER_COLOR } else { OTHER_COLOR }),
+                                            ));
+                                        }
+                                    });
+                            }
+                        });
+                    }
+                });
+
+            let status = match (champion, next) {
+                (Some(champion), _) => locale.format("bracket.champion", &[("name", &champion)]),
+                (None, Some((round, index))) => {
+                    let [left, right] = tournament.rounds[round][index].entrants;
+                    locale.format(
+                        "bracket.next_match",
+                        &[
+                            ("left", &tournament.entrant_name(round, left, &locale)),
+                            ("right", &tournament.entrant_name(round, right, &locale)),
+                        ],
+                    )
+                }
+                (None, None) => String::new(),
+            };
+            parent.spawn((
+                Text::new(status),
+                TextFont {
+                    font_size: 28.0,
+                    ..default()
+                },
+                TextColor(WINNER_COLOR),
+            ));
+
+            if champion.is_none() {
+                parent
+                    .spawn((menu_button(0), BracketAction::PlayNext))
+                    .with_children(|parent| {
+                        parent.spawn((button_text(""), Localized("bracket.play_next")));
+                    });
+            }
+            let leave_key = if champion.is_some() { "bracket.finish" } else { "bracket.abandon" };
+            parent
+                .spawn((menu_button(1), BracketAction::Leave, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized(leave_key)));
+                });
+        });
+}
+
+// Every match is a fresh local session with the tournament's rules, the left player on the
+// left half of the split keyboard
+fn bracket_input(
+    mut commands: Commands,
+    mut activated: EventReader<MenuActivated>,
+    actions: Query<&BracketAction>,
+    mut tournament: ResMut<Tournament>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        match actions.get(*entity) {
+            Ok(BracketAction::PlayNext) => {
+                let Some(pairing) = tournament.next_pairing() else {
+                    continue;
+                };
+                tournament.playing = Some(pairing);
+                tournament.decided_at = None;
+                commands.insert_resource(tournament.rules());
+                commands.insert_resource(SessionMode::Local);
+                next_state.set(GameState::InGame);
+            }
+            Ok(BracketAction::Leave) => next_state.set(GameState::MainMenu),
+            Err(_) => {}
+        }
+    }
+}
+
+fn spawn_versus_hud(mut commands: Commands, tournament: Res<Tournament>, locale: Res<Locale>) {
+    let Some((round, index)) = tournament.playing else {
+        return;
+    };
+    let [left, right] = tournament.rounds[round][index].entrants;
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(16.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(locale.format(
+            "bracket.versus",
+            &[
+                ("left", &tournament.entrant_name(round, left, &locale)),
+                ("right", &tournament.entrant_name(round, right, &locale)),
+            ],
+        )),
+        TextFont {
+            font_size: 24.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        VersusHud,
+    ));
+}
+
+// Local sessions never roll back, so the winner is settled the frame it's set. The result
+// stays up for a moment before the bracket comes back
+fn finish_match(
+    time: Res<Time<Real>>,
+    score: Option<Res<Score>>,
+    mut tournament: ResMut<Tournament>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let now = time.elapsed_secs_f64();
+    if let (Some((round, index)), None) = (tournament.playing, tournament.decided_at) {
+        let Some(handle) = score.and_then(|score| score.winner) else {
+            return;
+        };
+        // Handle 0 starts on the left, like the left entrant
+        if let Some(winner) = tournament.rounds[round][index].entrants[handle % 2] {
+            info!("{} wins pairing {index} of round {round}", tournament.names[winner]);
+            tournament.advance(round, index, winner);
+        }
+        tournament.decided_at = Some(now);
+    }
+    if tournament.decided_at.is_some_and(|at| now - at >= RESULT_SECS) {
+        tournament.playing = None;
+        tournament.decided_at = None;
+        next_state.set(GameState::Bracket);
+    }
+}
+
+// Later matches play by the usual rules again
+fn end_tournament(mut commands: Commands) {
+    commands.remove_resource::<Tournament>();
+    commands.insert_resource(MatchRules::default());
+}
+
+fn cleanup<T: Component>(mut commands: Commands, query: Query<Entity, With<T>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,23 @@
+use bevy::prelude::*;
+
+// Which build this is, stamped in by build.rs. Shown on the main menu and written into
+// everything a bug report might come with, so it's clear what the report was made on
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub date: &'static str,
+}
+
+impl BuildInfo {
+    pub const CURRENT: BuildInfo = BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("BUILD_GIT_HASH"),
+        date: env!("BUILD_DATE"),
+    };
+
+    // One line, e.g. 0.1.0 (3f2a9c01de, 2026-10-14)
+    pub fn label(&self) -> String {
+        format!("{} ({}, {})", self.version, self.git_hash, self.date)
+    }
+}
@@ -0,0 +1,252 @@
+use bevy::prelude::*;
+use bevy::render::camera::{ClearColorConfig, ScalingMode, Viewport};
+use bevy::render::view::RenderLayers;
+use crate::GameState;
+use crate::finale::Finale;
+use crate::game::{Player, SessionMode};
+use crate::level::ActiveLevel;
+use crate::race::RaceGhost;
+use crate::settings::Settings;
+
+pub struct ArenaCameraPlugin;
+
+// World units from the bottom of the view to the top at scale 1, as the match camera is set up
+pub const VIEW_HEIGHT: f32 = 10.0;
+// Room kept between the players and the edge of the view
+const VIEW_MARGIN: f32 = 2.5;
+// The shared camera zooms out this far to keep both players in view, past it the screen
+// splits. It merges again a little closer in so it doesn't flicker at the threshold
+const SPLIT_SCALE: f32 = 1.5;
+const MERGE_SCALE: f32 = 1.3;
+// Fraction of the way to the target covered per second
+const FOLLOW_RATE: f32 = 4.0;
+// Nothing reaches it, the match camera is moved here while split so it only draws the UI
+const UI_ONLY_LAYER: usize = 31;
+
+// The match camera. Follows the players in arenas bigger than the screen and draws the UI
+#[derive(Component)]
+pub struct ArenaCamera;
+
+// One half of a split screen, following one handle
+#[derive(Component)]
+struct SplitCamera {
+    handle: usize,
+}
+
+// On a match camera in pixel art mode. The following above works on the smooth view, what's
+// drawn is that view moved onto the pixel grid
+#[derive(Component, Clone, Copy)]
+struct PixelSnap {
+    smooth: (Vec3, f32),
+    snapped: (Vec3, f32),
+}
+
+impl Plugin for ArenaCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (unsnap, update_split, follow_players, follow_split, snap_to_pixels)
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), merge_on_exit);
+    }
+}
+
+// How far out the view has to be to show everything in it, a level smaller than the screen
+// never needs more than 1
+fn scale_to_fit(size: Vec2, aspect: f32) -> f32 {
+    (size.y / VIEW_HEIGHT).max(size.x / (VIEW_HEIGHT * aspect))
+}
+
+// The view's center as close to target as it gets while staying inside the level
+fn clamp_to_level(target: Vec2, view: Vec2, level: Rect) -> Vec2 {
+    let slack = ((level.size() - view) / 2.0).max(Vec2::ZERO);
+    level.center() + (target - level.center()).clamp(-slack, slack)
+}
+
+fn aspect(camera: &Camera) -> f32 {
+    camera.logical_viewport_size().map_or(16.0 / 10.0, |size| size.x / size.y.max(1.0))
+}
+
+// Players the cameras follow, the race ghost only tags along
+type TrackedPlayers<'w, 's> =
+    Query<'w, 's, (&'static Player, &'static Transform), (Without<Camera>, Without<RaceGhost>)>;
+
+fn player_positions(players: &TrackedPlayers) -> Vec<(usize, Vec2)> {
+    let mut positions: Vec<_> = players.iter().map(|(player, transform)| (player.handle, transform.translation.truncate())).collect();
+    positions.sort_by_key(|(handle, _)| *handle);
+    positions
+}
+
+// Smoothed, time based so it moves the same at any frame rate
+fn ease(from: f32, to: f32, rate: f32) -> f32 {
+    from + (to - from) * rate.min(1.0)
+}
+
+// Local play only, online both peers see the whole arena the same way. The finale zooms in
+// on the shared view. A race ghost isn't followed, so a race never splits
+fn update_split(
+    mut commands: Commands,
+    (settings, mode, level, finale): (Res<Settings>, Res<SessionMode>, Res<ActiveLevel>, Option<Res<Finale>>),
+    players: TrackedPlayers,
+    mut arena: Query<(Entity, &mut Camera, &Transform), With<ArenaCamera>>,
+    splits: Query<Entity, With<SplitCamera>>,
+) {
+    let Ok((entity, mut camera, transform)) = arena.get_single_mut() else {
+        return;
+    };
+    let split = !splits.is_empty();
+    let positions = player_positions(&players);
+    let allowed = settings.split_screen && *mode != SessionMode::Online && finale.is_none() && positions.len() == 2;
+    let needed = match positions.as_slice() {
+        [(_, a), (_, b)] => scale_to_fit((*a - *b).abs() + Vec2::splat(VIEW_MARGIN * 2.0), aspect(&camera)),
+        _ => 1.0,
+    };
+    let level_scale = scale_to_fit(level.0.bounds().size(), aspect(&camera));
+
+    if !split && allowed && needed > SPLIT_SCALE && level_scale > SPLIT_SCALE {
+        for (order, (handle, _)) in positions.iter().enumerate() {
+            commands.spawn((
+                Camera2d,
+                Camera { order: order as isize - 2, ..default() },
+                OrthographicProjection {
+                    scaling_mode: ScalingMode::FixedVertical { viewport_height: VIEW_HEIGHT },
+                    ..OrthographicProjection::default_2d()
+                },
+                *transform,
+                SplitCamera { handle: *handle },
+            ));
+        }
+        commands.entity(entity).insert(RenderLayers::layer(UI_ONLY_LAYER));
+        camera.clear_color = ClearColorConfig::None;
+    } else if split && (!allowed || needed < MERGE_SCALE) {
+        for split in splits.iter() {
+            commands.entity(split).despawn_recursive();
+        }
+        commands.entity(entity).remove::<RenderLayers>();
+        camera.clear_color = ClearColorConfig::Default;
+    }
+}
+
+// Keeps both players in view, zooming out as far as the level needs and no further. The
+// default arena fits the screen, so it stays put there
+fn follow_players(
+    time: Res<Time>,
+    level: Res<ActiveLevel>,
+    finale: Option<Res<Finale>>,
+    players: TrackedPlayers,
+    mut arena: Query<(&Camera, &mut Transform, &mut OrthographicProjection), With<ArenaCamera>>,
+) {
+    if finale.is_some() {
+        return;
+    }
+    let Ok((camera, mut transform, mut projection)) = arena.get_single_mut() else {
+        return;
+    };
+    let bounds = level.0.bounds();
+    let aspect = aspect(camera);
+    let positions: Vec<Vec2> = player_positions(&players).into_iter().map(|(_, position)| position).collect();
+    let (target, scale) = match positions.as_slice() {
+        [] => (bounds.center(), 1.0),
+        _ => {
+            let area = Rect::from_corners(
+                positions.iter().copied().reduce(Vec2::min).unwrap_or_default(),
+                positions.iter().copied().reduce(Vec2::max).unwrap_or_default(),
+            );
+            let scale = scale_to_fit(area.size() + Vec2::splat(VIEW_MARGIN * 2.0), aspect)
+                .clamp(1.0, scale_to_fit(bounds.size(), aspect).max(1.0));
+            (area.center(), scale)
+        }
+    };
+    let view = Vec2::new(VIEW_HEIGHT * aspect, VIEW_HEIGHT) * scale;
+    let target = clamp_to_level(target, view, bounds);
+    let rate = FOLLOW_RATE * time.delta_secs();
+    transform.translation.x = ease(transform.translation.x, target.x, rate);
+    transform.translation.y = ease(transform.translation.y, target.y, rate);
+    projection.scale = ease(projection.scale, scale, rate);
+}
+
+// The player further left gets the left half, so nobody has to look across the split for
+// themselves after crossing over
+fn follow_split(
+    time: Res<Time>,
+    level: Res<ActiveLevel>,
+    windows: Query<&Window>,
+    players: TrackedPlayers,
+    mut splits: Query<(&SplitCamera, &mut Camera, &mut Transform)>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let positions = player_positions(&players);
+    let leftmost = positions.iter().min_by(|a, b| a.1.x.total_cmp(&b.1.x)).map(|(handle, _)| *handle);
+    let half = UVec2::new(window.physical_width() / 2, window.physical_height());
+    let rate = FOLLOW_RATE * time.delta_secs();
+    for (split, mut camera, mut transform) in splits.iter_mut() {
+        let side = u32::from(Some(split.handle) != leftmost);
+        let viewport = Viewport { physical_position: UVec2::new(half.x * side, 0), physical_size: half, ..default() };
+        if camera.viewport.as_ref().is_none_or(|current| {
+            current.physical_position != viewport.physical_position || current.physical_size != viewport.physical_size
+        }) {
+            camera.viewport = Some(viewport);
+        }
+        let Some((_, position)) = positions.iter().find(|(handle, _)| *handle == split.handle) else {
+            continue;
+        };
+        let view = Vec2::new(VIEW_HEIGHT * half.x as f32 / half.y.max(1) as f32, VIEW_HEIGHT);
+        let target = clamp_to_level(*position, view, level.0.bounds());
+        transform.translation.x = ease(transform.translation.x, target.x, rate);
+        transform.translation.y = ease(transform.translation.y, target.y, rate);
+    }
+}
+
+// Puts the smooth view back for following. Left alone if something else, like the finale,
+// moved the camera since it was snapped
+fn unsnap(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut cameras: Query<(Entity, &PixelSnap, &mut Transform, &mut OrthographicProjection)>,
+) {
+    for (entity, snap, mut transform, mut projection) in cameras.iter_mut() {
+        if (transform.translation, projection.scale) == snap.snapped {
+            (transform.translation, projection.scale) = snap.smooth;
+        }
+        if !settings.pixel_art {
+            commands.entity(entity).remove::<PixelSnap>();
+        }
+    }
+}
+
+// A whole number of screen pixels per world unit, rounding towards showing a little more,
+// and the view's corner on a pixel boundary. Every edge then sits on the same pixels from
+// one frame to the next instead of shimmering as the camera moves
+#[allow(clippy::type_complexity)]
+fn snap_to_pixels(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut cameras: Query<(Entity, &Camera, &mut Transform, &mut OrthographicProjection), Or<(With<ArenaCamera>, With<SplitCamera>)>>,
+) {
+    if !settings.pixel_art {
+        return;
+    }
+    for (entity, camera, mut transform, mut projection) in cameras.iter_mut() {
+        let Some(size) = camera.physical_viewport_size().filter(|size| size.y > 0) else {
+            continue;
+        };
+        let size = size.as_vec2();
+        let smooth = (transform.translation, projection.scale);
+        let per_unit = (size.y / (VIEW_HEIGHT * projection.scale)).floor().max(1.0);
+        projection.scale = size.y / (VIEW_HEIGHT * per_unit);
+        let view = size / per_unit;
+        let corner = ((transform.translation.truncate() - view / 2.0) * per_unit).round() / per_unit;
+        transform.translation = (corner + view / 2.0).extend(transform.translation.z);
+        commands.entity(entity).insert(PixelSnap { smooth, snapped: (transform.translation, projection.scale) });
+    }
+}
+
+fn merge_on_exit(mut commands: Commands, splits: Query<Entity, With<SplitCamera>>) {
+    for split in splits.iter() {
+        commands.entity(split).despawn_recursive();
+    }
+}
@@ -0,0 +1,163 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{AsBindGroup, ShaderRef};
+use bevy::sprite::{AlphaMode2d, Material2d, Material2dPlugin};
+use crate::GameState;
+use crate::game::Player;
+use crate::presentation::{Appearance, Mirrors};
+use crate::rollback_budget::presenting;
+use crate::scoring::PointScored;
+use crate::strike::PlayerHit;
+
+pub struct CharacterMaterialPlugin;
+
+// Presentation frames, at 60 per second
+const FLASH_FRAMES: f32 = 6.0;
+const DISSOLVE_SECS: f32 = 1.2;
+
+// Draws a character from its Appearance sprite, see assets/shaders/character.wgsl
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+pub struct CharacterMaterial {
+    #[uniform(0)]
+    tint: LinearRgba,
+    // White flash in x, dissolve progress in y
+    #[uniform(1)]
+    effects: Vec4,
+    #[texture(2)]
+    #[sampler(3)]
+    image: Handle<Image>,
+}
+
+// On a character's display entity
+#[derive(Component, Default)]
+struct CharacterFx {
+    quad: Option<Entity>,
+    flash_secs: f32,
+    // Seconds since the knockout started, None while still standing
+    dissolve_secs: Option<f32>,
+}
+
+impl Material2d for CharacterMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/character.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode2d {
+        AlphaMode2d::Blend
+    }
+}
+
+impl Plugin for CharacterMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(Material2dPlugin::<CharacterMaterial>::default()).add_systems(
+                PostUpdate,
+            (attach_character_quads, play_confirmed_events, update_character_quads.run_if(presenting))
+                .chain()
+                .before(TransformSystem::TransformPropagate)
+                .run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+// The quad is a child sized to the texture, so the display entity keeps the sprite's scale
+fn attach_character_quads(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<CharacterMaterial>>,
+    displays: Query<(Entity, &Mirrors), Without<CharacterFx>>,
+    appearances: Query<&Appearance>,
+) {
+    for (display, mirrors) in displays.iter() {
+        let Ok(appearance) = appearances.get(mirrors.0) else {
+            continue;
+        };
+        if !appearance.material {
+            continue;
+        }
+        let material = materials.add(CharacterMaterial {
+            tint: appearance.sprite.color.into(),
+            effects: Vec4::ZERO,
+            image: appearance.sprite.image.clone(),
+        });
+        let quad = commands
+            .spawn((
+                Mesh2d(meshes.add(Rectangle::default())),
+                MeshMaterial2d(material),
+                Transform::default(),
+                Visibility::Hidden,
+            ))
+            .set_parent(display)
+            .id();
+        commands.entity(display).insert(CharacterFx { quad: Some(quad), ..default() });
+    }
+}
+
+// Winning the match knocks out everyone else
+fn play_confirmed_events(
+    mut hits: EventReader<PlayerHit>,
+    mut points: EventReader<PointScored>,
+    mut displays: Query<(&Mirrors, &mut CharacterFx)>,
+    players: Query<&Player>,
+) {
+    let hit: Vec<usize> = hits.read().map(|hit| hit.target).collect();
+    let winner = points.read().filter(|point| point.won_match).map(|point| point.winner).last();
+    for (mirrors, mut fx) in displays.iter_mut() {
+        let Ok(player) = players.get(mirrors.0) else {
+            continue;
+        };
+        if hit.contains(&player.handle) {
+            fx.flash_secs = FLASH_FRAMES / 60.0;
+        }
+        if winner.is_some_and(|winner| winner != player.handle) {
+            fx.dissolve_secs = fx.dissolve_secs.or(Some(0.0));
+        }
+    }
+}
+
+fn update_character_quads(
+    time: Res<Time>,
+    images: Res<Assets<Image>>,
+    mut materials: ResMut<Assets<CharacterMaterial>>,
+    mut displays: Query<(&Mirrors, &mut CharacterFx)>,
+    appearances: Query<&Appearance>,
+    mut quads: Query<(&MeshMaterial2d<CharacterMaterial>, &mut Transform, &mut Visibility)>,
+) {
+    let delta = time.delta_secs();
+    for (mirrors, mut fx) in displays.iter_mut() {
+        let Some(Ok((material, mut transform, mut visibility))) = fx.quad.map(|quad| quads.get_mut(quad)) else {
+            continue;
+        };
+        let Ok(appearance) = appearances.get(mirrors.0) else {
+            continue;
+        };
+        let sprite = &appearance.sprite;
+        let Some(image) = images.get(&sprite.image) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        visibility.set_if_neq(Visibility::Inherited);
+
+        let size = image.size_f32() * appearance.texel_scale;
+        let flip = Vec2::new(if sprite.flip_x { -1.0 } else { 1.0 }, 1.0);
+        transform.scale = (flip * size).extend(1.0);
+        transform.translation = (-flip * sprite.anchor.as_vec() * size).extend(0.0);
+
+        fx.flash_secs = (fx.flash_secs - delta).max(0.0);
+        if let Some(dissolve) = fx.dissolve_secs.as_mut() {
+            *dissolve += delta;
+        }
+        let flash = fx.flash_secs / (FLASH_FRAMES / 60.0);
+        let dissolve = fx.dissolve_secs.map_or(0.0, |secs| (secs / DISSOLVE_SECS).min(1.0));
+        let effects = Vec4::new(flash, dissolve, 0.0, 0.0);
+        let tint = LinearRgba::from(sprite.color);
+
+        // Only touch the asset when something changed, a mutable access re-uploads it
+        let current = materials.get(&material.0);
+        if current.is_none_or(|current| current.effects != effects || current.tint != tint || current.image != sprite.image)
+            && let Some(current) = materials.get_mut(&material.0)
+        {
+            current.effects = effects;
+            current.tint = tint;
+            current.image = sprite.image.clone();
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{button_text, menu_button, CancelButton, Focusable, Focused, MenuActivated, MenuAdjusted, NORMAL_BUTTON};
+use crate::settings::Settings;
+use crate::skins::{Character, CharacterDefs, CharacterRoster};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct CharacterSelectPlugin;
+
+const CARD_WIDTH: f32 = 240.0;
+const CARD_HEIGHT: f32 = 340.0;
+const PORTRAIT_SIZE: f32 = 128.0;
+// Border of the card of the character picked so far
+const PICKED_BORDER: Color = Color::srgb(1.0, 0.8, 0.2);
+
+// Picking who to play online, one card per character with how it moves. Reached from the join
+// screen, the pick goes to the opponent during level sync like before
+#[derive(Component)]
+struct CharacterSelectScreen;
+
+#[derive(Component, Clone, Copy)]
+struct CharacterCard(Character);
+
+#[derive(Component)]
+struct BackButton;
+
+impl Plugin for CharacterSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::CharacterSelect), setup_character_select)
+            .add_systems(
+                Update,
+                (step_between_cards, character_select_action, highlight_pick)
+                    .chain()
+                    .run_if(in_state(GameState::CharacterSelect)),
+            )
+            .add_systems(OnExit(GameState::CharacterSelect), cleanup_character_select);
+    }
+}
+
+// Multipliers read as percent of the original characters
+fn stats_label(character: Character, defs: &CharacterDefs, locale: &Locale) -> String {
+    let physique = defs.get(character).physique;
+    let percent = |value: f32| (value * 100.0).round();
+    locale.format(
+        "character_select.stats",
+        &[
+            ("run_speed", &percent(physique.run_speed)),
+            ("jump_height", &percent(physique.jump_height)),
+            ("jumps", &physique.jumps),
+            ("weight", &percent(physique.weight)),
+            ("fall_speed", &percent(physique.fall_speed)),
+        ],
+    )
+}
+
+fn setup_character_select(
+    mut commands: Commands,
+    (roster, defs): (Res<CharacterRoster>, Res<CharacterDefs>),
+    settings: Res<Settings>,
+    locale: Res<Locale>,
+    asset_server: Res<AssetServer>,
+) {
+    commands.spawn((Camera2d, CharacterSelectScreen));
+
+    let characters = roster.all();
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            CharacterSelectScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::default(),
+                Localized("character_select.title"),
+                TextFont {
+                    font_size: 40.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    flex_wrap: FlexWrap::Wrap,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                })
+                .with_children(|row| {
+                    for (order, character) in characters.iter().enumerate() {
+                        let info = roster.info(*character);
+                        let mut card = row.spawn((
+                            Button,
+                            Node {
+                                width: Val::Px(CARD_WIDTH),
+                                height: Val::Px(CARD_HEIGHT),
+                                margin: UiRect::all(Val::Px(12.0)),
+                                padding: UiRect::all(Val::Px(12.0)),
+                                border: UiRect::all(Val::Px(3.0)),
+                                flex_direction: FlexDirection::Column,
+                                align_items: AlignItems::Center,
+                                row_gap: Val::Px(8.0),
+                                ..default()
+                            },
+                            BackgroundColor(NORMAL_BUTTON),
+                            BorderColor(Color::NONE),
+                            Focusable::new(order as u32),
+                            CharacterCard(*character),
+                        ));
+                        // Opens on the current pick
+                        if *character == settings.character {
+                            card.insert(Focused);
+                        }
+                        card.with_children(|card| {
+                            card.spawn((
+                                ImageNode::new(asset_server.load(info.sprite)),
+                                // Only the height, so the sprite keeps its shape
+                                Node {
+                                    height: Val::Px(PORTRAIT_SIZE),
+                                    ..default()
+                                },
+                            ));
+                            card.spawn((
+                                Text::new(locale.get(info.label_key)),
+                                TextFont {
+                                    font_size: 28.0,
+                                    ..default()
+                                },
+                                TextColor(Color::WHITE),
+                            ));
+                            card.spawn((
+                                Text::new(stats_label(*character, &defs, &locale)),
+                                TextFont {
+                                    font_size: 18.0,
+                                    ..default()
+                                },
+                                TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                            ));
+                        });
+                    }
+                });
+            parent
+                .spawn((menu_button(characters.len() as u32), BackButton, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("settings.back")));
+                });
+        });
+}
+
+// The cards sit in a row, so left and right go from one to the next as well as up and down
+fn step_between_cards(
+    mut commands: Commands,
+    mut adjusted: EventReader<MenuAdjusted>,
+    cards: Query<(Entity, &Focusable), With<CharacterCard>>,
+) {
+    for adjusted in adjusted.read() {
+        let mut entries: Vec<_> = cards.iter().collect();
+        entries.sort_by_key(|(_, focusable)| focusable.order);
+        let Some(index) = entries.iter().position(|(entity, _)| *entity == adjusted.entity) else {
+            continue;
+        };
+        let next = (index as i32 + adjusted.step).rem_euclid(entries.len() as i32) as usize;
+        commands.entity(adjusted.entity).remove::<Focused>();
+        commands.entity(entries[next].0).insert(Focused);
+    }
+}
+
+fn character_select_action(
+    mut activated: EventReader<MenuActivated>,
+    cards: Query<&CharacterCard>,
+    back: Query<(), With<BackButton>>,
+    mut settings: ResMut<Settings>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        if let Ok(card) = cards.get(*entity) {
+            settings.character = card.0;
+            if let Err(err) = settings.save() {
+                warn!("failed to save settings: {err}");
+            }
+            next_state.set(GameState::JoinOnline);
+        } else if back.contains(*entity) {
+            next_state.set(GameState::JoinOnline);
+        }
+    }
+}
+
+fn highlight_pick(settings: Res<Settings>, mut cards: Query<(&CharacterCard, &mut BorderColor)>) {
+    for (card, mut border) in cards.iter_mut() {
+        let wanted = if card.0 == settings.character { PICKED_BORDER } else { Color::NONE };
+        if border.0 != wanted {
+            border.0 = wanted;
+        }
+    }
+}
+
+fn cleanup_character_select(mut commands: Commands, query: Query<Entity, With<CharacterSelectScreen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,168 @@
+use std::marker::PhantomData;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+use crate::input::Config;
+
+// Gameplay events raised inside the rollback schedule. Each one is held back until the frame
+// it happened on is confirmed and only then sent as a regular event, so presentation reacts
+// to what really happened instead of a prediction that may still be rolled back
+pub struct ConfirmedEventPlugin<E>(PhantomData<E>);
+
+#[derive(Resource)]
+pub struct Pending<E: Send + Sync + 'static>(Vec<(i32, E)>);
+
+// What rollback systems take to raise an event
+#[derive(SystemParam)]
+pub struct ConfirmedWriter<'w, E: Event + Clone> {
+    frame: Res<'w, RollbackFrameCount>,
+    pending: ResMut<'w, Pending<E>>,
+}
+
+// The newest frame no rollback can reach any more
+#[derive(SystemParam)]
+pub struct ConfirmedFrame<'w> {
+    confirmed: Res<'w, ConfirmedFrameCount>,
+    frame: Res<'w, RollbackFrameCount>,
+    session: Option<Res<'w, Session<Config>>>,
+}
+
+impl ConfirmedFrame<'_> {
+    // bevy_ggrs only moves ConfirmedFrameCount for online sessions and leaves it at -1 for a
+    // synctest session, which every offline mode runs on. There a frame is final once it's
+    // further back than the check distance
+    pub fn get(&self) -> i32 {
+        match self.session.as_deref() {
+            Some(Session::SyncTest(session)) => self.frame.0 - session.check_distance() as i32,
+            _ => i32::from(*self.confirmed),
+        }
+    }
+}
+
+impl<E> Default for ConfirmedEventPlugin<E> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<E: Event + Clone> ConfirmedWriter<'_, E> {
+    pub fn send(&mut self, event: E) {
+        let frame = self.frame.0;
+        self.pending.0.push((frame, event));
+    }
+}
+
+impl<E: Event + Clone> Plugin for ConfirmedEventPlugin<E> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<E>()
+            .insert_resource(Pending::<E>(Vec::new()))
+            // Held one more frame after a rollback over budget, so effects don't pile onto
+            // the frame that's already behind
+            .add_systems(
+                Update,
+                send_confirmed::<E>
+                    .after(crate::rollback_budget::judge_frame)
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(crate::rollback_budget::presenting),
+            )
+            .add_systems(OnExit(GameState::InGame), clear_pending::<E>);
+    }
+}
+
+// Runs first in the rollback schedule. A resimulated frame raises its events again
+pub fn rewind<E: Event + Clone>(frame: Res<RollbackFrameCount>, mut pending: ResMut<Pending<E>>) {
+    pending.0.retain(|(raised, _)| *raised < frame.0);
+}
+
+fn send_confirmed<E: Event + Clone>(
+    confirmed: ConfirmedFrame,
+    mut pending: ResMut<Pending<E>>,
+    mut events: EventWriter<E>,
+) {
+    let confirmed = confirmed.get();
+    let (ready, still_pending): (Vec<_>, Vec<_>) = pending.0.drain(..).partition(|(frame, _)| *frame <= confirmed);
+    pending.0 = still_pending;
+    events.send_batch(ready.into_iter().map(|(_, event)| event));
+}
+
+fn clear_pending<E: Event + Clone>(mut pending: ResMut<Pending<E>>) {
+    pending.0.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::state::app::StatesPlugin;
+    use bevy::time::TimeUpdateStrategy;
+    use bevy::utils::HashMap;
+    use bevy_ggrs::ggrs::SessionBuilder;
+    use crate::game::NUM_PLAYERS;
+    use crate::input::PlayerInput;
+    use crate::rollback_budget::RollbackBudgetPlugin;
+    use crate::scoring::PointScored;
+    use crate::settings::Settings;
+    use super::*;
+
+    const SCORED_ON: i32 = 5;
+
+    #[derive(Resource, Default)]
+    struct Received(Vec<PointScored>);
+
+    fn idle_inputs(mut commands: Commands, local_players: Res<LocalPlayers>) {
+        let inputs: HashMap<_, _> = local_players.0.iter().map(|handle| (*handle, PlayerInput::default())).collect();
+        commands.insert_resource(LocalInputs::<Config>(inputs));
+    }
+
+    // Raised again on every resimulation of its frame, like a real point
+    fn score_once(frame: Res<RollbackFrameCount>, mut points: ConfirmedWriter<PointScored>) {
+        if frame.0 == SCORED_ON {
+            points.send(PointScored { winner: 1, landed_at: Vec2::ZERO, rally_frames: 0, won_match: true });
+        }
+    }
+
+    fn receive(mut points: EventReader<PointScored>, mut received: ResMut<Received>) {
+        received.0.extend(points.read().copied());
+    }
+
+    fn synctest_app(check_distance: usize) -> App {
+        let session = SessionBuilder::<Config>::new()
+            .with_num_players(NUM_PLAYERS)
+            .with_check_distance(check_distance)
+            .start_synctest_session()
+            .expect("a synctest session starts");
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin, GgrsPlugin::<Config>::default(), RollbackBudgetPlugin))
+            .add_plugins(ConfirmedEventPlugin::<PointScored>::default())
+            .insert_resource(Settings::default())
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / 60.0)))
+            .insert_state(GameState::InGame)
+            .init_resource::<Received>()
+            .add_systems(ReadInputs, idle_inputs)
+            .add_systems(GgrsSchedule, (rewind::<PointScored>, score_once).chain())
+            .add_systems(Update, receive.after(send_confirmed::<PointScored>))
+            .insert_resource(Session::SyncTest(session));
+        app
+    }
+
+    #[test]
+    fn synctest_points_arrive_once_confirmed() {
+        let mut app = synctest_app(2);
+        for _ in 0..30 {
+            app.update();
+        }
+        let received = &app.world().resource::<Received>().0;
+        assert_eq!(received.len(), 1, "{received:?}");
+        assert!(received[0].won_match);
+        assert!(app.world().resource::<Pending<PointScored>>().0.is_empty());
+    }
+
+    #[test]
+    fn local_points_arrive_without_a_check_distance() {
+        let mut app = synctest_app(0);
+        for _ in 0..30 {
+            app.update();
+        }
+        assert_eq!(app.world().resource::<Received>().0.len(), 1);
+    }
+}
@@ -0,0 +1,255 @@
+use bevy::prelude::*;
+use bevy_ggrs::{PlayerInputs, RollbackFrameCount};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::panic::PanicHookInfo;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::GameState;
+use crate::build_info::BuildInfo;
+use crate::input::{Config, PlayerInput};
+use crate::logging;
+use crate::settings::Settings;
+
+pub struct CrashPlugin;
+
+#[cfg(not(target_arch = "wasm32"))]
+const REPORT_DIR: &str = "crash-reports";
+// About ten seconds of play
+const REPLAY_FRAMES: usize = 600;
+
+// The panic hook can't reach into the World, so whatever it reports is mirrored out here
+static SETTINGS_SNAPSHOT: Mutex<String> = Mutex::new(String::new());
+static REPLAY: Mutex<VecDeque<(i32, Vec<PlayerInput>)>> = Mutex::new(VecDeque::new());
+
+impl Plugin for CrashPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, remember_settings.run_if(resource_changed::<Settings>))
+            .add_systems(OnExit(GameState::InGame), clear_replay);
+    }
+}
+
+// Called first thing in main, so even a panic while building the app gets a report
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let report = build_report(info);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        match write_bundle("crash", &report) {
+            Ok(path) => eprintln!("crash report written to {path}, please attach it to your bug report"),
+            Err(err) => eprintln!("couldn't write crash report: {err}"),
+        }
+        #[cfg(target_arch = "wasm32")]
+        overlay::show_crash_overlay(&bundle_text(&report));
+    }));
+}
+
+fn remember_settings(settings: Res<Settings>) {
+    let text = ron::ser::to_string_pretty(settings.as_ref(), ron::ser::PrettyConfig::default())
+        .expect("settings always serialize");
+    *SETTINGS_SNAPSHOT.lock().unwrap_or_else(PoisonError::into_inner) = text;
+}
+
+// Runs in the rollback schedule. A resimulated frame replaces what was recorded for it
+// before, so the buffer always holds the inputs that led to the current state
+pub fn record_inputs(frame: Res<RollbackFrameCount>, inputs: Res<PlayerInputs<Config>>) {
+    let Ok(mut replay) = REPLAY.lock() else {
+        return;
+    };
+    while replay.back().is_some_and(|(recorded, _)| *recorded >= frame.0) {
+        replay.pop_back();
+    }
+    if replay.len() == REPLAY_FRAMES {
+        replay.pop_front();
+    }
+    replay.push_back((frame.0, inputs.iter().map(|(input, _)| *input).collect()));
+}
+
+fn clear_replay() {
+    REPLAY.lock().unwrap_or_else(PoisonError::into_inner).clear();
+}
+
+// One (file name, contents) pair per section of the report
+fn build_report(info: &PanicHookInfo) -> Vec<(&'static str, String)> {
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    let location = info.location().map(ToString::to_string).unwrap_or_default();
+
+    let mut panic = report_header();
+    let _ = writeln!(panic, "\n{message}\nat {location}\n");
+    let _ = writeln!(panic, "{}", std::backtrace::Backtrace::force_capture());
+
+    let mut report = vec![("panic.txt", panic)];
+    report.extend(shared_sections());
+    report
+}
+
+// Which build, when, and where in a match if it was in one
+fn report_header() -> String {
+    let (session, frame) = logging::session_context();
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut header = format!("{} {}\n", env!("CARGO_PKG_NAME"), BuildInfo::CURRENT.label());
+    let profile = if cfg!(debug_assertions) { "debug" } else { "release" };
+    let _ = writeln!(header, "{} {} {profile}", std::env::consts::OS, std::env::consts::ARCH);
+    let _ = writeln!(header, "time {time}");
+    if session != 0 {
+        let _ = writeln!(header, "session {session:016x} frame {frame}");
+    }
+    header
+}
+
+// What every report carries after its own summary
+fn shared_sections() -> Vec<(&'static str, String)> {
+    let mut replay = format!("build {}\n", BuildInfo::CURRENT.label());
+    replay.push_str("frame inputs (button bits per handle, then :axis when a stick was pushed)\n");
+    for (frame, inputs) in REPLAY.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+        let inputs: Vec<String> = inputs.iter().map(|input| input.to_field()).collect();
+        let _ = writeln!(replay, "{frame} {}", inputs.join(" "));
+    }
+
+    vec![
+        ("log.txt", logging::recent_lines()),
+        ("settings.ron", SETTINGS_SNAPSHOT.lock().unwrap_or_else(PoisonError::into_inner).clone()),
+        ("replay.txt", replay),
+    ]
+}
+
+// Filed by hand from the pause menu. Same sections as a crash report, with what the player
+// was doing in place of the panic. Returns where it went, a download name on the web
+pub fn write_problem_report(summary: &str) -> Result<String, String> {
+    let mut header = report_header();
+    let _ = writeln!(header, "\n{summary}");
+    let mut report = vec![("report.txt", header)];
+    report.extend(shared_sections());
+
+    #[cfg(not(target_arch = "wasm32"))]
+    return write_bundle("report", &report).map_err(|err| err.to_string());
+    #[cfg(target_arch = "wasm32")]
+    {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let name = format!("report-{time}.txt");
+        crate::web::save_file(&name, &bundle_text(&report));
+        Ok(name)
+    }
+}
+
+// Every section in one text, for where a zip can't be written
+#[cfg(target_arch = "wasm32")]
+fn bundle_text(report: &[(&'static str, String)]) -> String {
+    report.iter().map(|(name, contents)| format!("===== {name}\n{contents}\n")).collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_bundle(kind: &str, report: &[(&'static str, String)]) -> std::io::Result<String> {
+    std::fs::create_dir_all(REPORT_DIR)?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = format!("{REPORT_DIR}/{kind}-{time}.zip");
+    std::fs::write(&path, zip_stored(report))?;
+    Ok(path)
+}
+
+// Minimal zip writer, entries are stored uncompressed. Reports are a few hundred KB at most
+// and every OS can open these without extra tools
+#[cfg(not(target_arch = "wasm32"))]
+fn zip_stored(files: &[(&'static str, String)]) -> Vec<u8> {
+    // 1980-01-01, the earliest date zip can express. The real time is in the first file
+    const DOS_DATE: u16 = 0x21;
+
+    fn u16le(out: &mut Vec<u8>, value: u16) {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u32le(out: &mut Vec<u8>, value: u32) {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, contents) in files {
+        let crc = crc32fast::hash(contents.as_bytes());
+        let size = contents.len() as u32;
+        let offset = out.len() as u32;
+
+        u32le(&mut out, 0x0403_4b50);
+        for value in [20, 0, 0, 0, DOS_DATE] {
+            u16le(&mut out, value);
+        }
+        for value in [crc, size, size] {
+            u32le(&mut out, value);
+        }
+        u16le(&mut out, name.len() as u16);
+        u16le(&mut out, 0);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(contents.as_bytes());
+
+        u32le(&mut central, 0x0201_4b50);
+        for value in [20, 20, 0, 0, 0, DOS_DATE] {
+            u16le(&mut central, value);
+        }
+        for value in [crc, size, size] {
+            u32le(&mut central, value);
+        }
+        for value in [name.len() as u16, 0, 0, 0, 0] {
+            u16le(&mut central, value);
+        }
+        for value in [0, offset] {
+            u32le(&mut central, value);
+        }
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    u32le(&mut out, 0x0605_4b50);
+    for value in [0, 0, files.len() as u16, files.len() as u16] {
+        u16le(&mut out, value);
+    }
+    for value in [central.len() as u32, central_offset] {
+        u32le(&mut out, value);
+    }
+    u16le(&mut out, 0);
+    out
+}
+
+// The browser can't save a file on its own, so the details go in a page overlay with a
+// copy button instead. Built in plain DOM because the app can't draw anything after a panic
+#[cfg(target_arch = "wasm32")]
+mod overlay {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen(inline_js = r#"
+export function show_crash_overlay(details) {
+    const overlay = document.createElement("div");
+    overlay.style.cssText = "position:fixed;inset:0;z-index:1000;display:flex;flex-direction:column;gap:12px;padding:24px;background:rgba(10,10,14,0.94);color:#eee;font-family:sans-serif";
+    const title = document.createElement("h2");
+    title.textContent = "The game crashed";
+    const hint = document.createElement("p");
+    hint.textContent = "Copy the details below and attach them to your bug report.";
+    const text = document.createElement("textarea");
+    text.readOnly = true;
+    text.value = details;
+    text.style.cssText = "flex:1;font-family:monospace;font-size:12px";
+    const copy = document.createElement("button");
+    copy.textContent = "Copy details";
+    copy.onclick = () => {
+        text.select();
+        if (navigator.clipboard) {
+            navigator.clipboard.writeText(details);
+        } else {
+            document.execCommand("copy");
+        }
+        copy.textContent = "Copied";
+    };
+    overlay.append(title, hint, text, copy);
+    document.body.appendChild(overlay);
+}
+"#)]
+    extern "C" {
+        pub fn show_crash_overlay(details: &str);
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use bevy::prelude::*;
+use bevy::utils::SystemTime;
+use bevy_ggrs::RollbackFrameCount;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::drill::DummyBehavior;
+use crate::game::SessionMode;
+use crate::level::{ActiveLevel, LevelDef};
+use crate::locale::Locale;
+use crate::notifications::Notify;
+use crate::race::format_time;
+use crate::rng::{fnv1a, DeterministicRng};
+use crate::rules::{ActiveMutators, GameModeDef, GameRulesApp, Mutators};
+use crate::scoring::Score;
+use crate::ui_layout::SafeArea;
+
+pub struct DailyPlugin;
+
+const RECORD_PATH: &str = "daily_records.ron";
+const SECS_PER_DAY: u64 = 86_400;
+// Odds of each registered mutator being on for the day
+const MUTATOR_PERCENT: u32 = 50;
+
+// Today's match against the bot. Everyone on the same UTC day with the same levels saved
+// gets the same level, mutators and bot
+#[derive(Resource, Clone, Debug)]
+pub struct DailyChallenge {
+    // Days since the Unix epoch
+    day: u64,
+    mutators: Vec<&'static str>,
+    pub bot: DummyBehavior,
+    // Frames the bot has been driven for
+    pub bot_frame: u32,
+    // The level picked on the menu, put back when the challenge is left
+    menu_level: LevelDef,
+    finished: bool,
+}
+
+// Fastest win of each day, in frames
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct DailyRecords {
+    best: BTreeMap<u64, u32>,
+}
+
+#[derive(Component)]
+struct DailyHud;
+
+impl DailyChallenge {
+    // The built-in arena is one of the picks, so there's a level even with none saved
+    fn generate(day: u64, mutators: &Mutators, menu_level: LevelDef) -> (Self, LevelDef) {
+        let mut rng = DeterministicRng::new(fnv1a(format!("daily {day}").as_bytes()));
+        let names = LevelDef::available();
+        let pick = rng.range_u32(0, names.len() as u32 + 1) as usize;
+        let level = match names.get(pick) {
+            Some(name) => LevelDef::load(name).unwrap_or_else(|err| {
+                warn!("daily challenge level failed to load, playing the default arena: {err}");
+                LevelDef::default_arena()
+            }),
+            None => LevelDef::default_arena(),
+        };
+        let mutators = mutators.ids().iter().copied().filter(|_| rng.chance(MUTATOR_PERCENT)).collect();
+        let bot = if rng.chance(75) {
+            DummyBehavior::Follow
+        } else {
+            DummyBehavior::Jump(rng.range_u32(30, 90))
+        };
+        (Self { day, mutators, bot, bot_frame: 0, menu_level, finished: false }, level)
+    }
+}
+
+impl DailyRecords {
+    pub fn load() -> Self {
+        let Ok(text) = std::fs::read_to_string(RECORD_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("ignoring broken {RECORD_PATH}: {err}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("daily records always serialize");
+        std::fs::write(RECORD_PATH, text)
+    }
+
+    // True if it's the day's new best
+    fn submit(&mut self, day: u64, frames: u32) -> bool {
+        let best = self.best.entry(day).or_insert(u32::MAX);
+        let improved = frames < *best;
+        *best = (*best).min(frames);
+        improved
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs() / SECS_PER_DAY
+}
+
+// Year, month and day of a day counted from the Unix epoch, in the proleptic Gregorian calendar
+fn format_day(day: u64) -> String {
+    let days = day as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let of_era = days.rem_euclid(146_097);
+    let year_of_era = (of_era - of_era / 1460 + of_era / 36_524 - of_era / 146_096) / 365;
+    let of_year = of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * of_year + 2) / 153;
+    let day_of_month = of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year}-{month:02}-{day_of_month:02}")
+}
+
+impl Plugin for DailyPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_game_mode(GameModeDef {
+            label_key: "menu.daily",
+            session: SessionMode::Daily,
+            mutators: &[],
+            unplayable: |_| None,
+        })
+        .insert_resource(DailyRecords::load())
+            // Before the level is built on entering the match
+            .add_systems(OnExit(GameState::MainMenu), start_daily.run_if(resource_equals(SessionMode::Daily)))
+            .add_systems(OnEnter(GameState::MainMenu), end_daily.run_if(resource_exists::<DailyChallenge>))
+            .add_systems(OnEnter(GameState::InGame), spawn_daily_hud.run_if(resource_exists::<DailyChallenge>))
+            .add_systems(
+                Update,
+                (record_daily, update_daily_hud)
+                    .chain()
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<DailyChallenge>),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_daily_hud);
+    }
+}
+
+fn start_daily(mut commands: Commands, mutators: Res<Mutators>, mut level: ResMut<ActiveLevel>) {
+    let day = today();
+    let (daily, daily_level) = DailyChallenge::generate(day, &mutators, level.0.clone());
+    info!(
+        "daily challenge {}: level {:?}, mutators {:?}, bot {:?}",
+        format_day(day), daily_level.name, daily.mutators, daily.bot
+    );
+    level.0 = daily_level;
+    commands.insert_resource(ActiveMutators::from_ids(daily.mutators.clone()));
+    commands.insert_resource(daily);
+}
+
+fn end_daily(mut commands: Commands, daily: Res<DailyChallenge>, mut level: ResMut<ActiveLevel>) {
+    level.0 = daily.menu_level.clone();
+    commands.remove_resource::<DailyChallenge>();
+}
+
+fn spawn_daily_hud(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(60.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 24.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        DailyHud,
+    ));
+}
+
+// The bot never runs into rollbacks locally, so the winner is settled the frame it's set
+fn record_daily(
+    mut daily: ResMut<DailyChallenge>,
+    score: Option<Res<Score>>,
+    frame: Option<Res<RollbackFrameCount>>,
+    mut records: ResMut<DailyRecords>,
+    (locale, mut notify): (Res<Locale>, EventWriter<Notify>),
+) {
+    let Some(winner) = score.and_then(|score| score.winner) else {
+        return;
+    };
+    if daily.finished {
+        return;
+    }
+    daily.finished = true;
+    if winner != 0 {
+        notify.send(Notify::info(locale.get("daily.lost")));
+        return;
+    }
+    let frames = frame.map_or(0, |frame| frame.0.max(0) as u32);
+    let day = daily.day;
+    let key = if records.submit(day, frames) { "daily.new_best" } else { "daily.won" };
+    notify.send(Notify::info(locale.format(key, &[("time", &format_time(frames))])));
+    if let Err(err) = records.save() {
+        warn!("can't save {RECORD_PATH}: {err}");
+    }
+}
+
+fn update_daily_hud(
+    daily: Res<DailyChallenge>,
+    (records, locale): (Res<DailyRecords>, Res<Locale>),
+    mut huds: Query<&mut Text, With<DailyHud>>,
+) {
+    if !daily.is_changed() && !records.is_changed() && !locale.is_changed() {
+        return;
+    }
+    let best = records.best.get(&daily.day).map_or("-".to_string(), |frames| format_time(*frames));
+    let mutators = if daily.mutators.is_empty() { "-".to_string() } else { daily.mutators.join(", ") };
+    let label = locale.format(
+        "daily.hud",
+        &[("date", &format_day(daily.day)), ("mutators", &mutators), ("best", &best)],
+    );
+    for mut text in huds.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+fn cleanup_daily_hud(mut commands: Commands, huds: Query<Entity, With<DailyHud>>) {
+    for entity in huds.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,30 @@
+use bevy_ggrs::{Rollback, RollbackOrdered};
+use std::fmt::Debug;
+
+// Query iteration order depends on archetype layout, which can differ between peers and
+// across rollbacks. Anything where several entities compete for one outcome (pickups, hits)
+// has to be sorted by something both peers agree on first.
+
+// Sorts by the order entities were registered for rollback, which is identical on every peer
+pub fn sort_by_rollback<T>(items: &mut [T], order: &RollbackOrdered, rollback: impl Fn(&T) -> Rollback) {
+    items.sort_by_key(|item| order.order(rollback(item)));
+}
+
+// Panics in debug builds if keys aren't strictly increasing. Ties mean the outcome falls
+// back to query order, which is exactly what has to be avoided
+pub fn debug_assert_strictly_ordered<K: PartialOrd + Debug>(context: &str, keys: impl IntoIterator<Item = K>) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let mut previous: Option<K> = None;
+    for key in keys {
+        if let Some(previous) = &previous {
+            assert!(
+                *previous < key,
+                "{context}: iteration order is not deterministic ({previous:?} before {key:?})",
+            );
+        }
+        previous = Some(key);
+    }
+}
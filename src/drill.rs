@@ -0,0 +1,291 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::ball::Ball;
+use crate::daily::DailyChallenge;
+use crate::game::Player;
+use crate::input::{INPUT_LEFT, INPUT_RIGHT, INPUT_STRIKE, INPUT_UP};
+use crate::locale::Locale;
+use crate::scenario::{PendingScenario, Scenario, ScenarioMenuOpen};
+use crate::scoring::{PointScored, Score};
+use crate::strike::STRIKE_RANGE;
+use crate::training::{Combo, InputHistory};
+use crate::ui_layout::SafeArea;
+
+pub struct DrillPlugin;
+
+const RESTART_KEY: KeyCode = KeyCode::KeyR;
+// How far off the ball a following dummy stands before it walks
+const FOLLOW_SLACK: f32 = 0.3;
+// A following dummy jumps for balls this far above its head at most
+const FOLLOW_JUMP_HEIGHT: f32 = 2.5;
+
+// What the training dummy does during a scenario. Written in scenario files, so the variants
+// read as they are typed there
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum DummyBehavior {
+    #[default]
+    Idle,
+    // Walks under the ball, jumps for it and strikes it once in range
+    Follow,
+    // Jumps on the spot every so many frames
+    Jump(u32),
+    // Held inputs in a loop, each one as frames and the glyphs of the input strip, (30, ">X")
+    Sequence(Vec<(u32, String)>),
+}
+
+// What clears a scenario. Any point scored that isn't the goal fails it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Goal {
+    // Strikes landed in a row, as the training combo counts them
+    Combo(u32),
+    // Frames the ball stays in play
+    Rally(u32),
+    // The training player wins the point
+    WinPoint,
+}
+
+// The scenario last loaded in training, kept so it can be restarted
+#[derive(Resource)]
+pub struct ActiveDrill {
+    scenario: Scenario,
+    outcome: Option<bool>,
+    // Frames the dummy has been driven for
+    frame: u32,
+}
+
+#[derive(Component)]
+struct DrillText;
+
+// Everything the dummy looks at to pick its input
+#[derive(SystemParam)]
+pub struct DummyControl<'w, 's> {
+    drill: Option<ResMut<'w, ActiveDrill>>,
+    daily: Option<ResMut<'w, DailyChallenge>>,
+    players: Query<'w, 's, (&'static Player, &'static Transform)>,
+    balls: Query<'w, 's, &'static Transform, With<Ball>>,
+}
+
+fn glyph_bits(glyphs: &str) -> Result<u8, String> {
+    glyphs.chars().filter(|c| !c.is_whitespace()).try_fold(0, |input, glyph| {
+        let bit = match glyph {
+            '<' => INPUT_LEFT,
+            '>' => INPUT_RIGHT,
+            '^' => INPUT_UP,
+            'X' | 'x' => INPUT_STRIKE,
+            _ => return Err(format!("unknown input {glyph:?} in {glyphs:?}")),
+        };
+        Ok(input | bit)
+    })
+}
+
+impl DummyBehavior {
+    // Sequences are only worth loading if every step reads
+    pub fn check(&self) -> Result<(), String> {
+        if let DummyBehavior::Sequence(steps) = self {
+            if steps.iter().all(|(frames, _)| *frames == 0) {
+                return Err("a dummy sequence needs at least one step longer than 0 frames".to_string());
+            }
+            for (_, glyphs) in steps {
+                glyph_bits(glyphs)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ActiveDrill {
+    pub fn new(scenario: Scenario) -> Self {
+        Self { scenario, outcome: None, frame: 0 }
+    }
+
+    fn restart(&mut self) {
+        self.outcome = None;
+        self.frame = 0;
+    }
+}
+
+impl DummyControl<'_, '_> {
+    // Next input of the dummy, doing what the loaded drill says or else today's challenge.
+    // Called once per simulated frame while reading inputs
+    pub fn next_input(&mut self, handle: usize) -> u8 {
+        let frame = if let Some(drill) = self.drill.as_mut() {
+            drill.frame += 1;
+            drill.frame - 1
+        } else if let Some(daily) = self.daily.as_mut() {
+            daily.bot_frame += 1;
+            daily.bot_frame - 1
+        } else {
+            return 0;
+        };
+        let behavior = match (&self.drill, &self.daily) {
+            (Some(drill), _) => &drill.scenario.dummy,
+            (None, Some(daily)) => &daily.bot,
+            (None, None) => return 0,
+        };
+        self.behave(behavior, frame, handle)
+    }
+
+    fn behave(&self, behavior: &DummyBehavior, frame: u32, handle: usize) -> u8 {
+        match behavior {
+            DummyBehavior::Idle => 0,
+            DummyBehavior::Jump(every) => {
+                if *every > 0 && frame.is_multiple_of(*every) { INPUT_UP } else { 0 }
+            }
+            DummyBehavior::Sequence(steps) => {
+                let length: u32 = steps.iter().map(|(frames, _)| frames).sum();
+                let mut at = frame % length.max(1);
+                let mut input = 0;
+                for (frames, glyphs) in steps {
+                    if at < *frames {
+                        input = glyph_bits(glyphs).unwrap_or_default();
+                        break;
+                    }
+                    at -= frames;
+                }
+                input
+            }
+            DummyBehavior::Follow => self.follow_ball(handle),
+        }
+    }
+
+    // What Follow would press for the handle, whether or not a drill is loaded
+    pub fn follow_ball(&self, handle: usize) -> u8 {
+        let player = self.players.iter().find(|(player, _)| player.handle == handle);
+        match (player, self.balls.get_single()) {
+            (Some((_, player)), Ok(ball)) => follow(player.translation.truncate(), ball.translation.truncate()),
+            _ => 0,
+        }
+    }
+}
+
+fn follow(dummy: Vec2, ball: Vec2) -> u8 {
+    let offset = ball - dummy;
+    let mut input = 0;
+    if offset.x > FOLLOW_SLACK {
+        input |= INPUT_RIGHT;
+    } else if offset.x < -FOLLOW_SLACK {
+        input |= INPUT_LEFT;
+    }
+    if offset.y > STRIKE_RANGE && offset.y < FOLLOW_JUMP_HEIGHT && offset.x.abs() < STRIKE_RANGE {
+        input |= INPUT_UP;
+    }
+    if offset.length() < STRIKE_RANGE {
+        input |= INPUT_STRIKE;
+    }
+    input
+}
+
+impl Plugin for DrillPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), spawn_drill_text.run_if(resource_exists::<InputHistory>))
+            .add_systems(
+                Update,
+                (restart_drill, judge_drill.after(crate::training::count_combo), update_drill_text)
+                    .chain()
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<ActiveDrill>),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_drill);
+    }
+}
+
+fn spawn_drill_text(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(60.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 26.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        DrillText,
+    ));
+}
+
+// Puts the scenario back as it was loaded. Not while the panel is up, R could be part of a name
+fn restart_drill(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    scenario_menu: Option<Res<ScenarioMenuOpen>>,
+    mut drill: ResMut<ActiveDrill>,
+    mut combo: ResMut<Combo>,
+) {
+    if !keys.just_pressed(RESTART_KEY) || scenario_menu.is_some() {
+        return;
+    }
+    drill.restart();
+    combo.current = 0;
+    commands.insert_resource(PendingScenario::new(drill.scenario.snapshot));
+}
+
+fn judge_drill(
+    mut drill: ResMut<ActiveDrill>,
+    combo: Res<Combo>,
+    score: Option<Res<Score>>,
+    mut points: EventReader<PointScored>,
+) {
+    let point = points.read().last().copied();
+    if drill.outcome.is_some() {
+        return;
+    }
+    let Some(goal) = drill.scenario.success else {
+        return;
+    };
+    let cleared = match goal {
+        Goal::Combo(strikes) => combo.current >= strikes,
+        Goal::Rally(frames) => score.is_some_and(|score| score.rally_frames >= frames),
+        Goal::WinPoint => point.is_some_and(|point| point.winner == 0),
+    };
+    if cleared {
+        drill.outcome = Some(true);
+    } else if point.is_some() {
+        drill.outcome = Some(false);
+    }
+}
+
+fn update_drill_text(
+    drill: Res<ActiveDrill>,
+    locale: Res<Locale>,
+    mut texts: Query<&mut Text, With<DrillText>>,
+) {
+    if !drill.is_changed() && !locale.is_changed() {
+        return;
+    }
+    let name = &drill.scenario.name;
+    let mut label = match drill.scenario.success {
+        Some(Goal::Combo(strikes)) => locale.format("drill.combo", &[("name", name), ("count", &strikes)]),
+        Some(Goal::Rally(frames)) => {
+            let seconds = format!("{:.1}", frames as f32 / 60.0);
+            locale.format("drill.rally", &[("name", name), ("seconds", &seconds)])
+        }
+        Some(Goal::WinPoint) => locale.format("drill.win_point", &[("name", name)]),
+        None => name.clone(),
+    };
+    match drill.outcome {
+        Some(true) => label = format!("{label}   {}", locale.get("drill.cleared")),
+        Some(false) => label = format!("{label}   {}", locale.get("drill.failed")),
+        None => {}
+    }
+    for mut text in texts.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+fn cleanup_drill(mut commands: Commands, texts: Query<Entity, With<DrillText>>) {
+    for entity in texts.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ActiveDrill>();
+}
@@ -0,0 +1,445 @@
+use bevy::{prelude::*, render::camera::ScalingMode, window::PrimaryWindow};
+use crate::GameState;
+use crate::game::SessionMode;
+use crate::level::{self, ActiveLevel, LevelDef, LevelGeometry, LevelPiece, PieceKind};
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated};
+use crate::presentation::player_color;
+use crate::text_input::{spawn_text_input, InputFilter, TextInput};
+use crate::ui_layout::SafeArea;
+
+pub struct EditorPlugin;
+
+const GRID_SIZE: f32 = 0.5;
+const NAME_MAX_LEN: usize = 32;
+// Strikes a placed block takes to break, edit the saved level for tougher ones
+const BLOCK_HP: u8 = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum EditorTool {
+    #[default]
+    Wall,
+    Ground,
+    Platform,
+    Net,
+    Block,
+    Spawn,
+}
+
+// The level being edited. Kept around while playtesting so the editor resumes where it left off
+#[derive(Resource)]
+pub struct EditorDraft {
+    level: LevelDef,
+    status: String,
+}
+
+#[derive(Resource, Default)]
+struct EditorCursor {
+    tool: EditorTool,
+    drag_start: Option<Vec2>,
+    world_position: Option<Vec2>,
+    load_index: usize,
+}
+
+#[derive(Component)]
+struct EditorEntity;
+
+#[derive(Component)]
+struct EditorStatusText;
+
+#[derive(Component)]
+struct SpawnMarker;
+
+// Asks for the file name on Ctrl+S. The editor ignores its own keys while it's open
+#[derive(Component)]
+struct SavePrompt;
+
+#[derive(Component)]
+struct LevelNameField;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum SavePromptAction {
+    Save,
+    Cancel,
+}
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorCursor>()
+            .add_systems(OnEnter(GameState::Editor), setup_editor)
+            .add_systems(OnExit(GameState::Editor), (cleanup_editor, level::despawn_level))
+            .add_systems(
+                Update,
+                (
+                    track_cursor,
+                    editor_input,
+                    save_prompt_action,
+                    rebuild_level_preview,
+                    draw_editor_gizmos,
+                    update_status_text,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Editor)),
+            );
+    }
+}
+
+impl EditorTool {
+    fn piece_kind(self) -> Option<PieceKind> {
+        match self {
+            EditorTool::Wall => Some(PieceKind::Wall),
+            EditorTool::Ground => Some(PieceKind::Ground),
+            EditorTool::Platform => Some(PieceKind::Platform),
+            EditorTool::Net => Some(PieceKind::Net),
+            EditorTool::Block => Some(PieceKind::Block { hp: BLOCK_HP }),
+            EditorTool::Spawn => None,
+        }
+    }
+}
+
+fn snap(position: Vec2) -> Vec2 {
+    (position / GRID_SIZE).round() * GRID_SIZE
+}
+
+fn setup_editor(mut commands: Commands, draft: Option<ResMut<EditorDraft>>, active_level: Res<ActiveLevel>) {
+    commands.spawn((
+        Camera2d,
+        OrthographicProjection {
+            scaling_mode: ScalingMode::FixedVertical {
+                viewport_height: 10.,
+            },
+            ..OrthographicProjection::default_2d()
+        },
+        EditorEntity,
+    ));
+
+    match draft {
+        // Coming back from a playtest, force a rebuild of the preview
+        Some(mut draft) => draft.set_changed(),
+        // Unnamed until it's saved, so the level it started from isn't overwritten by accident
+        None => {
+            let mut level = active_level.0.clone();
+            level.name = String::new();
+            commands.insert_resource(EditorDraft {
+                level,
+                status: String::new(),
+            });
+        }
+    }
+
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            top: Val::Px(10.0),
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 16.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        EditorStatusText,
+        EditorEntity,
+    ));
+}
+
+type EditorScene = Or<(With<EditorEntity>, With<SpawnMarker>, With<SavePrompt>)>;
+
+fn cleanup_editor(
+    mut commands: Commands,
+    query: Query<Entity, EditorScene>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn track_cursor(
+    mut cursor: ResMut<EditorCursor>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<EditorEntity>>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let Ok((camera, camera_transform)) = cameras.get_single() else {
+        return;
+    };
+
+    cursor.world_position = window
+        .cursor_position()
+        .and_then(|position| camera.viewport_to_world_2d(camera_transform, position).ok());
+}
+
+fn editor_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut cursor: ResMut<EditorCursor>,
+    mut draft: ResMut<EditorDraft>,
+    (prompts, locale): (Query<(), With<SavePrompt>>, Res<Locale>),
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !prompts.is_empty() {
+        return;
+    }
+
+    for (key, tool) in [
+        (KeyCode::Digit1, EditorTool::Wall),
+        (KeyCode::Digit2, EditorTool::Ground),
+        (KeyCode::Digit3, EditorTool::Platform),
+        (KeyCode::Digit4, EditorTool::Net),
+        (KeyCode::Digit5, EditorTool::Spawn),
+        (KeyCode::Digit6, EditorTool::Block),
+    ] {
+        if keys.just_pressed(key) {
+            cursor.tool = tool;
+            cursor.drag_start = None;
+        }
+    }
+
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if ctrl && keys.just_pressed(KeyCode::KeyS) {
+        cursor.drag_start = None;
+        spawn_save_prompt(&mut commands, &draft.level.name, &locale);
+        return;
+    }
+    if ctrl && keys.just_pressed(KeyCode::KeyL) {
+        // Cycle through everything in the levels folder
+        let available = LevelDef::available();
+        draft.status = match available.get(cursor.load_index % available.len().max(1)) {
+            Some(name) => match LevelDef::load(name) {
+                Ok(level) => {
+                    draft.level = level;
+                    format!("Loaded {name}")
+                }
+                Err(err) => format!("Load failed: {err}"),
+            },
+            None => "No saved levels".to_string(),
+        };
+        cursor.load_index += 1;
+    }
+    if ctrl && keys.just_pressed(KeyCode::KeyN) {
+        draft.level.pieces.clear();
+        draft.level.spawn_points.clear();
+        draft.level.links.clear();
+        draft.level.scripts.clear();
+        draft.status = "Cleared level".to_string();
+    }
+
+    if keys.just_pressed(KeyCode::KeyP) {
+        commands.insert_resource(ActiveLevel(draft.level.clone()));
+        commands.insert_resource(SessionMode::Local);
+        next_state.set(GameState::InGame);
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.remove_resource::<EditorDraft>();
+        commands.insert_resource(ActiveLevel::default());
+        commands.insert_resource(SessionMode::Online);
+        next_state.set(GameState::MainMenu);
+        return;
+    }
+
+    let Some(world_position) = cursor.world_position else {
+        return;
+    };
+    let snapped = snap(world_position);
+
+    if mouse.just_pressed(MouseButton::Left) {
+        if cursor.tool == EditorTool::Spawn {
+            draft.level.spawn_points.push(snapped.into());
+        } else {
+            cursor.drag_start = Some(snapped);
+        }
+    }
+
+    if mouse.just_released(MouseButton::Left)
+        && let (Some(start), Some(kind)) = (cursor.drag_start.take(), cursor.tool.piece_kind())
+    {
+        let min = start.min(snapped);
+        // Always at least one grid cell so a click without dragging still places something
+        let size = (start.max(snapped) - min).max(Vec2::splat(GRID_SIZE));
+        draft.level.pieces.push(LevelPiece::new(kind, (min + size / 2.0).into(), size.into()));
+    }
+
+    if mouse.just_pressed(MouseButton::Right) {
+        // Spawn points first since they sit on top of pieces
+        if let Some(index) = draft
+            .level
+            .spawn_points
+            .iter()
+            .position(|point| Vec2::from(*point).distance(world_position) < GRID_SIZE)
+        {
+            draft.level.spawn_points.remove(index);
+        } else if let Some(index) = draft.level.pieces.iter().rposition(|piece| piece.contains(world_position)) {
+            draft.level.remove_piece(index);
+        }
+    }
+}
+
+fn spawn_save_prompt(commands: &mut Commands, name: &str, locale: &Locale) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                right: Val::Px(40.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7)),
+            SafeArea,
+            SavePrompt,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(locale.get("editor.save_title")),
+                TextFont {
+                    font_size: 30.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            let input = TextInput::new(name, NAME_MAX_LEN, InputFilter::FileName, "editor.level_name");
+            spawn_text_input(parent, 0, input).insert(LevelNameField);
+            parent.spawn((menu_button(1), SavePromptAction::Save)).with_children(|parent| {
+                parent.spawn((button_text(""), Localized("editor.save")));
+            });
+            parent
+                .spawn((menu_button(2), SavePromptAction::Cancel, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("editor.cancel")));
+                });
+        });
+}
+
+// Enter on the name field saves as well as the Save button does. The draft takes the name,
+// so the next save and a playtest both go by it
+fn save_prompt_action(
+    mut commands: Commands,
+    mut activated: EventReader<MenuActivated>,
+    actions: Query<&SavePromptAction>,
+    fields: Query<&TextInput, With<LevelNameField>>,
+    prompts: Query<Entity, With<SavePrompt>>,
+    mut draft: ResMut<EditorDraft>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        let save = match actions.get(*entity) {
+            Ok(SavePromptAction::Save) => true,
+            Ok(SavePromptAction::Cancel) => false,
+            Err(_) if fields.contains(*entity) => true,
+            Err(_) => continue,
+        };
+        if save {
+            let Some(name) = fields.get_single().ok().map(|field| field.value.trim().to_string()) else {
+                continue;
+            };
+            if name.is_empty() {
+                continue;
+            }
+            draft.level.name = name;
+            draft.status = match draft.level.save() {
+                Ok(path) => format!("Saved {} (hash {:016x})", path.display(), draft.level.content_hash()),
+                Err(err) => format!("Save failed: {err}"),
+            };
+        }
+        for entity in prompts.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+type DrawnLevel = Or<(With<LevelGeometry>, With<SpawnMarker>)>;
+
+fn rebuild_level_preview(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    draft: Res<EditorDraft>,
+    existing: Query<Entity, DrawnLevel>,
+) {
+    if !draft.is_changed() {
+        return;
+    }
+
+    for entity in existing.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    level::spawn_level(&mut commands, &asset_server, &draft.level);
+
+    for (index, point) in draft.level.spawn_points.iter().enumerate() {
+        commands.spawn((
+            Transform::from_translation(Vec2::from(*point).extend(1.0)),
+            Sprite {
+                color: player_color(index),
+                custom_size: Some(Vec2::splat(GRID_SIZE)),
+                ..default()
+            },
+            SpawnMarker,
+        ));
+    }
+}
+
+fn draw_editor_gizmos(mut gizmos: Gizmos, cursor: Res<EditorCursor>, draft: Res<EditorDraft>) {
+    let half = Vec2::new(draft.level.width, draft.level.height) / 2.0;
+    let grid_color = Color::srgba(1.0, 1.0, 1.0, 0.08);
+
+    let mut x = -half.x;
+    while x <= half.x {
+        gizmos.line_2d(Vec2::new(x, -half.y), Vec2::new(x, half.y), grid_color);
+        x += GRID_SIZE;
+    }
+    let mut y = -half.y;
+    while y <= half.y {
+        gizmos.line_2d(Vec2::new(-half.x, y), Vec2::new(half.x, y), grid_color);
+        y += GRID_SIZE;
+    }
+
+    let Some(world_position) = cursor.world_position else {
+        return;
+    };
+    let snapped = snap(world_position);
+
+    match cursor.drag_start {
+        Some(start) => {
+            let min = start.min(snapped);
+            let size = (start.max(snapped) - min).max(Vec2::splat(GRID_SIZE));
+            gizmos.rect_2d(Isometry2d::from_translation(min + size / 2.0), size, Color::srgb(1.0, 1.0, 0.0));
+        }
+        None => {
+            gizmos.circle_2d(Isometry2d::from_translation(snapped), GRID_SIZE / 4.0, Color::srgb(1.0, 1.0, 0.0));
+        }
+    }
+}
+
+fn update_status_text(
+    cursor: Res<EditorCursor>,
+    draft: Res<EditorDraft>,
+    mut texts: Query<&mut Text, With<EditorStatusText>>,
+) {
+    if !cursor.is_changed() && !draft.is_changed() {
+        return;
+    }
+
+    let position = cursor
+        .world_position
+        .map(|position| {
+            let snapped = snap(position);
+            format!("({:.1}, {:.1})", snapped.x, snapped.y)
+        })
+        .unwrap_or_default();
+
+    for mut text in texts.iter_mut() {
+        text.0 = format!(
+            "Tool: {:?} {}\n1 Wall  2 Ground  3 Platform  4 Net  5 Spawn  6 Block\n\
+             LMB drag place  RMB delete  P playtest  Ctrl+S save  Ctrl+L load next  Ctrl+N clear  Esc menu\n{}",
+            cursor.tool, position, draft.status,
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::locale::Localized;
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct ErrorScreenPlugin;
+
+// What went wrong, already readable. Insert this and switch to GameState::Error
+#[derive(Resource, Clone, Debug)]
+pub struct ErrorMessage(pub String);
+
+#[derive(Component)]
+struct ErrorScreen;
+
+#[derive(Component)]
+struct BackToMenuButton;
+
+impl Plugin for ErrorScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Error), setup_error_screen)
+            .add_systems(Update, back_to_menu.run_if(in_state(GameState::Error)))
+            .add_systems(OnExit(GameState::Error), cleanup_error_screen);
+    }
+}
+
+fn setup_error_screen(mut commands: Commands, message: Option<Res<ErrorMessage>>) {
+    let message = message.map(|message| message.0.clone()).unwrap_or_default();
+    error!("{message}");
+
+    commands.spawn((Camera2d, ErrorScreen));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(30.0),
+                ..full_screen()
+            },
+            SafeArea,
+            ErrorScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::default(),
+                TextFont {
+                    font_size: 36.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(1.0, 0.4, 0.4)),
+                Localized("error.title"),
+            ));
+            parent.spawn((
+                Text::new(message),
+                TextFont {
+                    font_size: 22.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                TextLayout::new_with_justify(JustifyText::Center),
+                Node {
+                    max_width: Val::Px(700.0),
+                    ..default()
+                },
+            ));
+            parent
+                .spawn((menu_button(0), BackToMenuButton, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("error.back_to_menu")));
+                });
+        });
+}
+
+fn back_to_menu(
+    mut activated: EventReader<MenuActivated>,
+    buttons: Query<(), With<BackToMenuButton>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if activated.read().any(|MenuActivated(entity)| buttons.contains(*entity)) {
+        next_state.set(GameState::MainMenu);
+    }
+}
+
+fn cleanup_error_screen(mut commands: Commands, query: Query<Entity, With<ErrorScreen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ErrorMessage>();
+}
@@ -0,0 +1,93 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::presentation::FreezeFrame;
+use crate::scoring::PointScored;
+
+pub struct FinalePlugin;
+
+// Seconds into the sequence
+const ZOOM_IN_END: f32 = 0.35;
+const HOLD_END: f32 = 1.6;
+const ZOOM_OUT_END: f32 = 2.2;
+// Fraction of the normal view still visible at the closest point
+const ZOOM: f32 = 0.45;
+
+// Freeze-frame and zoom on the spot where the match was decided, played once the winning
+// point is confirmed so a rolled back point never triggers it
+#[derive(Resource)]
+pub struct Finale {
+    elapsed: f32,
+    focus: Vec2,
+    // Where the camera was before, it goes back there afterwards
+    home: Vec2,
+    home_scale: f32,
+}
+
+impl Plugin for FinalePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (start_finale, play_finale).chain().run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), end_finale);
+    }
+}
+
+fn start_finale(
+    mut commands: Commands,
+    mut points: EventReader<PointScored>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+) {
+    let Some(point) = points.read().find(|point| point.won_match) else {
+        return;
+    };
+    let Ok((transform, projection)) = cameras.get_single() else {
+        return;
+    };
+    commands.insert_resource(Finale {
+        elapsed: 0.0,
+        focus: point.landed_at,
+        home: transform.translation.truncate(),
+        home_scale: projection.scale,
+    });
+    commands.insert_resource(FreezeFrame);
+}
+
+// Eases in, holds with everything frozen, then lets go and eases back out
+fn play_finale(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    finale: Option<ResMut<Finale>>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+) {
+    let Some(mut finale) = finale else {
+        return;
+    };
+    finale.elapsed += time.delta_secs();
+    let elapsed = finale.elapsed;
+
+    let closeness = if elapsed < ZOOM_IN_END {
+        elapsed / ZOOM_IN_END
+    } else if elapsed < HOLD_END {
+        1.0
+    } else {
+        1.0 - ((elapsed - HOLD_END) / (ZOOM_OUT_END - HOLD_END)).min(1.0)
+    };
+    let eased = closeness * closeness * (3.0 - 2.0 * closeness);
+
+    if elapsed >= HOLD_END {
+        commands.remove_resource::<FreezeFrame>();
+    }
+    for (mut transform, mut projection) in cameras.iter_mut() {
+        let position = finale.home.lerp(finale.focus, eased);
+        transform.translation = position.extend(transform.translation.z);
+        projection.scale = finale.home_scale * (1.0 - (1.0 - ZOOM) * eased);
+    }
+    if elapsed >= ZOOM_OUT_END {
+        commands.remove_resource::<Finale>();
+    }
+}
+
+fn end_finale(mut commands: Commands) {
+    commands.remove_resource::<Finale>();
+}
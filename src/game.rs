@@ -1,212 +1,673 @@
-use bevy::{prelude::*, render::camera::ScalingMode};
+use bevy::{ecs::system::SystemParam, prelude::*, render::camera::ScalingMode};
 use bevy_matchbox::prelude::*;
 use bevy_ggrs::*;
-use bevy_ggrs::prelude::SessionBuilder;
+use bevy_ggrs::prelude::GgrsEvent;
 use avian2d::prelude::*;
 use crate::GameState;
-use crate::input::{Config, get_input_direction, InputPlugin, INPUT_LEFT, INPUT_RIGHT, INPUT_UP, INPUT_UP_PRESSED};
+use crate::build_info::BuildInfo;
+use crate::confirmed::ConfirmedWriter;
+use crate::determinism::debug_assert_strictly_ordered;
+use crate::error_screen::ErrorMessage;
+use crate::guardrails::{SpawnRollbackExt, SpawnSource};
+use crate::handicap::Handicaps;
+use crate::presentation::{Appearance, PresentationPlugin};
+use crate::input::{Config, get_input_direction, InputPlugin, PlayerInput, INPUT_UP};
+use crate::level::{self, ActiveLevel, Ground, LevelDef, GROUND_LAYER, PLAYER_LAYER, WALL_LAYER};
+use crate::level_sync::{LevelSync, LevelSyncPlugin};
+use crate::lobby::{Lobby, LobbyPlugin};
+use crate::locale::{Locale, Localized};
+use crate::notifications::Notify;
+use crate::match_schedule::{self, MatchSchedule, MatchSchedulePlugin};
+use crate::matchmaking::{Matchmaking, MatchmakingState, MatchmakingStatus};
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated};
+use crate::network_feel::LoopbackConditions;
+use crate::reconnect::{Reconnecting, GRACE_SECS};
+use crate::regions::QuickPlay;
+use crate::rng::MatchSeed;
+use crate::rules::{GameModeDef, GameRulesApp};
+use crate::session::{SessionManager, SessionSpec};
+use crate::settings::Settings;
+use crate::shared_channel::SharedChannel;
+use crate::snapshots::SharedSnapshotApp;
+use crate::skins::{Character, CharacterDefs, CharacterRoster, Physique, PlayerSkins};
+use crate::speed_limit::SpeedLimit;
+use crate::ui_layout::{full_screen, SafeArea};
 
 pub struct GamePlugin;
 
-// Define collision layers
-const WALL_LAYER: u32 = 0b01;
-const PLAYER_LAYER: u32 = 0b10;
-const GROUND_LAYER: u32 = 0b100; // Different from WALL_LAYER
+// Player sprites are drawn at this scale, their collider is a child so it scales along
+pub const PLAYER_SCALE: f32 = 0.0025;
+
+// How the next match gets its GGRS session
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionMode {
+    #[default]
+    Online,
+    // Both handles are played on this machine through a synctest session
+    Local,
+    // Like Local, but handle 1 is a dummy that never moves, see training
+    Training,
+    // Time trial through a level's checkpoints, handle 1 is the ghost of the best run. See race
+    Race,
+    // Like Training, against a bot picked by the day's challenge. See daily
+    Daily,
+    // Both handles chase the ball on their own, played when the main menu sits idle. See attract
+    Attract,
+    // Like Local, but every frame is rolled back and resimulated to check it comes out the same
+    SyncTest,
+}
 
-#[derive(Component)]
-struct Ground; // Add a component to identify the ground
+impl SessionMode {
+    // The handle played by whoever sits at this machine. None with two people on one keyboard,
+    // nobody at all, or before an online session has said which
+    pub fn own_handle(self, local_players: Option<&LocalPlayers>) -> Option<usize> {
+        match self {
+            SessionMode::Online => match local_players.map(|local| local.0.as_slice()) {
+                Some([handle]) => Some(*handle),
+                _ => None,
+            },
+            SessionMode::Training | SessionMode::Race | SessionMode::Daily => Some(0),
+            SessionMode::Local | SessionMode::Attract | SessionMode::SyncTest => None,
+        }
+    }
+}
+
+pub const NUM_PLAYERS: usize = 2;
+pub const FRAME_MS: f32 = 1000.0 / 60.0;
+const DEFAULT_INPUT_DELAY: usize = 2;
+// Past this the controls feel sluggish, rolling back is the lesser evil
+pub const MAX_INPUT_DELAY: usize = 5;
+// Frames a synctest session rolls back every frame. Checksums that differ after resimulating
+// them are logged by GGRS as a mismatch
+const SYNCTEST_CHECK_DISTANCE: usize = 7;
+
+// Matchbox room to join. Empty means the shared room anyone can be paired up in
+#[derive(Resource, Default, Clone, Debug)]
+pub struct MatchRoom(pub String);
+
+// Present while going from one match of a rotation to the next. The connection and lobby
+// outlive the match instead of being closed with it
+#[derive(Resource)]
+pub struct KeepConnection;
 
 #[derive(Component)]
 struct WaitingText;
 
+// Backs out of matchmaking to the join screen
+#[derive(Component)]
+struct CancelMatchmakingButton;
+
+// Marks everything the match spawns so it can be torn down when leaving InGame
+#[derive(Component)]
+struct InGameEntity;
+
 #[derive(Component, Clone)]
-struct Player {
-    handle: usize,
-    jumps_remaining: u8,
-    is_grounded: bool,
-    previous_input: u8,  // Add field to track previous input
+pub struct Player {
+    pub handle: usize,
+    pub jumps_remaining: u8,
+    pub is_grounded: bool,
+    // Buttons held on the last frame, see just_pressed
+    pub previous_input: u8,
+    pub speed_boost_frames: u32,
+    pub facing_left: bool,
+    pub strike_cooldown: u8,
+    pub stun_frames: u8,
+    pub meter: u8,
+}
+
+// Sent once the frame a player jumped on is confirmed
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Jumped {
+    pub handle: usize,
+}
+
+impl Player {
+    pub fn new(handle: usize) -> Self {
+        Self {
+            handle,
+            jumps_remaining: 2,
+            is_grounded: false,
+            previous_input: 0,
+            speed_boost_frames: 0,
+            facing_left: handle % 2 == 1,
+            strike_cooldown: 0,
+            stun_frames: 0,
+            meter: 0,
+        }
+    }
+
+    // Whether any of the buttons went down this frame. Every press the simulation reacts to is
+    // found here rather than sent, so a repeated prediction of a held button is never a new press
+    pub fn just_pressed(&self, input: PlayerInput, buttons: u8) -> bool {
+        input.buttons & buttons & !self.previous_input != 0
+    }
+
+    // Whether any of the buttons came up this frame, the other edge of just_pressed
+    pub fn just_released(&self, input: PlayerInput, buttons: u8) -> bool {
+        !input.buttons & buttons & self.previous_input != 0
+    }
 }
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
+        // First in the menu, ahead of the modes the other plugins add
+        app.register_game_mode(GameModeDef {
+            label_key: "menu.local",
+            session: SessionMode::Local,
+            mutators: &[],
+            unplayable: |_| None,
+        })
+        .register_game_mode(GameModeDef {
+            label_key: "menu.synctest",
+            session: SessionMode::SyncTest,
+            mutators: &[],
+            unplayable: |_| None,
+        });
+
         app.add_plugins((
             GgrsPlugin::<Config>::default(),
             PhysicsPlugins::default(),
             PhysicsDebugPlugin::default(),
             InputPlugin,
+            MatchSchedulePlugin,
+            LevelSyncPlugin,
+            LobbyPlugin,
+            crate::pool::PoolPlugin,
+            crate::ball::BallPlugin,
+            crate::blocks::BlocksPlugin,
+            crate::scoring::ScoringPlugin,
+            crate::gameplay_constants::GameplayConstantsPlugin,
+            crate::speed_limit::SpeedLimitPlugin,
+            crate::serve::ServePlugin,
+            crate::handicap::HandicapPlugin,
         ))
+        .add_plugins((
+            crate::confirmed::ConfirmedEventPlugin::<crate::strike::PlayerHit>::default(),
+            crate::confirmed::ConfirmedEventPlugin::<crate::strike::StrikeLanded>::default(),
+            crate::confirmed::ConfirmedEventPlugin::<Jumped>::default(),
+        ))
+        .add_plugins((
+            PresentationPlugin,
+            crate::skins::SkinPlugin,
+            crate::outline::OutlinePlugin,
+            crate::character_material::CharacterMaterialPlugin,
+            crate::finale::FinalePlugin,
+            crate::stats::StatsPlugin,
+            crate::rollback_budget::RollbackBudgetPlugin,
+            crate::profiling::ProfilingPlugin,
+            crate::training::TrainingPlugin,
+            crate::daily::DailyPlugin,
+            crate::scenario::ScenarioPlugin,
+            crate::drill::DrillPlugin,
+            crate::sound::SoundPlugin,
+            crate::music::MusicPlugin,
+            crate::session_history::SessionHistoryPlugin,
+        ))
+        .add_plugins((
+            crate::camera::ArenaCameraPlugin,
+            crate::minimap::MinimapPlugin,
+            crate::level_logic::LevelLogicPlugin,
+            crate::race::RacePlugin,
+            crate::network_feel::NetworkFeelPlugin,
+            crate::pause_menu::PauseMenuPlugin,
+            crate::quick_settings::QuickSettingsPlugin,
+            crate::reconnect::ReconnectPlugin,
+            crate::matchmaking::MatchmakingPlugin,
+            crate::rules::RulesPlugin,
+            crate::hit_numbers::HitNumbersPlugin,
+            crate::perspective::PerspectivePlugin,
+            crate::latency::LatencyPlugin,
+        ));
+
+        #[cfg(debug_assertions)]
+        app.add_plugins(crate::guardrails::GuardrailsPlugin);
+        #[cfg(feature = "level-scripts")]
+        app.add_plugins(crate::level_script::LevelScriptPlugin);
+
+        app
             .rollback_component_with_clone::<Transform>()
             .rollback_component_with_clone::<LinearVelocity>()
-            .rollback_component_with_clone::<Restitution>()
-            .rollback_component_with_clone::<Friction>()
             .rollback_component_with_clone::<GravityScale>()
-            .rollback_component_with_clone::<CollisionLayers>()
-            .rollback_component_with_clone::<Collider>()
-            .add_systems(OnEnter(GameState::InGame), (setup, spawn_players, start_matchbox_socket))
-            .add_systems(Update, wait_for_players.run_if(in_state(GameState::InGame)))
-            .add_systems(GgrsSchedule, move_players.run_if(in_state(GameState::InGame)));
+            // What a synctest compares after resimulating, see SYNCTEST_CHECK_DISTANCE
+            .checksum_component::<Transform>(checksum_transform)
+            .checksum_component::<LinearVelocity>(|velocity| crate::rng::fnv1a(&bits(velocity.0.to_array())))
+            // Set once at spawn, sharing them also spares physics from seeing them change
+            // on every rollback
+            .rollback_component_with_sharing::<Restitution>()
+            .rollback_component_with_sharing::<Friction>()
+            .rollback_component_with_sharing::<CollisionLayers>()
+            .rollback_component_with_sharing::<Collider>()
+            // Broken blocks and open doors, added and removed mid match
+            .rollback_component_with_copy::<ColliderDisabled>()
+            .rollback_component_with_clone::<Player>()
+            .rollback_component_with_copy::<SpawnSource>()
+            .init_resource::<SessionMode>()
+            .init_resource::<SessionManager>()
+            .init_resource::<MatchRoom>()
+            .add_systems(OnEnter(GameState::InGame), (
+                (
+                    setup,
+                    spawn_players,
+                    open_matchbox_socket.run_if(resource_equals(SessionMode::Online)),
+                ).run_if(assets_sound),
+                report_broken_assets.run_if(not(assets_sound)),
+            ))
+            // Neither kind of session starts before every collider of the level is in
+            .add_systems(Update, start_local_session
+                .run_if(in_state(GameState::InGame))
+                .run_if(local_session)
+                .run_if(not(resource_exists::<bevy_ggrs::Session<Config>>))
+                .run_if(level::level_streamed))
+            .add_systems(OnExit(GameState::InGame), (cleanup_game, close_matchbox_socket))
+            // Kept open through the match over screen for a rematch, closed unless one starts
+            .add_systems(OnExit(GameState::MatchOver), close_matchbox_socket)
+            .add_systems(Update, update_player_appearance.run_if(in_state(GameState::InGame)))
+            .add_systems(Update, report_session_events
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<bevy_ggrs::Session<Config>>))
+            .add_systems(Update, cancel_matchmaking
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<Matchmaking>))
+            .add_systems(Update, start_online_session
+                .after(crate::matchmaking::advance_matchmaking)
+                .run_if(in_state(GameState::InGame))
+                .run_if(starting)
+                .run_if(resource_exists::<MatchboxSocket>)
+                .run_if(resource_exists::<Lobby>)
+                .run_if(level::level_streamed))
+            .add_systems(Update, remove_waiting_text
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_added::<bevy_ggrs::Session<Config>>))
+            .add_systems(OnEnter(GameState::NextMatch), start_next_match)
+            .configure_sets(GgrsSchedule, crate::rules::MutatorSet.after(move_players).before(crate::speed_limit::clamp_speeds))
+            .add_systems(GgrsSchedule, (
+                crate::logging::track_frame,
+                crate::rollback_budget::measure_rollback,
+                crate::crash::record_inputs,
+                crate::training::record_history.run_if(resource_exists::<crate::training::InputHistory>),
+                (
+                    crate::confirmed::rewind::<crate::strike::PlayerHit>,
+                    crate::confirmed::rewind::<crate::strike::StrikeLanded>,
+                    crate::confirmed::rewind::<crate::scoring::PointScored>,
+                    crate::confirmed::rewind::<Jumped>,
+                    crate::confirmed::rewind::<match_schedule::ItemCollected>,
+                )
+                    .chain(),
+                crate::scenario::apply_scenario.run_if(resource_exists::<crate::scenario::PendingScenario>),
+                crate::strike::strike,
+                crate::blocks::strike_blocks,
+                crate::level_logic::update_level_logic,
+                move_players,
+                (crate::race::record_run, crate::race::pass_checkpoints)
+                    .chain()
+                    .run_if(resource_exists::<crate::race::RaceProgress>),
+                crate::speed_limit::clamp_speeds,
+                crate::watchdog::recover_out_of_bounds,
+                (crate::serve::update_serve, crate::scoring::update_rally)
+                    .chain()
+                    .run_if(resource_exists::<crate::scoring::Score>),
+                remember_inputs,
+                (
+                    crate::scoring::reroll_stage.run_if(resource_exists::<crate::scoring::Score>),
+                    match_schedule::spawn_scheduled_items,
+                    match_schedule::collect_items,
+                    match_schedule::apply_hazards,
+                ).chain().run_if(resource_exists::<MatchSchedule>),
+                crate::audit::record_checksum.run_if(resource_exists::<crate::audit::AuditRun>),
+            ).chain().run_if(in_state(GameState::InGame)));
+    }
+}
+
+// Last thing in the frame, so every system before it sees the same edges through just_pressed
+fn remember_inputs(inputs: Res<PlayerInputs<Config>>, mut players: Query<&mut Player>) {
+    for mut player in &mut players {
+        player.previous_input = inputs[player.handle].0.buttons;
     }
 }
 
-fn setup(mut commands: Commands) {
+// A level or roster that would come out broken never gets an arena, the error screen lists
+// what's wrong with it instead
+pub fn assets_sound(level: Res<ActiveLevel>, roster: Res<CharacterRoster>, defs: Res<CharacterDefs>) -> bool {
+    level.0.problems().is_empty() && roster.problems().is_empty() && defs.problems(&roster).is_empty()
+}
+
+fn report_broken_assets(
+    mut commands: Commands,
+    (level, roster, defs, locale): (Res<ActiveLevel>, Res<CharacterRoster>, Res<CharacterDefs>, Res<Locale>),
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let mut problems: Vec<String> = level.0.problems().into_iter().map(|problem| format!("{}: {problem}", level.0.name)).collect();
+    problems.extend(roster.problems());
+    problems.extend(defs.problems(&roster));
+    commands.insert_resource(ErrorMessage(locale.format("error.broken_assets", &[("problems", &problems.join("\n"))])));
+    next_state.set(GameState::Error);
+}
+
+// The level itself is spawned by the level plugin
+fn setup(mut commands: Commands, mode: Res<SessionMode>) {
     // Camera setup
     commands.spawn((
         Camera2d,
         OrthographicProjection {
             scaling_mode: ScalingMode::FixedVertical {
-                viewport_height: 10.,
+                viewport_height: crate::camera::VIEW_HEIGHT,
             },
             ..OrthographicProjection::default_2d()
         },
+        crate::camera::ArenaCamera,
+        IsDefaultUiCamera,
+        InGameEntity,
     ));
 
-    // Border dimensions
-    let border_thickness = 0.5;
-    let width = 16.0; // Viewport width (assuming 16:10 aspect ratio)
-    let height = 10.0; // Matches viewport_height
-
-    // Spawn borders
-    // Top wall
-    commands.spawn((
-        Transform::from_xyz(0.0, height/2.0, 0.0),
-        Sprite {
-            color: Color::BLACK,
-            custom_size: Some(Vec2::new(width, border_thickness)),
-            ..default()
-        },
-        RigidBody::Static,
-        Collider::rectangle(width, border_thickness),
-        CollisionLayers::new([WALL_LAYER], !WALL_LAYER),
-    ));
-
-    // Bottom wall (ground)
-    commands.spawn((
-        Transform::from_xyz(0.0, -height/2.0, 0.0),
-        Sprite {
-            color: Color::BLACK,
-            custom_size: Some(Vec2::new(width, border_thickness)),
-            ..default()
-        },
-        RigidBody::Static,
-        Collider::rectangle(width, border_thickness),
-        CollisionLayers::new([GROUND_LAYER], !GROUND_LAYER),
-        Ground,
-    ));
-
-    // Left wall
-    commands.spawn((
-        Transform::from_xyz(-width/2.0, 0.0, 0.0),
-        Sprite {
-            color: Color::BLACK,
-            custom_size: Some(Vec2::new(border_thickness, height)),
-            ..default()
-        },
-        RigidBody::Static,
-        Collider::rectangle(border_thickness, height),
-        CollisionLayers::new([WALL_LAYER], !WALL_LAYER),
-    ));
-
-    // Right wall
-    commands.spawn((
-        Transform::from_xyz(width/2.0, 0.0, 0.0),
-        Sprite {
-            color: Color::BLACK,
-            custom_size: Some(Vec2::new(border_thickness, height)),
-            ..default()
-        },
-        RigidBody::Static,
-        Collider::rectangle(border_thickness, height),
-        CollisionLayers::new([WALL_LAYER], !WALL_LAYER),
-    ));
-
-    // Net
-    commands.spawn((
-        Transform::from_xyz(0.0, -height/4.0, 0.0),
-        Sprite {
-            color: Color::BLACK,
-            custom_size: Some(Vec2::new(border_thickness, height * 0.5)),
-            ..default()
-        },
-        RigidBody::Static,
-        Collider::rectangle(border_thickness, height * 0.5),
-        CollisionLayers::new([WALL_LAYER], !WALL_LAYER),
-    ));
+    if *mode != SessionMode::Online {
+        return;
+    }
 
     // Spawn waiting text
     commands
         .spawn((
             Node {
-                width: Val::Percent(100.0),
-                height: Val::Percent(100.0),
                 justify_content: JustifyContent::Center,
                 align_items: AlignItems::Center,
-                ..default()
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
             },
+            SafeArea,
             WaitingText,
+            InGameEntity,
         ))
         .with_children(|parent| {
             parent.spawn((
-                Text::new("Waiting for other player..."),
+                Text::default(),
                 TextFont {
                     font_size: 30.0,
                     ..default()
                 },
                 TextColor(Color::WHITE),
+                MatchmakingStatus,
             ));
+            parent
+                .spawn((menu_button(0), CancelMatchmakingButton, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("game.cancel")));
+                });
         });
 }
 
-fn start_matchbox_socket(mut commands: Commands) {
-    let room_url = "ws://ec2-54-67-37-240.us-west-1.compute.amazonaws.com:3536/extreme_bevy?next=2";
+// Every attempt gets a socket of its own. One left over from an attempt that never reached
+// OnExit is closed first, so its half-open channels can't leak into this one
+fn open_matchbox_socket(
+    mut commands: Commands,
+    (room, quick_play): (Res<MatchRoom>, Option<Res<QuickPlay>>),
+    stale: Option<ResMut<MatchboxSocket>>,
+    keep: Option<Res<KeepConnection>>,
+    settings: Res<Settings>,
+    time: Res<Time<Real>>,
+) {
+    commands.insert_resource(Matchmaking::new(time.elapsed_secs_f64()));
+    // Next match of a rotation, same peers on the same socket. Only the level starts over
+    if keep.is_some() {
+        commands.remove_resource::<KeepConnection>();
+        commands.insert_resource(LevelSync::default());
+        return;
+    }
+    if let Some(mut stale) = stale {
+        warn!("closing a stale matchbox socket before reconnecting");
+        stale.close();
+    }
+    // The shared room pairs strangers two at a time, a named room is a lobby anyone can drop into
+    let drop_in = !room.0.is_empty();
+    let server = settings.matchbox_server.trim_end_matches('/');
+    let room_url = if drop_in {
+        format!("{server}/extreme_bevy_{}", room.0)
+    } else if let Some(quick_play) = quick_play {
+        quick_play.region().room_url(server)
+    } else {
+        format!("{server}/extreme_bevy?next={NUM_PLAYERS}")
+    };
+    open_socket(&mut commands, room_url, drop_in, &settings);
+}
+
+// A fresh socket, lobby and level sync for the room. Quick play also comes here when it moves
+// on to the next region
+pub fn open_socket(commands: &mut Commands, room_url: String, drop_in: bool, settings: &Settings) {
     info!("connecting to matchbox server: {room_url}");
-    commands.insert_resource(MatchboxSocket::new_unreliable(room_url));
+    // Unreliable channel for GGRS, reliable ones for agreeing on the level and the lineup beforehand
+    let mut socket = MatchboxSocket::from(
+        WebRtcSocketBuilder::new(room_url)
+            .add_unreliable_channel()
+            .add_reliable_channel()
+            .add_reliable_channel(),
+    );
+    let channel = socket.take_channel(0).expect("the GGRS channel was just added");
+    commands.insert_resource(SharedChannel::new(channel, settings.packet_copies));
+    commands.insert_resource(socket);
+    commands.insert_resource(LevelSync::default());
+    commands.insert_resource(Lobby::new(drop_in));
+}
+
+fn starting(matchmaking: Option<Res<Matchmaking>>) -> bool {
+    matchmaking.is_some_and(|matchmaking| matchmaking.state() == MatchmakingState::Starting)
+}
+
+fn local_session(mode: Res<SessionMode>) -> bool {
+    !matches!(*mode, SessionMode::Online)
 }
 
-fn wait_for_players(
-    mut socket: ResMut<MatchboxSocket>, 
+// --local or --synctest on the command line go straight into that match, no menus and no relay
+// server. For LAN parties and determinism checks
+pub fn requested_session() -> Option<SessionMode> {
+    #[cfg(not(target_arch = "wasm32"))]
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--local" => return Some(SessionMode::Local),
+            "--synctest" => return Some(SessionMode::SyncTest),
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn skip_menus(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::InGame);
+}
+
+// Starts the session of a match played on this machine alone, no matchbox involved
+fn start_local_session(
     mut commands: Commands,
-    waiting_text: Query<Entity, With<WaitingText>>,
+    mut sessions: ResMut<SessionManager>,
+    level: Res<ActiveLevel>,
+    (locale, settings): (Res<Locale>, Res<Settings>),
+    mode: Res<SessionMode>,
+    mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if socket.get_channel(0).is_err() {
-        return; // we've already started
+    let seed = MatchSeed(level.0.content_hash());
+    crate::logging::begin_session(seed, &[]);
+    commands.insert_resource(seed);
+    // No items or gusts in a race, every run gets the same course
+    if *mode != SessionMode::Race {
+        let schedule = MatchSchedule::generate(seed);
+        match_schedule::spawn_schedule_preview(&mut commands, &locale, seed, &schedule);
+        commands.insert_resource(schedule);
     }
 
-    // Check for new connections
-    socket.update_peers();
-    let players = socket.players();
+    // Training can take on the feel of an online match, against a dummy on the other end of
+    // a loopback
+    let spec = if *mode == SessionMode::Training && crate::network_feel::enabled(&settings) {
+        SessionSpec::Loopback {
+            input_delay: settings.training_input_delay,
+            conditions: LoopbackConditions::from_settings(&settings),
+        }
+    } else {
+        let check_distance = if *mode == SessionMode::SyncTest { SYNCTEST_CHECK_DISTANCE } else { 0 };
+        SessionSpec::Local { check_distance }
+    };
 
-    let num_players = 2;
-    if players.len() < num_players {
-        return; // wait for more players
+    if let Err(err) = sessions.start(&mut commands, spec) {
+        commands.insert_resource(ErrorMessage(locale.format("error.local_session", &[("reason", &err)])));
+        next_state.set(GameState::Error);
     }
+}
 
-    info!("All peers have joined, going in-game");
-
-    // Remove waiting text
-    if let Ok(entity) = waiting_text.get_single() {
+fn cleanup_game(mut commands: Commands, query: Query<Entity, With<InGameEntity>>, mut sessions: ResMut<SessionManager>) {
+    for entity in query.iter() {
         commands.entity(entity).despawn_recursive();
     }
+    sessions.stop(&mut commands);
+}
 
-    // create a GGRS P2P session
-    let mut session_builder = SessionBuilder::<Config>::new()
-        .with_num_players(num_players)
-        .with_input_delay(2);
+// Closing the channels before dropping the socket lets the peer see us leave right away
+// instead of waiting for a timeout. Dropping it also stops its message loop task
+fn close_matchbox_socket(
+    mut commands: Commands,
+    socket: Option<ResMut<MatchboxSocket>>,
+    channel: Option<Res<SharedChannel>>,
+    keep: Option<Res<KeepConnection>>,
+) {
+    let Some(mut socket) = socket else {
+        return;
+    };
+    if keep.is_some() {
+        return;
+    }
+    info!("closing matchbox socket");
+    if let Some(channel) = channel {
+        channel.close();
+    }
+    socket.close();
+    commands.remove_resource::<MatchboxSocket>();
+    commands.remove_resource::<SharedChannel>();
+    commands.remove_resource::<LevelSync>();
+    commands.remove_resource::<Lobby>();
+}
 
-    for (i, player) in players.into_iter().enumerate() {
-        session_builder = session_builder
-            .add_player(player, i)
-            .expect("failed to add player");
+fn cancel_matchmaking(
+    mut activated: EventReader<MenuActivated>,
+    buttons: Query<(), With<CancelMatchmakingButton>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if activated.read().any(|MenuActivated(entity)| buttons.contains(*entity)) {
+        next_state.set(GameState::JoinOnline);
     }
+}
 
-    // move the channel out of the socket (required because GGRS takes ownership of it)
-    let channel = socket.take_channel(0).unwrap();
+// Matchmaking has everyone agreed, see matchmaking::MatchmakingState
+fn start_online_session(
+    mut commands: Commands,
+    mut sessions: ResMut<SessionManager>,
+    mut socket: ResMut<MatchboxSocket>,
+    channel: Res<SharedChannel>,
+    mut lobby: ResMut<Lobby>,
+    (locale, mut notify): (Res<Locale>, EventWriter<Notify>),
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let (Some(local), Some(lineup)) = (socket.id(), lobby.lineup.clone()) else {
+        return;
+    };
+    let delay = lineup.opponent_of(local).and_then(|opponent| lobby.input_delay(opponent));
+    commands.remove_resource::<Matchmaking>();
+
+    info!("Lineup is ready, going in-game");
+
+    // Everyone derives the same seed from who plays, so the item and hazard schedule matches
+    let seed = MatchSeed::from_peers(&lineup.players);
+    crate::logging::begin_session(seed, &lineup.players);
+    let schedule = MatchSchedule::generate(seed);
+    match_schedule::spawn_schedule_preview(&mut commands, &locale, seed, &schedule);
+    commands.insert_resource(seed);
+    commands.insert_resource(schedule);
+
+    let channel = channel.clone();
+    let spec = if let Some(input_delay) = delay {
+        lobby.finish_delay_talk();
+        let ms = (input_delay as f32 * FRAME_MS).round();
+        notify.send(Notify::info(locale.format("toast.input_delay", &[("frames", &input_delay), ("ms", &ms)])));
+        SessionSpec::Online { channel, local, lineup, input_delay }
+    } else {
+        SessionSpec::Spectator { channel, lineup }
+    };
+    if let Err(err) = sessions.start(&mut commands, spec) {
+        commands.insert_resource(ErrorMessage(locale.format("error.online_session", &[("reason", &err)])));
+        next_state.set(GameState::Error);
+    }
+}
 
-    // start the GGRS session
-    let ggrs_session = session_builder
-        .start_p2p_session(channel)
-        .expect("failed to start session");
+// Enough delay to cover the trip one way, so most remote inputs arrive before they're needed
+// and rollbacks stay short. Without a measurement it keeps what always worked on LAN
+pub fn input_delay(rtt_ms: Option<f32>) -> usize {
+    rtt_ms.map_or(DEFAULT_INPUT_DELAY, |rtt| {
+        ((rtt / 2.0 / FRAME_MS).ceil() as usize).clamp(1, MAX_INPUT_DELAY)
+    })
+}
 
-    commands.insert_resource(bevy_ggrs::Session::P2P(ggrs_session));
+fn remove_waiting_text(mut commands: Commands, waiting_text: Query<Entity, With<WaitingText>>) {
+    for entity in waiting_text.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn start_next_match(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::InGame);
+}
+
+// Turns the GGRS events worth telling the player about into toasts. A silent opponent gets
+// the reconnect overlay until they're back or the grace period is up, and only then is an
+// undecided match over
+fn report_session_events(
+    mut commands: Commands,
+    mut session: ResMut<bevy_ggrs::Session<Config>>,
+    (mode, score): (Res<SessionMode>, Option<Res<crate::scoring::Score>>),
+    locale: Res<Locale>,
+    mut notify: EventWriter<Notify>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let bevy_ggrs::Session::P2P(session) = session.as_mut() else {
+        return;
+    };
+    let events: Vec<_> = session.events().collect();
+    for event in events {
+        info!("ggrs event: {event:?}");
+        // Spectators come and go without holding up the match
+        let player = |addr| session.handles_by_address(addr).iter().any(|handle| *handle < NUM_PLAYERS);
+        match event {
+            GgrsEvent::Disconnected { addr } if player(addr) => {
+                commands.remove_resource::<Reconnecting>();
+                let decided = score.as_ref().is_some_and(|score| score.winner.is_some());
+                if *mode == SessionMode::Online && !decided {
+                    commands.insert_resource(ErrorMessage(locale.format("error.opponent_lost", &[("seconds", &GRACE_SECS)])));
+                    next_state.set(GameState::Error);
+                } else {
+                    notify.send(Notify::warning(locale.get("toast.opponent_disconnected")));
+                }
+            }
+            GgrsEvent::NetworkInterrupted { addr, disconnect_timeout } if player(addr) => {
+                commands.insert_resource(Reconnecting::new(disconnect_timeout));
+            }
+            GgrsEvent::NetworkResumed { addr } if player(addr) => {
+                commands.remove_resource::<Reconnecting>();
+                notify.send(Notify::info(locale.get("toast.network_resumed")));
+            }
+            GgrsEvent::DesyncDetected { frame, local_checksum, remote_checksum, addr } => {
+                error!(
+                    "desync with {addr} at frame {frame}, checksums {local_checksum:x} here and {remote_checksum:x} there, build {}",
+                    BuildInfo::CURRENT.label()
+                );
+                notify.send(Notify::error(locale.format("toast.desync", &[("frame", &frame)])));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Bit patterns, so -0.0 against 0.0 counts as a difference like it would after a desync
+fn bits<const N: usize>(values: [f32; N]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_bits().to_le_bytes()).collect()
+}
+
+fn checksum_transform(transform: &Transform) -> u64 {
+    let mut bytes = bits(transform.translation.to_array());
+    bytes.extend(bits(transform.rotation.to_array()));
+    crate::rng::fnv1a(&bytes)
 }
 
 // Helper function to add common physics components to a player
@@ -218,127 +679,162 @@ fn add_player_physics(commands: &mut Commands, entity: Entity) {
         Restitution::new(0.0),
         Friction::new(0.01),
         GravityScale(1.0), // Enable gravity for jumping
+        SpeedLimit::PLAYER,
     ));
 }
 
-fn spawn_players(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let scale = 0.0025;
-    let sprite_height = 440.0;
-    let sprite_width = 200.0;
-    
-    // Player 1
-    let player1 = commands
-        .spawn((
-            Player { 
-                handle: 0,
-                jumps_remaining: 2,
-                is_grounded: false,
-                previous_input: 0,
-            },
-            Transform::from_translation(Vec3::new(-2., 0., 0.))
-                .with_scale(Vec3::splat(scale)),
-            Sprite {
-                image: asset_server.load("sprites/ice3.png"),
-                ..default()
-            },
-        ))
-        .add_rollback()
-        .id();
-    
-    add_player_physics(&mut commands, player1);
+// Where and how one handle enters the match. It only depends on the handle, the level and the
+// agreed picks, so every peer spawns the same players in the same order
+struct PlayerSpawn {
+    handle: usize,
+    position: Vec2,
+    facing_left: bool,
+    character: Character,
+}
 
-    // Spawn collider as child
-    commands.spawn((
-        Collider::rectangle(sprite_width, sprite_height),
-        CollisionLayers::new(
-            [PLAYER_LAYER],
-            !(PLAYER_LAYER) | WALL_LAYER | GROUND_LAYER
-        ),
-    ))
-    .add_rollback()
-    .set_parent(player1);
-
-    // Player 2
-    let player2 = commands
-        .spawn((
-            Player { 
-                handle: 1,
-                jumps_remaining: 2,
-                is_grounded: false,
-                previous_input: 0,
-            },
-            Transform::from_translation(Vec3::new(2., 0., 0.))
-                .with_scale(Vec3::splat(scale)),
-            Sprite {
-                image: asset_server.load("sprites/zapp.png"),
-                ..default()
-            },
+fn player_spawns(level: &LevelDef, mode: SessionMode, skins: &PlayerSkins) -> Vec<PlayerSpawn> {
+    // A race ghost replays a run made as handle 0, so it starts where and how that run did
+    let race = mode == SessionMode::Race;
+    (0..NUM_PLAYERS)
+        .map(|handle| {
+            let side = if race { 0 } else { handle };
+            PlayerSpawn {
+                handle,
+                position: level.spawn_point(side),
+                facing_left: Player::new(side).facing_left,
+                character: skins.character(handle),
+            }
+        })
+        .collect()
+}
+
+// Spawned in handle order, so the rollback ids of the players match across peers
+fn spawn_players(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level: Res<ActiveLevel>,
+    mode: Res<SessionMode>,
+    (roster, defs, skins): (Res<CharacterRoster>, Res<CharacterDefs>, Res<PlayerSkins>),
+) {
+    for spawn in player_spawns(&level.0, *mode, &skins) {
+        // Skins swap in later picks before the session starts
+        let body = defs.get(spawn.character).sprite;
+        let player = commands
+            .spawn_rollback("spawn_players", (
+                Player {
+                    facing_left: spawn.facing_left,
+                    jumps_remaining: defs.get(spawn.character).physique.jumps,
+                    ..Player::new(spawn.handle)
+                },
+                Transform::from_translation(spawn.position.extend(0.))
+                    .with_scale(Vec3::splat(PLAYER_SCALE)),
+                Appearance::material(Sprite {
+                    image: asset_server.load(roster.info(spawn.character).sprite),
+                    ..default()
+                }),
+                InGameEntity,
+            ))
+            .id();
+
+        add_player_physics(&mut commands, player);
+
+        // Spawn collider as child
+        commands.spawn_rollback("spawn_players", (
+            body.collider(),
+            Transform::from_translation(body.collider_translation()),
+            CollisionLayers::new(
+                [PLAYER_LAYER],
+                !(PLAYER_LAYER) | WALL_LAYER | GROUND_LAYER
+            ),
         ))
-        .add_rollback()
-        .id();
-    
-    add_player_physics(&mut commands, player2);
+        .set_parent(player);
+    }
+}
 
-    // Spawn collider as child
-    commands.spawn((
-        Collider::rectangle(sprite_width, sprite_height),
-        CollisionLayers::new(
-            [PLAYER_LAYER],
-            !(PLAYER_LAYER) | WALL_LAYER | GROUND_LAYER
-        ),
-    ))
-    .add_rollback()
-    .set_parent(player2);
+fn update_player_appearance(mut players: Query<(&Player, &mut Appearance), Changed<Player>>) {
+    for (player, mut appearance) in players.iter_mut() {
+        if appearance.sprite.flip_x != player.facing_left {
+            appearance.sprite.flip_x = player.facing_left;
+        }
+    }
+}
+
+// How each handle's body moves, from its character and handicap. Every peer plays with the
+// same bodies, level sync compares them
+#[derive(SystemParam)]
+struct PlayerTuning<'w> {
+    handicaps: Res<'w, Handicaps>,
+    defs: Res<'w, CharacterDefs>,
+    skins: Res<'w, PlayerSkins>,
+}
+
+impl PlayerTuning<'_> {
+    fn physique(&self, handle: usize) -> Physique {
+        self.defs.get(self.skins.character(handle)).physique
+    }
+
+    fn speed_scale(&self, handle: usize) -> f32 {
+        self.handicaps.of(handle).speed_scale()
+    }
 }
 
 fn move_players(
-    mut query: Query<(Entity, &mut Transform, &mut LinearVelocity, &mut Sprite, &mut Player)>,
+    mut query: Query<(Entity, &mut LinearVelocity, &mut Player)>,
     mut collision_events: EventReader<Collision>,
     inputs: Res<PlayerInputs<Config>>,
+    tuning: PlayerTuning,
     children_query: Query<&Parent>,
     ground_query: Query<Entity, With<Ground>>,
+    mut jumps: ConfirmedWriter<Jumped>,
 ) {
-    let ground_entity = if let Ok(entity) = ground_query.get_single() {
-        entity
-    } else {
-        return;
-    };
+    // Read the events once up front; reading inside the loop would hand them all to the first player
+    let contacts: Vec<_> = collision_events.read().map(|Collision(contacts)| contacts).collect();
 
-    for (player_entity, mut transform, mut velocity, mut sprite, mut player) in query.iter_mut() {
+    let mut players: Vec<_> = query.iter_mut().collect();
+    players.sort_by_key(|(_, _, player)| player.handle);
+    debug_assert_strictly_ordered("move_players", players.iter().map(|(_, _, player)| player.handle));
+
+    for (player_entity, mut velocity, mut player) in players {
         // Handle movement and jumping first
         let (input, _) = inputs[player.handle];
+        let physique = tuning.physique(player.handle);
         
-        // Flip sprite based on movement direction
-        if input & INPUT_LEFT != 0 {
-            sprite.flip_x = true;
-        } else if input & INPUT_RIGHT != 0 {
-            sprite.flip_x = false;
+        // Track facing direction; the sprite flip happens in presentation
+        let direction = get_input_direction(input);
+        if direction.x < 0.0 {
+            player.facing_left = true;
+        } else if direction.x > 0.0 {
+            player.facing_left = false;
         }
 
+        // Knocked back players drift without control until the stun wears off
+        let stunned = player.stun_frames > 0;
+        player.stun_frames = player.stun_frames.saturating_sub(1);
+
         // Handle horizontal movement
-        let direction = get_input_direction(input);
-        let move_speed = 7.;
-        velocity.0.x = direction.x * move_speed;
-
-        // Handle jumping - check if UP was just pressed by comparing with previous input
-        let just_pressed_up = (input & INPUT_UP != 0) && (player.previous_input & INPUT_UP == 0);
-        let is_jumping = if just_pressed_up && player.jumps_remaining > 0 {
-            info!("Player {} jumping, {} jumps remaining", player.handle, player.jumps_remaining - 1);
-            velocity.0.y = 10.0; // Jump impulse
+        let boosted = player.speed_boost_frames > 0;
+        player.speed_boost_frames = player.speed_boost_frames.saturating_sub(1);
+        let move_speed = physique.move_speed(boosted) * tuning.speed_scale(player.handle);
+        if !stunned {
+            velocity.0.x = direction.x * move_speed;
+        }
+
+        // Handle jumping
+        let is_jumping = if !stunned && player.just_pressed(input, INPUT_UP) && player.jumps_remaining > 0 {
+            debug!("Player {} jumping, {} jumps remaining", player.handle, player.jumps_remaining - 1);
+            velocity.0.y = physique.jump_velocity();
             player.jumps_remaining -= 1;
+            jumps.send(Jumped { handle: player.handle });
             true
         } else {
             false
         };
 
-        // Store current input for next frame
-        player.previous_input = input;
-
         // Reset grounded state
         player.is_grounded = false;
 
         // Check for collisions and reset jumps
-        for Collision(contacts) in collision_events.read() {
+        for contacts in &contacts {
             // Get parent entities if the colliding entities are children
             let entity1_parent = children_query.get(contacts.entity1).ok().map(Parent::get);
             let entity2_parent = children_query.get(contacts.entity2).ok().map(Parent::get);
@@ -349,15 +845,15 @@ fn move_players(
                 || Some(player_entity) == entity1_parent 
                 || Some(player_entity) == entity2_parent;
 
-            // Check if one of the entities is the ground
-            let has_ground = contacts.entity1 == ground_entity || contacts.entity2 == ground_entity;
+            // Check if one of the entities is the ground (or a platform)
+            let has_ground = ground_query.contains(contacts.entity1) || ground_query.contains(contacts.entity2);
 
             if is_player_collision && has_ground {
                 player.is_grounded = true;
                 // Only reset jumps if we're not currently jumping and don't have max jumps
-                if !is_jumping && player.jumps_remaining < 2 {
-                    info!("Player {} touched ground, resetting jumps", player.handle);
-                    player.jumps_remaining = 2;
+                if !is_jumping && player.jumps_remaining < physique.jumps {
+                    debug!("Player {} touched ground, resetting jumps", player.handle);
+                    player.jumps_remaining = physique.jumps;
                 }
             }
         }
@@ -0,0 +1,180 @@
+use bevy::ecs::system::SystemParam;
+use bevy::input::gamepad::{GamepadConnection, GamepadConnectionEvent};
+use bevy::prelude::*;
+use crate::GameState;
+use crate::input::{PlayerInput, AXIS_MAX, INPUT_LEFT, INPUT_RIGHT, INPUT_STRIKE, INPUT_UP};
+use crate::locale::Locale;
+use crate::settings::Settings;
+use crate::ui_layout::SafeArea;
+
+pub struct GamepadPlugin;
+
+// One pad per local seat at most, seat 0 being the first local handle
+const SEATS: usize = 2;
+
+// How a pad is recognised when it comes back. The entity can change on reconnect, the
+// device it reports usually doesn't
+#[derive(Clone, Debug, PartialEq)]
+struct PadIdentity {
+    name: String,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Seat {
+    pad: Option<Entity>,
+    identity: Option<PadIdentity>,
+    // The pad went away mid-use, the seat is on the keyboard until it's back
+    lost: bool,
+}
+
+// Which pad plays which local seat. Pads are handed out in the order they connect and keep
+// their seat across disconnects
+#[derive(Resource, Default)]
+pub struct PadSeats([Seat; SEATS]);
+
+#[derive(Component)]
+struct PadLostOverlay;
+
+// What human_inputs asks for the pad side of a seat
+#[derive(SystemParam)]
+pub struct PadInputs<'w, 's> {
+    seats: Res<'w, PadSeats>,
+    settings: Res<'w, Settings>,
+    gamepads: Query<'w, 's, &'static Gamepad>,
+}
+
+impl PadSeats {
+    fn connect(&mut self, pad: Entity, identity: PadIdentity) {
+        // Back where it was, matched on the entity first and the device second
+        let returning = self.0.iter().position(|seat| seat.pad == Some(pad)).or_else(|| {
+            self.0.iter().position(|seat| seat.lost && seat.identity.as_ref() == Some(&identity))
+        });
+        let seat = returning.or_else(|| self.0.iter().position(|seat| seat.pad.is_none() && !seat.lost));
+        let Some(seat) = seat else {
+            return;
+        };
+        if returning.is_some() {
+            info!("gamepad {pad} back on seat {seat}");
+        }
+        self.0[seat] = Seat { pad: Some(pad), identity: Some(identity), lost: false };
+    }
+
+    fn disconnect(&mut self, pad: Entity) {
+        for seat in self.0.iter_mut().filter(|seat| seat.pad == Some(pad)) {
+            seat.pad = None;
+            seat.lost = true;
+        }
+    }
+
+    pub fn any_lost(&self) -> bool {
+        self.0.iter().any(|seat| seat.lost)
+    }
+}
+
+// The stick rescaled so the deadzone reads as 0 and saturation as full travel, keeping its
+// direction. Drift inside the deadzone never reaches the thresholds, so it can't flicker
+// inputs that the peer then has to roll back
+pub fn shape_stick(raw: Vec2, deadzone: f32, saturation: f32) -> Vec2 {
+    let length = raw.length();
+    if length <= deadzone {
+        return Vec2::ZERO;
+    }
+    let scaled = ((length - deadzone) / (saturation - deadzone).max(0.01)).min(1.0);
+    raw / length * scaled
+}
+
+impl PadInputs<'_, '_> {
+    // None while the seat has no pad, the keyboard plays it then
+    pub fn input(&self, seat: usize) -> Option<PlayerInput> {
+        let pad = self.seats.0.get(seat)?.pad?;
+        let gamepad = self.gamepads.get(pad).ok()?;
+        let stick = shape_stick(gamepad.left_stick(), self.settings.stick_deadzone, self.settings.stick_saturation);
+        let direction = (stick + gamepad.dpad()).clamp(Vec2::NEG_ONE, Vec2::ONE);
+        let threshold = self.settings.stick_threshold;
+        let mut input = 0;
+        if direction.x < -threshold {
+            input |= INPUT_LEFT;
+        }
+        if direction.x > threshold {
+            input |= INPUT_RIGHT;
+        }
+        if gamepad.pressed(GamepadButton::South) {
+            input |= INPUT_UP;
+        }
+        if gamepad.pressed(GamepadButton::West) || gamepad.pressed(GamepadButton::RightTrigger) {
+            input |= INPUT_STRIKE;
+        }
+        let axis = (direction.x * f32::from(AXIS_MAX)).round() as i8;
+        Some(PlayerInput { buttons: input, axis })
+    }
+}
+
+impl Plugin for GamepadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PadSeats>()
+            .add_systems(PreUpdate, track_pads.after(bevy::input::InputSystem))
+            .add_systems(OnEnter(GameState::InGame), spawn_overlay)
+            .add_systems(Update, update_overlay.run_if(in_state(GameState::InGame)))
+            .add_systems(OnExit(GameState::InGame), cleanup_overlay);
+    }
+}
+
+// Seats are settled before inputs are read, so a pad that just dropped is never asked for
+// one. Inputs keep going to the session either way, neutral until the keyboard is used
+fn track_pads(mut seats: ResMut<PadSeats>, mut connections: EventReader<GamepadConnectionEvent>) {
+    for event in connections.read() {
+        match &event.connection {
+            GamepadConnection::Connected { name, vendor_id, product_id } => {
+                let identity = PadIdentity { name: name.clone(), vendor_id: *vendor_id, product_id: *product_id };
+                seats.connect(event.gamepad, identity);
+            }
+            GamepadConnection::Disconnected => seats.disconnect(event.gamepad),
+        }
+    }
+}
+
+fn spawn_overlay(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(60.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 26.0,
+            ..default()
+        },
+        TextColor(Color::srgb(1.0, 0.5, 0.4)),
+        Visibility::Hidden,
+        PadLostOverlay,
+    ));
+}
+
+fn update_overlay(
+    seats: Res<PadSeats>,
+    locale: Res<Locale>,
+    mut overlays: Query<(&mut Text, &mut Visibility), With<PadLostOverlay>>,
+) {
+    if !seats.is_changed() && !locale.is_changed() {
+        return;
+    }
+    let lost: Vec<String> = (0..SEATS).filter(|seat| seats.0[*seat].lost).map(|seat| (seat + 1).to_string()).collect();
+    let label = locale.format("gamepad.lost", &[("seats", &lost.join(", "))]);
+    for (mut text, mut visibility) in overlays.iter_mut() {
+        visibility.set_if_neq(if seats.any_lost() { Visibility::Inherited } else { Visibility::Hidden });
+        text.0 = label.clone();
+    }
+}
+
+fn cleanup_overlay(mut commands: Commands, overlays: Query<Entity, With<PadLostOverlay>>) {
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use avian2d::dynamics::solver::SolverConfig;
+use avian2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::ball::BALL_RADIUS;
+use crate::game::{FRAME_MS, PLAYER_SCALE};
+use crate::rng::fnv1a;
+use crate::serve::SERVE_FRAMES;
+use crate::settings::Settings;
+use crate::strike::{METER_MAX, STRIKE_RANGE};
+
+pub struct GameplayConstantsPlugin;
+
+// Peers compare it before playing. Bump it whenever the simulation or the messages change in a
+// way an older build can't follow, tuning_hash only catches the values listed there
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Tuning that changes what the simulation computes, so both peers have to run with the same
+// values. Online, the host's copy is adopted during level sync
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameplayConstants {
+    pub physics_substeps: u32,
+    pub restitution_iterations: usize,
+}
+
+// Beyond these the solver takes longer than a frame or stops converging
+const MAX_SUBSTEPS: u32 = 32;
+const MAX_RESTITUTION_ITERATIONS: usize = 8;
+
+// What the settings menu offers instead of raw numbers. Low is for machines that can't keep
+// up with resimulating several frames of full precision physics
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PhysicsQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl PhysicsQuality {
+    pub const ALL: [PhysicsQuality; 3] = [PhysicsQuality::Low, PhysicsQuality::Medium, PhysicsQuality::High];
+
+    pub fn label_key(self) -> &'static str {
+        match self {
+            PhysicsQuality::Low => "settings.quality_low",
+            PhysicsQuality::Medium => "settings.quality_medium",
+            PhysicsQuality::High => "settings.quality_high",
+        }
+    }
+
+    pub fn constants(self) -> GameplayConstants {
+        let (physics_substeps, restitution_iterations) = match self {
+            PhysicsQuality::Low => (3, 1),
+            PhysicsQuality::Medium => (6, 1),
+            PhysicsQuality::High => (12, 2),
+        };
+        GameplayConstants { physics_substeps, restitution_iterations }
+    }
+}
+
+impl GameplayConstants {
+    // A host could send anything, these are what the physics can run with
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !(1..=MAX_SUBSTEPS).contains(&self.physics_substeps) {
+            problems.push(format!("{} physics substeps, it takes 1 to {MAX_SUBSTEPS}", self.physics_substeps));
+        }
+        if !(1..=MAX_RESTITUTION_ITERATIONS).contains(&self.restitution_iterations) {
+            problems.push(format!(
+                "{} restitution iterations, it takes 1 to {MAX_RESTITUTION_ITERATIONS}",
+                self.restitution_iterations
+            ));
+        }
+        problems
+    }
+}
+
+// avian2d's own defaults
+impl Default for GameplayConstants {
+    fn default() -> Self {
+        PhysicsQuality::Medium.constants()
+    }
+}
+
+// What this build simulates with that isn't negotiated at level sync: every quality level's
+// constants, the tuning shared between modules and the features that change play. Builds
+// that differ here desync right away
+pub fn tuning_hash() -> u64 {
+    let tuning = format!(
+        "{:?} {FRAME_MS} {PLAYER_SCALE} {BALL_RADIUS} {STRIKE_RANGE} {METER_MAX} {SERVE_FRAMES} {}",
+        PhysicsQuality::ALL.map(PhysicsQuality::constants),
+        // A build without level scripts plays scripted levels differently
+        cfg!(feature = "level-scripts")
+    );
+    fnv1a(tuning.as_bytes())
+}
+
+impl Plugin for GameplayConstantsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameplayConstants>()
+            .add_systems(OnEnter(GameState::InGame), use_local_constants)
+            .add_systems(Update, apply_to_physics.run_if(resource_changed::<GameplayConstants>));
+    }
+}
+
+// Every match starts from this machine's preference, level sync may replace it with the host's
+fn use_local_constants(settings: Res<Settings>, mut constants: ResMut<GameplayConstants>) {
+    constants.set_if_neq(settings.physics_quality.constants());
+}
+
+fn apply_to_physics(
+    constants: Res<GameplayConstants>,
+    mut substeps: ResMut<SubstepCount>,
+    mut solver: ResMut<SolverConfig>,
+) {
+    info!(
+        "physics running with {} substeps, {} restitution iterations",
+        constants.physics_substeps, constants.restitution_iterations
+    );
+    substeps.0 = constants.physics_substeps;
+    solver.restitution_iterations = constants.restitution_iterations;
+}
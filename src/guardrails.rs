@@ -0,0 +1,115 @@
+use bevy::{ecs::archetype::Archetypes, ecs::system::EntityCommands, prelude::*};
+use bevy_ggrs::*;
+use crate::GameState;
+
+pub struct GuardrailsPlugin;
+
+// Past this many rollback entities something is leaking, snapshots get expensive quickly
+const ROLLBACK_ENTITY_WARN: usize = 128;
+const ROLLBACK_ENTITY_LIMIT: usize = 1024;
+// New archetypes mid-match mean components are being added/removed every frame
+const ARCHETYPE_CHURN_WARN: usize = 4;
+// Spawning/despawning this many rollback entities in one frame is a hint that pooling is needed
+const ENTITY_CHURN_WARN: usize = 16;
+
+// Which system spawned a rollback entity. Entities with Rollback but no SpawnSource
+// were spawned without going through spawn_rollback
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SpawnSource(pub &'static str);
+
+#[derive(Resource, Default)]
+struct GuardrailStats {
+    rollback_entities: usize,
+    archetypes: usize,
+    warned_budget: bool,
+}
+
+pub trait SpawnRollbackExt {
+    // The sanctioned way to create simulation entities
+    fn spawn_rollback(&mut self, source: &'static str, bundle: impl Bundle) -> EntityCommands<'_>;
+}
+
+impl SpawnRollbackExt for Commands<'_, '_> {
+    fn spawn_rollback(&mut self, source: &'static str, bundle: impl Bundle) -> EntityCommands<'_> {
+        let mut entity = self.spawn((bundle, SpawnSource(source)));
+        entity.add_rollback();
+        entity
+    }
+}
+
+impl Plugin for GuardrailsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GuardrailStats>()
+            .add_systems(
+                Last,
+                (check_unsanctioned_spawns, check_entity_budget).run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(OnExit(GameState::InGame), reset_stats);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn check_unsanctioned_spawns(spawned: Query<(Entity, Option<&Name>), (Added<Rollback>, Without<SpawnSource>)>) {
+    for (entity, name) in spawned.iter() {
+        error!(
+            "rollback entity {entity} ({}) was spawned without spawn_rollback",
+            name.map_or("unnamed", Name::as_str),
+        );
+    }
+}
+
+fn check_entity_budget(
+    mut stats: ResMut<GuardrailStats>,
+    rollback_entities: Query<Option<&SpawnSource>, With<Rollback>>,
+    archetypes: &Archetypes,
+    frame: Res<RollbackFrameCount>,
+) {
+    let count = rollback_entities.iter().count();
+    debug_assert!(
+        count <= ROLLBACK_ENTITY_LIMIT,
+        "{count} rollback entities alive at frame {}, something is leaking",
+        frame.0,
+    );
+
+    if count > ROLLBACK_ENTITY_WARN && !stats.warned_budget {
+        let mut by_source: Vec<(&str, usize)> = Vec::new();
+        for source in rollback_entities.iter() {
+            let source = source.map_or("unsanctioned", |source| source.0);
+            match by_source.iter_mut().find(|(name, _)| *name == source) {
+                Some((_, total)) => *total += 1,
+                None => by_source.push((source, 1)),
+            }
+        }
+        by_source.sort_by_key(|(_, total)| std::cmp::Reverse(*total));
+        warn!("{count} rollback entities at frame {}: {by_source:?}", frame.0);
+        stats.warned_budget = true;
+    } else if count <= ROLLBACK_ENTITY_WARN {
+        stats.warned_budget = false;
+    }
+
+    // The first frame of a match creates plenty of archetypes, only growth after that is churn
+    let archetype_count = archetypes.len();
+    if stats.archetypes > 0 && archetype_count >= stats.archetypes + ARCHETYPE_CHURN_WARN {
+        warn!(
+            "{} new archetypes in one frame at frame {} ({} rollback entities)",
+            archetype_count - stats.archetypes,
+            frame.0,
+            count,
+        );
+    }
+
+    if stats.rollback_entities.abs_diff(count) >= ENTITY_CHURN_WARN {
+        warn!(
+            "rollback entity count went from {} to {count} in one frame at frame {}",
+            stats.rollback_entities,
+            frame.0,
+        );
+    }
+
+    stats.archetypes = archetype_count;
+    stats.rollback_entities = count;
+}
+
+fn reset_stats(mut stats: ResMut<GuardrailStats>) {
+    *stats = GuardrailStats::default();
+}
@@ -0,0 +1,84 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::game::{Player, PLAYER_SCALE};
+
+pub struct HandicapPlugin;
+
+// Picked by each player for themselves in the join screen, so friends of different skill
+// can still have a close match. The effects don't care which handle they land on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Handicap {
+    #[default]
+    None,
+    // Every set starts with a few points on the board
+    HeadStart,
+    // Bigger body, easier to get in the ball's way
+    Larger,
+    // Runs slower
+    Slower,
+}
+
+// One per handle. Agreed during level sync online, local matches have no lobby to pick in
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Handicaps(pub [Handicap; 2]);
+
+impl Handicap {
+    pub const ALL: [Handicap; 4] = [Handicap::None, Handicap::HeadStart, Handicap::Larger, Handicap::Slower];
+
+    pub fn label_key(self) -> &'static str {
+        match self {
+            Handicap::None => "handicap.none",
+            Handicap::HeadStart => "handicap.head_start",
+            Handicap::Larger => "handicap.larger",
+            Handicap::Slower => "handicap.slower",
+        }
+    }
+
+    pub fn head_start(self) -> u32 {
+        if self == Handicap::HeadStart { 3 } else { 0 }
+    }
+
+    pub fn size_scale(self) -> f32 {
+        if self == Handicap::Larger { 1.3 } else { 1.0 }
+    }
+
+    pub fn speed_scale(self) -> f32 {
+        if self == Handicap::Slower { 0.8 } else { 1.0 }
+    }
+}
+
+impl Handicaps {
+    pub fn of(&self, handle: usize) -> Handicap {
+        self.0.get(handle).copied().unwrap_or_default()
+    }
+
+    pub fn head_starts(&self) -> [u32; 2] {
+        self.0.map(Handicap::head_start)
+    }
+}
+
+impl Plugin for HandicapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Handicaps>()
+            .add_systems(OnEnter(GameState::InGame), reset_handicaps)
+            .add_systems(
+                Update,
+                apply_player_size
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_changed::<Handicaps>),
+            );
+    }
+}
+
+fn reset_handicaps(mut handicaps: ResMut<Handicaps>) {
+    handicaps.set_if_neq(Handicaps::default());
+}
+
+// The collider is a child of the player, so scaling the player scales both it and the sprite.
+// Only changes before the session starts, while level sync is still running
+fn apply_player_size(handicaps: Res<Handicaps>, mut players: Query<(&Player, &mut Transform)>) {
+    for (player, mut transform) in players.iter_mut() {
+        transform.scale = Vec3::splat(PLAYER_SCALE * handicaps.of(player.handle).size_scale());
+    }
+}
@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::ball::Ball;
+use crate::game::Player;
+use crate::locale::Locale;
+use crate::perspective::Legible;
+use crate::settings::Settings;
+use crate::strike::{PlayerHit, StrikeLanded};
+
+pub struct HitNumbersPlugin;
+
+const POPUP_SECS: f32 = 0.8;
+// World units the popup floats up over its life, starting this far above what was hit
+const POPUP_RISE: f32 = 0.8;
+const POPUP_START: f32 = 0.9;
+// Text is laid out in pixels, this brings it down to world units
+const POPUP_TEXT_SCALE: f32 = 0.01;
+const HIT_COLOR: Color = Color::srgb(1.0, 0.85, 0.3);
+
+// A number floating up from a hit. Presentation only, spawned from confirmed events
+#[derive(Component)]
+struct HitPopup {
+    from: Vec2,
+    age: f32,
+}
+
+impl Plugin for HitNumbersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (spawn_popups.run_if(|settings: Res<Settings>| settings.hit_numbers), animate_popups)
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), cleanup_popups);
+    }
+}
+
+fn spawn_popup(commands: &mut Commands, from: Vec2, text: String, color: Color) {
+    commands.spawn((
+        Text2d::new(text),
+        TextFont {
+            font_size: 32.0,
+            ..default()
+        },
+        TextColor(color),
+        Transform::from_translation((from + Vec2::Y * POPUP_START).extend(10.0)).with_scale(Vec3::splat(POPUP_TEXT_SCALE)),
+        HitPopup { from, age: 0.0 },
+        Legible,
+    ));
+}
+
+// Knockback and stun over the player that took the hit, launch speed over the ball. For
+// tuning strikes during playtests, see settings
+fn spawn_popups(
+    mut commands: Commands,
+    mut hits: EventReader<PlayerHit>,
+    mut landed: EventReader<StrikeLanded>,
+    locale: Res<Locale>,
+    players: Query<(&Player, &Transform)>,
+    balls: Query<&Transform, With<Ball>>,
+) {
+    for hit in hits.read() {
+        let Some((_, transform)) = players.iter().find(|(player, _)| player.handle == hit.target) else {
+            continue;
+        };
+        let text = locale.format(
+            "hits.player",
+            &[("speed", &format!("{:.1}", hit.knockback.length())), ("frames", &hit.stun_frames)],
+        );
+        spawn_popup(&mut commands, transform.translation.truncate(), text, HIT_COLOR);
+    }
+    for strike in landed.read().filter(|strike| strike.ball) {
+        let Ok(transform) = balls.get_single() else {
+            continue;
+        };
+        let text = locale.format("hits.ball", &[("speed", &format!("{:.1}", strike.speed))]);
+        spawn_popup(&mut commands, transform.translation.truncate(), text, Color::WHITE);
+    }
+}
+
+fn animate_popups(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut popups: Query<(Entity, &mut HitPopup, &mut Transform, &mut TextColor)>,
+) {
+    for (entity, mut popup, mut transform, mut color) in popups.iter_mut() {
+        popup.age += time.delta_secs();
+        let progress = popup.age / POPUP_SECS;
+        if progress >= 1.0 {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        // Eases out, so it pops up fast and settles
+        let rise = 1.0 - (1.0 - progress).powi(2);
+        transform.translation.y = popup.from.y + POPUP_START + POPUP_RISE * rise;
+        color.0.set_alpha(1.0 - progress * progress);
+    }
+}
+
+fn cleanup_popups(mut commands: Commands, popups: Query<Entity, With<HitPopup>>) {
+    for entity in popups.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,61 +1,191 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy::utils::HashMap;
 use bevy_ggrs::*;
 use bevy_matchbox::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::audit::AuditRun;
+use crate::drill::DummyControl;
+use crate::game::SessionMode;
+use crate::gamepads::PadInputs;
+use crate::keybindings::Keybindings;
+use crate::latency::ExtraInputDelay;
+use crate::pause_menu::PauseMenuOpen;
+use crate::race::{Ghost, GHOST_HANDLE};
+use crate::scenario::ScenarioMenuOpen;
 
 pub const INPUT_UP: u8 = 1 << 0;
 pub const INPUT_LEFT: u8 = 1 << 1;
 pub const INPUT_RIGHT: u8 = 1 << 2;
 pub const INPUT_STRIKE: u8 = 1 << 3;
-pub const INPUT_UP_PRESSED: u8 = 1 << 4;  // New flag for just pressed up
 
-pub type Config = bevy_ggrs::GgrsConfig<u8, PeerId>;
+// Full stick travel on the axis
+pub const AXIS_MAX: i8 = 127;
+
+// What a handle sends each frame: the button bits and how far a stick is pushed sideways,
+// quantized so every peer walks the player at exactly the same speed. Keys and the d-pad
+// leave the axis at 0 and walk at full speed off the direction bits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub buttons: u8,
+    pub axis: i8,
+}
+
+pub type Config = bevy_ggrs::GgrsConfig<PlayerInput, PeerId>;
+
+impl PlayerInput {
+    pub fn buttons(buttons: u8) -> Self {
+        Self { buttons, axis: 0 }
+    }
+
+    // One field of a replay.txt line, the bits and then the axis if there is one
+    pub fn to_field(self) -> String {
+        match self.axis {
+            0 => format!("{:08b}", self.buttons),
+            axis => format!("{:08b}:{axis}", self.buttons),
+        }
+    }
+
+    pub fn parse_field(field: &str) -> Result<Self, String> {
+        let (buttons, axis) = field.split_once(':').unwrap_or((field, "0"));
+        let buttons = u8::from_str_radix(buttons, 2).map_err(|err| format!("{field:?}: {err}"))?;
+        let axis = axis.parse::<i8>().map_err(|err| format!("{field:?}: {err}"))?;
+        Ok(Self { buttons, axis })
+    }
+}
 
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(ReadInputs, read_local_inputs);
+        // Each source fills in the handles it plays and leaves the rest to the next one
+        app.add_systems(
+            ReadInputs,
+            (
+                clear_local_inputs,
+                audit_inputs.run_if(resource_exists::<AuditRun>),
+                bot_inputs,
+                human_inputs,
+            )
+                .chain(),
+        );
+    }
+}
+
+// The keyboard and pads, and the menus that keep the player standing while they're up
+#[derive(SystemParam)]
+struct HumanControls<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    keybindings: Res<'w, Keybindings>,
+    pads: PadInputs<'w, 's>,
+    scenario_menu: Option<Res<'w, ScenarioMenuOpen>>,
+    pause_menu: Option<Res<'w, PauseMenuOpen>>,
+}
+
+impl HumanControls<'_, '_> {
+    fn input(&self, mode: SessionMode, local_players: &LocalPlayers, seat: usize, handle: usize) -> PlayerInput {
+        // The player stands still while they type a scenario name or pick from the pause menu
+        if (mode == SessionMode::Training && self.scenario_menu.is_some()) || self.pause_menu.is_some() {
+            return PlayerInput::default();
+        }
+        let shared_keyboard = matches!(mode, SessionMode::Local | SessionMode::SyncTest | SessionMode::Online);
+        let bindings = if local_players.0.len() > 1 && shared_keyboard {
+            &self.keybindings.local[handle % self.keybindings.local.len()]
+        } else {
+            &self.keybindings.shared
+        };
+        let mut input = 0u8;
+        for (action, codes) in bindings.actions() {
+            if self.keys.any_pressed(codes.iter().copied()) {
+                input |= action.bit();
+            }
+        }
+
+        // A seat's pad and keys both work, so a pad that drops out leaves the keys playing.
+        // A held key walks at full speed whatever the stick says
+        let pad = self.pads.input(seat).unwrap_or_default();
+        let keyed = input & (INPUT_LEFT | INPUT_RIGHT) != 0;
+        PlayerInput { buttons: input | pad.buttons, axis: if keyed { 0 } else { pad.axis } }
     }
 }
 
-fn read_local_inputs(
-    mut commands: Commands,
-    keys: Res<ButtonInput<KeyCode>>,
+fn clear_local_inputs(mut commands: Commands) {
+    commands.insert_resource(LocalInputs::<Config>(HashMap::new()));
+}
+
+// An audit plays the recorded inputs back for every handle
+fn audit_inputs(
+    mut local_inputs: ResMut<LocalInputs<Config>>,
     local_players: Res<LocalPlayers>,
+    mut audit: ResMut<AuditRun>,
 ) {
-    let mut local_inputs = HashMap::new();
-
+    let recorded = audit.next_inputs();
     for handle in &local_players.0 {
-        let mut input = 0u8;
+        local_inputs.0.insert(*handle, recorded.get(*handle).copied().unwrap_or_default());
+    }
+}
 
-        if keys.any_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) {
-            input |= INPUT_UP;
-        }
-        if keys.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) {
-            input |= INPUT_UP_PRESSED;
-        }
-        if keys.any_pressed([KeyCode::ArrowLeft, KeyCode::KeyA]) {
-            input |= INPUT_LEFT
+// Handles nobody at this machine plays
+fn bot_inputs(
+    mut local_inputs: ResMut<LocalInputs<Config>>,
+    local_players: Res<LocalPlayers>,
+    mode: Res<SessionMode>,
+    mut dummy: DummyControl,
+    mut ghost: Option<ResMut<Ghost>>,
+) {
+    for handle in &local_players.0 {
+        if local_inputs.0.contains_key(handle) {
+            continue;
         }
-        if keys.any_pressed([KeyCode::ArrowRight, KeyCode::KeyD]) {
-            input |= INPUT_RIGHT;
+        let input = match *mode {
+            // The training dummy does what the loaded drill says, standing still without one.
+            // The daily challenge's bot plays the same way
+            SessionMode::Training | SessionMode::Daily if *handle != 0 => PlayerInput::buttons(dummy.next_input(*handle)),
+            // The race ghost replays the best run
+            SessionMode::Race if *handle == GHOST_HANDLE => {
+                ghost.as_mut().map_or_else(PlayerInput::default, |ghost| ghost.next_input())
+            }
+            // Nobody is playing the attract match, both sides chase the ball like the dummy can
+            SessionMode::Attract => PlayerInput::buttons(dummy.follow_ball(*handle)),
+            _ => continue,
+        };
+        local_inputs.0.insert(*handle, input);
+    }
+}
+
+// Everything left is played from this machine's keyboard and pads
+fn human_inputs(
+    mut local_inputs: ResMut<LocalInputs<Config>>,
+    local_players: Res<LocalPlayers>,
+    mode: Res<SessionMode>,
+    controls: HumanControls,
+    mut extra_delay: Option<ResMut<ExtraInputDelay>>,
+) {
+    for (seat, handle) in local_players.0.iter().enumerate() {
+        if local_inputs.0.contains_key(handle) {
+            continue;
         }
-        if keys.any_pressed([KeyCode::Space, KeyCode::Enter]) {
-            input |= INPUT_STRIKE;
+        let mut input = controls.input(*mode, &local_players, seat, *handle);
+        // Online matches that kept lagging play with more delay than their session has
+        if let Some(extra) = extra_delay.as_mut() {
+            input = extra.delay(input);
         }
-
-        local_inputs.insert(*handle, input);
+        local_inputs.0.insert(*handle, input);
     }
-
-    commands.insert_resource(LocalInputs::<Config>(local_inputs));
 }
 
 // Helper function to get direction from input
-pub fn get_input_direction(input: u8) -> Vec2 {
+pub fn get_input_direction(input: PlayerInput) -> Vec2 {
     let mut direction = Vec2::ZERO;
 
+    // A stick walks as far as it's pushed
+    if input.axis != 0 {
+        direction.x = f32::from(input.axis.max(-AXIS_MAX)) / f32::from(AXIS_MAX);
+        return direction;
+    }
+
     // Only handle horizontal movement here
+    let input = input.buttons;
     if input & INPUT_RIGHT != 0 {
         direction.x += 1.;
     }
@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use bevy::utils::SystemTime;
+use crate::GameState;
+use crate::game::{MatchRoom, SessionMode};
+use crate::handicap::Handicap;
+use crate::skins::CharacterRoster;
+use crate::locale::{Locale, Localized};
+use crate::regions::{QuickPlay, Region};
+use crate::rng::DeterministicRng;
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated, MenuAdjusted};
+use crate::settings::Settings;
+use crate::text_input::{spawn_text_input, InputFilter, TextInput};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct JoinMenuPlugin;
+
+const NAME_MAX_LEN: usize = 16;
+const ROOM_CODE_MAX_LEN: usize = 8;
+const GENERATED_CODE_LEN: usize = 5;
+// No 0/O or 1/I, the code gets read out loud and copied by hand
+const CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Component)]
+struct JoinMenu;
+
+#[derive(Component)]
+struct NameField;
+
+#[derive(Component)]
+struct RoomCodeField;
+
+// Text of a picker button, relabelled whenever the pick changes
+#[derive(Component)]
+struct PickerLabel(JoinAction);
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum JoinAction {
+    // Fills the room code with a fresh one to hand to a friend
+    GenerateRoom,
+    Character,
+    Handicap,
+    Region,
+    Connect,
+    // Pairs up with a stranger, home region first, see regions
+    QuickPlay,
+    Back,
+}
+
+impl Plugin for JoinMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::JoinOnline), setup_join_menu)
+            .add_systems(
+                Update,
+                (adjust_pickers, join_menu_action, update_picker_labels).chain().run_if(in_state(GameState::JoinOnline)),
+            )
+            .add_systems(OnExit(GameState::JoinOnline), cleanup_join_menu);
+    }
+}
+
+fn setup_join_menu(mut commands: Commands, settings: Res<Settings>, room: Res<MatchRoom>) {
+    commands.spawn((Camera2d, JoinMenu));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            JoinMenu,
+        ))
+        .with_children(|parent| {
+            spawn_text_input(
+                parent,
+                0,
+                TextInput::new(settings.player_name.clone(), NAME_MAX_LEN, InputFilter::Any, "join.name"),
+            )
+            .insert(NameField);
+            spawn_text_input(
+                parent,
+                1,
+                TextInput::new(room.0.clone(), ROOM_CODE_MAX_LEN, InputFilter::RoomCode, "join.room_code"),
+            )
+            .insert(RoomCodeField);
+            parent
+                .spawn((menu_button(2), JoinAction::GenerateRoom))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("join.generate_room")));
+                });
+
+            for (order, picker) in [(3, JoinAction::Character), (4, JoinAction::Handicap), (5, JoinAction::Region)] {
+                parent
+                    .spawn((menu_button(order), picker))
+                    .with_children(|parent| {
+                        parent.spawn((button_text(""), PickerLabel(picker)));
+                    });
+            }
+            parent
+                .spawn((menu_button(6), JoinAction::Connect))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("join.connect")));
+                });
+            parent
+                .spawn((menu_button(7), JoinAction::QuickPlay))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("join.quick_play")));
+                });
+            parent
+                .spawn((menu_button(8), JoinAction::Back, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("settings.back")));
+                });
+        });
+}
+
+fn join_menu_action(
+    mut commands: Commands,
+    mut activated: EventReader<MenuActivated>,
+    actions: Query<&JoinAction>,
+    names: Query<&TextInput, With<NameField>>,
+    mut room_codes: Query<&mut TextInput, With<RoomCodeField>>,
+    (mut settings, roster): (ResMut<Settings>, Res<CharacterRoster>),
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        match actions.get(*entity) {
+            Ok(JoinAction::GenerateRoom) => {
+                for mut input in room_codes.iter_mut() {
+                    input.value = generate_room_code();
+                }
+            }
+            // The card screen comes back here, so what's typed so far is kept for it
+            Ok(JoinAction::Character) => {
+                if let Some(name) = names.get_single().ok().map(|name| name.value.trim()).filter(|name| !name.is_empty()) {
+                    settings.player_name = name.to_string();
+                }
+                let room = room_codes.get_single().map(|input| input.value.clone()).unwrap_or_default();
+                commands.insert_resource(MatchRoom(room));
+                next_state.set(GameState::CharacterSelect);
+            }
+            Ok(picker @ (JoinAction::Handicap | JoinAction::Region)) => cycle(&mut settings, &roster, *picker, 1),
+            Ok(action @ (JoinAction::Connect | JoinAction::QuickPlay)) => {
+                if let Ok(name) = names.get_single() {
+                    let name = name.value.trim();
+                    if !name.is_empty() {
+                        settings.player_name = name.to_string();
+                    }
+                }
+                if let Err(err) = settings.save() {
+                    warn!("failed to save settings: {err}");
+                }
+                // Quick play ignores the room code, it's always a stranger in a region's room
+                if *action == JoinAction::QuickPlay {
+                    commands.insert_resource(MatchRoom::default());
+                    commands.insert_resource(QuickPlay::new(settings.region));
+                } else {
+                    let room = room_codes.get_single().map(|input| input.value.clone()).unwrap_or_default();
+                    commands.insert_resource(MatchRoom(room));
+                }
+                commands.insert_resource(SessionMode::Online);
+                next_state.set(GameState::InGame);
+            }
+            Ok(JoinAction::Back) => next_state.set(GameState::MainMenu),
+            Err(_) => {}
+        }
+    }
+}
+
+// Seeded from the clock, two players generating one in the same nanosecond is no worry
+fn generate_room_code() -> String {
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let mut rng = DeterministicRng::new(nanos as u64);
+    (0..GENERATED_CODE_LEN)
+        .map(|_| CODE_CHARS[rng.range_u32(0, CODE_CHARS.len() as u32) as usize] as char)
+        .collect()
+}
+
+fn adjust_pickers(
+    mut adjusted: EventReader<MenuAdjusted>,
+    actions: Query<&JoinAction>,
+    mut settings: ResMut<Settings>,
+    roster: Res<CharacterRoster>,
+) {
+    for adjusted in adjusted.read() {
+        if let Ok(action) = actions.get(adjusted.entity) {
+            cycle(&mut settings, &roster, *action, adjusted.step);
+        }
+    }
+}
+
+fn next<T: Copy + PartialEq>(options: &[T], current: T, by: i32) -> T {
+    let index = options.iter().position(|option| *option == current).unwrap_or(0) as i32;
+    options[(index + by).rem_euclid(options.len() as i32) as usize]
+}
+
+fn cycle(settings: &mut Settings, roster: &CharacterRoster, picker: JoinAction, by: i32) {
+    match picker {
+        JoinAction::Character => settings.character = next(&roster.all(), settings.character, by),
+        JoinAction::Handicap => settings.handicap = next(&Handicap::ALL, settings.handicap, by),
+        JoinAction::Region => settings.region = next(&Region::ALL, settings.region, by),
+        JoinAction::GenerateRoom | JoinAction::Connect | JoinAction::QuickPlay | JoinAction::Back => {}
+    }
+}
+
+fn update_picker_labels(
+    settings: Res<Settings>,
+    (locale, roster): (Res<Locale>, Res<CharacterRoster>),
+    mut labels: Query<(&mut Text, Ref<PickerLabel>)>,
+) {
+    for (mut text, label) in labels.iter_mut() {
+        if !label.is_added() && !settings.is_changed() && !locale.is_changed() {
+            continue;
+        }
+        text.0 = match label.0 {
+            JoinAction::Character => {
+                locale.format("join.character", &[("value", &locale.get(roster.info(settings.character).label_key))])
+            }
+            JoinAction::Handicap => {
+                locale.format("join.handicap", &[("value", &locale.get(settings.handicap.label_key()))])
+            }
+            JoinAction::Region => locale.format("join.region", &[("value", &locale.get(settings.region.label_key()))]),
+            JoinAction::GenerateRoom | JoinAction::Connect | JoinAction::QuickPlay | JoinAction::Back => continue,
+        };
+    }
+}
+
+fn cleanup_join_menu(mut commands: Commands, query: Query<Entity, With<JoinMenu>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,345 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::input::{INPUT_LEFT, INPUT_RIGHT, INPUT_STRIKE, INPUT_UP};
+use crate::locale::Locale;
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated, NavPaused};
+use crate::notifications::Notify;
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct KeybindingsPlugin;
+
+const KEYBINDINGS_PATH: &str = "keybindings.ron";
+// Heading over each key set's column, in set order
+const SET_TITLES: [&str; 3] = ["controls.shared", "controls.local_left", "controls.local_right"];
+
+// Keyboard controls, kept in keybindings.ron next to the executable and changed in the
+// controls screen. Gamepads always use the same buttons, see gamepads
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Keybindings {
+    // Used when this machine only controls one handle
+    pub shared: KeySet,
+    // Split keyboard for local sessions where both handles are on this machine
+    pub local: [KeySet; 2],
+}
+
+// Any of an action's keys does it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeySet {
+    pub up: Vec<KeyCode>,
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub strike: Vec<KeyCode>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Left,
+    Right,
+    Strike,
+}
+
+#[derive(Component)]
+struct ControlsScreen;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum ControlsButton {
+    // Which key set, 0 for the shared one and 1 or 2 for a side of the split keyboard
+    Bind { set: usize, action: Action },
+    Reset,
+    Back,
+}
+
+// Waiting for the key to bind. Menu navigation stays off until every key is let go, so the
+// key just bound doesn't also move the focus or leave the screen
+#[derive(Resource)]
+struct Listening {
+    target: ControlsButton,
+    captured: bool,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            shared: KeySet {
+                up: vec![KeyCode::ArrowUp, KeyCode::KeyW],
+                left: vec![KeyCode::ArrowLeft, KeyCode::KeyA],
+                right: vec![KeyCode::ArrowRight, KeyCode::KeyD],
+                strike: vec![KeyCode::Space, KeyCode::Enter],
+            },
+            local: [
+                KeySet {
+                    up: vec![KeyCode::KeyW],
+                    left: vec![KeyCode::KeyA],
+                    right: vec![KeyCode::KeyD],
+                    strike: vec![KeyCode::Space],
+                },
+                KeySet {
+                    up: vec![KeyCode::ArrowUp],
+                    left: vec![KeyCode::ArrowLeft],
+                    right: vec![KeyCode::ArrowRight],
+                    strike: vec![KeyCode::Enter],
+                },
+            ],
+        }
+    }
+}
+
+impl Keybindings {
+    pub fn load() -> Self {
+        let Ok(text) = std::fs::read_to_string(KEYBINDINGS_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("ignoring broken {KEYBINDINGS_PATH}: {err}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("keybindings always serialize");
+        std::fs::write(KEYBINDINGS_PATH, text)
+    }
+
+    fn set_mut(&mut self, set: usize) -> &mut KeySet {
+        match set {
+            0 => &mut self.shared,
+            side => &mut self.local[(side - 1) % 2],
+        }
+    }
+
+    fn set(&self, set: usize) -> &KeySet {
+        match set {
+            0 => &self.shared,
+            side => &self.local[(side - 1) % 2],
+        }
+    }
+}
+
+impl KeySet {
+    // Every action with the keys bound to it, the way human_inputs goes through them
+    pub fn actions(&self) -> impl Iterator<Item = (Action, &[KeyCode])> {
+        Action::ALL.into_iter().map(|action| (action, self.keys(action)))
+    }
+
+    fn keys(&self, action: Action) -> &[KeyCode] {
+        match action {
+            Action::Up => &self.up,
+            Action::Left => &self.left,
+            Action::Right => &self.right,
+            Action::Strike => &self.strike,
+        }
+    }
+
+    fn keys_mut(&mut self, action: Action) -> &mut Vec<KeyCode> {
+        match action {
+            Action::Up => &mut self.up,
+            Action::Left => &mut self.left,
+            Action::Right => &mut self.right,
+            Action::Strike => &mut self.strike,
+        }
+    }
+}
+
+impl Action {
+    pub const ALL: [Action; 4] = [Action::Up, Action::Left, Action::Right, Action::Strike];
+
+    // The input bit it sets
+    pub fn bit(self) -> u8 {
+        match self {
+            Action::Up => INPUT_UP,
+            Action::Left => INPUT_LEFT,
+            Action::Right => INPUT_RIGHT,
+            Action::Strike => INPUT_STRIKE,
+        }
+    }
+
+    fn label_key(self) -> &'static str {
+        match self {
+            Action::Up => "controls.up",
+            Action::Left => "controls.left",
+            Action::Right => "controls.right",
+            Action::Strike => "controls.strike",
+        }
+    }
+}
+
+impl ControlsButton {
+    fn label(self, bindings: &Keybindings, listening: Option<&Listening>, locale: &Locale) -> String {
+        match self {
+            ControlsButton::Bind { set, action } => {
+                let keys = if listening.is_some_and(|listening| listening.target == self && !listening.captured) {
+                    locale.get("controls.listening").to_string()
+                } else {
+                    bindings.set(set).keys(action).iter().map(|key| key_name(*key)).collect::<Vec<_>>().join(" / ")
+                };
+                locale.format(action.label_key(), &[("keys", &keys)])
+            }
+            ControlsButton::Reset => locale.get("controls.reset").to_string(),
+            ControlsButton::Back => locale.get("controls.back").to_string(),
+        }
+    }
+}
+
+// KeyW reads better as W, Digit1 as 1
+fn key_name(key: KeyCode) -> String {
+    let name = format!("{key:?}");
+    name.strip_prefix("Key").or_else(|| name.strip_prefix("Digit")).unwrap_or(&name).to_string()
+}
+
+impl Plugin for KeybindingsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world().contains_resource::<Keybindings>() {
+            app.insert_resource(Keybindings::load());
+        }
+        app.add_systems(OnEnter(GameState::Controls), setup_controls_screen)
+            .add_systems(
+                Update,
+                (capture_key, controls_screen_input, update_controls_labels)
+                    .chain()
+                    .run_if(in_state(GameState::Controls)),
+            )
+            .add_systems(OnExit(GameState::Controls), (save_keybindings, cleanup_controls_screen));
+    }
+}
+
+fn setup_controls_screen(mut commands: Commands, bindings: Res<Keybindings>, locale: Res<Locale>) {
+    commands.spawn((Camera2d, ControlsScreen));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            ControlsScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                })
+                .with_children(|row| {
+                    for (set, title) in SET_TITLES.into_iter().enumerate() {
+                        row.spawn(Node {
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        })
+                        .with_children(|column| {
+                            column.spawn(button_text(locale.get(title)));
+                            for action in Action::ALL {
+                                let button = ControlsButton::Bind { set, action };
+                                let order = (set * Action::ALL.len() + action as usize) as u32;
+                                column.spawn((menu_button(order), button)).with_children(|parent| {
+                                    parent.spawn((button_text(button.label(&bindings, None, &locale)), button));
+                                });
+                            }
+                        });
+                    }
+                });
+            let last = (SET_TITLES.len() * Action::ALL.len()) as u32;
+            parent.spawn((menu_button(last), ControlsButton::Reset)).with_children(|parent| {
+                parent.spawn((button_text(ControlsButton::Reset.label(&bindings, None, &locale)), ControlsButton::Reset));
+            });
+            parent
+                .spawn((menu_button(last + 1), ControlsButton::Back, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(ControlsButton::Back.label(&bindings, None, &locale)), ControlsButton::Back));
+                });
+        });
+}
+
+// The first key pressed takes the action over, Esc keeps what it had
+fn capture_key(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    listening: Option<ResMut<Listening>>,
+    mut bindings: ResMut<Keybindings>,
+) {
+    let Some(mut listening) = listening else {
+        return;
+    };
+    if listening.captured {
+        if keys.get_pressed().next().is_none() {
+            commands.remove_resource::<Listening>();
+            commands.remove_resource::<NavPaused>();
+        }
+        return;
+    }
+    let Some(key) = keys.get_just_pressed().next().copied() else {
+        return;
+    };
+    listening.captured = true;
+    if key == KeyCode::Escape {
+        return;
+    }
+    if let ControlsButton::Bind { set, action } = listening.target {
+        *bindings.set_mut(set).keys_mut(action) = vec![key];
+    }
+}
+
+fn controls_screen_input(
+    mut commands: Commands,
+    mut activated: EventReader<MenuActivated>,
+    buttons: Query<&ControlsButton, With<Button>>,
+    listening: Option<Res<Listening>>,
+    mut bindings: ResMut<Keybindings>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        // A click while listening doesn't start another one
+        if listening.is_some() {
+            continue;
+        }
+        match buttons.get(*entity) {
+            Ok(button @ ControlsButton::Bind { .. }) => {
+                commands.insert_resource(Listening { target: *button, captured: false });
+                commands.insert_resource(NavPaused);
+            }
+            Ok(ControlsButton::Reset) => *bindings = Keybindings::default(),
+            Ok(ControlsButton::Back) => next_state.set(GameState::Settings),
+            Err(_) => {}
+        }
+    }
+}
+
+fn update_controls_labels(
+    bindings: Res<Keybindings>,
+    listening: Option<Res<Listening>>,
+    locale: Res<Locale>,
+    mut labels: Query<(&ControlsButton, &mut Text)>,
+) {
+    let listening_changed = listening.as_ref().is_some_and(|listening| listening.is_changed());
+    if !bindings.is_changed() && !locale.is_changed() && !listening_changed {
+        return;
+    }
+    for (button, mut text) in labels.iter_mut() {
+        let label = button.label(&bindings, listening.as_deref(), &locale);
+        if text.0 != label {
+            text.0 = label;
+        }
+    }
+}
+
+fn save_keybindings(bindings: Res<Keybindings>, locale: Res<Locale>, mut notify: EventWriter<Notify>) {
+    match bindings.save() {
+        Ok(()) => notify.send(Notify::info(locale.get("toast.controls_saved"))),
+        Err(err) => notify.send(Notify::error(locale.format("toast.controls_save_failed", &[("reason", &err)]))),
+    };
+}
+
+fn cleanup_controls_screen(mut commands: Commands, query: Query<Entity, With<ControlsScreen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<Listening>();
+    commands.remove_resource::<NavPaused>();
+}
@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+
+// How far a target leaning on the stick tilts its launch sideways, as a fraction of the
+// launch speed at full tilt
+const INFLUENCE_TILT: f32 = 0.35;
+// Nothing is launched as if it weighed less than this, so a bad character file can't fling
+// a player across the arena
+const MIN_WEIGHT: f32 = 0.25;
+pub const NORMAL_WEIGHT: f32 = 1.0;
+
+// Everything a hit's launch depends on. Strikes and hazards fill this in and leave the math
+// to launch, so balance changes happen in one place
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    // Launch for a normal weight target with the stick at rest, pointing right
+    pub base: Vec2,
+    // 1 to send the target right, -1 left
+    pub away: f32,
+    // The target's stick from -1 (left) to 1 (right)
+    pub influence: f32,
+    pub weight: f32,
+}
+
+// Plain arithmetic and a square root, no trigonometry, so it comes out the same to the bit
+// on every peer and platform. Influence shifts the launch sideways at the same speed: holding
+// with the launch flattens it, holding against lifts it
+pub fn launch(hit: Hit) -> Vec2 {
+    let launch = Vec2::new(hit.base.x * hit.away, hit.base.y) / hit.weight.max(MIN_WEIGHT);
+    let speed = launch.length();
+    let tilt = Vec2::new(hit.influence.clamp(-1.0, 1.0) * INFLUENCE_TILT * speed, 0.0);
+    (launch + tilt).normalize_or_zero() * speed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Vec2 = Vec2::new(3.0, 4.0);
+
+    fn hit(away: f32, influence: f32, weight: f32) -> Hit {
+        Hit { base: BASE, away, influence, weight }
+    }
+
+    fn assert_close(actual: Vec2, expected: Vec2) {
+        assert!(actual.abs_diff_eq(expected, 1e-5), "{actual} isn't {expected}");
+    }
+
+    #[test]
+    fn normal_weight_at_rest_launches_at_base() {
+        assert_close(launch(hit(1.0, 0.0, NORMAL_WEIGHT)), BASE);
+    }
+
+    #[test]
+    fn weight_divides_the_speed() {
+        assert_close(launch(hit(1.0, 0.0, 2.0)), BASE / 2.0);
+        assert_close(launch(hit(1.0, 0.0, 0.5)), BASE * 2.0);
+    }
+
+    #[test]
+    fn light_weights_are_clamped() {
+        let clamped = launch(hit(1.0, 0.0, MIN_WEIGHT));
+        assert_close(launch(hit(1.0, 0.0, MIN_WEIGHT / 2.0)), clamped);
+        assert_close(launch(hit(1.0, 0.0, 0.0)), clamped);
+        assert_close(launch(hit(1.0, 0.0, -1.0)), clamped);
+    }
+
+    #[test]
+    fn influence_toward_the_launch_flattens_it() {
+        let launched = launch(hit(1.0, 1.0, NORMAL_WEIGHT));
+        assert!(launched.x > BASE.x && launched.y < BASE.y, "{launched}");
+        assert!((launched.length() - BASE.length()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn influence_away_from_the_launch_lifts_it() {
+        let launched = launch(hit(1.0, -1.0, NORMAL_WEIGHT));
+        assert!(launched.x < BASE.x && launched.y > BASE.y, "{launched}");
+        assert!((launched.length() - BASE.length()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn influence_is_clamped_to_full_tilt() {
+        assert_close(launch(hit(1.0, 5.0, NORMAL_WEIGHT)), launch(hit(1.0, 1.0, NORMAL_WEIGHT)));
+        assert_close(launch(hit(1.0, -5.0, NORMAL_WEIGHT)), launch(hit(1.0, -1.0, NORMAL_WEIGHT)));
+    }
+
+    #[test]
+    fn influence_tilts_a_straight_up_launch_sideways() {
+        let up = Hit { base: Vec2::new(0.0, 5.0), away: 1.0, influence: 0.0, weight: NORMAL_WEIGHT };
+        assert_close(launch(up), up.base);
+        let right = launch(Hit { influence: 1.0, ..up });
+        let left = launch(Hit { influence: -1.0, ..up });
+        assert!(right.x > 0.0 && right.y < 5.0, "{right}");
+        assert_close(left, Vec2::new(-right.x, right.y));
+        assert!((right.length() - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn facing_left_mirrors_the_launch() {
+        assert_close(launch(hit(-1.0, 0.0, NORMAL_WEIGHT)), Vec2::new(-BASE.x, BASE.y));
+        for influence in [-1.0, -0.5, 0.5, 1.0] {
+            let right = launch(hit(1.0, influence, NORMAL_WEIGHT));
+            let left = launch(hit(-1.0, -influence, NORMAL_WEIGHT));
+            assert_close(left, Vec2::new(-right.x, right.y));
+        }
+    }
+
+    #[test]
+    fn no_base_launches_nowhere() {
+        for influence in [-1.0, 0.0, 1.0] {
+            let launched = launch(Hit { base: Vec2::ZERO, away: 1.0, influence, weight: NORMAL_WEIGHT });
+            assert_eq!(launched, Vec2::ZERO);
+        }
+    }
+}
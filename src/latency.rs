@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use bevy_matchbox::prelude::MatchboxSocket;
+use crate::GameState;
+use crate::game::{input_delay, SessionMode, FRAME_MS, MAX_INPUT_DELAY};
+use crate::input::{Config, PlayerInput};
+use crate::lobby::{request_delay_bump, Lobby};
+use crate::locale::Locale;
+use crate::notifications::Notify;
+use crate::scoring::PointScored;
+use crate::session::SessionManager;
+use crate::settings::Settings;
+use crate::ui_layout::SafeArea;
+
+pub struct LatencyPlugin;
+
+// Round trips from here up count as lagging, like on the lobby's member cards
+const HIGH_PING_MS: u128 = 150;
+// How long the round trip has to stay up before it's more than a spike
+const SUSTAINED_SECS: f64 = 5.0;
+const SAMPLE_INTERVAL_SECS: f32 = 1.0;
+const WARNING_COLOR: Color = Color::srgb(1.0, 0.8, 0.2);
+
+// Input delay on top of what the session was built with. GGRS can't change a running
+// session's delay, so the local inputs wait here for the difference instead
+#[derive(Resource, Default)]
+pub struct ExtraInputDelay {
+    frames: usize,
+    queue: VecDeque<PlayerInput>,
+}
+
+// How the round trip to the opponent has been going this match
+#[derive(Resource, Default)]
+struct LatencyWatch {
+    // Latest round trip in milliseconds, from GGRS
+    ping: Option<u128>,
+    // Real seconds when it went over the line, None while it's under
+    high_since: Option<f64>,
+    // Input delay agreed with the opponent, waiting for the rally to end
+    pending: Option<usize>,
+}
+
+#[derive(Component)]
+struct LatencyWarning;
+
+impl ExtraInputDelay {
+    // The input from this many frames back. Growing the delay holds the oldest input for the
+    // frames added, harmless between rallies, which is the only time it grows
+    pub fn delay(&mut self, input: PlayerInput) -> PlayerInput {
+        self.queue.push_back(input);
+        if self.queue.len() > self.frames {
+            self.queue.pop_front().unwrap_or(input)
+        } else {
+            self.queue.front().copied().unwrap_or(input)
+        }
+    }
+}
+
+impl LatencyWatch {
+    fn sustained(&self, now: f64) -> bool {
+        self.high_since.is_some_and(|since| now - since >= SUSTAINED_SECS)
+    }
+}
+
+impl Plugin for LatencyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                start_watch.run_if(resource_added::<Session<Config>>),
+                (sample_ping, ask_for_bump, take_agreed_bump, bump_at_rally, update_warning)
+                    .chain()
+                    .run_if(resource_exists::<LatencyWatch>),
+            )
+                .chain()
+                .run_if(in_state(GameState::InGame))
+                .run_if(|mode: Res<SessionMode>| *mode == SessionMode::Online)
+                .run_if(resource_exists::<Session<Config>>)
+                .run_if(resource_exists::<MatchboxSocket>)
+                .run_if(resource_exists::<Lobby>),
+        )
+        .add_systems(OnExit(GameState::InGame), end_watch);
+    }
+}
+
+// Every session starts on the delay it was built with
+fn start_watch(mut commands: Commands, warnings: Query<Entity, With<LatencyWarning>>) {
+    commands.insert_resource(ExtraInputDelay::default());
+    commands.insert_resource(LatencyWatch::default());
+    if !warnings.is_empty() {
+        return;
+    }
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(56.0),
+            right: Val::Px(20.0),
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 22.0,
+            ..default()
+        },
+        TextColor(WARNING_COLOR),
+        Visibility::Hidden,
+        LatencyWarning,
+    ));
+}
+
+// Spectators aren't remote players, only the opponent's round trip counts
+fn sample_ping(
+    time: Res<Time<Real>>,
+    mut since_last: Local<f32>,
+    session: Res<Session<Config>>,
+    mut watch: ResMut<LatencyWatch>,
+) {
+    *since_last += time.delta_secs();
+    if *since_last < SAMPLE_INTERVAL_SECS {
+        return;
+    }
+    *since_last = 0.0;
+    let Session::P2P(session) = session.as_ref() else {
+        return;
+    };
+    let ping = session
+        .remote_player_handles()
+        .into_iter()
+        .filter_map(|handle| session.network_stats(handle).ok())
+        .map(|stats| stats.ping)
+        .max();
+    watch.ping = ping;
+    if ping.is_some_and(|ping| ping >= HIGH_PING_MS) {
+        watch.high_since.get_or_insert(time.elapsed_secs_f64());
+    } else {
+        watch.high_since = None;
+    }
+}
+
+// Enough delay to cover the trip as it is now, and at least a frame more than already
+fn ask_for_bump(
+    time: Res<Time<Real>>,
+    settings: Res<Settings>,
+    (manager, extra): (Res<SessionManager>, Res<ExtraInputDelay>),
+    mut socket: ResMut<MatchboxSocket>,
+    mut lobby: ResMut<Lobby>,
+    watch: Res<LatencyWatch>,
+) {
+    if !settings.auto_delay_bump || watch.pending.is_some() || !watch.sustained(time.elapsed_secs_f64()) {
+        return;
+    }
+    let (Some(base), Some(ping)) = (manager.input_delay(), watch.ping) else {
+        return;
+    };
+    let current = base + extra.frames;
+    let wanted = input_delay(Some(ping as f32)).max(current + 1).min(MAX_INPUT_DELAY);
+    if wanted > current && request_delay_bump(&mut socket, &mut lobby, wanted) {
+        info!("round trip at {ping} ms for {SUSTAINED_SECS} s, asking for {wanted} frames of input delay");
+    }
+}
+
+fn take_agreed_bump(mut lobby: ResMut<Lobby>, mut watch: ResMut<LatencyWatch>) {
+    if let Some(frames) = lobby.take_delay_bump() {
+        watch.pending = Some(watch.pending.map_or(frames, |pending| pending.max(frames)));
+    }
+}
+
+// A point going in is the one moment nobody's in the middle of anything
+fn bump_at_rally(
+    mut points: EventReader<PointScored>,
+    time: Res<Time<Real>>,
+    (manager, mut extra, mut watch): (Res<SessionManager>, ResMut<ExtraInputDelay>, ResMut<LatencyWatch>),
+    locale: Res<Locale>,
+    mut notify: EventWriter<Notify>,
+) {
+    if points.read().count() == 0 {
+        return;
+    }
+    let (Some(frames), Some(base)) = (watch.pending, manager.input_delay()) else {
+        return;
+    };
+    watch.pending = None;
+    if frames <= base + extra.frames {
+        return;
+    }
+    extra.frames = frames - base;
+    info!("input delay now {frames} frames, {} over the session's", extra.frames);
+    // Another bump only after the new delay had its own stretch to settle things
+    if watch.high_since.is_some() {
+        watch.high_since = Some(time.elapsed_secs_f64());
+    }
+    let ms = (frames as f32 * FRAME_MS).round();
+    notify.send(Notify::warning(locale.format("toast.input_delay_bumped", &[("frames", &frames), ("ms", &ms)])));
+}
+
+fn update_warning(
+    time: Res<Time<Real>>,
+    watch: Res<LatencyWatch>,
+    locale: Res<Locale>,
+    mut warnings: Query<(&mut Text, &mut Visibility), With<LatencyWarning>>,
+) {
+    let shown = watch.sustained(time.elapsed_secs_f64());
+    for (mut text, mut visibility) in warnings.iter_mut() {
+        visibility.set_if_neq(if shown { Visibility::Inherited } else { Visibility::Hidden });
+        if let Some(ping) = watch.ping.filter(|_| shown) {
+            let label = locale.format("latency.warning", &[("ms", &ping)]);
+            if text.0 != label {
+                text.0 = label;
+            }
+        }
+    }
+}
+
+fn end_watch(mut commands: Commands, warnings: Query<Entity, With<LatencyWarning>>) {
+    for entity in warnings.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<LatencyWatch>();
+    commands.remove_resource::<ExtraInputDelay>();
+}
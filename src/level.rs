@@ -0,0 +1,494 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use crate::GameState;
+use crate::blocks::Breakable;
+use crate::game::NUM_PLAYERS;
+use crate::level_logic::LogicPiece;
+use crate::guardrails::SpawnRollbackExt;
+use crate::rng::fnv1a;
+
+pub struct LevelPlugin;
+
+// Define collision layers
+pub const WALL_LAYER: u32 = 0b01;
+pub const PLAYER_LAYER: u32 = 0b10;
+pub const GROUND_LAYER: u32 = 0b100; // Different from WALL_LAYER
+pub const BALL_LAYER: u32 = 0b1000;
+
+const LEVEL_DIR: &str = "assets/levels";
+// Pieces spawned per frame while a level streams in, big arenas spread over a few frames
+const CHUNK_PIECES: usize = 32;
+// How far past the arena's edge a piece or spawn can be before the level is rejected
+const EDGE_TOLERANCE: f32 = 0.5;
+// Room the ball needs between the top of a net and the ceiling
+const NET_CLEARANCE: f32 = crate::ball::BALL_RADIUS * 2.0;
+
+#[derive(Component)]
+pub struct Ground; // Add a component to identify the ground
+
+#[derive(Component)]
+pub struct Net;
+
+// Marks everything spawned from a LevelDef so it can be rebuilt or cleaned up
+#[derive(Component)]
+pub struct LevelGeometry;
+
+// Where a spawned piece sits in LevelDef::pieces
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PieceIndex(pub usize);
+
+// A collider spawned for the level with this content hash
+#[derive(Component)]
+pub struct StreamedPiece(u64);
+
+// The level being spawned into the match a chunk at a time. Stays around once done so the
+// session can check every collider is really there before it starts
+#[derive(Resource)]
+pub struct LevelStream {
+    hash: u64,
+    total: usize,
+    pending: VecDeque<(usize, LevelPiece)>,
+    // The level's sprites, by their index in LevelDef::sprites
+    sprites: Vec<Handle<Image>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PieceKind {
+    Wall,
+    Ground,
+    Platform,
+    Net,
+    // Stands like a platform until enough strikes break it, see blocks
+    Block { hp: u8 },
+    // Level logic, see level_logic. Plates are held down by standing on them, switches flip
+    // when struck, and both drive the doors and hazards they're linked to
+    Plate,
+    Switch,
+    Door,
+    Hazard,
+    // A gate of the race course, passed in the order they're listed. The last one is the finish
+    Checkpoint,
+}
+
+impl PieceKind {
+    // What players collide with
+    pub fn is_solid(self) -> bool {
+        matches!(
+            self,
+            PieceKind::Wall | PieceKind::Ground | PieceKind::Platform | PieceKind::Net | PieceKind::Block { .. } | PieceKind::Door
+        )
+    }
+}
+
+// A plate or switch at piece index `from` driving the door or hazard at `to`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LevelLink {
+    pub from: usize,
+    pub to: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct LevelPiece {
+    pub kind: PieceKind,
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    // Index into the level's sprites to draw the piece with instead of its kind's color. Only
+    // looks, the collider is the piece's size either way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sprite: Option<usize>,
+}
+
+// A level script driving the piece at index `piece`, see level_script. Only builds with the
+// level-scripts feature run them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LevelScript {
+    pub piece: usize,
+    pub source: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LevelDef {
+    pub name: String,
+    pub width: f32,
+    pub height: f32,
+    pub pieces: Vec<LevelPiece>,
+    pub spawn_points: Vec<[f32; 2]>,
+    // Image under assets drawn behind the arena. Only looks, so it can finish loading after
+    // the match starts. Left out when unset, which keeps the hash of older levels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    // Same for links, only levels with switches have any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<LevelLink>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scripts: Vec<LevelScript>,
+    // Images under assets the pieces can be drawn with, see LevelPiece::sprite
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sprites: Vec<String>,
+}
+
+// The level the next match is built from
+#[derive(Resource, Clone, Debug)]
+pub struct ActiveLevel(pub LevelDef);
+
+impl Default for ActiveLevel {
+    fn default() -> Self {
+        Self(LevelDef::default_arena())
+    }
+}
+
+impl LevelPiece {
+    // Drawn in its kind's color
+    pub fn new(kind: PieceKind, position: [f32; 2], size: [f32; 2]) -> Self {
+        Self { kind, position, size, sprite: None }
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        let half = Vec2::from(self.size) / 2.0;
+        let offset = (point - Vec2::from(self.position)).abs();
+        offset.x <= half.x && offset.y <= half.y
+    }
+}
+
+impl LevelDef {
+    // The original hand-built court: four walls and a net in the middle
+    pub fn default_arena() -> Self {
+        let border_thickness = 0.5;
+        let width = 16.0; // Viewport width (assuming 16:10 aspect ratio)
+        let height = 10.0; // Matches viewport_height
+
+        Self {
+            name: "default".to_string(),
+            width,
+            height,
+            pieces: vec![
+                // Top wall
+                LevelPiece::new(PieceKind::Wall, [0.0, height / 2.0], [width, border_thickness]),
+                // Bottom wall (ground)
+                LevelPiece::new(PieceKind::Ground, [0.0, -height / 2.0], [width, border_thickness]),
+                // Left wall
+                LevelPiece::new(PieceKind::Wall, [-width / 2.0, 0.0], [border_thickness, height]),
+                // Right wall
+                LevelPiece::new(PieceKind::Wall, [width / 2.0, 0.0], [border_thickness, height]),
+                // Net
+                LevelPiece::new(PieceKind::Net, [0.0, -height / 4.0], [border_thickness, height * 0.5]),
+            ],
+            spawn_points: vec![[-2.0, 0.0], [2.0, 0.0]],
+            background: None,
+            links: Vec::new(),
+            scripts: Vec::new(),
+            sprites: Vec::new(),
+        }
+    }
+
+    // The arena is centered on the origin
+    pub fn bounds(&self) -> Rect {
+        Rect::from_center_size(Vec2::ZERO, Vec2::new(self.width, self.height))
+    }
+
+    // Drops the piece along with its links and script and shifts the ones past it down, so
+    // they keep pointing at the same pieces
+    pub fn remove_piece(&mut self, index: usize) {
+        self.pieces.remove(index);
+        self.links.retain(|link| link.from != index && link.to != index);
+        for link in &mut self.links {
+            link.from -= usize::from(link.from > index);
+            link.to -= usize::from(link.to > index);
+        }
+        self.scripts.retain(|script| script.piece != index);
+        for script in &mut self.scripts {
+            script.piece -= usize::from(script.piece > index);
+        }
+    }
+
+    // Everything that would spawn a broken arena, worded for the player. Empty for a level
+    // that can be played
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !(self.width > 0.0 && self.height > 0.0 && self.width.is_finite() && self.height.is_finite()) {
+            problems.push(format!("the arena is {} by {}", self.width, self.height));
+            return problems;
+        }
+        // Walls sit on the edge, so a little past it still counts as inside
+        let inside = self.bounds().inflate(EDGE_TOLERANCE);
+        for (index, piece) in self.pieces.iter().enumerate() {
+            let [width, height] = piece.size;
+            if !(width > 0.0 && height > 0.0 && width.is_finite() && height.is_finite()) {
+                problems.push(format!("piece {index} ({:?}) is {width} by {height}", piece.kind));
+            }
+            if !inside.contains(Vec2::from(piece.position)) {
+                problems.push(format!("piece {index} ({:?}) is outside the arena", piece.kind));
+            }
+            if let Some(sprite) = piece.sprite.filter(|sprite| *sprite >= self.sprites.len()) {
+                problems.push(format!("piece {index} ({:?}) is drawn with sprite {sprite}, which doesn't exist", piece.kind));
+            }
+        }
+        for handle in 0..NUM_PLAYERS {
+            let spawn = self.spawn_point(handle);
+            if !inside.contains(spawn) {
+                problems.push(format!("player {} spawns outside the arena", handle + 1));
+            }
+            if let Some(index) = self.pieces.iter().position(|piece| piece.kind.is_solid() && piece.contains(spawn)) {
+                problems.push(format!("player {} spawns inside piece {index}", handle + 1));
+            }
+        }
+        for (index, net) in self.pieces.iter().enumerate().filter(|(_, piece)| piece.kind == PieceKind::Net) {
+            let top = net.position[1] + net.size[1] / 2.0;
+            if top >= self.bounds().max.y - NET_CLEARANCE {
+                problems.push(format!("the net (piece {index}) reaches the ceiling, the ball can't get over it"));
+            }
+            let sides = [self.spawn_point(0).x, self.spawn_point(1).x].map(|x| x < net.position[0]);
+            if sides[0] == sides[1] {
+                problems.push(format!("both players spawn on the same side of the net (piece {index})"));
+            }
+        }
+        let kind_of = |index: usize| self.pieces.get(index).map(|piece| piece.kind);
+        for link in &self.links {
+            if !matches!(kind_of(link.from), Some(PieceKind::Plate | PieceKind::Switch)) {
+                problems.push(format!("a link starts at piece {}, which isn't a plate or switch", link.from));
+            }
+            if !matches!(kind_of(link.to), Some(PieceKind::Door | PieceKind::Hazard)) {
+                problems.push(format!("a link ends at piece {}, which isn't a door or hazard", link.to));
+            }
+        }
+        for script in &self.scripts {
+            if script.piece >= self.pieces.len() {
+                problems.push(format!("a script drives piece {}, which doesn't exist", script.piece));
+            }
+        }
+        problems
+    }
+
+    pub fn checkpoints(&self) -> impl Iterator<Item = &LevelPiece> {
+        self.pieces.iter().filter(|piece| piece.kind == PieceKind::Checkpoint)
+    }
+
+    // Only levels with a course can be raced
+    pub fn is_race_course(&self) -> bool {
+        self.checkpoints().next().is_some()
+    }
+
+    pub fn spawn_point(&self, handle: usize) -> Vec2 {
+        self.spawn_points
+            .get(handle)
+            .copied()
+            .map(Vec2::from)
+            .unwrap_or(Vec2::new(if handle.is_multiple_of(2) { -2.0 } else { 2.0 }, 0.0))
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("level definitions always serialize")
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(text)
+    }
+
+    // Hash of the serialized form, so peers can check they're about to build the same colliders
+    pub fn content_hash(&self) -> u64 {
+        fnv1a(self.to_ron().as_bytes())
+    }
+
+    pub fn path_for(name: &str) -> PathBuf {
+        PathBuf::from(LEVEL_DIR).join(format!("{name}.ron"))
+    }
+
+    pub fn save(&self) -> std::io::Result<PathBuf> {
+        let path = Self::path_for(&self.name);
+        std::fs::create_dir_all(LEVEL_DIR)?;
+        std::fs::write(&path, self.to_ron())?;
+        Ok(path)
+    }
+
+    pub fn load(name: &str) -> Result<Self, String> {
+        let path = Self::path_for(name);
+        let text = std::fs::read_to_string(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::from_ron(&text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    // Names of every level saved in the levels folder, sorted so both peers list them the same way
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(LEVEL_DIR)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                (path.extension()? == "ron").then(|| path.file_stem()?.to_str().map(str::to_string))?
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn find_by_hash(hash: u64) -> Option<Self> {
+        Self::available()
+            .iter()
+            .filter_map(|name| Self::load(name).ok())
+            .find(|level| level.content_hash() == hash)
+    }
+}
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveLevel>()
+            .add_systems(OnEnter(GameState::InGame), load_active_level.run_if(crate::game::assets_sound))
+            .add_systems(
+                Update,
+                stream_level_chunks.run_if(in_state(GameState::InGame)).run_if(resource_exists::<LevelStream>),
+            )
+            .add_systems(OnExit(GameState::InGame), despawn_level);
+    }
+}
+
+// The level picked on the menu, or whatever level sync settles on later
+fn load_active_level(mut commands: Commands, asset_server: Res<AssetServer>, level: Res<ActiveLevel>) {
+    stream_level(&mut commands, &asset_server, &level.0);
+}
+
+fn load_sprites(asset_server: &AssetServer, level: &LevelDef) -> Vec<Handle<Image>> {
+    level.sprites.iter().map(|path| asset_server.load(path.clone())).collect()
+}
+
+// All at once, for the editor where there's no session waiting on it
+pub fn spawn_level(commands: &mut Commands, asset_server: &AssetServer, level: &LevelDef) {
+    spawn_background(commands, asset_server, level);
+    let sprites = load_sprites(asset_server, level);
+    for (index, piece) in level.pieces.iter().enumerate() {
+        spawn_piece(commands, index, piece, &sprites);
+    }
+}
+
+// Starts spawning the level for a match, replacing one still streaming in
+pub fn stream_level(commands: &mut Commands, asset_server: &AssetServer, level: &LevelDef) {
+    if !level.scripts.is_empty() && !cfg!(feature = "level-scripts") {
+        warn!("{} has {} level scripts, this build plays it without them", level.name, level.scripts.len());
+    }
+    spawn_background(commands, asset_server, level);
+    commands.insert_resource(LevelStream {
+        hash: level.content_hash(),
+        total: level.pieces.len(),
+        pending: level.pieces.iter().copied().enumerate().collect(),
+        sprites: load_sprites(asset_server, level),
+    });
+}
+
+pub fn stream_level_chunks(mut commands: Commands, mut stream: ResMut<LevelStream>) {
+    let hash = stream.hash;
+    let count = stream.pending.len().min(CHUNK_PIECES);
+    let LevelStream { pending, sprites, .. } = stream.as_mut();
+    for (index, piece) in pending.drain(..count) {
+        let entity = spawn_piece(&mut commands, index, &piece, sprites);
+        commands.entity(entity).insert(StreamedPiece(hash));
+    }
+}
+
+// Run condition for starting a session. Counts the colliders that actually made it into the
+// world rather than trusting the queue, so pending commands can't slip past it
+pub fn level_streamed(stream: Option<Res<LevelStream>>, pieces: Query<&StreamedPiece, With<Collider>>) -> bool {
+    let Some(stream) = stream else {
+        return false;
+    };
+    stream.pending.is_empty() && pieces.iter().filter(|piece| piece.0 == stream.hash).count() == stream.total
+}
+
+// The asset server loads it in the background, the sprite shows up once it's there
+fn spawn_background(commands: &mut Commands, asset_server: &AssetServer, level: &LevelDef) {
+    let Some(path) = &level.background else {
+        return;
+    };
+    commands.spawn((
+        Transform::from_xyz(0.0, 0.0, -10.0),
+        Sprite {
+            image: asset_server.load(path.clone()),
+            custom_size: Some(Vec2::new(level.width, level.height)),
+            ..default()
+        },
+        LevelGeometry,
+    ));
+}
+
+// Sprites load in the background like the level's background, the piece is invisible until
+// its image is there
+fn spawn_piece(commands: &mut Commands, index: usize, piece: &LevelPiece, sprites: &[Handle<Image>]) -> Entity {
+    let size = Vec2::from(piece.size);
+    let color = match piece.kind {
+        PieceKind::Platform => Color::srgb(0.3, 0.3, 0.3),
+        PieceKind::Block { .. } => crate::blocks::BLOCK_COLOR,
+        PieceKind::Plate | PieceKind::Switch | PieceKind::Door | PieceKind::Hazard => {
+            crate::level_logic::idle_color(piece.kind)
+        }
+        PieceKind::Checkpoint => Color::srgba(0.3, 0.9, 0.5, 0.25),
+        _ => Color::BLACK,
+    };
+
+    let bundle = (
+        Transform::from_translation(Vec2::from(piece.position).extend(0.0)),
+        match piece.sprite.and_then(|sprite| sprites.get(sprite)) {
+            Some(image) => Sprite {
+                image: image.clone(),
+                custom_size: Some(size),
+                ..default()
+            },
+            None => Sprite {
+                color,
+                custom_size: Some(size),
+                ..default()
+            },
+        },
+        RigidBody::Static,
+        Collider::rectangle(size.x, size.y),
+        LevelGeometry,
+        PieceIndex(index),
+    );
+    // Blocks can break and logic pieces change state mid match, so they're rollback entities
+    // like everything else that changes
+    let mut entity = match piece.kind {
+        PieceKind::Block { .. } | PieceKind::Plate | PieceKind::Switch | PieceKind::Door | PieceKind::Hazard => {
+            commands.spawn_rollback("spawn_level", bundle)
+        }
+        _ => commands.spawn(bundle),
+    };
+
+    match piece.kind {
+        PieceKind::Wall => {
+            entity.insert(CollisionLayers::new([WALL_LAYER], !WALL_LAYER));
+        }
+        PieceKind::Net => {
+            entity.insert((CollisionLayers::new([WALL_LAYER], !WALL_LAYER), Net));
+        }
+        PieceKind::Ground | PieceKind::Platform => {
+            entity.insert((CollisionLayers::new([GROUND_LAYER], !GROUND_LAYER), Ground));
+        }
+        PieceKind::Block { hp } => {
+            entity.insert((CollisionLayers::new([GROUND_LAYER], !GROUND_LAYER), Ground, Breakable::new(hp, size)));
+        }
+        // Solid while closed, standable like a platform
+        PieceKind::Door => {
+            entity.insert((CollisionLayers::new([GROUND_LAYER], !GROUND_LAYER), Ground, LogicPiece::new(index, size)));
+        }
+        // Nothing bumps into these, level_logic checks who overlaps them
+        PieceKind::Plate | PieceKind::Switch => {
+            entity.insert((CollisionLayers::NONE, LogicPiece::new(index, size)));
+        }
+        PieceKind::Hazard => {
+            entity.insert((CollisionLayers::NONE, LogicPiece::new(index, size)));
+        }
+        // Passed through, race checks positions against the level instead
+        PieceKind::Checkpoint => {
+            entity.insert(CollisionLayers::NONE);
+        }
+    }
+    entity.id()
+}
+
+pub fn despawn_level(mut commands: Commands, query: Query<Entity, With<LevelGeometry>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<LevelStream>();
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+use crate::ball::{Ball, BALL_RADIUS};
+use crate::game::Player;
+use crate::input::{get_input_direction, Config};
+use crate::knockback::{self, Hit};
+use crate::level::{ActiveLevel, PieceKind};
+use crate::skins::{CharacterDefs, PlayerSkins};
+use crate::strike::{strike_started, STRIKE_RANGE};
+
+pub struct LevelLogicPlugin;
+
+// How far past a plate or hazard a player's center can be and still touch it, about half
+// the player's collider
+const BODY_REACH: Vec2 = Vec2::new(0.25, 0.55);
+const HAZARD_KNOCKBACK: f32 = 7.0;
+const HAZARD_STUN_FRAMES: u8 = 12;
+
+// A plate, switch, door or hazard from the level, by its index in LevelDef::pieces so links
+// can find it. `on` is the only part that changes: pressed for plates, flipped for switches,
+// powered by a link for doors (open) and hazards (off)
+#[derive(Component, Clone, Copy, Debug)]
+pub struct LogicPiece {
+    pub index: usize,
+    half_size: Vec2,
+    pub on: bool,
+}
+
+// Doors and hazards a level script drives, by piece index, in place of their links. Worked
+// out again every frame before the logic runs, see level_script
+#[derive(Resource, Default)]
+pub struct ScriptedPower(pub HashMap<usize, bool>);
+
+impl LogicPiece {
+    pub fn new(index: usize, size: Vec2) -> Self {
+        Self { index, half_size: size / 2.0, on: false }
+    }
+
+    fn touches(&self, center: Vec2, point: Vec2, reach: Vec2) -> bool {
+        let offset = (point - center).abs();
+        let extent = self.half_size + reach;
+        offset.x <= extent.x && offset.y <= extent.y
+    }
+}
+
+impl Plugin for LevelLogicPlugin {
+    fn build(&self, app: &mut App) {
+        app.rollback_component_with_copy::<LogicPiece>()
+            .init_resource::<ScriptedPower>()
+            .add_systems(Update, update_logic_visuals.run_if(in_state(GameState::InGame)));
+    }
+}
+
+pub fn idle_color(kind: PieceKind) -> Color {
+    color(kind, false)
+}
+
+fn color(kind: PieceKind, on: bool) -> Color {
+    match (kind, on) {
+        (PieceKind::Plate | PieceKind::Switch, false) => Color::srgb(0.45, 0.4, 0.2),
+        (PieceKind::Plate | PieceKind::Switch, true) => Color::srgb(0.95, 0.85, 0.3),
+        (PieceKind::Door, false) => Color::srgb(0.25, 0.35, 0.6),
+        (PieceKind::Door, true) => Color::srgba(0.25, 0.35, 0.6, 0.2),
+        (PieceKind::Hazard, false) => Color::srgb(0.9, 0.2, 0.15),
+        (PieceKind::Hazard, true) => Color::srgba(0.9, 0.2, 0.15, 0.2),
+        _ => Color::BLACK,
+    }
+}
+
+// Triggers first, then whatever they're linked to, then live hazards on whoever is in them.
+// Pieces go by level index and players by handle, so every peer settles it the same way
+pub fn update_level_logic(
+    mut commands: Commands,
+    level: Res<ActiveLevel>,
+    mut players: Query<(&mut Player, &Transform, &mut LinearVelocity)>,
+    balls: Query<&Transform, With<Ball>>,
+    mut pieces: Query<(Entity, &mut LogicPiece, &Transform)>,
+    (scripted, inputs): (Res<ScriptedPower>, Res<PlayerInputs<Config>>),
+    (defs, skins): (Res<CharacterDefs>, Res<PlayerSkins>),
+) {
+    let mut pieces: Vec<_> = pieces.iter_mut().collect();
+    if pieces.is_empty() {
+        return;
+    }
+    pieces.sort_by_key(|(_, piece, _)| piece.index);
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(player, ..)| player.handle);
+    let kind_of = |index: usize| level.0.pieces.get(index).map(|piece| piece.kind);
+
+    for (_, piece, transform) in pieces.iter_mut() {
+        let center = transform.translation.truncate();
+        match kind_of(piece.index) {
+            Some(PieceKind::Plate) => {
+                let pressed = players.iter().any(|(_, body, _)| piece.touches(center, body.translation.truncate(), BODY_REACH))
+                    || balls.iter().any(|ball| piece.touches(center, ball.translation.truncate(), Vec2::splat(BALL_RADIUS)));
+                if piece.on != pressed {
+                    piece.on = pressed;
+                }
+            }
+            Some(PieceKind::Switch) => {
+                for (player, transform, _) in players.iter().filter(|(player, ..)| strike_started(player)) {
+                    if piece.touches(center, transform.translation.truncate(), Vec2::splat(STRIKE_RANGE)) {
+                        piece.on = !piece.on;
+                        debug!("player {} flipped switch {}", player.handle, piece.index);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let triggered: Vec<usize> = pieces.iter().filter(|(_, piece, _)| piece.on).map(|(_, piece, _)| piece.index).collect();
+    for (entity, piece, transform) in pieces.iter_mut() {
+        let kind = kind_of(piece.index);
+        if !matches!(kind, Some(PieceKind::Door | PieceKind::Hazard)) {
+            continue;
+        }
+        let powered = scripted.0.get(&piece.index).copied().unwrap_or_else(|| {
+            level.0.links.iter().any(|link| link.to == piece.index && triggered.contains(&link.from))
+        });
+        if piece.on != powered {
+            piece.on = powered;
+            if kind == Some(PieceKind::Door) {
+                if powered {
+                    commands.entity(*entity).insert(ColliderDisabled);
+                } else {
+                    commands.entity(*entity).remove::<ColliderDisabled>();
+                }
+            }
+        }
+        if kind != Some(PieceKind::Hazard) || piece.on {
+            continue;
+        }
+        let center = transform.translation.truncate();
+        for (player, player_transform, velocity) in players.iter_mut() {
+            let position = player_transform.translation.truncate();
+            if player.stun_frames > 0 || !piece.touches(center, position, BODY_REACH) {
+                continue;
+            }
+            let away = if position.x < center.x { -1.0 } else { 1.0 };
+            velocity.0 = knockback::launch(Hit {
+                base: Vec2::new(HAZARD_KNOCKBACK * 0.5, HAZARD_KNOCKBACK),
+                away,
+                influence: get_input_direction(inputs[player.handle].0).x,
+                weight: defs.get(skins.character(player.handle)).physique.weight,
+            });
+            player.stun_frames = HAZARD_STUN_FRAMES;
+        }
+    }
+}
+
+fn update_logic_visuals(
+    level: Res<ActiveLevel>,
+    mut pieces: Query<(&LogicPiece, &mut Sprite), Changed<LogicPiece>>,
+) {
+    for (piece, mut sprite) in pieces.iter_mut() {
+        if let Some(level_piece) = level.0.pieces.get(piece.index) {
+            sprite.color = color(level_piece.kind, piece.on);
+        }
+    }
+}
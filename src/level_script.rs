@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_ggrs::{GgrsSchedule, RollbackFrameCount};
+use crate::GameState;
+use crate::level::{ActiveLevel, LevelDef, PieceIndex, PieceKind};
+use crate::level_logic::{update_level_logic, LogicPiece, ScriptedPower};
+
+pub struct LevelScriptPlugin;
+
+// Scripts run every frame on every peer, these keep one from costing more than a few lines
+const MAX_SOURCE_LEN: usize = 4096;
+const MAX_NESTING: usize = 32;
+// x and y are set in thousandths of a world unit
+const OFFSET_SCALE: f32 = 1000.0;
+// tri() goes from 0 up to this and back
+const WAVE_PEAK: i64 = 1000;
+
+// A small language for driving level pieces from the frame number and each other's state. A
+// script is a list of `name = expression;` lines, `#` starts a comment. Everything is a whole
+// number with wrapping math and dividing by zero gives 0, so every peer gets the same answer.
+// There are no loops and nothing to read but `frame` and these:
+//   on(i)            1 if the plate, switch, door or hazard at piece index i is on, else 0
+//   tri(t, period)   a triangle wave from 0 to 1000 and back over period frames
+//   abs(v) min(a, b) max(a, b) clamp(v, lo, hi)
+// with + - * / % < <= > >= == != && || ! and `c ? a : b`. What the script leaves in
+//   on   drives a door (open) or hazard (off) instead of its links
+//   x y  moves the piece this many thousandths of a unit from where the level puts it
+#[derive(Resource, Default)]
+struct LevelScripts {
+    // The hash of the level they were compiled for
+    hash: u64,
+    scripts: Vec<Compiled>,
+}
+
+struct Compiled {
+    piece: usize,
+    base: Vec2,
+    // One evaluated into each slot in turn
+    lines: Vec<(usize, Expr)>,
+    slots: usize,
+    on: Option<usize>,
+    x: Option<usize>,
+    y: Option<usize>,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Slot(usize),
+    Call(Function, Vec<Expr>),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    Choose(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    On,
+    Tri,
+    Abs,
+    Min,
+    Max,
+    Clamp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// Longest first, so <= isn't read as < then =
+const SYMBOLS: [&str; 21] = [
+    "<=", ">=", "==", "!=", "&&", "||", "(", ")", ",", ";", "=", "?", ":", "+", "-", "*", "/", "%", "<", ">", "!",
+];
+
+// The slot `frame` is read from, declared before anything the script sets
+const FRAME_SLOT: usize = 0;
+
+impl Function {
+    fn by_name(name: &str) -> Option<(Self, usize)> {
+        Some(match name {
+            "on" => (Function::On, 1),
+            "tri" => (Function::Tri, 2),
+            "abs" => (Function::Abs, 1),
+            "min" => (Function::Min, 2),
+            "max" => (Function::Max, 2),
+            "clamp" => (Function::Clamp, 3),
+            _ => return None,
+        })
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let line_number = number + 1;
+        let mut rest = line.split('#').next().unwrap_or_default().trim_start();
+        while !rest.is_empty() {
+            let first = rest.chars().next().unwrap_or_default();
+            let taken = if first.is_ascii_digit() {
+                let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                let value = rest[..digits]
+                    .parse::<i64>()
+                    .map_err(|err| format!("line {line_number}: {:?}: {err}", &rest[..digits]))?;
+                tokens.push((line_number, Token::Number(value)));
+                digits
+            } else if first.is_ascii_alphabetic() || first == '_' {
+                let length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                tokens.push((line_number, Token::Name(rest[..length].to_string())));
+                length
+            } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+                tokens.push((line_number, Token::Symbol(symbol)));
+                symbol.len()
+            } else {
+                return Err(format!("line {line_number}: unexpected {first:?}"));
+            };
+            rest = rest[taken..].trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    next: usize,
+    depth: usize,
+    // What each name set so far reads from
+    slots: HashMap<String, usize>,
+}
+
+impl Parser<'_> {
+    fn line(&self) -> usize {
+        self.tokens.get(self.next).or(self.tokens.last()).map_or(1, |(line, _)| *line)
+    }
+
+    fn error(&self, message: impl std::fmt::Display) -> String {
+        format!("line {}: {message}", self.line())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) { Ok(()) } else { Err(self.error(format!("expected {symbol:?}"))) }
+    }
+
+    fn statement(&mut self) -> Result<(usize, Expr), String> {
+        let Some(Token::Name(name)) = self.peek().cloned() else {
+            return Err(self.error("expected a name to set"));
+        };
+        if name == "frame" || Function::by_name(&name).is_some() {
+            return Err(self.error(format!("{name} can't be set")));
+        }
+        self.next += 1;
+        self.expect("=")?;
+        let value = self.expression()?;
+        self.expect(";")?;
+        let next_slot = self.slots.len() + 1;
+        let slot = *self.slots.entry(name).or_insert(next_slot);
+        Ok((slot, value))
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING { Err(self.error("nested too deep")) } else { Ok(()) }
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.nest()?;
+        let condition = self.binary(0)?;
+        let expr = if self.eat("?") {
+            let then = self.expression()?;
+            self.expect(":")?;
+            let otherwise = self.expression()?;
+            Expr::Choose(Box::new(condition), Box::new(then), Box::new(otherwise))
+        } else {
+            condition
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    // Loosest binding first: ||, &&, comparisons, + -, * / %
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        const LEVELS: [&[(&str, Operator)]; 5] = [
+            &[("||", Operator::Or)],
+            &[("&&", Operator::And)],
+            &[
+                ("<=", Operator::LessOrEqual),
+                (">=", Operator::GreaterOrEqual),
+                ("==", Operator::Equal),
+                ("!=", Operator::NotEqual),
+                ("<", Operator::Less),
+                (">", Operator::Greater),
+            ],
+            &[("+", Operator::Add), ("-", Operator::Subtract)],
+            &[("*", Operator::Multiply), ("/", Operator::Divide), ("%", Operator::Remainder)],
+        ];
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some((_, operator)) = operators.iter().find(|(symbol, _)| self.eat(symbol)) {
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(*operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            self.nest()?;
+            let inner = self.unary()?;
+            self.depth -= 1;
+            return Ok(Expr::Negate(Box::new(inner)));
+        }
+        if self.eat("!") {
+            self.nest()?;
+            let inner = self.unary()?;
+            self.depth -= 1;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        if self.eat("(") {
+            let inner = self.expression()?;
+            self.expect(")")?;
+            return Ok(inner);
+        }
+        match self.peek().cloned() {
+            Some(Token::Number(value)) => {
+                self.next += 1;
+                Ok(Expr::Number(value))
+            }
+            Some(Token::Name(name)) => {
+                self.next += 1;
+                if let Some((function, arity)) = Function::by_name(&name) {
+                    return self.call(&name, function, arity);
+                }
+                if name == "frame" {
+                    return Ok(Expr::Slot(FRAME_SLOT));
+                }
+                match self.slots.get(&name) {
+                    Some(slot) => Ok(Expr::Slot(*slot)),
+                    None => Err(self.error(format!("{name} is read before it's set"))),
+                }
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn call(&mut self, name: &str, function: Function, arity: usize) -> Result<Expr, String> {
+        self.expect("(")?;
+        let mut arguments = Vec::new();
+        if !self.eat(")") {
+            loop {
+                arguments.push(self.expression()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if arguments.len() != arity {
+            return Err(self.error(format!("{name} takes {arity} values, not {}", arguments.len())));
+        }
+        Ok(Expr::Call(function, arguments))
+    }
+}
+
+fn compile(level: &LevelDef, piece: usize, source: &str) -> Result<Compiled, String> {
+    let Some(target) = level.pieces.get(piece) else {
+        return Err(format!("there's no piece {piece}"));
+    };
+    if source.len() > MAX_SOURCE_LEN {
+        return Err(format!("longer than {MAX_SOURCE_LEN} characters"));
+    }
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens: &tokens, next: 0, depth: 0, slots: HashMap::new() };
+    let mut lines = Vec::new();
+    while parser.next < tokens.len() {
+        lines.push(parser.statement()?);
+    }
+    let on = parser.slots.get("on").copied();
+    if on.is_some() && !matches!(target.kind, PieceKind::Door | PieceKind::Hazard) {
+        return Err(format!("sets on, but piece {piece} is a {:?} rather than a door or hazard", target.kind));
+    }
+    Ok(Compiled {
+        piece,
+        base: Vec2::from(target.position),
+        lines,
+        slots: parser.slots.len() + 1,
+        on,
+        x: parser.slots.get("x").copied(),
+        y: parser.slots.get("y").copied(),
+    })
+}
+
+impl Expr {
+    fn evaluate(&self, slots: &[i64], states: &HashMap<usize, bool>) -> i64 {
+        let value = |expr: &Expr| expr.evaluate(slots, states);
+        match self {
+            Expr::Number(number) => *number,
+            Expr::Slot(slot) => slots[*slot],
+            Expr::Negate(inner) => value(inner).wrapping_neg(),
+            Expr::Not(inner) => i64::from(value(inner) == 0),
+            Expr::Choose(condition, then, otherwise) => {
+                if value(condition) != 0 { value(then) } else { value(otherwise) }
+            }
+            Expr::Binary(operator, left, right) => {
+                let left = value(left);
+                // Both sides always run, there's nothing a side could do but compute
+                let right = value(right);
+                match operator {
+                    Operator::Add => left.wrapping_add(right),
+                    Operator::Subtract => left.wrapping_sub(right),
+                    Operator::Multiply => left.wrapping_mul(right),
+                    Operator::Divide => left.checked_div(right).unwrap_or(0),
+                    Operator::Remainder => left.checked_rem(right).unwrap_or(0),
+                    Operator::Less => i64::from(left < right),
+                    Operator::LessOrEqual => i64::from(left <= right),
+                    Operator::Greater => i64::from(left > right),
+                    Operator::GreaterOrEqual => i64::from(left >= right),
+                    Operator::Equal => i64::from(left == right),
+                    Operator::NotEqual => i64::from(left != right),
+                    Operator::And => i64::from(left != 0 && right != 0),
+                    Operator::Or => i64::from(left != 0 || right != 0),
+                }
+            }
+            Expr::Call(function, arguments) => {
+                let arguments: Vec<i64> = arguments.iter().map(value).collect();
+                match (function, arguments.as_slice()) {
+                    (Function::On, [index]) => {
+                        i64::from(usize::try_from(*index).ok().and_then(|index| states.get(&index)).copied().unwrap_or(false))
+                    }
+                    (Function::Tri, [time, period]) => {
+                        let period = (*period).max(1);
+                        let phase = time.rem_euclid(period);
+                        let rising = phase.wrapping_mul(WAVE_PEAK * 2) / period;
+                        if rising > WAVE_PEAK { WAVE_PEAK * 2 - rising } else { rising }
+                    }
+                    (Function::Abs, [value]) => value.wrapping_abs(),
+                    (Function::Min, [a, b]) => *a.min(b),
+                    (Function::Max, [a, b]) => *a.max(b),
+                    (Function::Clamp, [value, low, high]) => (*value).max(*low).min(*high),
+                    _ => 0,
+                }
+            }
+        }
+    }
+}
+
+impl Compiled {
+    fn run(&self, frame: i64, states: &HashMap<usize, bool>) -> Vec<i64> {
+        let mut slots = vec![0; self.slots];
+        slots[FRAME_SLOT] = frame;
+        for (slot, expr) in &self.lines {
+            slots[*slot] = expr.evaluate(&slots, states);
+        }
+        slots
+    }
+}
+
+impl Plugin for LevelScriptPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelScripts>()
+            .add_systems(Update, compile_level_scripts.run_if(resource_changed::<ActiveLevel>))
+            .add_systems(
+                GgrsSchedule,
+                run_level_scripts.before(update_level_logic).run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+// A script that doesn't compile is left out and said so in the log, the level plays without it
+fn compile_level_scripts(level: Res<ActiveLevel>, mut scripts: ResMut<LevelScripts>) {
+    let hash = level.0.content_hash();
+    if scripts.hash == hash {
+        return;
+    }
+    scripts.hash = hash;
+    scripts.scripts = level
+        .0
+        .scripts
+        .iter()
+        .filter_map(|script| match compile(&level.0, script.piece, &script.source) {
+            Ok(compiled) => Some(compiled),
+            Err(err) => {
+                warn!("{}: script for piece {}: {err}", level.0.name, script.piece);
+                None
+            }
+        })
+        .collect();
+    if !scripts.scripts.is_empty() {
+        info!("{} runs {} level scripts", level.0.name, scripts.scripts.len());
+    }
+}
+
+// Reads the pieces as the last frame left them, so the order scripts run in doesn't matter
+fn run_level_scripts(
+    scripts: Res<LevelScripts>,
+    frame: Res<RollbackFrameCount>,
+    mut power: ResMut<ScriptedPower>,
+    logic: Query<&LogicPiece>,
+    mut pieces: Query<(&PieceIndex, &mut Transform)>,
+) {
+    power.0.clear();
+    if scripts.scripts.is_empty() {
+        return;
+    }
+    let states: HashMap<usize, bool> = logic.iter().map(|piece| (piece.index, piece.on)).collect();
+    let mut moves = HashMap::new();
+    for script in &scripts.scripts {
+        let slots = script.run(i64::from(frame.0), &states);
+        if let Some(on) = script.on {
+            power.0.insert(script.piece, slots[on] != 0);
+        }
+        if script.x.is_some() || script.y.is_some() {
+            let offset = |slot: Option<usize>| slot.map_or(0.0, |slot| slots[slot] as f32 / OFFSET_SCALE);
+            moves.insert(script.piece, script.base + Vec2::new(offset(script.x), offset(script.y)));
+        }
+    }
+    for (index, mut transform) in pieces.iter_mut() {
+        if let Some(position) = moves.get(&index.0) {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+    }
+}
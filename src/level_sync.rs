@@ -0,0 +1,412 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::game::Player;
+use crate::gameplay_constants::GameplayConstants;
+use crate::handicap::{Handicap, Handicaps};
+use crate::input::Config;
+use crate::level::{self, ActiveLevel, LevelDef, LevelGeometry};
+use crate::lobby::{Lineup, Lobby};
+use crate::ui_layout::SafeArea;
+use crate::locale::Locale;
+use crate::settings::Settings;
+use crate::skins::{Character, CharacterDefs, PlayerSkins};
+
+pub struct LevelSyncPlugin;
+
+// Channel 0 is handed to GGRS, this one carries everything negotiated before the session starts
+pub const RELIABLE_CHANNEL: usize = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+enum LevelMessage {
+    Announce { name: String, hash: u64, constants: GameplayConstants, handicap: Handicap, character: Character },
+    Request { hash: u64 },
+    Definition { ron: String },
+    // bodies is CharacterDefs::body_hash of the skins
+    Ready { hash: u64, constants: GameplayConstants, handicaps: Handicaps, skins: PlayerSkins, bodies: u64 },
+    // The host's answer to Ready. The guest only starts once it's accepted
+    Accept { hash: u64 },
+    // Either player giving up on the match, so the other stops waiting and shows why
+    Reject { problem: SyncProblem },
+    // From the host to everyone watching, once both players agree
+    Spectate { ron: String, constants: GameplayConstants, handicaps: Handicaps, skins: PlayerSkins },
+}
+
+// Why the players can't play the same match. Sent over the wire, so it's worded the same from
+// either side
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum SyncProblem {
+    LevelHash { host: u64, guest: u64 },
+    Constants,
+    Handicaps,
+    Characters,
+    Bodies,
+    WrongDefinition,
+    UnreadableLevel(String),
+    Unplayable(Vec<String>),
+}
+
+impl SyncProblem {
+    pub fn describe(&self, locale: &Locale) -> String {
+        match self {
+            SyncProblem::LevelHash { host, guest } => locale.format(
+                "sync.level_hash",
+                &[("host", &format!("{host:016x}")), ("guest", &format!("{guest:016x}"))],
+            ),
+            SyncProblem::Constants => locale.get("sync.constants").to_string(),
+            SyncProblem::Handicaps => locale.get("sync.handicaps").to_string(),
+            SyncProblem::Characters => locale.get("sync.characters").to_string(),
+            SyncProblem::Bodies => locale.get("sync.bodies").to_string(),
+            SyncProblem::WrongDefinition => locale.get("sync.wrong_definition").to_string(),
+            SyncProblem::UnreadableLevel(err) => locale.format("sync.unreadable_level", &[("error", err)]),
+            SyncProblem::Unplayable(problems) => {
+                locale.format("sync.unplayable", &[("problems", &problems.join(", "))])
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LevelSyncState {
+    #[default]
+    Negotiating,
+    Agreed(u64),
+    Mismatch(SyncProblem),
+}
+
+// Tracks agreement on which level both players build before the GGRS session exists.
+// The player with the lower id hosts, and its level and gameplay constants are the ones that
+// get played. Each player brings its own handicap and character. Spectators are handed the
+// result by the host
+#[derive(Resource, Default)]
+pub struct LevelSync {
+    pub state: LevelSyncState,
+    // Negotiation starts over whenever the lobby lines up someone else
+    lineup: Option<Lineup>,
+    announced: bool,
+    host_hash: Option<u64>,
+}
+
+#[derive(Component)]
+struct LevelSyncText;
+
+// What gets rebuilt when a peer's level is adopted
+#[derive(SystemParam)]
+struct Arena<'w, 's> {
+    asset_server: Res<'w, AssetServer>,
+    geometry: Query<'w, 's, Entity, With<LevelGeometry>>,
+    players: Query<'w, 's, (&'static Player, &'static mut Transform)>,
+}
+
+// Match terms settled alongside the level
+#[derive(SystemParam)]
+struct Terms<'w> {
+    constants: ResMut<'w, GameplayConstants>,
+    handicaps: ResMut<'w, Handicaps>,
+    skins: ResMut<'w, PlayerSkins>,
+    defs: Res<'w, CharacterDefs>,
+    settings: Res<'w, Settings>,
+}
+
+impl Plugin for LevelSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            // A running session has already settled its level, rotating the lineup under it
+            // only matters for the next match
+            (sync_level.run_if(not(resource_exists::<bevy_ggrs::Session<Config>>)), update_sync_text)
+                .chain()
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<LevelSync>)
+                .run_if(resource_exists::<MatchboxSocket>),
+        )
+        .add_systems(OnExit(GameState::InGame), cleanup_level_sync);
+    }
+}
+
+impl LevelSync {
+    pub fn is_agreed(&self) -> bool {
+        matches!(self.state, LevelSyncState::Agreed(_))
+    }
+
+    pub fn problem(&self) -> Option<&SyncProblem> {
+        match &self.state {
+            LevelSyncState::Mismatch(problem) => Some(problem),
+            _ => None,
+        }
+    }
+}
+
+fn send(socket: &mut MatchboxSocket, peer: PeerId, message: &LevelMessage) {
+    let text = ron::to_string(message).expect("level messages always serialize");
+    socket.channel_mut(RELIABLE_CHANNEL).send(text.into_bytes().into_boxed_slice(), peer);
+}
+
+// Gives up on the match and tells the other player why, so neither sits waiting for the other
+fn reject(socket: &mut MatchboxSocket, peer: PeerId, sync: &mut LevelSync, problem: SyncProblem) {
+    warn!("level sync with {peer} failed: {problem:?}");
+    send(socket, peer, &LevelMessage::Reject { problem: problem.clone() });
+    sync.state = LevelSyncState::Mismatch(problem);
+}
+
+fn sync_level(
+    mut commands: Commands,
+    mut socket: ResMut<MatchboxSocket>,
+    mut sync: ResMut<LevelSync>,
+    lobby: Res<Lobby>,
+    mut active_level: ResMut<ActiveLevel>,
+    mut terms: Terms,
+    mut arena: Arena,
+) {
+    if sync.lineup != lobby.lineup {
+        *sync = LevelSync { lineup: lobby.lineup.clone(), ..default() };
+    }
+    let (Some(local_id), Some(lineup)) = (socket.id(), lobby.lineup.clone()) else {
+        return;
+    };
+    if sync.state != LevelSyncState::Negotiating || socket.get_channel(RELIABLE_CHANNEL).is_err() {
+        return;
+    }
+
+    let Some(remote_id) = lineup.opponent_of(local_id) else {
+        watch_level(&mut commands, &mut socket, &mut sync, &lineup, &mut active_level, &mut terms, &mut arena);
+        return;
+    };
+    // Handles go to peers in id order, so the host plays handle 0
+    let is_host = local_id < remote_id;
+    let (local_handle, remote_handle) = if is_host { (0, 1) } else { (1, 0) };
+
+    if !sync.announced {
+        terms.handicaps.0[local_handle] = terms.settings.handicap;
+        terms.skins.0[local_handle] = terms.settings.character;
+        let message = LevelMessage::Announce {
+            name: active_level.0.name.clone(),
+            hash: active_level.0.content_hash(),
+            constants: *terms.constants,
+            handicap: terms.settings.handicap,
+            character: terms.settings.character,
+        };
+        send(&mut socket, remote_id, &message);
+        sync.announced = true;
+    }
+
+    for (peer, message) in receive(&mut socket) {
+        if peer != remote_id {
+            continue;
+        }
+        let mut adopted = None;
+        match message {
+            LevelMessage::Announce { handicap, character, .. } if is_host => {
+                terms.handicaps.0[remote_handle] = handicap;
+                terms.skins.0[remote_handle] = character;
+            }
+            LevelMessage::Announce { name, hash, constants: host_constants, handicap, character } if !is_host => {
+                info!("host is playing level {name} ({hash:016x}) with {host_constants:?}, {character:?}, handicap {handicap:?}");
+                sync.host_hash = Some(hash);
+                terms.constants.set_if_neq(host_constants);
+                terms.handicaps.0[remote_handle] = handicap;
+                terms.skins.0[remote_handle] = character;
+                if active_level.0.content_hash() == hash {
+                    adopted = Some(active_level.0.clone());
+                } else if let Some(level) = LevelDef::find_by_hash(hash) {
+                    adopted = Some(level);
+                } else {
+                    send(&mut socket, peer, &LevelMessage::Request { hash });
+                }
+            }
+            LevelMessage::Request { hash } if is_host && hash == active_level.0.content_hash() => {
+                send(&mut socket, peer, &LevelMessage::Definition { ron: active_level.0.to_ron() });
+            }
+            LevelMessage::Definition { ron } if !is_host => match LevelDef::from_ron(&ron) {
+                Ok(level) if Some(level.content_hash()) == sync.host_hash => adopted = Some(level),
+                Ok(_) => reject(&mut socket, peer, &mut sync, SyncProblem::WrongDefinition),
+                Err(err) => reject(&mut socket, peer, &mut sync, SyncProblem::UnreadableLevel(err.to_string())),
+            },
+            LevelMessage::Ready { hash, constants: peer_constants, handicaps: peer_handicaps, skins: peer_skins, bodies } if is_host => {
+                let local_hash = active_level.0.content_hash();
+                let problem = if hash != local_hash {
+                    Some(SyncProblem::LevelHash { host: local_hash, guest: hash })
+                } else if peer_constants != *terms.constants {
+                    warn!("peer runs {peer_constants:?}, expected {:?}", *terms.constants);
+                    Some(SyncProblem::Constants)
+                } else if peer_handicaps != *terms.handicaps {
+                    warn!("peer has handicaps {peer_handicaps:?}, expected {:?}", *terms.handicaps);
+                    Some(SyncProblem::Handicaps)
+                } else if peer_skins != *terms.skins {
+                    warn!("peer has characters {peer_skins:?}, expected {:?}", *terms.skins);
+                    Some(SyncProblem::Characters)
+                } else if bodies != terms.defs.body_hash(&terms.skins) {
+                    Some(SyncProblem::Bodies)
+                } else {
+                    None
+                };
+                match problem {
+                    Some(problem) => reject(&mut socket, peer, &mut sync, problem),
+                    None => {
+                        send(&mut socket, peer, &LevelMessage::Accept { hash });
+                        brief_spectators(&mut socket, &lineup, &active_level.0, &terms);
+                        sync.state = LevelSyncState::Agreed(hash);
+                    }
+                }
+            }
+            LevelMessage::Accept { hash } if !is_host && hash == active_level.0.content_hash() => {
+                sync.state = LevelSyncState::Agreed(hash);
+            }
+            LevelMessage::Reject { problem } => {
+                warn!("{peer} gave up on level sync: {problem:?}");
+                sync.state = LevelSyncState::Mismatch(problem);
+            }
+            _ => {}
+        }
+
+        if let Some(level) = adopted {
+            if let Some(problem) = unplayable(&level, &terms.constants) {
+                reject(&mut socket, peer, &mut sync, problem);
+                continue;
+            }
+            let hash = adopt_level(&mut commands, &mut active_level, &mut arena, level);
+            let message = LevelMessage::Ready {
+                hash,
+                constants: *terms.constants,
+                handicaps: *terms.handicaps,
+                skins: *terms.skins,
+                bodies: terms.defs.body_hash(&terms.skins),
+            };
+            // Agreed once the host accepts it
+            send(&mut socket, peer, &message);
+        }
+    }
+}
+
+// What's wrong with the host's level or physics, checked before anything is built from them
+fn unplayable(level: &LevelDef, constants: &GameplayConstants) -> Option<SyncProblem> {
+    let problems: Vec<String> = level.problems().into_iter().chain(constants.problems()).collect();
+    (!problems.is_empty()).then_some(SyncProblem::Unplayable(problems))
+}
+
+fn receive(socket: &mut MatchboxSocket) -> Vec<(PeerId, LevelMessage)> {
+    let received = socket.channel_mut(RELIABLE_CHANNEL).receive();
+    received
+        .into_iter()
+        .filter_map(|(peer, packet)| {
+            let message = std::str::from_utf8(&packet).ok().and_then(|text| ron::from_str(text).ok());
+            if message.is_none() {
+                warn!("ignoring malformed level message from {peer}");
+            }
+            Some((peer, message?))
+        })
+        .collect()
+}
+
+// Rebuilds the arena so every peer starts from identical colliders and spawns
+fn adopt_level(commands: &mut Commands, active_level: &mut ActiveLevel, arena: &mut Arena, level: LevelDef) -> u64 {
+    let hash = level.content_hash();
+    if hash != active_level.0.content_hash() {
+        for entity in arena.geometry.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        level::stream_level(commands, &arena.asset_server, &level);
+        for (player, mut transform) in arena.players.iter_mut() {
+            transform.translation = level.spawn_point(player.handle).extend(0.);
+        }
+        active_level.0 = level;
+    }
+    hash
+}
+
+fn brief_spectators(socket: &mut MatchboxSocket, lineup: &Lineup, level: &LevelDef, terms: &Terms) {
+    let message = LevelMessage::Spectate {
+        ron: level.to_ron(),
+        constants: *terms.constants,
+        handicaps: *terms.handicaps,
+        skins: *terms.skins,
+    };
+    for spectator in &lineup.queue {
+        send(socket, *spectator, &message);
+    }
+}
+
+// Spectators don't negotiate, they take whatever the host settled on with the other player
+fn watch_level(
+    commands: &mut Commands,
+    socket: &mut MatchboxSocket,
+    sync: &mut LevelSync,
+    lineup: &Lineup,
+    active_level: &mut ActiveLevel,
+    terms: &mut Terms,
+    arena: &mut Arena,
+) {
+    for (peer, message) in receive(socket) {
+        let LevelMessage::Spectate { ron, constants, handicaps, skins } = message else {
+            continue;
+        };
+        if peer != lineup.session_host() {
+            continue;
+        }
+        match LevelDef::from_ron(&ron) {
+            Ok(level) if let Some(problem) = unplayable(&level, &constants) => sync.state = LevelSyncState::Mismatch(problem),
+            Ok(level) => {
+                terms.constants.set_if_neq(constants);
+                terms.handicaps.set_if_neq(handicaps);
+                terms.skins.set_if_neq(skins);
+                let hash = adopt_level(commands, active_level, arena, level);
+                sync.state = LevelSyncState::Agreed(hash);
+            }
+            Err(err) => sync.state = LevelSyncState::Mismatch(SyncProblem::UnreadableLevel(err.to_string())),
+        }
+    }
+}
+
+fn update_sync_text(
+    mut commands: Commands,
+    sync: Res<LevelSync>,
+    active_level: Res<ActiveLevel>,
+    locale: Res<Locale>,
+    mut texts: Query<&mut Text, With<LevelSyncText>>,
+) {
+    if !sync.is_changed() && !locale.is_changed() {
+        return;
+    }
+
+    let label = match &sync.state {
+        LevelSyncState::Negotiating => String::new(),
+        LevelSyncState::Agreed(hash) => locale.format(
+            "sync.agreed",
+            &[("name", &active_level.0.name), ("hash", &format!("{hash:016x}"))],
+        ),
+        LevelSyncState::Mismatch(problem) => locale.format("sync.mismatch", &[("reason", &problem.describe(&locale))]),
+    };
+
+    if let Ok(mut text) = texts.get_single_mut() {
+        text.0 = label;
+        return;
+    }
+
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(20.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(label),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        LevelSyncText,
+    ));
+}
+
+fn cleanup_level_sync(mut commands: Commands, texts: Query<Entity, With<LevelSyncText>>) {
+    for entity in texts.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<LevelSync>();
+}
@@ -0,0 +1,951 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::build_info::BuildInfo;
+use crate::game::{input_delay, KeepConnection, FRAME_MS, MAX_INPUT_DELAY};
+use crate::gameplay_constants::{tuning_hash, PROTOCOL_VERSION};
+use crate::input::{Config, PlayerInput};
+use crate::locale::Locale;
+use crate::menu_nav::{button_text, menu_button, MenuActivated};
+use crate::notifications::Notify;
+use crate::race::{self, Leaderboards, RaceTime};
+use crate::scoring::PointScored;
+use crate::session_history::SessionHistory;
+use crate::settings::Settings;
+
+pub struct LobbyPlugin;
+
+// Membership and who plays, settled before level sync starts
+pub const LOBBY_CHANNEL: usize = 2;
+// Time for the winner to celebrate before the next challenger steps in
+const ROTATION_DELAY_SECS: f32 = 6.0;
+const PING_INTERVAL_SECS: f32 = 1.0;
+// Round trips from here up are flagged on the member cards
+const HIGH_RTT_MS: f32 = 150.0;
+// Builds from before the handshake never answer it, a peer that stays quiet this long after
+// connecting is taken for one of them
+const BUILD_TIMEOUT_SECS: f64 = 5.0;
+
+#[derive(Serialize, Deserialize, Debug)]
+enum LobbyMessage {
+    // Sent to every peer once, as soon as they show up
+    Hello { name: String },
+    // Also on arrival, which build this is. Nobody plays a peer whose build differs, see
+    // check_build. The label is only for telling the player
+    Build { protocol: u32, tuning: u64, label: String },
+    // From the host, everyone not playing watches and waits their turn in queue order
+    Lineup { players: [PeerId; 2], queue: Vec<PeerId> },
+    // Round trip probes, the pong echoes the ping's send time back
+    Ping { sent: f64 },
+    Pong { sent: f64 },
+    // From a player once the match is decided, to everyone so spectators follow along
+    Rematch,
+    // A time trial ghost to race against, see race
+    Ghost { course: u64, name: String, frames: u32, runs: Vec<(PlayerInput, u32)> },
+    // Between the two players before each session, the input delay they'd rather play with
+    // or None for the one derived from the round trip. A value needs the other's answer
+    DelayStance { frames: Option<usize> },
+    DelayAnswer { frames: usize, accepted: bool },
+    // Mid-match, from a player whose round trip stayed high, the input delay they'd move both
+    // to at the next rally. See latency
+    DelayBump { frames: usize },
+    DelayBumpAnswer { frames: usize, accepted: bool },
+}
+
+// What a member said about their build
+#[derive(Clone, Debug, PartialEq)]
+enum PeerBuild {
+    // Connected at this time and not heard from yet
+    Waiting(f64),
+    Known { protocol: u32, tuning: u64, label: String },
+    // Their messages don't parse, or they never said, so an older build
+    Unreadable,
+}
+
+// The two peers playing, in handle order, and everyone else in the room
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lineup {
+    pub players: [PeerId; 2],
+    pub queue: Vec<PeerId>,
+}
+
+// Everyone in the matchbox room. The member with the lowest id hosts the lobby and picks the
+// first lineup, after that whoever hosts the session rotates winner stays on. Pairing rooms
+// (the shared one, joined with ?next=2) only ever hold two members and line up on their own
+#[derive(Resource, Default)]
+pub struct Lobby {
+    pub drop_in: bool,
+    // In the order they joined, this peer first
+    members: Vec<PeerId>,
+    names: HashMap<PeerId, String>,
+    builds: HashMap<PeerId, PeerBuild>,
+    // Host only, the players picked so far
+    selected: Vec<PeerId>,
+    pub lineup: Option<Lineup>,
+    // Session host only, counting down to the next rotation with the peer who won
+    rotation: Option<(PeerId, Timer)>,
+    // The lineup changed under a running session, which gets rebuilt for it
+    next_match: bool,
+    // Smoothed round trip to each peer in milliseconds, from pings on the lobby channel
+    rtt_ms: HashMap<PeerId, f32>,
+    // Players who asked to play the same lineup again
+    rematch: Vec<PeerId>,
+    // This match's input delay talk, see delay_agreed: what this player said once it's sent,
+    // what the opponent said once it's heard, and the answer to a value one of them asked for
+    delay_sent: Option<Option<usize>>,
+    delay_heard: Option<Option<usize>>,
+    delay_answer: Option<bool>,
+    // A mid-match bump this player asked for and is waiting to hear about, and one both
+    // players agreed on that the match hasn't taken up yet
+    bump_sent: Option<usize>,
+    bump_agreed: Option<usize>,
+}
+
+#[derive(Component)]
+struct LobbyPanel {
+    // Members the cards were built for, rebuilt when this goes stale
+    shown: Vec<PeerId>,
+    picking: bool,
+}
+
+#[derive(Component)]
+struct MemberCard(PeerId);
+
+#[derive(Component)]
+struct StartButton;
+
+// Under the waiting text, what input delay the match will have and any value asked for
+#[derive(Component)]
+struct DelayPanel;
+
+#[derive(Component)]
+struct DelayText;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum DelayButton {
+    Accept,
+    Decline,
+}
+
+impl Lineup {
+    // Handles follow peer id order, like GGRS hands them out
+    fn new(mut players: [PeerId; 2], queue: Vec<PeerId>) -> Self {
+        players.sort();
+        Self { players, queue }
+    }
+
+    pub fn handle_of(&self, peer: PeerId) -> Option<usize> {
+        self.players.iter().position(|player| *player == peer)
+    }
+
+    pub fn opponent_of(&self, peer: PeerId) -> Option<PeerId> {
+        let handle = self.handle_of(peer)?;
+        Some(self.players[1 - handle])
+    }
+
+    // The handle 0 peer hosts the GGRS session, spectators connect to it
+    pub fn session_host(&self) -> PeerId {
+        self.players[0]
+    }
+}
+
+impl Lobby {
+    pub fn new(drop_in: bool) -> Self {
+        Self { drop_in, ..default() }
+    }
+
+    pub fn host(&self) -> Option<PeerId> {
+        self.members.iter().min().copied()
+    }
+
+    fn is_host(&self, local: PeerId) -> bool {
+        self.host() == Some(local)
+    }
+
+    // A newcomer arriving mid-match hears the lineup from whoever runs the session, even if
+    // they'd host the lobby themselves
+    fn decides_lineup(&self, peer: PeerId, proposed: &Lineup) -> bool {
+        match &self.lineup {
+            Some(lineup) => lineup.session_host() == peer,
+            None => self.host() == Some(peer) || proposed.session_host() == peer,
+        }
+    }
+
+    // Everyone waiting for a turn: the queue, then members who joined after it was made
+    pub fn waiting(&self) -> Vec<PeerId> {
+        let Some(lineup) = &self.lineup else {
+            return Vec::new();
+        };
+        let mut waiting = lineup.queue.clone();
+        waiting.extend(
+            self.members
+                .iter()
+                .filter(|member| !lineup.players.contains(member) && !lineup.queue.contains(member)),
+        );
+        waiting
+    }
+
+    // Winner stays on against the first in line, the loser goes to the back
+    fn rotated(&self, winner: PeerId) -> Option<Lineup> {
+        let loser = self.lineup.as_ref()?.opponent_of(winner)?;
+        let mut waiting = self.waiting();
+        if waiting.is_empty() {
+            return None;
+        }
+        let challenger = waiting.remove(0);
+        waiting.push(loser);
+        Some(Lineup::new([winner, challenger], waiting))
+    }
+
+    // Both players asking starts the next match, on every peer at once since they all heard
+    // both requests. Returns whether the other player had already asked
+    fn wants_rematch(&mut self, peer: PeerId) -> bool {
+        let Some(lineup) = &self.lineup else {
+            return false;
+        };
+        if lineup.handle_of(peer).is_none() || self.rematch.contains(&peer) {
+            return false;
+        }
+        self.rematch.push(peer);
+        if self.rematch.len() < 2 {
+            return false;
+        }
+        self.rematch.clear();
+        self.next_match = true;
+        true
+    }
+
+    pub fn rtt_ms(&self, peer: PeerId) -> Option<f32> {
+        self.rtt_ms.get(&peer).copied()
+    }
+
+    // Weighted like TCP's smoothed round trip, so one slow packet doesn't flag a peer
+    fn record_rtt(&mut self, peer: PeerId, sample: f32) {
+        let rtt = self.rtt_ms.entry(peer).or_insert(sample);
+        *rtt = *rtt * 0.875 + sample * 0.125;
+    }
+
+    // Settled once both stances are in and any value asked for is answered. Some(None) is
+    // each player deriving their own from the round trip, as without asking
+    fn delay_agreed(&self) -> Option<Option<usize>> {
+        let (Some(sent), Some(heard)) = (self.delay_sent, self.delay_heard) else {
+            return None;
+        };
+        match (sent, heard) {
+            (None, None) => Some(None),
+            // Both asked, the longer one keeps either of them from rolling back more than wanted
+            (Some(ours), Some(theirs)) => Some(Some(ours.max(theirs))),
+            (Some(frames), None) | (None, Some(frames)) => self.delay_answer.map(|accepted| accepted.then_some(frames)),
+        }
+    }
+
+    // A value the opponent asked for that's waiting on this player
+    fn delay_offer(&self) -> Option<usize> {
+        match (self.delay_sent, self.delay_heard, self.delay_answer) {
+            (Some(None), Some(Some(frames)), None) => Some(frames),
+            _ => None,
+        }
+    }
+
+    // What this match plays with against the opponent, None while the players are still
+    // talking it over
+    pub fn input_delay(&self, opponent: PeerId) -> Option<usize> {
+        let agreed = self.delay_agreed()?;
+        Some(agreed.unwrap_or_else(|| input_delay(self.rtt_ms(opponent))))
+    }
+
+    // An agreed bump, once. Both players take it up at their next rally
+    pub fn take_delay_bump(&mut self) -> Option<usize> {
+        self.bump_agreed.take()
+    }
+
+    pub fn delay_bump_pending(&self) -> bool {
+        self.bump_sent.is_some() || self.bump_agreed.is_some()
+    }
+
+    // Once the session is built. Both players are past the talk by then, so nothing said
+    // for this match can arrive after it and be taken for the next one
+    pub fn finish_delay_talk(&mut self) {
+        self.delay_sent = None;
+        self.delay_heard = None;
+        self.delay_answer = None;
+        self.bump_sent = None;
+        self.bump_agreed = None;
+    }
+
+    // Whether this build can play against the peer's, None until they've said. The error is
+    // for the player, saying which side is off
+    pub fn check_build(&self, peer: PeerId, locale: &Locale) -> Option<Result<(), String>> {
+        let name = self.name(peer);
+        match self.builds.get(&peer)? {
+            PeerBuild::Waiting(_) => None,
+            PeerBuild::Known { protocol, tuning, .. } if *protocol == PROTOCOL_VERSION && *tuning == tuning_hash() => {
+                Some(Ok(()))
+            }
+            PeerBuild::Known { protocol, label, .. } if *protocol != PROTOCOL_VERSION => Some(Err(locale.format(
+                "error.protocol_mismatch",
+                &[("name", &name), ("theirs", label), ("ours", &BuildInfo::CURRENT.label())],
+            ))),
+            PeerBuild::Known { .. } => Some(Err(locale.format("error.tuning_mismatch", &[("name", &name)]))),
+            PeerBuild::Unreadable => Some(Err(locale.format("error.outdated_build", &[("name", &name)]))),
+        }
+    }
+
+    fn expire_builds(&mut self, now: f64) {
+        for build in self.builds.values_mut() {
+            if let PeerBuild::Waiting(since) = *build
+                && now - since > BUILD_TIMEOUT_SECS
+            {
+                *build = PeerBuild::Unreadable;
+            }
+        }
+    }
+
+    pub fn name(&self, peer: PeerId) -> String {
+        self.names.get(&peer).cloned().unwrap_or_else(|| peer.to_string()[..8].to_string())
+    }
+}
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                track_members,
+                receive_lobby_messages,
+                (schedule_rotation, rotate_players, begin_next_match).chain(),
+                (ping_members, auto_lineup, pick_lineup, update_lobby_panel).chain().run_if(lobby_open),
+                (send_delay_stance, answer_delay, update_delay_panel).chain().run_if(lobby_open),
+            )
+                .chain()
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<Lobby>)
+                .run_if(resource_exists::<MatchboxSocket>),
+        )
+        // Still listening after the match, for the opponent's answer to a rematch
+        .add_systems(
+            Update,
+            (track_members, receive_lobby_messages, begin_next_match)
+                .chain()
+                .run_if(in_state(GameState::MatchOver))
+                .run_if(resource_exists::<Lobby>)
+                .run_if(resource_exists::<MatchboxSocket>),
+        )
+        .add_systems(
+            Update,
+            (cleanup_lobby_panel, cleanup_delay_panel).run_if(in_state(GameState::InGame)).run_if(not(lobby_open)),
+        )
+        .add_systems(OnExit(GameState::InGame), (cleanup_lobby_panel, cleanup_delay_panel));
+    }
+}
+
+// Members keep being tracked during a match, but the panel is only up until it starts
+fn lobby_open(session: Option<Res<bevy_ggrs::Session<Config>>>) -> bool {
+    session.is_none()
+}
+
+fn send(socket: &mut MatchboxSocket, peer: PeerId, message: &LobbyMessage) {
+    let text = ron::to_string(message).expect("lobby messages always serialize");
+    socket.channel_mut(LOBBY_CHANNEL).send(text.into_bytes().into_boxed_slice(), peer);
+}
+
+fn track_members(
+    mut socket: ResMut<MatchboxSocket>,
+    mut lobby: ResMut<Lobby>,
+    settings: Res<Settings>,
+    (locale, time): (Res<Locale>, Res<Time<Real>>),
+    session: Option<Res<bevy_ggrs::Session<Config>>>,
+    mut notify: EventWriter<Notify>,
+) {
+    let Some(local) = socket.id() else {
+        return;
+    };
+    // The session reports its own players coming and going
+    let quiet = session.is_some();
+    if lobby.members.is_empty() {
+        lobby.members.push(local);
+        lobby.names.insert(local, settings.player_name.clone());
+    }
+
+    for (peer, state) in socket.update_peers() {
+        match state {
+            PeerState::Connected => {
+                if !quiet {
+                    notify.send(Notify::info(locale.get("toast.opponent_connected")));
+                }
+                lobby.members.push(peer);
+                lobby.builds.insert(peer, PeerBuild::Waiting(time.elapsed_secs_f64()));
+                send(&mut socket, peer, &LobbyMessage::Hello { name: settings.player_name.clone() });
+                let build = LobbyMessage::Build { protocol: PROTOCOL_VERSION, tuning: tuning_hash(), label: BuildInfo::CURRENT.label() };
+                send(&mut socket, peer, &build);
+                send(&mut socket, peer, &LobbyMessage::Ping { sent: time.elapsed_secs_f64() });
+                // Joined mid-match, tell them who's on so they wait for their turn
+                if let Some(lineup) = &lobby.lineup
+                    && lineup.session_host() == local
+                {
+                    let message = LobbyMessage::Lineup { players: lineup.players, queue: lineup.queue.clone() };
+                    send(&mut socket, peer, &message);
+                }
+            }
+            PeerState::Disconnected => {
+                if !quiet {
+                    notify.send(Notify::warning(locale.get("toast.opponent_disconnected")));
+                }
+                lobby.members.retain(|member| *member != peer);
+                lobby.selected.retain(|member| *member != peer);
+                lobby.rtt_ms.remove(&peer);
+                lobby.builds.remove(&peer);
+                lobby.rematch.retain(|member| *member != peer);
+                // A player leaving before the start sends everyone back to picking
+                if lobby.lineup.as_ref().is_some_and(|lineup| lineup.handle_of(peer).is_some()) {
+                    lobby.lineup = None;
+                    lobby.finish_delay_talk();
+                } else if let Some(lineup) = lobby.lineup.as_mut() {
+                    lineup.queue.retain(|member| *member != peer);
+                }
+            }
+        }
+    }
+    lobby.expire_builds(time.elapsed_secs_f64());
+}
+
+fn receive_lobby_messages(
+    mut socket: ResMut<MatchboxSocket>,
+    mut lobby: ResMut<Lobby>,
+    session: Option<Res<bevy_ggrs::Session<Config>>>,
+    (time, settings): (Res<Time<Real>>, Res<Settings>),
+    (locale, mut leaderboards): (Res<Locale>, ResMut<Leaderboards>),
+    mut notify: EventWriter<Notify>,
+) {
+    let received = socket.channel_mut(LOBBY_CHANNEL).receive();
+    for (peer, packet) in received {
+        let Some(message) = std::str::from_utf8(&packet)
+            .ok()
+            .and_then(|text| ron::from_str::<LobbyMessage>(text).ok())
+        else {
+            warn!("ignoring malformed lobby message from {peer}");
+            if let Some(build @ PeerBuild::Waiting(_)) = lobby.builds.get_mut(&peer) {
+                *build = PeerBuild::Unreadable;
+            }
+            continue;
+        };
+        match message {
+            LobbyMessage::Hello { name } => {
+                lobby.names.insert(peer, name);
+            }
+            LobbyMessage::Build { protocol, tuning, label } => {
+                if protocol != PROTOCOL_VERSION || tuning != tuning_hash() {
+                    warn!(
+                        "{peer} runs {label}, protocol {protocol} tuning {tuning:016x}, this is {} {PROTOCOL_VERSION} {:016x}",
+                        BuildInfo::CURRENT.label(),
+                        tuning_hash()
+                    );
+                }
+                lobby.builds.insert(peer, PeerBuild::Known { protocol, tuning, label });
+            }
+            LobbyMessage::Lineup { players, queue } => {
+                let lineup = Lineup::new(players, queue);
+                if !lobby.decides_lineup(peer, &lineup) {
+                    warn!("ignoring lineup from {peer}, who isn't hosting");
+                    continue;
+                }
+                info!("lineup from {peer}: {:?}, queue {:?}", lineup.players, lineup.queue);
+                if session.is_some() && lobby.lineup.as_ref() != Some(&lineup) {
+                    lobby.next_match = true;
+                }
+                lobby.lineup = Some(lineup);
+            }
+            LobbyMessage::Ping { sent } => send(&mut socket, peer, &LobbyMessage::Pong { sent }),
+            LobbyMessage::Pong { sent } => {
+                let sample = (time.elapsed_secs_f64() - sent) * 1000.0;
+                lobby.record_rtt(peer, sample as f32);
+            }
+            LobbyMessage::Rematch => {
+                let from_opponent =
+                    socket.id().and_then(|local| lobby.lineup.as_ref()?.opponent_of(local)) == Some(peer);
+                // Once both asked there's nothing left to tell
+                if !lobby.wants_rematch(peer) && from_opponent {
+                    notify.send(Notify::info(locale.get("toast.rematch_asked")));
+                }
+            }
+            LobbyMessage::Ghost { course, name, frames, runs } => match RaceTime::from_runs(name, frames, &runs) {
+                Ok(ghost) => {
+                    notify.send(Notify::info(race::ghost_received(&locale, &ghost)));
+                    leaderboards.import(course, ghost);
+                    if let Err(err) = leaderboards.save() {
+                        warn!("can't save the ghost from {peer}: {err}");
+                    }
+                }
+                Err(err) => warn!("ignoring ghost from {peer}: {err}"),
+            },
+            LobbyMessage::DelayStance { frames } => {
+                let from_opponent =
+                    socket.id().and_then(|local| lobby.lineup.as_ref()?.opponent_of(local)) == Some(peer);
+                if !from_opponent {
+                    warn!("ignoring input delay from {peer}, who isn't playing against us");
+                    continue;
+                }
+                lobby.delay_heard = Some(frames.map(|frames| frames.clamp(1, MAX_INPUT_DELAY)));
+            }
+            LobbyMessage::DelayAnswer { frames, accepted } => {
+                if lobby.delay_sent != Some(Some(frames)) || lobby.delay_answer.is_some() {
+                    continue;
+                }
+                lobby.delay_answer = Some(accepted);
+                if !accepted {
+                    let name = lobby.name(peer);
+                    notify.send(Notify::info(locale.format("toast.delay_declined", &[("name", &name), ("frames", &frames)])));
+                }
+            }
+            // Taken whenever this player lets the match bump the delay at all, a stuttering
+            // match is worse for both
+            LobbyMessage::DelayBump { frames } => {
+                let from_opponent =
+                    socket.id().and_then(|local| lobby.lineup.as_ref()?.opponent_of(local)) == Some(peer);
+                if session.is_none() || !from_opponent {
+                    warn!("ignoring input delay bump from {peer}, who isn't playing against us");
+                    continue;
+                }
+                let frames = frames.clamp(1, MAX_INPUT_DELAY);
+                let accepted = settings.auto_delay_bump;
+                send(&mut socket, peer, &LobbyMessage::DelayBumpAnswer { frames, accepted });
+                if accepted {
+                    lobby.bump_agreed = Some(lobby.bump_agreed.map_or(frames, |agreed| agreed.max(frames)));
+                }
+            }
+            LobbyMessage::DelayBumpAnswer { frames, accepted } => {
+                if lobby.bump_sent != Some(frames) {
+                    continue;
+                }
+                lobby.bump_sent = None;
+                if accepted {
+                    lobby.bump_agreed = Some(lobby.bump_agreed.map_or(frames, |agreed| agreed.max(frames)));
+                } else {
+                    info!("{peer} kept the input delay as it is");
+                }
+            }
+        }
+    }
+}
+
+fn first_lineup(lobby: &Lobby, players: [PeerId; 2]) -> Lineup {
+    let queue = lobby.members.iter().copied().filter(|member| !players.contains(member)).collect();
+    Lineup::new(players, queue)
+}
+
+fn announce_lineup(socket: &mut MatchboxSocket, lobby: &mut Lobby, lineup: Lineup) {
+    info!("starting with {:?}, queue {:?}", lineup.players, lineup.queue);
+    let message = LobbyMessage::Lineup { players: lineup.players, queue: lineup.queue.clone() };
+    let peers: Vec<PeerId> = socket.connected_peers().collect();
+    for peer in peers {
+        send(socket, peer, &message);
+    }
+    lobby.lineup = Some(lineup);
+}
+
+// Keeps probing until the session starts, which takes the last measurement for its input delay
+fn ping_members(
+    time: Res<Time<Real>>,
+    mut since_last: Local<f32>,
+    mut socket: ResMut<MatchboxSocket>,
+    lobby: Res<Lobby>,
+) {
+    *since_last += time.delta_secs();
+    if *since_last < PING_INTERVAL_SECS {
+        return;
+    }
+    *since_last = 0.0;
+    let message = LobbyMessage::Ping { sent: time.elapsed_secs_f64() };
+    for peer in lobby.members.iter().skip(1) {
+        send(&mut socket, *peer, &message);
+    }
+}
+
+// Pairing rooms have nobody to wait for once the second peer is in
+fn auto_lineup(mut socket: ResMut<MatchboxSocket>, mut lobby: ResMut<Lobby>) {
+    let Some(local) = socket.id() else {
+        return;
+    };
+    if lobby.drop_in || lobby.lineup.is_some() || !lobby.is_host(local) {
+        return;
+    }
+    if let [first, second] = lobby.members[..] {
+        let lineup = first_lineup(&lobby, [first, second]);
+        announce_lineup(&mut socket, &mut lobby, lineup);
+    }
+}
+
+fn pick_lineup(
+    mut activated: EventReader<MenuActivated>,
+    cards: Query<&MemberCard>,
+    starts: Query<(), With<StartButton>>,
+    mut socket: ResMut<MatchboxSocket>,
+    mut lobby: ResMut<Lobby>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        if let Ok(MemberCard(peer)) = cards.get(*entity) {
+            if let Some(index) = lobby.selected.iter().position(|selected| selected == peer) {
+                lobby.selected.remove(index);
+            } else if lobby.selected.len() < 2 {
+                lobby.selected.push(*peer);
+            }
+        } else if starts.contains(*entity)
+            && lobby.lineup.is_none()
+            && let [first, second] = lobby.selected[..]
+        {
+            let lineup = first_lineup(&lobby, [first, second]);
+            announce_lineup(&mut socket, &mut lobby, lineup);
+        }
+    }
+}
+
+// Points only arrive once confirmed, so the winner is the same on every peer
+fn schedule_rotation(mut points: EventReader<PointScored>, mut socket: ResMut<MatchboxSocket>, mut lobby: ResMut<Lobby>) {
+    let Some(point) = points.read().find(|point| point.won_match) else {
+        return;
+    };
+    let (Some(local), Some(lineup)) = (socket.id(), &lobby.lineup) else {
+        return;
+    };
+    if !lobby.drop_in || lineup.session_host() != local || lobby.waiting().is_empty() {
+        return;
+    }
+    let winner = lineup.players[point.winner];
+    lobby.rotation = Some((winner, Timer::from_seconds(ROTATION_DELAY_SECS, TimerMode::Once)));
+}
+
+fn rotate_players(time: Res<Time>, mut socket: ResMut<MatchboxSocket>, mut lobby: ResMut<Lobby>) {
+    let Some((winner, timer)) = lobby.rotation.as_mut() else {
+        return;
+    };
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let winner = *winner;
+    lobby.rotation = None;
+    // Picked now rather than when the match was won, so anyone who left meanwhile is skipped
+    if let Some(lineup) = lobby.rotated(winner) {
+        announce_lineup(&mut socket, &mut lobby, lineup);
+        lobby.next_match = true;
+    }
+}
+
+// Same room and socket, only the session and the level start over
+fn begin_next_match(mut commands: Commands, mut lobby: ResMut<Lobby>, mut next_state: ResMut<NextState<GameState>>) {
+    if !lobby.next_match {
+        return;
+    }
+    lobby.next_match = false;
+    commands.insert_resource(KeepConnection);
+    next_state.set(GameState::NextMatch);
+}
+
+// A player asking for the same lineup again, answered by rematch messages. Rooms with someone
+// waiting rotate instead, so it's refused there
+pub fn request_rematch(socket: &mut MatchboxSocket, lobby: &mut Lobby) -> bool {
+    let Some(local) = socket.id() else {
+        return false;
+    };
+    if !lobby.waiting().is_empty() || lobby.lineup.as_ref().is_none_or(|lineup| lineup.handle_of(local).is_none()) {
+        return false;
+    }
+    let peers: Vec<PeerId> = socket.connected_peers().collect();
+    for peer in peers {
+        send(socket, peer, &LobbyMessage::Rematch);
+    }
+    lobby.wants_rematch(local);
+    true
+}
+
+// Asks the opponent to move both players to this input delay at the next rally. Nothing
+// changes unless they answer yes
+pub fn request_delay_bump(socket: &mut MatchboxSocket, lobby: &mut Lobby, frames: usize) -> bool {
+    let Some(opponent) = socket.id().and_then(|local| lobby.lineup.as_ref()?.opponent_of(local)) else {
+        return false;
+    };
+    if lobby.delay_bump_pending() {
+        return false;
+    }
+    send(socket, opponent, &LobbyMessage::DelayBump { frames });
+    lobby.bump_sent = Some(frames);
+    true
+}
+
+// Everyone in the room gets the ghost for the level this room plays, to race on their own
+pub fn send_ghost(socket: &mut MatchboxSocket, course: u64, ghost: &RaceTime) {
+    let message = LobbyMessage::Ghost {
+        course,
+        name: ghost.name.clone(),
+        frames: ghost.frames,
+        runs: ghost.runs(),
+    };
+    let peers: Vec<PeerId> = socket.connected_peers().collect();
+    for peer in peers {
+        send(socket, peer, &message);
+    }
+}
+
+fn member_label(lobby: &Lobby, history: &SessionHistory, local: PeerId, peer: PeerId, locale: &Locale) -> String {
+    let mut label = lobby.name(peer);
+    if peer == local {
+        label = locale.format("lobby.you", &[("name", &label)]);
+    }
+    if lobby.host() == Some(peer) {
+        label = locale.format("lobby.host", &[("name", &label)]);
+    }
+    let role = match &lobby.lineup {
+        Some(lineup) => match lineup.handle_of(peer) {
+            Some(handle) => locale.format("lobby.player", &[("player", &(handle + 1))]),
+            None => {
+                let position = lobby.waiting().iter().position(|waiting| *waiting == peer).unwrap_or(0);
+                locale.format("lobby.queued", &[("position", &(position + 1))])
+            }
+        },
+        None if lobby.selected.contains(&peer) => locale.get("lobby.picked").to_string(),
+        None => String::new(),
+    };
+    if !role.is_empty() {
+        label = format!("{label}  {role}");
+    }
+    if let Some(rtt) = lobby.rtt_ms(peer) {
+        let key = if rtt >= HIGH_RTT_MS { "lobby.ping_high" } else { "lobby.ping" };
+        label = format!("{label}  {}", locale.format(key, &[("ms", &(rtt.round() as u32))]));
+    }
+    // Against this player, from where the local player stands
+    if peer != local
+        && let Some([wins, losses]) = history.tally(&lobby.name(local), &lobby.name(peer))
+    {
+        label = format!("{label}  {}", locale.format("lobby.tonight", &[("wins", &wins), ("losses", &losses)]));
+    }
+    label
+}
+
+// Only the host gets buttons, everyone else just sees who's in
+fn update_lobby_panel(
+    mut commands: Commands,
+    mut socket: ResMut<MatchboxSocket>,
+    (lobby, history): (Res<Lobby>, Res<SessionHistory>),
+    locale: Res<Locale>,
+    panels: Query<(Entity, &LobbyPanel)>,
+    mut labels: Query<(&mut Text, &MemberCard)>,
+    mut start_labels: Query<&mut Text, (With<StartButton>, Without<MemberCard>)>,
+) {
+    let Some(local) = socket.id() else {
+        return;
+    };
+    if !lobby.drop_in || (!lobby.is_changed() && !history.is_changed() && !locale.is_changed()) {
+        return;
+    }
+    let picking = lobby.is_host(local) && lobby.lineup.is_none();
+
+    let current = panels.get_single().ok();
+    if current.is_none_or(|(_, panel)| panel.shown != lobby.members || panel.picking != picking) {
+        if let Some((entity, _)) = current {
+            commands.entity(entity).despawn_recursive();
+        }
+        spawn_lobby_panel(&mut commands, &lobby, &history, local, picking, &locale);
+        return;
+    }
+
+    for (mut text, MemberCard(peer)) in labels.iter_mut() {
+        text.0 = member_label(&lobby, &history, local, *peer, &locale);
+    }
+    for mut text in start_labels.iter_mut() {
+        text.0 = start_label(&lobby, &locale);
+    }
+}
+
+fn start_label(lobby: &Lobby, locale: &Locale) -> String {
+    if lobby.selected.len() == 2 {
+        locale.get("lobby.start").to_string()
+    } else {
+        locale.get("lobby.pick_two").to_string()
+    }
+}
+
+fn spawn_lobby_panel(
+    commands: &mut Commands,
+    lobby: &Lobby,
+    history: &SessionHistory,
+    local: PeerId,
+    picking: bool,
+    locale: &Locale,
+) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                left: Val::Px(40.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            LobbyPanel { shown: lobby.members.clone(), picking },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(locale.get("lobby.title")),
+                TextFont {
+                    font_size: 30.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            for (order, peer) in lobby.members.iter().enumerate() {
+                let label = member_label(lobby, history, local, *peer, locale);
+                if picking {
+                    parent
+                        .spawn((menu_button(order as u32 + 1), MemberCard(*peer)))
+                        .with_children(|parent| {
+                            parent.spawn((button_text(label), MemberCard(*peer)));
+                        });
+                } else {
+                    parent.spawn((button_text(label), MemberCard(*peer)));
+                }
+            }
+            if picking {
+                parent
+                    .spawn((menu_button(lobby.members.len() as u32 + 1), StartButton))
+                    .with_children(|parent| {
+                        parent.spawn((button_text(start_label(lobby, locale)), StartButton));
+                    });
+            }
+        });
+}
+
+fn cleanup_lobby_panel(mut commands: Commands, panels: Query<Entity, With<LobbyPanel>>) {
+    for entity in panels.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Each player says once per match what they'd rather play with, as set in the settings
+fn send_delay_stance(mut socket: ResMut<MatchboxSocket>, mut lobby: ResMut<Lobby>, settings: Res<Settings>) {
+    let Some(opponent) = socket.id().and_then(|local| lobby.lineup.as_ref()?.opponent_of(local)) else {
+        return;
+    };
+    if lobby.delay_sent.is_some() {
+        return;
+    }
+    let frames = settings.online_input_delay.map(|frames| frames.clamp(1, MAX_INPUT_DELAY));
+    send(&mut socket, opponent, &LobbyMessage::DelayStance { frames });
+    lobby.delay_sent = Some(frames);
+}
+
+fn answer_delay(
+    mut activated: EventReader<MenuActivated>,
+    buttons: Query<&DelayButton>,
+    mut socket: ResMut<MatchboxSocket>,
+    mut lobby: ResMut<Lobby>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        let (Ok(button), Some(frames)) = (buttons.get(*entity), lobby.delay_offer()) else {
+            continue;
+        };
+        let Some(opponent) = socket.id().and_then(|local| lobby.lineup.as_ref()?.opponent_of(local)) else {
+            continue;
+        };
+        let accepted = *button == DelayButton::Accept;
+        send(&mut socket, opponent, &LobbyMessage::DelayAnswer { frames, accepted });
+        lobby.delay_answer = Some(accepted);
+    }
+}
+
+fn delay_label(lobby: &Lobby, opponent: PeerId, locale: &Locale) -> String {
+    let name = lobby.name(opponent);
+    if let Some(frames) = lobby.delay_offer() {
+        return locale.format("lobby.delay_offered", &[("name", &name), ("frames", &frames)]);
+    }
+    if let (Some(Some(frames)), None) = (lobby.delay_sent, lobby.delay_agreed()) {
+        return locale.format("lobby.delay_proposed", &[("name", &name), ("frames", &frames)]);
+    }
+    let frames = lobby.input_delay(opponent).unwrap_or_else(|| input_delay(lobby.rtt_ms(opponent)));
+    let ms = (frames as f32 * FRAME_MS).round();
+    match lobby.delay_agreed() {
+        Some(Some(_)) => locale.format("lobby.delay_agreed", &[("frames", &frames), ("ms", &ms), ("name", &name)]),
+        _ => locale.format("lobby.delay", &[("frames", &frames), ("ms", &ms)]),
+    }
+}
+
+// Rebuilt when an answer is wanted or no longer is, so the buttons only show while they count
+fn update_delay_panel(
+    mut commands: Commands,
+    mut socket: ResMut<MatchboxSocket>,
+    lobby: Res<Lobby>,
+    locale: Res<Locale>,
+    panels: Query<Entity, With<DelayPanel>>,
+    buttons: Query<(), With<DelayButton>>,
+    mut texts: Query<&mut Text, With<DelayText>>,
+) {
+    let opponent = socket.id().and_then(|local| lobby.lineup.as_ref()?.opponent_of(local));
+    let Some(opponent) = opponent else {
+        for entity in panels.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    };
+    let asking = lobby.delay_offer().is_some();
+    let current = panels.get_single().ok();
+    let showing_buttons = !buttons.is_empty();
+    if current.is_none() || asking != showing_buttons {
+        if let Some(entity) = current {
+            commands.entity(entity).despawn_recursive();
+        }
+        spawn_delay_panel(&mut commands, delay_label(&lobby, opponent, &locale), asking, &locale);
+        return;
+    }
+    if lobby.is_changed() || locale.is_changed() {
+        for mut text in texts.iter_mut() {
+            text.0 = delay_label(&lobby, opponent, &locale);
+        }
+    }
+}
+
+fn spawn_delay_panel(commands: &mut Commands, label: String, asking: bool, locale: &Locale) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(60.0),
+                left: Val::Px(0.0),
+                right: Val::Px(0.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            DelayPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(label),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                DelayText,
+            ));
+            if !asking {
+                return;
+            }
+            parent
+                .spawn(Node { flex_direction: FlexDirection::Row, ..default() })
+                .with_children(|parent| {
+                    for (order, (button, key)) in
+                        [(DelayButton::Accept, "lobby.accept"), (DelayButton::Decline, "lobby.decline")].into_iter().enumerate()
+                    {
+                        parent.spawn((menu_button(50 + order as u32), button)).with_children(|parent| {
+                            parent.spawn(button_text(locale.get(key)));
+                        });
+                    }
+                });
+        });
+}
+
+fn cleanup_delay_panel(mut commands: Commands, panels: Query<Entity, With<DelayPanel>>) {
+    for entity in panels.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
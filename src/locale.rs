@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use crate::settings::Settings;
+
+pub struct LocalePlugin;
+
+const LOCALE_DIR: &str = "assets/locale";
+const FALLBACK_LANGUAGE: &str = "en";
+// Every language with a file in the locale folder, in the order the settings menu cycles them
+pub const LANGUAGES: [&str; 2] = ["en", "de"];
+
+// Key-value strings for the current language, with English underneath for missing keys
+#[derive(Resource, Debug, Default)]
+pub struct Locale {
+    pub language: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+// Text that is a plain lookup, refreshed automatically when the language changes.
+// Labels with values in them are formatted by the system that owns them instead
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Localized(pub &'static str);
+
+impl Locale {
+    fn path_for(language: &str) -> PathBuf {
+        PathBuf::from(LOCALE_DIR).join(format!("{language}.ron"))
+    }
+
+    fn read(language: &str) -> HashMap<String, String> {
+        let path = Self::path_for(language);
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| ron::from_str(&text).map_err(|err| err.to_string()));
+        parsed.unwrap_or_else(|err| {
+            warn!("failed to load {}: {err}", path.display());
+            HashMap::new()
+        })
+    }
+
+    pub fn load(language: &str) -> Self {
+        Self {
+            language: language.to_string(),
+            strings: Self::read(language),
+            fallback: if language == FALLBACK_LANGUAGE { HashMap::new() } else { Self::read(FALLBACK_LANGUAGE) },
+        }
+    }
+
+    // Falls back to English, then to the key itself so a missing string is obvious but harmless
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map(String::as_str)
+            .unwrap_or(key)
+    }
+
+    // Fills {name} placeholders in the looked up string
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let mut text = self.get(key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{name}}}"), &value.to_string());
+        }
+        text
+    }
+}
+
+impl Plugin for LocalePlugin {
+    fn build(&self, app: &mut App) {
+        let language = app
+            .world()
+            .get_resource::<Settings>()
+            .map(|settings| settings.language.clone())
+            .unwrap_or_else(|| FALLBACK_LANGUAGE.to_string());
+
+        app.insert_resource(Locale::load(&language))
+            .add_systems(Update, (follow_language_setting, refresh_localized).chain());
+    }
+}
+
+fn follow_language_setting(settings: Res<Settings>, mut locale: ResMut<Locale>) {
+    if settings.is_changed() && settings.language != locale.language {
+        *locale = Locale::load(&settings.language);
+    }
+}
+
+fn refresh_localized(locale: Res<Locale>, mut texts: Query<(Ref<Localized>, &mut Text)>) {
+    for (localized, mut text) in texts.iter_mut() {
+        if locale.is_changed() || localized.is_added() {
+            text.0 = locale.get(localized.0).to_string();
+        }
+    }
+}
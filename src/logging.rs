@@ -0,0 +1,195 @@
+use bevy::log::tracing_subscriber::layer::{Context, Layer};
+use bevy::log::{BoxedLayer, DEFAULT_FILTER};
+use bevy::prelude::*;
+use bevy::utils::tracing::field::{Field, Visit};
+use bevy::utils::tracing::{Event, Subscriber};
+use bevy_ggrs::{RollbackFrameCount, Session};
+use bevy_matchbox::prelude::PeerId;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::GameState;
+use crate::input::Config;
+use crate::rng::MatchSeed;
+use crate::settings::Settings;
+
+pub struct LoggingPlugin;
+
+const LOG_DIR: &str = "logs";
+const APP_LOG: &str = "logs/app.log";
+const NETWORK_STATS_INTERVAL: Duration = Duration::from_secs(5);
+// Lines kept in memory for crash reports
+const RECENT_LINES: usize = 400;
+
+// Context stamped on every line, so a playtester's file can be lined up with the other
+// peer's. Statics because the tracing layer lives outside the ECS
+static SESSION_ID: AtomicU64 = AtomicU64::new(0);
+static FRAME: AtomicI32 = AtomicI32::new(-1);
+static LOG_FILE: Mutex<Option<std::fs::File>> = Mutex::new(None);
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+// Writes every event that gets past the log filter into the current log file, and keeps
+// the last few lines around for the crash handler
+struct FileLayer;
+
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for FileLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let metadata = event.metadata();
+        let mut line = format!("{millis} {:>5} ", metadata.level());
+        let session = SESSION_ID.load(Ordering::Relaxed);
+        if session != 0 {
+            let _ = write!(line, "[session={session:016x} frame={}] ", FRAME.load(Ordering::Relaxed));
+        }
+        let _ = writeln!(line, "{}: {}{}", metadata.target(), visitor.message, visitor.fields);
+
+        if let Ok(mut file) = LOG_FILE.lock()
+            && let Some(file) = file.as_mut()
+        {
+            let _ = file.write_all(line.as_bytes());
+        }
+        if let Ok(mut recent) = RECENT.lock() {
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line);
+        }
+    }
+}
+
+// Also called from the panic hook, so a lock poisoned by the panicking thread is still read
+pub fn recent_lines() -> String {
+    RECENT.lock().unwrap_or_else(PoisonError::into_inner).iter().map(String::as_str).collect()
+}
+
+// Seed of the running session (0 outside a match) and the last simulated frame
+pub fn session_context() -> (u64, i32) {
+    (SESSION_ID.load(Ordering::Relaxed), FRAME.load(Ordering::Relaxed))
+}
+
+fn open_log(path: &str) {
+    if let Err(err) = std::fs::create_dir_all(LOG_DIR) {
+        eprintln!("can't create {LOG_DIR}: {err}");
+        return;
+    }
+    match std::fs::File::create(path) {
+        Ok(file) => {
+            if let Ok(mut current) = LOG_FILE.lock() {
+                *current = Some(file);
+            }
+        }
+        Err(err) => eprintln!("can't open log file {path}: {err}"),
+    }
+}
+
+// Hooked into LogPlugin::custom_layer. There's no filesystem on the web, so there the layer
+// only fills the crash report buffer
+pub fn file_layer(_app: &mut App) -> Option<BoxedLayer> {
+    if !cfg!(target_arch = "wasm32") {
+        open_log(APP_LOG);
+    }
+    Some(Box::new(FileLayer))
+}
+
+// Gameplay chatter (jumps, landings) is logged at debug and only kept with verbose logging on
+pub fn log_filter(settings: &Settings) -> String {
+    if settings.verbose_logging {
+        format!("{DEFAULT_FILTER},{}=debug", env!("CARGO_CRATE_NAME"))
+    } else {
+        DEFAULT_FILTER.to_string()
+    }
+}
+
+// Starts a log file for the match, named after the seed both peers share
+pub fn begin_session(seed: MatchSeed, peers: &[PeerId]) {
+    if !cfg!(target_arch = "wasm32") {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        open_log(&format!("{LOG_DIR}/session-{started}-{:016x}.log", seed.0));
+    }
+    SESSION_ID.store(seed.0.max(1), Ordering::Relaxed);
+    FRAME.store(0, Ordering::Relaxed);
+
+    let mut peers: Vec<String> = peers.iter().map(|peer| peer.0.to_string()).collect();
+    peers.sort();
+    info!("session started, seed {:016x}, peers [{}]", seed.0, peers.join(", "));
+}
+
+impl Plugin for LoggingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, log_network_stats.run_if(resource_exists::<Session<Config>>))
+            .add_systems(OnExit(GameState::InGame), end_session);
+    }
+}
+
+// First thing in the rollback schedule, so lines logged while resimulating carry the frame
+// being resimulated rather than the latest one
+pub fn track_frame(frame: Res<RollbackFrameCount>) {
+    FRAME.store(frame.0, Ordering::Relaxed);
+}
+
+fn log_network_stats(session: Res<Session<Config>>, time: Res<Time>, mut since_last: Local<Duration>) {
+    *since_last += time.delta();
+    if *since_last < NETWORK_STATS_INTERVAL {
+        return;
+    }
+    *since_last = Duration::ZERO;
+
+    let Session::P2P(session) = session.as_ref() else {
+        return;
+    };
+    for handle in session.remote_player_handles() {
+        if let Ok(stats) = session.network_stats(handle) {
+            info!(
+                target: "netstats",
+                handle,
+                ping_ms = stats.ping,
+                send_queue = stats.send_queue_len,
+                kbps_sent = stats.kbps_sent,
+                local_frames_behind = stats.local_frames_behind,
+                remote_frames_behind = stats.remote_frames_behind,
+                "network stats"
+            );
+        }
+    }
+}
+
+fn end_session() {
+    if SESSION_ID.swap(0, Ordering::Relaxed) == 0 {
+        return;
+    }
+    info!("session ended");
+    FRAME.store(-1, Ordering::Relaxed);
+    if cfg!(target_arch = "wasm32") {
+        return;
+    }
+    match std::fs::OpenOptions::new().append(true).open(APP_LOG) {
+        Ok(file) => {
+            if let Ok(mut current) = LOG_FILE.lock() {
+                *current = Some(file);
+            }
+        }
+        Err(err) => warn!("can't reopen {APP_LOG}: {err}"),
+    }
+}
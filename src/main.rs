@@ -1,27 +1,166 @@
+
 use bevy::prelude::*;
 use bevy::render::settings::{Backends, WgpuSettings};
 mod main_menu;
 mod game;
+mod gamepads;
+mod achievements;
+mod attract;
+mod audit;
+mod gameplay_constants;
+mod ball;
+mod blocks;
+mod bracket;
+mod build_info;
+mod camera;
+mod character_material;
+mod character_select;
+mod confirmed;
+mod crash;
+mod daily;
+mod determinism;
+mod drill;
+mod editor;
+mod error_screen;
+mod finale;
+mod guardrails;
+mod handicap;
+mod hit_numbers;
 mod input;
+mod join_menu;
+mod keybindings;
+mod knockback;
+mod latency;
+mod level;
+mod level_logic;
+#[cfg(feature = "level-scripts")]
+mod level_script;
+mod level_sync;
+mod lobby;
+mod locale;
+mod logging;
+mod pool;
+mod presentation;
+mod race;
+mod profiling;
+mod match_over;
+mod match_schedule;
+mod matchmaking;
+mod menu_nav;
+mod minimap;
+mod music;
+mod network_feel;
+mod notifications;
+mod outline;
+mod pause_menu;
+mod perspective;
+mod quick_settings;
+mod reconnect;
+mod regions;
+mod rng;
+mod rollback_budget;
+mod rules;
+mod scenario;
+mod settings;
+mod shared_channel;
+mod shortcuts;
+mod skins;
+mod sound;
+mod snapshots;
+mod speed_limit;
+mod stats;
+mod scoring;
+mod serve;
+mod session;
+mod session_history;
+mod strike;
+mod text_input;
+mod training;
+mod ui_layout;
+mod watchdog;
+#[cfg(target_arch = "wasm32")]
+mod web;
 
 #[derive(States, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
 pub enum GameState {
     #[default]
     MainMenu,
     InGame,
+    Editor,
+    Settings,
+    // Rebinding the keyboard, reached from the settings
+    Controls,
+    // What has been earned, and the colors it unlocked
+    Achievements,
+    // Name and room code entry before going online
+    JoinOnline,
+    // Picking the character to play online, reached from the join screen
+    CharacterSelect,
+    // Something made the match impossible, see error_screen::ErrorMessage
+    Error,
+    // Passed through between two matches of a winner-stays-on rotation, straight back to InGame
+    NextMatch,
+    // Entering the players of a couch tournament
+    TournamentSetup,
+    // The tournament's bracket, shown between its matches
+    Bracket,
+    // Who won, with a rematch or back to the menu. See match_over
+    MatchOver,
 }
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(bevy::render::RenderPlugin {
+    crash::install_panic_hook();
+    #[cfg(target_arch = "wasm32")]
+    web::install_page_handlers();
+    // Loaded up front because the log filter can't change once logging has started
+    let settings = settings::Settings::load();
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(bevy::render::RenderPlugin {
             render_creation: WgpuSettings {
                 backends: Some(Backends::VULKAN),
                 ..default()
             }.into(),
             ..default()
+        }).set(WindowPlugin {
+            // Only used on the web, where the canvas follows the page size and keeps key
+            // presses from reaching the page
+            primary_window: Some(Window {
+                fit_canvas_to_parent: true,
+                prevent_default_event_handling: true,
+                ..default()
+            }),
+            ..default()
+        }).set(if settings.pixel_art {
+            ImagePlugin::default_nearest()
+        } else {
+            ImagePlugin::default_linear()
+        }).set(bevy::log::LogPlugin {
+            filter: logging::log_filter(&settings),
+            custom_layer: logging::file_layer,
+            ..default()
         }))
+        .insert_resource(settings)
+        .insert_resource(build_info::BuildInfo::CURRENT)
+        .add_plugins((logging::LoggingPlugin, crash::CrashPlugin))
         .init_state::<GameState>()
-        .add_plugins(main_menu::MainMenuPlugin)
-        .add_plugins(game::GamePlugin)
-        .run();
+        .add_plugins((settings::SettingsPlugin, keybindings::KeybindingsPlugin))
+        .add_plugins((locale::LocalePlugin, ui_layout::UiLayoutPlugin))
+        .add_plugins((menu_nav::MenuNavPlugin, notifications::NotificationPlugin))
+        .add_plugins((text_input::TextInputPlugin, main_menu::MainMenuPlugin, join_menu::JoinMenuPlugin))
+        .add_plugins((character_select::CharacterSelectPlugin, bracket::BracketPlugin))
+        .add_plugins((attract::AttractPlugin, achievements::AchievementPlugin))
+        .add_plugins((game::GamePlugin, level::LevelPlugin))
+        .add_plugins(editor::EditorPlugin)
+        .add_plugins((error_screen::ErrorScreenPlugin, match_over::MatchOverPlugin))
+        .add_plugins((audit::AuditPlugin, shortcuts::ShortcutPlugin, gamepads::GamepadPlugin));
+    // After the plugins, so the log is up to report a bad input file
+    if let Some(run) = audit::requested() {
+        app.insert_resource(run).insert_resource(game::SessionMode::Local);
+    } else if let Some(mode) = game::requested_session() {
+        app.insert_resource(mode).add_systems(Startup, game::skip_menus);
+    }
+    #[cfg(target_arch = "wasm32")]
+    app.add_plugins(web::WebPlugin);
+    app.run();
 }
\ No newline at end of file
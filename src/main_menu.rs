@@ -1,22 +1,40 @@
 use bevy::prelude::*;
 use crate::GameState;
+use crate::build_info::BuildInfo;
+use crate::level::{ActiveLevel, LevelDef};
+use crate::locale::{Locale, Localized};
+use crate::notifications::Notify;
+use crate::rules::{ActiveMutators, GameModes};
+use crate::menu_nav::{button_text, menu_button, Focusable, MenuActivated, NORMAL_BUTTON};
+use crate::ui_layout::{full_screen, SafeArea};
 
 pub struct MainMenuPlugin;
 
 #[derive(Component)]
 struct MainMenu;
 
+#[derive(Component)]
+struct LevelLabel;
+
 impl Plugin for MainMenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_main_menu)
-           .add_systems(Update, button_system)
-           .add_systems(OnEnter(GameState::InGame), cleanup_main_menu);
+        app.add_systems(OnEnter(GameState::MainMenu), setup_main_menu)
+           .add_systems(Update, (button_system, update_level_label))
+           .add_systems(OnExit(GameState::MainMenu), cleanup_main_menu);
     }
 }
 
 #[derive(Component)]
 enum MenuButtonAction {
     StartGame,
+    // By its index in GameModes
+    Mode(usize),
+    // Sets up a couch tournament, see bracket
+    Tournament,
+    CycleLevel,
+    Editor,
+    Settings,
+    Achievements,
     Quit,
 }
 
@@ -29,20 +47,50 @@ fn cleanup_main_menu(
     }
 }
 
-fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup_main_menu(
+    mut commands: Commands,
+    _asset_server: Res<AssetServer>,
+    active_level: Res<ActiveLevel>,
+    locale: Res<Locale>,
+    (build, modes): (Res<BuildInfo>, Res<GameModes>),
+) {
+    // Registered modes and the tournament go between online play and the level picker
+    let after_modes = 2 + modes.iter().count() as u32;
     commands.spawn((Camera2d, MainMenu));
 
+    // Which build this is, in the corner for screenshots of bugs
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::FlexEnd,
+                align_items: AlignItems::FlexEnd,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..full_screen()
+            },
+            SafeArea,
+            MainMenu,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(build.label()),
+                TextFont {
+                    font_size: 14.0,
+                    ..default()
+                },
+                TextColor(Color::srgba(0.9, 0.9, 0.9, 0.5)),
+            ));
+        });
+
     commands
         .spawn((
             Node {
-                width: Val::Percent(100.0),
-                height: Val::Percent(100.0),
                 justify_content: JustifyContent::Center,
                 align_items: AlignItems::Center,
                 flex_direction: FlexDirection::Column,
-                ..default()
+                ..full_screen()
             },
             BackgroundColor(Color::NONE),
+            SafeArea,
             MainMenu,
         ))
         .with_children(|parent| {
@@ -58,12 +106,14 @@ fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
                         align_items: AlignItems::Center,
                         ..default()
                     },
-                    BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                    BackgroundColor(NORMAL_BUTTON),
                     MenuButtonAction::StartGame,
+                    Focusable::new(0),
                 ))
                 .with_children(|parent| {
                     parent.spawn((
-                        Text::new("Start Game"),
+                        Text::default(),
+                        Localized("menu.start_game"),
                         TextFont {
                             //font: asset_server.load("fonts/FiraSans-Bold.ttf"),
                             font_size: 30.0,
@@ -73,6 +123,91 @@ fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
                     ));
                 });
 
+            // Offline modes, training and time trials to begin with
+            for (index, mode) in modes.iter().enumerate() {
+                parent
+                    .spawn((menu_button(1 + index as u32), MenuButtonAction::Mode(index)))
+                    .with_children(|parent| {
+                        parent.spawn((button_text(""), Localized(mode.label_key)));
+                    });
+            }
+
+            parent
+                .spawn((menu_button(after_modes - 1), MenuButtonAction::Tournament))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("menu.tournament")));
+                });
+
+            // Level select button, cycles through the saved levels
+            parent
+                .spawn((
+                    Button,
+                    Node {
+                        width: Val::Px(200.0),
+                        height: Val::Px(65.0),
+                        margin: UiRect::all(Val::Px(20.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    BackgroundColor(NORMAL_BUTTON),
+                    MenuButtonAction::CycleLevel,
+                    Focusable::new(after_modes),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::new(locale.format("menu.level", &[("name", &active_level.0.name)])),
+                        TextFont {
+                            //font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                            font_size: 24.0,
+                            ..default()
+                        },
+                        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                        LevelLabel,
+                    ));
+                });
+
+            // Level editor button
+            parent
+                .spawn((
+                    Button,
+                    Node {
+                        width: Val::Px(200.0),
+                        height: Val::Px(65.0),
+                        margin: UiRect::all(Val::Px(20.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    BackgroundColor(NORMAL_BUTTON),
+                    MenuButtonAction::Editor,
+                    Focusable::new(after_modes + 1),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::default(),
+                        Localized("menu.editor"),
+                        TextFont {
+                            //font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                            font_size: 30.0,
+                            ..default()
+                        },
+                        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                    ));
+                });
+
+            parent
+                .spawn((menu_button(after_modes + 2), MenuButtonAction::Settings))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("menu.settings")));
+                });
+
+            parent
+                .spawn((menu_button(after_modes + 3), MenuButtonAction::Achievements))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("menu.achievements")));
+                });
+
             // Quit button
             parent
                 .spawn((
@@ -85,12 +220,14 @@ fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
                         align_items: AlignItems::Center,
                         ..default()
                     },
-                    BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                    BackgroundColor(NORMAL_BUTTON),
                     MenuButtonAction::Quit,
+                    Focusable::new(after_modes + 4),
                 ))
                 .with_children(|parent| {
                     parent.spawn((
-                        Text::new("Quit"),
+                        Text::default(),
+                        Localized("menu.quit"),
                         TextFont {
                             //font: asset_server.load("fonts/FiraSans-Bold.ttf"),
                             font_size: 30.0,
@@ -103,23 +240,77 @@ fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
 }
 
 fn button_system(
-    mut interaction_query: Query<
-        (&Interaction, &MenuButtonAction),
-        (Changed<Interaction>, With<Button>),
-    >,
+    mut commands: Commands,
+    mut activated: EventReader<MenuActivated>,
+    actions: Query<&MenuButtonAction>,
     mut next_state: ResMut<NextState<GameState>>,
     mut exit: EventWriter<bevy::app::AppExit>,
+    mut active_level: ResMut<ActiveLevel>,
+    (locale, mut notify, modes): (Res<Locale>, EventWriter<Notify>, Res<GameModes>),
 ) {
-    for (interaction, menu_button_action) in interaction_query.iter_mut() {
-        if *interaction == Interaction::Pressed {
-            match menu_button_action {
-                MenuButtonAction::StartGame => {
-                    next_state.set(GameState::InGame);
-                }
-                MenuButtonAction::Quit => {
-                    exit.send(bevy::app::AppExit::default());
+    for MenuActivated(entity) in activated.read() {
+        let Ok(menu_button_action) = actions.get(*entity) else {
+            continue;
+        };
+        match menu_button_action {
+            MenuButtonAction::StartGame => {
+                next_state.set(GameState::JoinOnline);
+            }
+            MenuButtonAction::Mode(index) => {
+                let Some(mode) = modes.get(*index) else {
+                    continue;
+                };
+                if let Some(reason) = (mode.unplayable)(&active_level.0) {
+                    notify.send(Notify::warning(locale.get(reason)));
+                    continue;
                 }
+                commands.insert_resource(mode.session);
+                commands.insert_resource(ActiveMutators::of(mode));
+                next_state.set(GameState::InGame);
+            }
+            MenuButtonAction::Tournament => {
+                next_state.set(GameState::TournamentSetup);
+            }
+            MenuButtonAction::CycleLevel => {
+                let names = LevelDef::available();
+                // The built-in arena sits before the saved levels in the rotation
+                let next = match names.iter().position(|name| *name == active_level.0.name) {
+                    Some(index) => names.get(index + 1),
+                    None => names.first(),
+                };
+                active_level.0 = match next {
+                    Some(name) => LevelDef::load(name).unwrap_or_else(|err| {
+                        warn!("failed to load level, falling back to default: {err}");
+                        LevelDef::default_arena()
+                    }),
+                    None => LevelDef::default_arena(),
+                };
+            }
+            MenuButtonAction::Editor => {
+                next_state.set(GameState::Editor);
+            }
+            MenuButtonAction::Settings => {
+                next_state.set(GameState::Settings);
+            }
+            MenuButtonAction::Achievements => {
+                next_state.set(GameState::Achievements);
+            }
+            MenuButtonAction::Quit => {
+                exit.send(bevy::app::AppExit::default());
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn update_level_label(
+    active_level: Res<ActiveLevel>,
+    locale: Res<Locale>,
+    mut labels: Query<&mut Text, With<LevelLabel>>,
+) {
+    if !active_level.is_changed() && !locale.is_changed() {
+        return;
+    }
+    for mut text in labels.iter_mut() {
+        text.0 = locale.format("menu.level", &[("name", &active_level.0.name)]);
+    }
+}
@@ -0,0 +1,215 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::MatchboxSocket;
+use crate::GameState;
+use crate::bracket::Tournament;
+use crate::editor::EditorDraft;
+use crate::game::{KeepConnection, SessionMode};
+use crate::lobby::{request_rematch, Lobby};
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated};
+use crate::notifications::Notify;
+use crate::scoring::{PointScored, Score};
+use crate::shortcuts::Shortcut;
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct MatchOverPlugin;
+
+// Long enough for the finale to play out over the arena first
+const RESULT_SECS: f64 = 3.0;
+
+// How the match ended, kept for the screen after the arena and the score are gone
+#[derive(Resource, Clone, Copy, Debug)]
+struct MatchResult {
+    winner: usize,
+    sets: [u32; 2],
+    decided_at: f64,
+}
+
+#[derive(Component)]
+struct MatchOverScreen;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum MatchOverAction {
+    Rematch,
+    Leave,
+}
+
+impl Plugin for MatchOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (record_result, show_result.run_if(resource_exists::<MatchResult>))
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        )
+        // A match left before its result came up doesn't show it at the start of the next one
+        .add_systems(OnEnter(GameState::InGame), forget_result)
+        .add_systems(OnEnter(GameState::MatchOver), setup_match_over)
+        .add_systems(Update, match_over_action.run_if(in_state(GameState::MatchOver)))
+        .add_systems(OnExit(GameState::MatchOver), cleanup_match_over);
+    }
+}
+
+// Matches that end somewhere else don't come here: tournaments go back to their bracket,
+// playtests to the editor, and rooms with someone waiting rotate the winner on
+fn ends_here(
+    mode: SessionMode,
+    tournament: bool,
+    draft: bool,
+    room: (Option<&mut MatchboxSocket>, Option<&Lobby>),
+) -> bool {
+    match mode {
+        SessionMode::Local => !tournament && !draft,
+        SessionMode::SyncTest => true,
+        SessionMode::Online => match room {
+            (Some(socket), Some(lobby)) => {
+                let playing = socket
+                    .id()
+                    .is_some_and(|local| lobby.lineup.as_ref().is_some_and(|lineup| lineup.handle_of(local).is_some()));
+                playing && lobby.waiting().is_empty()
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// Points only arrive once confirmed, so a rolled back winner never ends the match
+fn record_result(
+    mut commands: Commands,
+    mut points: EventReader<PointScored>,
+    (mode, score, time): (Res<SessionMode>, Option<Res<Score>>, Res<Time<Real>>),
+    (tournament, draft): (Option<Res<Tournament>>, Option<Res<EditorDraft>>),
+    mut room: (Option<ResMut<MatchboxSocket>>, Option<Res<Lobby>>),
+) {
+    let Some(point) = points.read().find(|point| point.won_match) else {
+        return;
+    };
+    let room = (room.0.as_deref_mut(), room.1.as_deref());
+    if !ends_here(*mode, tournament.is_some(), draft.is_some(), room) {
+        return;
+    }
+    commands.insert_resource(MatchResult {
+        winner: point.winner,
+        sets: score.map_or([0; 2], |score| score.sets),
+        decided_at: time.elapsed_secs_f64(),
+    });
+}
+
+// Online the connection outlives the arena, the rematch is asked for on the same socket
+fn show_result(
+    mut commands: Commands,
+    result: Res<MatchResult>,
+    mode: Res<SessionMode>,
+    time: Res<Time<Real>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if time.elapsed_secs_f64() - result.decided_at < RESULT_SECS {
+        return;
+    }
+    if *mode == SessionMode::Online {
+        commands.insert_resource(KeepConnection);
+    }
+    next_state.set(GameState::MatchOver);
+}
+
+fn setup_match_over(mut commands: Commands, result: Option<Res<MatchResult>>, locale: Res<Locale>) {
+    let Some(result) = result else {
+        return;
+    };
+    commands.spawn((Camera2d, MatchOverScreen));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..full_screen()
+            },
+            SafeArea,
+            MatchOverScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(locale.format("score.match_won", &[("player", &(result.winner + 1))])),
+                TextFont {
+                    font_size: 40.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            parent.spawn((
+                Text::new(locale.format(
+                    "match_over.sets",
+                    &[("left", &result.sets[0]), ("right", &result.sets[1])],
+                )),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.8, 0.8, 0.8)),
+            ));
+            parent
+                .spawn((menu_button(0), MatchOverAction::Rematch))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("match_over.rematch")));
+                });
+            parent
+                .spawn((menu_button(1), MatchOverAction::Leave, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("match_over.leave")));
+                });
+        });
+}
+
+// Locally there's nobody to ask, online the opponent has to pick it too. F5 still works here
+fn match_over_action(
+    mut commands: Commands,
+    (mut activated, mut shortcuts): (EventReader<MenuActivated>, EventReader<Shortcut>),
+    actions: Query<&MatchOverAction>,
+    mode: Res<SessionMode>,
+    room: (Option<ResMut<MatchboxSocket>>, Option<ResMut<Lobby>>),
+    (locale, mut notify): (Res<Locale>, EventWriter<Notify>),
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let mut picked: Vec<MatchOverAction> =
+        activated.read().filter_map(|MenuActivated(entity)| actions.get(*entity).ok().copied()).collect();
+    if shortcuts.read().any(|shortcut| *shortcut == Shortcut::Rematch) {
+        picked.push(MatchOverAction::Rematch);
+    }
+    let (mut socket, mut lobby) = room;
+    for action in picked {
+        match action {
+            MatchOverAction::Rematch if *mode != SessionMode::Online => next_state.set(GameState::NextMatch),
+            MatchOverAction::Rematch => {
+                let asked = match (socket.as_deref_mut(), lobby.as_deref_mut()) {
+                    (Some(socket), Some(lobby)) => request_rematch(socket, lobby),
+                    _ => false,
+                };
+                if asked {
+                    notify.send(Notify::info(locale.get("toast.rematch_sent")));
+                } else {
+                    notify.send(Notify::warning(locale.get("toast.rematch_unavailable")));
+                }
+            }
+            // Without KeepConnection the socket closes on the way out
+            MatchOverAction::Leave => {
+                commands.remove_resource::<KeepConnection>();
+                next_state.set(GameState::MainMenu);
+            }
+        }
+    }
+}
+
+fn forget_result(mut commands: Commands) {
+    commands.remove_resource::<MatchResult>();
+}
+
+fn cleanup_match_over(mut commands: Commands, query: Query<Entity, With<MatchOverScreen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<MatchResult>();
+}
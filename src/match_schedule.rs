@@ -0,0 +1,390 @@
+use bevy::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+use crate::confirmed::{ConfirmedEventPlugin, ConfirmedWriter};
+use crate::game::Player;
+use crate::determinism::{debug_assert_strictly_ordered, sort_by_rollback};
+use crate::guardrails::SpawnRollbackExt;
+use crate::pool::{self, ParticleBurst, Pooled};
+use crate::presentation::Appearance;
+use crate::locale::Locale;
+use crate::rng::{DeterministicRng, MatchSeed};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct MatchSchedulePlugin;
+
+// The schedule loops after this many frames so long matches keep getting events
+const SCHEDULE_LENGTH_FRAMES: u32 = 60 * 180;
+const FIRST_EVENT_FRAME: u32 = 60 * 5;
+const ITEM_LIFETIME_FRAMES: u32 = 60 * 8;
+const PICKUP_RADIUS: f32 = 0.6;
+const GUST_STRENGTH: f32 = 3.0;
+const SPEED_BOOST_FRAMES: u32 = 60 * 4;
+const PREVIEW_SECONDS: f32 = 5.0;
+const PREVIEW_ENTRIES: usize = 6;
+// Items live for 8 seconds and spawn at most every 8, so a handful of slots is plenty
+const ITEM_POOL_SIZE: u16 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemKind {
+    ExtraJump,
+    SpeedBoost,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ItemSpawn {
+    pub frame: u32,
+    pub kind: ItemKind,
+    pub position: Vec2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HazardKind {
+    Gust { direction: i8 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HazardWindow {
+    pub start_frame: u32,
+    pub duration: u32,
+    pub kind: HazardKind,
+}
+
+// Everything that happens on a timer during a match, generated up front from the seed
+#[derive(Resource, Clone, Debug, Default)]
+pub struct MatchSchedule {
+    pub items: Vec<ItemSpawn>,
+    pub hazards: Vec<HazardWindow>,
+    // Which set this schedule was generated for, see scoring::reroll_stage
+    pub set: u32,
+}
+
+// A player picked an item up, sent once its frame is confirmed
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ItemCollected {
+    pub kind: ItemKind,
+    pub position: Vec2,
+}
+
+#[derive(Component, Clone)]
+pub struct Item {
+    kind: ItemKind,
+    expires_at: i32,
+}
+
+#[derive(Component)]
+struct SchedulePreview {
+    timer: Timer,
+}
+
+#[derive(Component)]
+struct HazardBanner;
+
+impl ItemKind {
+    fn label_key(self) -> &'static str {
+        match self {
+            ItemKind::ExtraJump => "item.extra_jump",
+            ItemKind::SpeedBoost => "item.speed_boost",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            ItemKind::ExtraJump => Color::srgb(0.3, 0.8, 1.0),
+            ItemKind::SpeedBoost => Color::srgb(1.0, 0.8, 0.2),
+        }
+    }
+}
+
+impl MatchSchedule {
+    pub fn generate(seed: MatchSeed) -> Self {
+        let mut rng = DeterministicRng::new(seed.0);
+        let mut schedule = MatchSchedule::default();
+
+        let mut frame = FIRST_EVENT_FRAME;
+        while frame < SCHEDULE_LENGTH_FRAMES {
+            let kind = if rng.chance(50) { ItemKind::ExtraJump } else { ItemKind::SpeedBoost };
+            // Mirror the position half the time so neither side is favoured
+            let x = rng.range_f32(1.0, 7.0) * if rng.chance(50) { 1.0 } else { -1.0 };
+            let y = rng.range_f32(-3.0, 2.0);
+            schedule.items.push(ItemSpawn { frame, kind, position: Vec2::new(x, y) });
+            frame += rng.range_u32(60 * 8, 60 * 15);
+        }
+
+        let mut frame = FIRST_EVENT_FRAME + rng.range_u32(60 * 10, 60 * 20);
+        while frame < SCHEDULE_LENGTH_FRAMES {
+            let direction = if rng.chance(50) { 1 } else { -1 };
+            let duration = rng.range_u32(60 * 2, 60 * 4);
+            schedule.hazards.push(HazardWindow {
+                start_frame: frame,
+                duration,
+                kind: HazardKind::Gust { direction },
+            });
+            frame += duration + rng.range_u32(60 * 15, 60 * 30);
+        }
+
+        schedule
+    }
+
+    pub fn active_hazard(&self, frame: i32) -> Option<HazardWindow> {
+        let frame = schedule_frame(frame);
+        self.hazards
+            .iter()
+            .find(|hazard| frame >= hazard.start_frame && frame < hazard.start_frame + hazard.duration)
+            .copied()
+    }
+}
+
+impl Plugin for MatchSchedulePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ConfirmedEventPlugin::<ItemCollected>::default())
+            .rollback_component_with_clone::<Item>()
+            .add_systems(OnEnter(GameState::InGame), prewarm_item_pool)
+            .add_systems(
+                Update,
+                (update_schedule_preview, update_hazard_banner, update_item_visuals, burst_on_pickup)
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_schedule);
+    }
+}
+
+fn schedule_frame(frame: i32) -> u32 {
+    frame.max(0) as u32 % SCHEDULE_LENGTH_FRAMES
+}
+
+fn format_frame(frame: u32) -> String {
+    let seconds = frame / 60;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Item entities exist for the whole match and are switched on when the schedule says so
+fn prewarm_item_pool(mut commands: Commands) {
+    for index in 0..ITEM_POOL_SIZE {
+        commands.spawn_rollback("item_pool", (
+            Item {
+                kind: ItemKind::ExtraJump,
+                expires_at: 0,
+            },
+            Pooled::new(index),
+            Transform::default(),
+            Appearance::new(Sprite {
+                custom_size: Some(Vec2::splat(0.4)),
+                ..default()
+            }),
+            Visibility::Hidden,
+        ));
+    }
+}
+
+pub fn spawn_scheduled_items(
+    schedule: Res<MatchSchedule>,
+    frame: Res<RollbackFrameCount>,
+    mut items: Query<(Entity, &mut Item, &mut Pooled, &mut Transform)>,
+) {
+    let current = schedule_frame(frame.0);
+    for spawn in schedule.items.iter().filter(|spawn| spawn.frame == current) {
+        let Some(entity) = pool::first_free(items.iter().map(|(entity, _, pooled, _)| (entity, pooled))) else {
+            warn!("item pool exhausted, skipping {:?} at frame {}", spawn.kind, frame.0);
+            continue;
+        };
+        let Ok((_, mut item, mut pooled, mut transform)) = items.get_mut(entity) else {
+            continue;
+        };
+
+        item.kind = spawn.kind;
+        item.expires_at = frame.0 + ITEM_LIFETIME_FRAMES as i32;
+        pooled.active = true;
+        transform.translation = spawn.position.extend(0.);
+    }
+}
+
+pub fn collect_items(
+    mut items: Query<(&Rollback, &Item, &mut Pooled, &Transform), Without<Player>>,
+    mut players: Query<(&Transform, &mut Player)>,
+    frame: Res<RollbackFrameCount>,
+    order: Res<RollbackOrdered>,
+    mut collected: ConfirmedWriter<ItemCollected>,
+) {
+    // Sort so both peers resolve a simultaneous pickup in favour of the same player
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(_, player)| player.handle);
+    debug_assert_strictly_ordered("collect_items players", players.iter().map(|(_, player)| player.handle));
+
+    // And the same for two items landing on one player in the same frame
+    let mut items: Vec<_> = items.iter_mut().filter(|(_, _, pooled, _)| pooled.active).collect();
+    sort_by_rollback(&mut items, &order, |(rollback, ..)| **rollback);
+
+    for (_, item, mut pooled, item_transform) in items {
+        if frame.0 >= item.expires_at {
+            pooled.active = false;
+            continue;
+        }
+
+        let item_position = item_transform.translation.truncate();
+        let collector = players.iter_mut().find(|(transform, _)| {
+            transform.translation.truncate().distance(item_position) < PICKUP_RADIUS
+        });
+
+        if let Some((_, player)) = collector {
+            match item.kind {
+                ItemKind::ExtraJump => player.jumps_remaining += 1,
+                ItemKind::SpeedBoost => player.speed_boost_frames = SPEED_BOOST_FRAMES,
+            }
+            info!("Player {} picked up {:?}", player.handle, item.kind);
+            pooled.active = false;
+            collected.send(ItemCollected { kind: item.kind, position: item_position });
+        }
+    }
+}
+
+pub fn apply_hazards(
+    mut players: Query<&mut avian2d::prelude::LinearVelocity, With<Player>>,
+    schedule: Res<MatchSchedule>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let Some(hazard) = schedule.active_hazard(frame.0) else {
+        return;
+    };
+
+    match hazard.kind {
+        HazardKind::Gust { direction } => {
+            for mut velocity in players.iter_mut() {
+                velocity.0.x += direction as f32 * GUST_STRENGTH;
+            }
+        }
+    }
+}
+
+// Shown on the loading screen once the seed is known, so both players see what's coming
+pub fn spawn_schedule_preview(commands: &mut Commands, locale: &Locale, seed: MatchSeed, schedule: &MatchSchedule) {
+    let mut events: Vec<(u32, &str)> = schedule
+        .items
+        .iter()
+        .map(|spawn| (spawn.frame, locale.get(spawn.kind.label_key())))
+        .chain(schedule.hazards.iter().map(|hazard| {
+            let key = match hazard.kind {
+                HazardKind::Gust { direction } if direction > 0 => "preview.gust_right",
+                HazardKind::Gust { .. } => "preview.gust_left",
+            };
+            (hazard.start_frame, locale.get(key))
+        }))
+        .collect();
+    events.sort_by_key(|(frame, _)| *frame);
+
+    let mut lines = vec![locale.format("preview.seed", &[("seed", &format!("{:016x}", seed.0))])];
+    lines.extend(
+        events
+            .into_iter()
+            .take(PREVIEW_ENTRIES)
+            .map(|(frame, label)| format!("{}  {}", format_frame(frame), label)),
+    );
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..full_screen()
+            },
+            SafeArea,
+            SchedulePreview {
+                timer: Timer::from_seconds(PREVIEW_SECONDS, TimerMode::Once),
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(lines.join("\n")),
+                TextFont {
+                    font_size: 24.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+        });
+
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(20.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 30.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.6, 0.9, 1.0)),
+        HazardBanner,
+    ));
+}
+
+fn update_schedule_preview(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut previews: Query<(Entity, &mut SchedulePreview)>,
+) {
+    for (entity, mut preview) in previews.iter_mut() {
+        if preview.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn update_item_visuals(mut items: Query<(&Item, &mut Appearance), Changed<Pooled>>) {
+    for (item, mut appearance) in items.iter_mut() {
+        appearance.sprite.color = item.kind.color();
+    }
+}
+
+// Only for confirmed pickups. Pooled changes on every rollback and when an item expires, so
+// it can't tell a pickup apart
+fn burst_on_pickup(mut collected: EventReader<ItemCollected>, mut bursts: EventWriter<ParticleBurst>) {
+    for pickup in collected.read() {
+        bursts.send(ParticleBurst {
+            position: pickup.position,
+            color: pickup.kind.color(),
+            count: 8,
+        });
+    }
+}
+
+fn update_hazard_banner(
+    schedule: Option<Res<MatchSchedule>>,
+    frame: Res<RollbackFrameCount>,
+    locale: Res<Locale>,
+    mut banners: Query<&mut Text, With<HazardBanner>>,
+) {
+    let Some(schedule) = schedule else {
+        return;
+    };
+
+    let label = match schedule.active_hazard(frame.0).map(|hazard| hazard.kind) {
+        Some(HazardKind::Gust { direction }) if direction > 0 => locale.get("hazard.gust_right"),
+        Some(HazardKind::Gust { .. }) => locale.get("hazard.gust_left"),
+        None => "",
+    };
+
+    for mut text in banners.iter_mut() {
+        if text.0 != label {
+            text.0 = label.to_string();
+        }
+    }
+}
+
+type ScheduleEntities = Or<(With<SchedulePreview>, With<HazardBanner>, With<Item>)>;
+
+fn cleanup_schedule(
+    mut commands: Commands,
+    query: Query<Entity, ScheduleEntities>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<MatchSchedule>();
+    commands.remove_resource::<MatchSeed>();
+}
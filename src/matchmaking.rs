@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use crate::GameState;
+use crate::error_screen::ErrorMessage;
+use crate::level_sync::LevelSync;
+use crate::lobby::{Lineup, Lobby};
+use crate::locale::Locale;
+use crate::notifications::Notify;
+use crate::regions::QuickPlay;
+use crate::settings::Settings;
+use crate::shared_channel::SharedChannel;
+
+pub struct MatchmakingPlugin;
+
+// Longest the signaling server gets to hand out a peer id
+const SIGNALING_TIMEOUT_SECS: f64 = 15.0;
+// Longest the lineup gets to agree on builds, the level and the input delay
+const METADATA_TIMEOUT_SECS: f64 = 30.0;
+// Quick play waits this long for someone in a region before trying the next one
+const REGION_TIMEOUT_SECS: f64 = 20.0;
+
+// Where an online match is on its way in. Peers leaving can send it back a step, so any
+// stage can follow any other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchmakingState {
+    // No peer id from the matchbox server yet
+    ConnectingToSignaling,
+    // Connected, the lobby hasn't picked who plays yet
+    WaitingForPeers,
+    // Checking builds, agreeing on the level and settling the input delay with the lineup
+    ExchangingMetadata,
+    // All agreed, the GGRS session starts once the level is in, see game::start_online_session
+    Starting,
+}
+
+// Present from opening the socket until the session starts
+#[derive(Resource, Debug)]
+pub struct Matchmaking {
+    state: MatchmakingState,
+    // Real seconds when the current stage began
+    since: f64,
+}
+
+// Text of the waiting screen, saying which stage it's at
+#[derive(Component)]
+pub struct MatchmakingStatus;
+
+impl MatchmakingState {
+    fn timeout(self) -> Option<f64> {
+        match self {
+            MatchmakingState::ConnectingToSignaling => Some(SIGNALING_TIMEOUT_SECS),
+            MatchmakingState::ExchangingMetadata => Some(METADATA_TIMEOUT_SECS),
+            // Strangers can take a while to show up, the player cancels when they've had enough
+            MatchmakingState::WaitingForPeers | MatchmakingState::Starting => None,
+        }
+    }
+
+    fn label_key(self) -> &'static str {
+        match self {
+            MatchmakingState::ConnectingToSignaling => "matchmaking.connecting",
+            MatchmakingState::WaitingForPeers => "matchmaking.waiting",
+            MatchmakingState::ExchangingMetadata => "matchmaking.exchanging",
+            MatchmakingState::Starting => "matchmaking.starting",
+        }
+    }
+
+    fn timeout_key(self) -> &'static str {
+        match self {
+            MatchmakingState::ConnectingToSignaling => "error.signaling_timeout",
+            _ => "error.handshake_timeout",
+        }
+    }
+}
+
+impl Matchmaking {
+    pub fn new(now: f64) -> Self {
+        Self { state: MatchmakingState::ConnectingToSignaling, since: now }
+    }
+
+    pub fn state(&self) -> MatchmakingState {
+        self.state
+    }
+
+    fn enter(&mut self, state: MatchmakingState, now: f64) {
+        if self.state != state {
+            info!("matchmaking: {:?} -> {state:?}", self.state);
+            self.state = state;
+            self.since = now;
+        }
+    }
+}
+
+impl Plugin for MatchmakingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (advance_matchmaking, update_status)
+                .chain()
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<Matchmaking>)
+                .run_if(resource_exists::<MatchboxSocket>)
+                .run_if(resource_exists::<LevelSync>)
+                .run_if(resource_exists::<Lobby>),
+        )
+        .add_systems(OnExit(GameState::InGame), end_matchmaking);
+    }
+}
+
+// Whether the lineup has agreed on everything, or why it never will
+fn metadata_agreed(
+    local: PeerId,
+    lineup: &Lineup,
+    lobby: &Lobby,
+    level_sync: &LevelSync,
+    locale: &Locale,
+) -> Result<bool, String> {
+    // Players check each other, spectators the peer whose session they'd watch
+    let counterpart = lineup.opponent_of(local).unwrap_or(lineup.session_host());
+    match lobby.check_build(counterpart, locale) {
+        Some(Ok(())) => {}
+        Some(Err(reason)) => {
+            warn!("refusing to play {counterpart}: {reason}");
+            return Err(reason);
+        }
+        None => return Ok(false), // they haven't said which build they run yet
+    }
+    if let Some(problem) = level_sync.problem() {
+        return Err(locale.format("sync.mismatch", &[("reason", &problem.describe(locale))]));
+    }
+    if !level_sync.is_agreed() {
+        return Ok(false); // both players need to be building the same level first
+    }
+    // Spectators don't send inputs, so there's no delay to settle
+    Ok(lineup.opponent_of(local).is_none_or(|opponent| lobby.input_delay(opponent).is_some()))
+}
+
+pub fn advance_matchmaking(
+    mut commands: Commands,
+    (mut socket, channel): (ResMut<MatchboxSocket>, Option<Res<SharedChannel>>),
+    (lobby, level_sync): (Res<Lobby>, Res<LevelSync>),
+    mut matchmaking: ResMut<Matchmaking>,
+    (time, locale, settings): (Res<Time<Real>>, Res<Locale>, Res<Settings>),
+    (quick_play, mut notify): (Option<ResMut<QuickPlay>>, EventWriter<Notify>),
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let now = time.elapsed_secs_f64();
+    let state = match (socket.id(), lobby.lineup.as_ref()) {
+        (None, _) => MatchmakingState::ConnectingToSignaling,
+        (Some(_), None) => MatchmakingState::WaitingForPeers,
+        (Some(local), Some(lineup)) => match metadata_agreed(local, lineup, &lobby, &level_sync, &locale) {
+            Ok(true) => MatchmakingState::Starting,
+            Ok(false) => MatchmakingState::ExchangingMetadata,
+            Err(reason) => {
+                commands.insert_resource(ErrorMessage(reason));
+                next_state.set(GameState::Error);
+                return;
+            }
+        },
+    };
+    matchmaking.enter(state, now);
+    if let Some(mut quick_play) = quick_play {
+        // Nobody around here, a fresh socket in the next region over
+        if state == MatchmakingState::WaitingForPeers && now - matchmaking.since > REGION_TIMEOUT_SECS {
+            let from = quick_play.region();
+            quick_play.advance();
+            info!("nobody in {from:?} after {REGION_TIMEOUT_SECS} s, trying {:?}", quick_play.region());
+            // The GGRS channel goes with the socket it came from
+            if let Some(channel) = channel {
+                channel.close();
+            }
+            socket.close();
+            commands.remove_resource::<SharedChannel>();
+            let server = settings.matchbox_server.trim_end_matches('/');
+            crate::game::open_socket(&mut commands, quick_play.region().room_url(server), false, &settings);
+            matchmaking.enter(MatchmakingState::ConnectingToSignaling, now);
+            return;
+        }
+        if matches!(state, MatchmakingState::ExchangingMetadata | MatchmakingState::Starting) && !quick_play.reported {
+            quick_play.reported = true;
+            let region = locale.get(quick_play.region().label_key());
+            notify.send(Notify::info(locale.format("toast.quick_play_matched", &[("region", &region)])));
+        }
+    }
+    let Some(limit) = state.timeout() else {
+        return;
+    };
+    if now - matchmaking.since > limit {
+        warn!("matchmaking timed out in {state:?} after {limit} s");
+        commands.insert_resource(ErrorMessage(locale.format(state.timeout_key(), &[("seconds", &limit)])));
+        next_state.set(GameState::Error);
+    }
+}
+
+// Quick play also says which region it's looking in
+fn update_status(
+    matchmaking: Res<Matchmaking>,
+    (locale, quick_play): (Res<Locale>, Option<Res<QuickPlay>>),
+    mut texts: Query<&mut Text, With<MatchmakingStatus>>,
+) {
+    let label = match quick_play {
+        Some(quick_play) if matchmaking.state == MatchmakingState::WaitingForPeers => {
+            let region = locale.get(quick_play.region().label_key());
+            locale.format("matchmaking.quick_play_waiting", &[("region", &region)])
+        }
+        _ => locale.get(matchmaking.state.label_key()).to_string(),
+    };
+    for mut text in texts.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+fn end_matchmaking(mut commands: Commands) {
+    commands.remove_resource::<Matchmaking>();
+    commands.remove_resource::<QuickPlay>();
+}
@@ -0,0 +1,285 @@
+use bevy::prelude::*;
+
+pub struct MenuNavPlugin;
+
+pub const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+pub const FOCUSED_BUTTON: Color = Color::srgb(0.3, 0.3, 0.45);
+// How far the stick has to be pushed before it counts as a step
+const STICK_THRESHOLD: f32 = 0.5;
+
+// Anything the keyboard/gamepad can move focus to. Lower order comes first
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Focusable {
+    pub order: u32,
+}
+
+#[derive(Component)]
+pub struct Focused;
+
+// Focused entries with this take typed text, so only the arrow keys, Tab, Enter and Esc
+// navigate while they have focus
+#[derive(Component, Default)]
+pub struct CapturesKeyboard;
+
+// Gets activated by cancel (Esc, B) no matter where focus is, usually a Back button
+#[derive(Component)]
+pub struct CancelButton;
+
+// While present the keyboard and gamepad leave the menu alone, for screens that want the raw
+// keys for themselves, like binding one. The mouse still works
+#[derive(Resource)]
+pub struct NavPaused;
+
+// Sent for the button that was clicked or confirmed. Menus react to this instead of
+// Interaction so mouse, keyboard and gamepad all go through the same path
+#[derive(Event, Clone, Copy, Debug)]
+pub struct MenuActivated(pub Entity);
+
+// Left/right on the focused entry, for sliders and option pickers. Step is -1 or 1
+#[derive(Event, Clone, Copy, Debug)]
+pub struct MenuAdjusted {
+    pub entity: Entity,
+    pub step: i32,
+}
+
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+enum NavCommand {
+    Previous,
+    Next,
+    Decrease,
+    Increase,
+    Confirm,
+    Cancel,
+}
+
+impl Focusable {
+    pub fn new(order: u32) -> Self {
+        Self { order }
+    }
+}
+
+// The standard menu button, text goes in as a child spawned with button_text
+pub fn menu_button(order: u32) -> impl Bundle {
+    (
+        Button,
+        Node {
+            width: Val::Px(260.0),
+            height: Val::Px(65.0),
+            margin: UiRect::all(Val::Px(12.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        Focusable::new(order),
+    )
+}
+
+pub fn button_text(label: impl Into<String>) -> impl Bundle {
+    (
+        Text::new(label),
+        TextFont {
+            font_size: 26.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+    )
+}
+
+impl Plugin for MenuNavPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MenuActivated>()
+            .add_event::<MenuAdjusted>()
+            .add_event::<NavCommand>()
+            .add_systems(
+                Update,
+                (ensure_focus, follow_pointer, read_nav_input, navigate, highlight_focus).chain(),
+            );
+    }
+}
+
+// A freshly opened menu starts focused on its first entry
+fn ensure_focus(
+    mut commands: Commands,
+    focusables: Query<(Entity, &Focusable)>,
+    focused: Query<(), (With<Focused>, With<Focusable>)>,
+) {
+    if !focused.is_empty() {
+        return;
+    }
+    if let Some((entity, _)) = focusables.iter().min_by_key(|(_, focusable)| focusable.order) {
+        commands.entity(entity).insert(Focused);
+    }
+}
+
+fn move_focus(commands: &mut Commands, focused: impl Iterator<Item = Entity>, target: Entity) {
+    for entity in focused {
+        if entity != target {
+            commands.entity(entity).remove::<Focused>();
+        }
+    }
+    commands.entity(target).insert(Focused);
+}
+
+// The mouse moves focus too, so there's only ever one highlighted entry
+#[allow(clippy::type_complexity)]
+fn follow_pointer(
+    mut commands: Commands,
+    interactions: Query<(Entity, &Interaction), (Changed<Interaction>, With<Focusable>)>,
+    focused: Query<Entity, With<Focused>>,
+    mut activated: EventWriter<MenuActivated>,
+) {
+    for (entity, interaction) in interactions.iter() {
+        match interaction {
+            Interaction::Hovered => move_focus(&mut commands, focused.iter(), entity),
+            Interaction::Pressed => {
+                move_focus(&mut commands, focused.iter(), entity);
+                activated.send(MenuActivated(entity));
+            }
+            Interaction::None => {}
+        }
+    }
+}
+
+fn read_command(
+    keys: &ButtonInput<KeyCode>,
+    gamepads: &Query<&Gamepad>,
+    stick_held: &mut bool,
+    typing: bool,
+) -> Option<NavCommand> {
+    let pressed = |plain: KeyCode, letter: KeyCode| {
+        keys.just_pressed(plain) || (!typing && keys.just_pressed(letter))
+    };
+    if pressed(KeyCode::ArrowUp, KeyCode::KeyW) {
+        return Some(NavCommand::Previous);
+    }
+    if pressed(KeyCode::ArrowDown, KeyCode::KeyS) || keys.just_pressed(KeyCode::Tab) {
+        return Some(NavCommand::Next);
+    }
+    if pressed(KeyCode::ArrowLeft, KeyCode::KeyA) {
+        return Some(NavCommand::Decrease);
+    }
+    if pressed(KeyCode::ArrowRight, KeyCode::KeyD) {
+        return Some(NavCommand::Increase);
+    }
+    if pressed(KeyCode::Enter, KeyCode::Space) {
+        return Some(NavCommand::Confirm);
+    }
+    if pressed(KeyCode::Escape, KeyCode::Backspace) {
+        return Some(NavCommand::Cancel);
+    }
+
+    let mut stick = Vec2::ZERO;
+    for gamepad in gamepads.iter() {
+        if gamepad.just_pressed(GamepadButton::DPadUp) {
+            return Some(NavCommand::Previous);
+        }
+        if gamepad.just_pressed(GamepadButton::DPadDown) {
+            return Some(NavCommand::Next);
+        }
+        if gamepad.just_pressed(GamepadButton::DPadLeft) {
+            return Some(NavCommand::Decrease);
+        }
+        if gamepad.just_pressed(GamepadButton::DPadRight) {
+            return Some(NavCommand::Increase);
+        }
+        if gamepad.just_pressed(GamepadButton::South) {
+            return Some(NavCommand::Confirm);
+        }
+        if gamepad.just_pressed(GamepadButton::East) {
+            return Some(NavCommand::Cancel);
+        }
+        if gamepad.left_stick().length() > stick.length() {
+            stick = gamepad.left_stick();
+        }
+    }
+
+    // The stick steps once per push, it has to come back to center before the next step
+    if stick.abs().max_element() < STICK_THRESHOLD {
+        *stick_held = false;
+        return None;
+    }
+    if *stick_held {
+        return None;
+    }
+    *stick_held = true;
+    Some(match (stick.x.abs() > stick.y.abs(), stick.x > 0.0, stick.y > 0.0) {
+        (true, true, _) => NavCommand::Increase,
+        (true, false, _) => NavCommand::Decrease,
+        (false, _, true) => NavCommand::Previous,
+        (false, _, false) => NavCommand::Next,
+    })
+}
+
+fn read_nav_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    mut stick_held: Local<bool>,
+    typing: Query<(), (With<Focused>, With<CapturesKeyboard>)>,
+    paused: Option<Res<NavPaused>>,
+    mut nav_commands: EventWriter<NavCommand>,
+) {
+    if paused.is_some() {
+        return;
+    }
+    if let Some(command) = read_command(&keys, &gamepads, &mut stick_held, !typing.is_empty()) {
+        nav_commands.send(command);
+    }
+}
+
+fn navigate(
+    mut commands: Commands,
+    mut nav_commands: EventReader<NavCommand>,
+    focusables: Query<(Entity, &Focusable, Has<Focused>)>,
+    cancel_buttons: Query<Entity, (With<CancelButton>, With<Focusable>)>,
+    mut activated: EventWriter<MenuActivated>,
+    mut adjusted: EventWriter<MenuAdjusted>,
+) {
+    // Only the latest command per frame, focus changes from earlier ones aren't applied yet
+    let Some(command) = nav_commands.read().last().copied() else {
+        return;
+    };
+    if focusables.is_empty() {
+        return;
+    }
+
+    let mut entries: Vec<_> = focusables.iter().collect();
+    entries.sort_by_key(|(entity, focusable, _)| (focusable.order, *entity));
+    let current = entries.iter().position(|(_, _, focused)| *focused);
+
+    match command {
+        NavCommand::Previous | NavCommand::Next => {
+            let count = entries.len();
+            // Wraps around at both ends
+            let next = match (current, command) {
+                (None, _) => 0,
+                (Some(index), NavCommand::Next) => (index + 1) % count,
+                (Some(index), _) => (index + count - 1) % count,
+            };
+            let focused = entries.iter().filter(|(_, _, focused)| *focused).map(|(entity, ..)| *entity);
+            move_focus(&mut commands, focused, entries[next].0);
+        }
+        NavCommand::Decrease | NavCommand::Increase => {
+            if let Some(index) = current {
+                let step = if command == NavCommand::Increase { 1 } else { -1 };
+                adjusted.send(MenuAdjusted { entity: entries[index].0, step });
+            }
+        }
+        NavCommand::Confirm => {
+            if let Some(index) = current {
+                activated.send(MenuActivated(entries[index].0));
+            }
+        }
+        NavCommand::Cancel => {
+            if let Some(entity) = cancel_buttons.iter().next() {
+                activated.send(MenuActivated(entity));
+            }
+        }
+    }
+}
+
+fn highlight_focus(mut buttons: Query<(Has<Focused>, &mut BackgroundColor), With<Focusable>>) {
+    for (focused, mut background) in buttons.iter_mut() {
+        background.set_if_neq(BackgroundColor(if focused { FOCUSED_BUTTON } else { NORMAL_BUTTON }));
+    }
+}
@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::ball::Ball;
+use crate::camera::VIEW_HEIGHT;
+use crate::game::Player;
+use crate::level::{ActiveLevel, LevelDef, PieceKind};
+use crate::perspective::MirroredView;
+use crate::presentation::{player_color, Mirrors};
+use crate::ui_layout::SafeArea;
+
+pub struct MinimapPlugin;
+
+// Width of the map on screen, its height follows the level's proportions
+const MAP_WIDTH: f32 = 220.0;
+const DOT_SIZE: f32 = 8.0;
+// Levels up to this size fit on screen as it is, the map would only repeat it
+const SCREEN_SIZE: Vec2 = Vec2::new(VIEW_HEIGHT * 1.6, VIEW_HEIGHT);
+
+// Corner map of the level, built from its definition and rebuilt when level sync swaps it
+#[derive(Component)]
+struct Minimap {
+    level_hash: u64,
+}
+
+// A dot following a display entity, so it moves with what's drawn rather than the raw
+// simulation
+#[derive(Component)]
+struct MinimapDot(Entity);
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (build_minimap, update_dots, mirror_minimap).chain().run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), cleanup_minimap);
+    }
+}
+
+fn percent_of(level: &LevelDef, position: Vec2, size: Vec2) -> (Val, Val, Val, Val) {
+    let bounds = level.bounds();
+    let corner = position - size / 2.0 - bounds.min;
+    (
+        Val::Percent(corner.x / bounds.width() * 100.0),
+        Val::Percent(corner.y / bounds.height() * 100.0),
+        Val::Percent(size.x / bounds.width() * 100.0),
+        Val::Percent(size.y / bounds.height() * 100.0),
+    )
+}
+
+fn build_minimap(
+    mut commands: Commands,
+    level: Res<ActiveLevel>,
+    maps: Query<(Entity, &Minimap)>,
+) {
+    let hash = level.0.content_hash();
+    if maps.iter().any(|(_, map)| map.level_hash == hash) {
+        return;
+    }
+    for (entity, _) in maps.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let bounds = level.0.bounds();
+    let large = bounds.width() > SCREEN_SIZE.x || bounds.height() > SCREEN_SIZE.y;
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                right: Val::Px(20.0),
+                bottom: Val::Px(20.0),
+                width: Val::Px(MAP_WIDTH),
+                height: Val::Px(MAP_WIDTH * bounds.height() / bounds.width()),
+                display: if large { Display::Flex } else { Display::None },
+                ..default()
+            },
+            SafeArea,
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.5)),
+            Minimap { level_hash: hash },
+        ))
+        .with_children(|parent| {
+            for piece in &level.0.pieces {
+                let (left, bottom, width, height) = percent_of(&level.0, Vec2::from(piece.position), Vec2::from(piece.size));
+                let color = match piece.kind {
+                    PieceKind::Net => Color::srgb(0.9, 0.9, 0.9),
+                    PieceKind::Block { .. } => crate::blocks::BLOCK_COLOR,
+                    _ => Color::srgb(0.55, 0.55, 0.55),
+                };
+                parent.spawn((
+                    Node { position_type: PositionType::Absolute, left, bottom, width, height, ..default() },
+                    BackgroundColor(color),
+                ));
+            }
+        });
+}
+
+// Dots are added for displays as they show up and dropped with them
+fn update_dots(
+    mut commands: Commands,
+    level: Res<ActiveLevel>,
+    maps: Query<Entity, With<Minimap>>,
+    displays: Query<(Entity, &Mirrors, &Transform)>,
+    simulated: Query<(Option<&Player>, Has<Ball>)>,
+    mut dots: Query<(Entity, &MinimapDot, &mut Node)>,
+) {
+    let Ok(map) = maps.get_single() else {
+        return;
+    };
+    let dot = Vec2::splat(DOT_SIZE);
+    for (entity, MinimapDot(display), mut node) in dots.iter_mut() {
+        let Ok((_, _, transform)) = displays.get(*display) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let (left, bottom, ..) = percent_of(&level.0, transform.translation.truncate(), Vec2::ZERO);
+        node.left = left;
+        node.bottom = bottom;
+    }
+    for (display, Mirrors(source), transform) in displays.iter() {
+        if dots.iter().any(|(_, MinimapDot(followed), _)| *followed == display) {
+            continue;
+        }
+        let color = match simulated.get(*source) {
+            Ok((Some(player), _)) => player_color(player.handle),
+            Ok((None, true)) => Color::WHITE,
+            _ => continue,
+        };
+        let (left, bottom, ..) = percent_of(&level.0, transform.translation.truncate(), Vec2::ZERO);
+        let dot = commands
+            .spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    left,
+                    bottom,
+                    width: Val::Px(dot.x),
+                    height: Val::Px(dot.y),
+                    margin: UiRect::new(Val::Px(-dot.x / 2.0), Val::ZERO, Val::ZERO, Val::Px(-dot.y / 2.0)),
+                    ..default()
+                },
+                BorderRadius::MAX,
+                BackgroundColor(color),
+                MinimapDot(display),
+            ))
+            .id();
+        commands.entity(map).add_child(dot);
+    }
+}
+
+// Flipped along with the match camera, see perspective
+fn mirror_minimap(mirrored: Res<MirroredView>, mut maps: Query<&mut Transform, With<Minimap>>) {
+    let flip = if mirrored.0 { -1.0 } else { 1.0 };
+    for mut transform in maps.iter_mut() {
+        if transform.scale.x != flip {
+            transform.scale.x = flip;
+        }
+    }
+}
+
+fn cleanup_minimap(mut commands: Commands, maps: Query<Entity, With<Minimap>>) {
+    for entity in maps.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+use bevy::audio::Volume;
+use bevy::prelude::*;
+use crate::GameState;
+use crate::scoring::PointScored;
+use crate::settings::Settings;
+use crate::strike::StrikeLanded;
+
+pub struct MusicPlugin;
+
+// Stems of the match track, all started together so they stay in time. Each one is silent
+// until the rally has had that many touches
+const STEMS: [(&str, u32); 4] = [
+    ("music/rally_base.ogg", 0),
+    ("music/rally_5.ogg", 5),
+    ("music/rally_10.ogg", 10),
+    ("music/rally_15.ogg", 15),
+];
+// Volume change per second while a stem fades in or out
+const FADE_PER_SECOND: f32 = 0.5;
+
+// Touches on the ball in the rally being played, counted from confirmed strikes so the music
+// never swells for a touch that gets rolled back
+#[derive(Resource, Default)]
+pub struct RallyState {
+    pub touches: u32,
+}
+
+#[derive(Component)]
+struct MusicStem {
+    threshold: u32,
+}
+
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), start_music)
+            .add_systems(Update, (count_touches, fade_stems).chain().run_if(in_state(GameState::InGame)))
+            .add_systems(OnExit(GameState::InGame), stop_music);
+    }
+}
+
+// Stems not in this build's assets are left out, the others still layer
+fn start_music(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(RallyState::default());
+    for (path, threshold) in STEMS {
+        if !cfg!(target_arch = "wasm32") && !PathBuf::from("assets").join(path).is_file() {
+            debug!("no music stem {path}");
+            continue;
+        }
+        let volume = if threshold == 0 { 1.0 } else { 0.0 };
+        commands.spawn((
+            AudioPlayer::new(asset_server.load(path)),
+            PlaybackSettings::LOOP.with_volume(Volume::new(volume)),
+            MusicStem { threshold },
+        ));
+    }
+}
+
+// A point ends the rally, even when it's confirmed together with the serve's first touch
+fn count_touches(
+    mut rally: ResMut<RallyState>,
+    mut strikes: EventReader<StrikeLanded>,
+    mut points: EventReader<PointScored>,
+) {
+    if points.read().count() > 0 {
+        rally.touches = 0;
+    }
+    let touches = strikes.read().filter(|strike| strike.ball).count() as u32;
+    if touches > 0 {
+        rally.touches += touches;
+    }
+}
+
+// A stem's own sink ignores the global volume once it plays, so the setting is faded in here
+fn fade_stems(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    rally: Res<RallyState>,
+    stems: Query<(&MusicStem, &AudioSink)>,
+) {
+    let step = FADE_PER_SECOND * time.delta_secs();
+    for (stem, sink) in stems.iter() {
+        let target = if rally.touches >= stem.threshold { settings.volume } else { 0.0 };
+        let volume = sink.volume();
+        if volume != target {
+            let faded = if volume < target { (volume + step).min(target) } else { (volume - step).max(target) };
+            sink.set_volume(faded);
+        }
+    }
+}
+
+fn stop_music(mut commands: Commands, stems: Query<Entity, With<MusicStem>>) {
+    for entity in stems.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<RallyState>();
+}
@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use bevy::prelude::*;
+use bevy::utils::{Duration, Instant};
+use bevy_ggrs::ggrs::{GgrsRequest, Message, NonBlockingSocket, P2PSession, PlayerType, SessionBuilder, SessionState};
+use bevy_ggrs::ReadInputs;
+use bevy_matchbox::prelude::PeerId;
+use uuid::Uuid;
+use crate::GameState;
+use crate::drill::DummyControl;
+use crate::game::FRAME_MS;
+use crate::input::{Config, PlayerInput};
+use crate::rng::DeterministicRng;
+use crate::settings::Settings;
+
+pub struct NetworkFeelPlugin;
+
+// Stand-ins for the two ends of the loopback, nothing is looked up by them
+const PLAYER_PEER: PeerId = PeerId(Uuid::from_u128(1));
+const DUMMY_PEER: PeerId = PeerId(Uuid::from_u128(2));
+const DUMMY_HANDLE: usize = 1;
+// Each packet is held up to this many frames, so remote inputs show up late by a different
+// amount every time and the rewinds they cause come in all depths
+const MAX_JITTER_FRAMES: u32 = 5;
+
+// How the loopback mistreats packets, from the training settings. A bad run of losses
+// brings up the reconnect overlay like a real interruption would
+#[derive(Clone, Copy, Debug)]
+pub struct LoopbackConditions {
+    latency: Duration,
+    jitter: bool,
+    loss_percent: u32,
+}
+
+// Packets on their way to one end, with when they get there
+type Inbox = Arc<Mutex<VecDeque<(Instant, PeerId, Message)>>>;
+
+// One end of an in-process connection. What's sent turns up in the other end's inbox
+struct LoopbackSocket {
+    local: PeerId,
+    inbox: Inbox,
+    outbox: Inbox,
+    conditions: LoopbackConditions,
+    rng: DeterministicRng,
+}
+
+// The training dummy as a peer of its own, so the player's session predicts its inputs and
+// rolls back when they arrive, the way it does online. It only relays the dummy's inputs, it
+// never simulates anything
+#[derive(Resource)]
+struct DummyPeer(P2PSession<Config>);
+
+impl LoopbackSocket {
+    fn pair(conditions: LoopbackConditions) -> (Self, Self) {
+        let to_player = Inbox::default();
+        let to_dummy = Inbox::default();
+        (
+            Self {
+                local: PLAYER_PEER,
+                inbox: to_player.clone(),
+                outbox: to_dummy.clone(),
+                conditions,
+                rng: DeterministicRng::new(1),
+            },
+            Self { local: DUMMY_PEER, inbox: to_dummy, outbox: to_player, conditions, rng: DeterministicRng::new(2) },
+        )
+    }
+}
+
+impl NonBlockingSocket<PeerId> for LoopbackSocket {
+    fn send_to(&mut self, msg: &Message, _addr: &PeerId) {
+        if self.conditions.loss_percent > 0 && self.rng.chance(self.conditions.loss_percent) {
+            return;
+        }
+        let frames = if self.conditions.jitter { self.rng.range_u32(0, MAX_JITTER_FRAMES + 1) } else { 0 };
+        let arrives = Instant::now()
+            + self.conditions.latency
+            + Duration::from_secs_f32(frames as f32 * FRAME_MS / 1000.0);
+        self.outbox.lock().unwrap_or_else(PoisonError::into_inner).push_back((arrives, self.local, msg.clone()));
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        let now = Instant::now();
+        let mut inbox = self.inbox.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut arrived = Vec::new();
+        inbox.retain(|(arrives, from, msg)| {
+            let due = *arrives <= now;
+            if due {
+                arrived.push((*from, msg.clone()));
+            }
+            !due
+        });
+        arrived
+    }
+}
+
+impl Plugin for NetworkFeelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, poll_dummy.run_if(resource_exists::<DummyPeer>))
+            .add_systems(ReadInputs, drive_dummy.run_if(resource_exists::<DummyPeer>))
+            .add_systems(OnExit(GameState::InGame), remove_dummy);
+    }
+}
+
+impl LoopbackConditions {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            latency: Duration::from_secs_f32(settings.training_latency_ms / 1000.0),
+            jitter: settings.training_rollbacks,
+            loss_percent: settings.training_packet_loss.round() as u32,
+        }
+    }
+}
+
+pub fn enabled(settings: &Settings) -> bool {
+    settings.training_input_delay > 0
+        || settings.training_rollbacks
+        || settings.training_latency_ms > 0.0
+        || settings.training_packet_loss > 0.0
+}
+
+// The player's session, with the dummy's end of it set up alongside
+pub fn start_session(
+    commands: &mut Commands,
+    input_delay: usize,
+    conditions: LoopbackConditions,
+) -> Result<P2PSession<Config>, String> {
+    let (player_socket, dummy_socket) = LoopbackSocket::pair(conditions);
+    let player = SessionBuilder::<Config>::new()
+        .with_num_players(2)
+        .with_input_delay(input_delay)
+        .add_player(PlayerType::Local, 0)
+        .and_then(|builder| builder.add_player(PlayerType::Remote(DUMMY_PEER), DUMMY_HANDLE))
+        .and_then(|builder| builder.start_p2p_session(player_socket))
+        .map_err(|err| err.to_string())?;
+    let dummy = SessionBuilder::<Config>::new()
+        .with_num_players(2)
+        .add_player(PlayerType::Remote(PLAYER_PEER), 0)
+        .and_then(|builder| builder.add_player(PlayerType::Local, DUMMY_HANDLE))
+        .and_then(|builder| builder.start_p2p_session(dummy_socket))
+        .map_err(|err| err.to_string())?;
+    info!("training with {input_delay} frames of input delay, {conditions:?}");
+    commands.insert_resource(DummyPeer(dummy));
+    Ok(player)
+}
+
+fn poll_dummy(mut dummy: ResMut<DummyPeer>) {
+    dummy.0.poll_remote_clients();
+}
+
+// Runs once per frame the player's session advances, so both ends move in step. The dummy
+// has no state, saving a frame only tells its session the frame happened
+fn drive_dummy(mut dummy: ResMut<DummyPeer>, mut control: DummyControl) {
+    if dummy.0.current_state() != SessionState::Running {
+        return;
+    }
+    let input = PlayerInput::buttons(control.next_input(DUMMY_HANDLE));
+    if let Err(err) = dummy.0.add_local_input(DUMMY_HANDLE, input) {
+        warn!("training dummy input dropped: {err}");
+        return;
+    }
+    match dummy.0.advance_frame() {
+        Ok(requests) => {
+            for request in requests {
+                if let GgrsRequest::SaveGameState { cell, frame } = request {
+                    cell.save(frame, None, None);
+                }
+            }
+        }
+        Err(err) => debug!("training dummy skipped a frame: {err}"),
+    }
+}
+
+fn remove_dummy(mut commands: Commands) {
+    commands.remove_resource::<DummyPeer>();
+}
@@ -0,0 +1,167 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use crate::ui_layout::SafeArea;
+
+pub struct NotificationPlugin;
+
+const TOAST_SECONDS: f32 = 3.0;
+// The last part of a toast's lifetime is spent fading out
+const FADE_SECONDS: f32 = 0.5;
+const MAX_VISIBLE: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+// Send this from anywhere to pop up a toast in the corner. Messages are shown in order,
+// at most a few at a time
+#[derive(Event, Clone, Debug)]
+pub struct Notify {
+    pub message: String,
+    pub severity: Severity,
+}
+
+#[derive(Resource, Default)]
+struct ToastQueue(VecDeque<Notify>);
+
+#[derive(Component)]
+struct ToastStack;
+
+#[derive(Component)]
+struct Toast {
+    remaining: f32,
+    color: Color,
+}
+
+impl Notify {
+    pub fn info(message: impl Into<String>) -> Self {
+        Self { message: message.into(), severity: Severity::Info }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self { message: message.into(), severity: Severity::Warning }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self { message: message.into(), severity: Severity::Error }
+    }
+}
+
+impl Severity {
+    fn color(self) -> Color {
+        match self {
+            Severity::Info => Color::srgb(0.15, 0.25, 0.4),
+            Severity::Warning => Color::srgb(0.5, 0.35, 0.05),
+            Severity::Error => Color::srgb(0.55, 0.1, 0.1),
+        }
+    }
+}
+
+impl Plugin for NotificationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Notify>()
+            .init_resource::<ToastQueue>()
+            .add_systems(Startup, setup_toast_stack)
+            .add_systems(Update, (queue_notifications, show_toasts, fade_toasts).chain());
+    }
+}
+
+// Lives for the whole app so toasts survive state changes
+fn setup_toast_stack(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::FlexEnd,
+            row_gap: Val::Px(6.0),
+            ..default()
+        },
+        GlobalZIndex(100),
+        SafeArea,
+        ToastStack,
+    ));
+}
+
+fn queue_notifications(mut events: EventReader<Notify>, mut queue: ResMut<ToastQueue>) {
+    for notify in events.read() {
+        match notify.severity {
+            Severity::Info => info!("notification: {}", notify.message),
+            Severity::Warning => warn!("notification: {}", notify.message),
+            Severity::Error => error!("notification: {}", notify.message),
+        }
+        queue.0.push_back(notify.clone());
+    }
+}
+
+fn show_toasts(
+    mut commands: Commands,
+    mut queue: ResMut<ToastQueue>,
+    stacks: Query<Entity, With<ToastStack>>,
+    toasts: Query<(), With<Toast>>,
+) {
+    let Ok(stack) = stacks.get_single() else {
+        return;
+    };
+
+    let mut visible = toasts.iter().count();
+    while visible < MAX_VISIBLE {
+        let Some(notify) = queue.0.pop_front() else {
+            break;
+        };
+        visible += 1;
+
+        let color = notify.severity.color();
+        commands.entity(stack).with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        padding: UiRect::axes(Val::Px(14.0), Val::Px(8.0)),
+                        ..default()
+                    },
+                    BackgroundColor(color),
+                    Toast {
+                        remaining: TOAST_SECONDS,
+                        color,
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::new(notify.message),
+                        TextFont {
+                            font_size: 18.0,
+                            ..default()
+                        },
+                        TextColor(Color::WHITE),
+                    ));
+                });
+        });
+    }
+}
+
+fn fade_toasts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut toasts: Query<(Entity, &mut Toast, &mut BackgroundColor, &Children)>,
+    mut texts: Query<&mut TextColor>,
+) {
+    for (entity, mut toast, mut background, children) in toasts.iter_mut() {
+        toast.remaining -= time.delta_secs();
+        if toast.remaining <= 0.0 {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let alpha = (toast.remaining / FADE_SECONDS).min(1.0);
+        background.0 = toast.color.with_alpha(alpha);
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.0 = Color::WHITE.with_alpha(alpha);
+            }
+        }
+    }
+}
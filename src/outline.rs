@@ -0,0 +1,138 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{AsBindGroup, ShaderRef};
+use bevy::sprite::{AlphaMode2d, Material2d, Material2dPlugin};
+use bevy_ggrs::LocalPlayers;
+use crate::GameState;
+use crate::game::Player;
+use crate::presentation::{player_color, Appearance, Mirrors};
+use crate::rollback_budget::presenting;
+use crate::settings::Settings;
+
+pub struct OutlinePlugin;
+
+// In sprite texels. The local player gets the heavier rim so they find themselves first
+const LOCAL_THICKNESS: f32 = 9.0;
+const REMOTE_THICKNESS: f32 = 5.0;
+// Room around the sprite for the thickest outline
+const PADDING: f32 = LOCAL_THICKNESS + 1.0;
+
+// Drawn on a quad behind a player's sprite, see assets/shaders/outline.wgsl
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+pub struct OutlineMaterial {
+    #[uniform(0)]
+    color: LinearRgba,
+    // Outline width in uv units of the sprite in xy, how much larger the quad is in zw
+    #[uniform(1)]
+    extent: Vec4,
+    #[texture(2)]
+    #[sampler(3)]
+    image: Handle<Image>,
+}
+
+// On a player's display entity, points at the child carrying its outline
+#[derive(Component)]
+struct Outlined(Entity);
+
+impl Material2d for OutlineMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/outline.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode2d {
+        AlphaMode2d::Blend
+    }
+}
+
+fn outline_extent(local: bool, size: Vec2) -> Vec4 {
+    let thickness = Vec2::splat(if local { LOCAL_THICKNESS } else { REMOTE_THICKNESS }) / size;
+    let expand = (size + 2.0 * PADDING) / size;
+    Vec4::new(thickness.x, thickness.y, expand.x, expand.y)
+}
+
+impl Plugin for OutlinePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(Material2dPlugin::<OutlineMaterial>::default()).add_systems(
+            PostUpdate,
+            (attach_outlines, update_outlines.run_if(presenting))
+                .chain()
+                .before(TransformSystem::TransformPropagate)
+                .run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+// Outlines are children of the display entity, so they follow its smoothing and go away with it
+fn attach_outlines(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<OutlineMaterial>>,
+    displays: Query<(Entity, &Mirrors), Without<Outlined>>,
+    players: Query<(&Player, &Appearance)>,
+) {
+    for (display, mirrors) in displays.iter() {
+        let Ok((player, appearance)) = players.get(mirrors.0) else {
+            continue;
+        };
+        let sprite = &appearance.sprite;
+        let material = materials.add(OutlineMaterial {
+            color: player_color(player.handle).into(),
+            extent: outline_extent(false, Vec2::ONE),
+            image: sprite.image.clone(),
+        });
+        let outline = commands
+            .spawn((
+                Mesh2d(meshes.add(Rectangle::default())),
+                MeshMaterial2d(material),
+                // Just behind the sprite
+                Transform::from_xyz(0.0, 0.0, -1.0),
+                Visibility::Hidden,
+            ))
+            .set_parent(display)
+            .id();
+        commands.entity(display).insert(Outlined(outline));
+    }
+}
+
+// Sized from the sprite's texture, which may still be loading or swapped by a skin change
+fn update_outlines(
+    settings: Res<Settings>,
+    local_players: Option<Res<LocalPlayers>>,
+    images: Res<Assets<Image>>,
+    mut materials: ResMut<Assets<OutlineMaterial>>,
+    displays: Query<(&Mirrors, &Outlined)>,
+    players: Query<(&Player, &Appearance)>,
+    mut outlines: Query<(&MeshMaterial2d<OutlineMaterial>, &mut Transform, &mut Visibility)>,
+) {
+    for (mirrors, outlined) in displays.iter() {
+        let Ok((material, mut transform, mut visibility)) = outlines.get_mut(outlined.0) else {
+            continue;
+        };
+        let Ok((player, appearance)) = players.get(mirrors.0) else {
+            continue;
+        };
+        let sprite = &appearance.sprite;
+        let Some(image) = images.get(&sprite.image) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        visibility.set_if_neq(if settings.player_outlines { Visibility::Inherited } else { Visibility::Hidden });
+
+        // Padding and thickness are in texels, so they're scaled along with the quad
+        let texels = image.size_f32();
+        let local = local_players.as_ref().is_some_and(|local| local.0.contains(&player.handle));
+        let extent = outline_extent(local, texels);
+        let flip = Vec2::new(if sprite.flip_x { -1.0 } else { 1.0 }, 1.0);
+        let size = texels * appearance.texel_scale;
+        transform.scale = (flip * (texels + 2.0 * PADDING) * appearance.texel_scale).extend(1.0);
+        transform.translation = (-flip * sprite.anchor.as_vec() * size).extend(-1.0);
+
+        // Only touch the asset when something changed, a mutable access re-uploads it
+        let current = materials.get(&material.0);
+        if current.is_none_or(|current| current.extent != extent || current.image != sprite.image)
+            && let Some(current) = materials.get_mut(&material.0)
+        {
+            current.extent = extent;
+            current.image = sprite.image.clone();
+        }
+    }
+}
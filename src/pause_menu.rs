@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::editor::EditorDraft;
+use crate::game::SessionMode;
+use crate::level::ActiveLevel;
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated};
+use crate::notifications::Notify;
+use crate::quick_settings::QuickSettingsOpen;
+use crate::scoring::Score;
+use crate::shortcuts::Shortcut;
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct PauseMenuPlugin;
+
+// Open while the pause menu is up. The keyboard belongs to the menu then, see input
+#[derive(Resource)]
+pub struct PauseMenuOpen;
+
+#[derive(Component)]
+struct PauseMenu;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum PauseAction {
+    Resume,
+    // Swaps the menu for the quick settings wheel, see quick_settings
+    QuickSettings,
+    Report,
+    Leave,
+}
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+                Update,
+                (open_pause_menu, pause_action, reopen_pause_menu.run_if(resource_removed::<QuickSettingsOpen>))
+                    .chain()
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(OnExit(GameState::InGame), close_on_exit);
+    }
+}
+
+fn decided(score: &Option<Res<Score>>) -> bool {
+    score.as_ref().is_some_and(|score| score.winner.is_some())
+}
+
+// Back to wherever the match was started from. An online match still being played keeps
+// going, it can only be left once it's decided
+fn leave_to(mode: SessionMode, draft: bool, score: &Option<Res<Score>>) -> Option<GameState> {
+    match mode {
+        // A playtest drops back into the editor with the draft intact
+        SessionMode::Local if draft => Some(GameState::Editor),
+        SessionMode::Local
+        | SessionMode::SyncTest
+        | SessionMode::Training
+        | SessionMode::Race
+        | SessionMode::Daily
+        | SessionMode::Attract => Some(GameState::MainMenu),
+        SessionMode::Online if decided(score) => Some(GameState::MainMenu),
+        _ => None,
+    }
+}
+
+// Esc with nothing else open. A local match stops while the menu is up, online the match
+// goes on underneath with this player standing still
+fn open_pause_menu(
+    mut commands: Commands,
+    mut shortcuts: EventReader<Shortcut>,
+    (mode, draft, score): (Res<SessionMode>, Option<Res<EditorDraft>>, Option<Res<Score>>),
+    open: Option<Res<PauseMenuOpen>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if !shortcuts.read().any(|shortcut| *shortcut == Shortcut::Back) || open.is_some() {
+        return;
+    }
+    if *mode != SessionMode::Online {
+        time.pause();
+    }
+    commands.insert_resource(PauseMenuOpen);
+    spawn_pause_menu(&mut commands, leave_to(*mode, draft.is_some(), &score).is_some());
+}
+
+// Back from the quick settings wheel, the match still paused
+fn reopen_pause_menu(
+    mut commands: Commands,
+    (mode, draft, score): (Res<SessionMode>, Option<Res<EditorDraft>>, Option<Res<Score>>),
+    open: Option<Res<PauseMenuOpen>>,
+) {
+    if open.is_some() {
+        spawn_pause_menu(&mut commands, leave_to(*mode, draft.is_some(), &score).is_some());
+    }
+}
+
+fn spawn_pause_menu(commands: &mut Commands, leave: bool) {
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            PauseMenu,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::default(),
+                Localized("pause.title"),
+                TextFont {
+                    font_size: 40.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            let mut entries = vec![
+                (PauseAction::Resume, "pause.resume"),
+                (PauseAction::QuickSettings, "pause.quick_settings"),
+                (PauseAction::Report, "pause.report"),
+            ];
+            if leave {
+                entries.push((PauseAction::Leave, "pause.leave"));
+            }
+            for (order, (action, key)) in entries.into_iter().enumerate() {
+                let mut button = parent.spawn((menu_button(order as u32), action));
+                if action == PauseAction::Resume {
+                    button.insert(CancelButton);
+                }
+                button.with_children(|parent| {
+                    parent.spawn((button_text(""), Localized(key)));
+                });
+            }
+        });
+}
+
+fn close(commands: &mut Commands, menus: &Query<Entity, With<PauseMenu>>, time: &mut Time<Virtual>) {
+    for entity in menus.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<PauseMenuOpen>();
+    time.unpause();
+}
+
+// Where the player was, at the top of the report
+fn report_summary(mode: SessionMode, level: &ActiveLevel, score: &Option<Res<Score>>) -> String {
+    let mut summary = format!("reported from the pause menu\nmode {mode:?}\nlevel {:?} {:016x}\n", level.0.name, level.0.content_hash());
+    if let Some(score) = score {
+        summary.push_str(&format!("score {:?}\n", score.points));
+    }
+    summary
+}
+
+#[allow(clippy::type_complexity)]
+fn pause_action(
+    mut commands: Commands,
+    mut activated: EventReader<MenuActivated>,
+    (actions, menus): (Query<&PauseAction>, Query<Entity, With<PauseMenu>>),
+    (mode, draft, score, level): (Res<SessionMode>, Option<Res<EditorDraft>>, Option<Res<Score>>, Res<ActiveLevel>),
+    (locale, mut notify): (Res<Locale>, EventWriter<Notify>),
+    mut time: ResMut<Time<Virtual>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        match actions.get(*entity) {
+            Ok(PauseAction::Resume) => close(&mut commands, &menus, &mut time),
+            Ok(PauseAction::QuickSettings) => {
+                for entity in menus.iter() {
+                    commands.entity(entity).despawn_recursive();
+                }
+                commands.insert_resource(QuickSettingsOpen::default());
+            }
+            Ok(PauseAction::Report) => match crate::crash::write_problem_report(&report_summary(*mode, &level, &score)) {
+                Ok(path) => {
+                    info!("problem report written to {path}");
+                    notify.send(Notify::info(locale.format("toast.report_saved", &[("path", &path)])));
+                }
+                Err(err) => {
+                    notify.send(Notify::error(locale.format("toast.report_failed", &[("reason", &err)])));
+                }
+            },
+            Ok(PauseAction::Leave) => {
+                if let Some(state) = leave_to(*mode, draft.is_some(), &score) {
+                    next_state.set(state);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+fn close_on_exit(mut commands: Commands, menus: Query<Entity, With<PauseMenu>>, mut time: ResMut<Time<Virtual>>) {
+    close(&mut commands, &menus, &mut time);
+}
@@ -0,0 +1,129 @@
+use bevy::prelude::*;
+use bevy_ggrs::LocalPlayers;
+use crate::GameState;
+use crate::game::{Player, SessionMode};
+use crate::locale::Locale;
+use crate::presentation::{player_color, Mirrors};
+use crate::scoring::Score;
+use crate::settings::Settings;
+
+pub struct PerspectivePlugin;
+
+// World units from the own player's middle up to the marker
+const MARKER_HEIGHT: f32 = 1.3;
+// Text is laid out in pixels, this brings it down to world units
+const MARKER_TEXT_SCALE: f32 = 0.01;
+
+// Whether the match is drawn mirrored, so the own player is on the left while the simulation
+// has them on the right. Only what's drawn flips, see face_cameras
+#[derive(Resource, Default, PartialEq, Eq)]
+pub struct MirroredView(pub bool);
+
+// Text in the world that has to stay readable in a mirrored view
+#[derive(Component)]
+pub struct Legible;
+
+// "You" over the player sitting at this machine
+#[derive(Component)]
+struct YouMarker;
+
+impl Plugin for PerspectivePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MirroredView>()
+            .add_systems(OnEnter(GameState::InGame), spawn_marker)
+            .add_systems(
+                Update,
+                (update_mirrored, face_cameras, face_text, follow_own_player)
+                    .chain()
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup);
+    }
+}
+
+// Turned half way round the vertical axis, a 2D camera looks at the arena from behind with
+// left and right swapped. Sprites are drawn from both sides and still sort by z
+fn facing(mirrored: bool) -> Quat {
+    if mirrored {
+        Quat::from_rotation_y(std::f32::consts::PI)
+    } else {
+        Quat::IDENTITY
+    }
+}
+
+fn update_mirrored(
+    settings: Res<Settings>,
+    (mode, local_players): (Res<SessionMode>, Option<Res<LocalPlayers>>),
+    score: Option<Res<Score>>,
+    mut mirrored: ResMut<MirroredView>,
+) {
+    let own = mode.own_handle(local_players.as_deref());
+    let wanted = settings.own_side_left
+        && score.is_some_and(|score| own.is_some_and(|own| score.side_of(own) == 1));
+    mirrored.set_if_neq(MirroredView(wanted));
+}
+
+fn face_cameras(mirrored: Res<MirroredView>, mut cameras: Query<&mut Transform, With<Camera2d>>) {
+    let rotation = facing(mirrored.0);
+    for mut transform in cameras.iter_mut() {
+        if transform.rotation != rotation {
+            transform.rotation = rotation;
+        }
+    }
+}
+
+// Turned along with the camera, so it reads the right way round again
+fn face_text(mirrored: Res<MirroredView>, mut texts: Query<&mut Transform, (With<Legible>, Without<Camera2d>)>) {
+    let rotation = facing(mirrored.0);
+    for mut transform in texts.iter_mut() {
+        if transform.rotation != rotation {
+            transform.rotation = rotation;
+        }
+    }
+}
+
+fn spawn_marker(mut commands: Commands, locale: Res<Locale>) {
+    commands.spawn((
+        Text2d::new(locale.get("perspective.you")),
+        TextFont {
+            font_size: 28.0,
+            ..default()
+        },
+        Transform::from_scale(Vec3::splat(MARKER_TEXT_SCALE)),
+        Visibility::Hidden,
+        Legible,
+        YouMarker,
+    ));
+}
+
+// Follows the display rather than the simulation, so it moves with what's drawn. Nobody is
+// marked with two people on one keyboard
+fn follow_own_player(
+    (mode, local_players): (Res<SessionMode>, Option<Res<LocalPlayers>>),
+    players: Query<&Player>,
+    displays: Query<(&Mirrors, &Transform, &Visibility), Without<YouMarker>>,
+    mut markers: Query<(&mut Transform, &mut Visibility, &mut TextColor), With<YouMarker>>,
+) {
+    let own = mode.own_handle(local_players.as_deref());
+    let display = own.and_then(|own| {
+        displays
+            .iter()
+            .find(|(mirrors, ..)| players.get(mirrors.0).is_ok_and(|player| player.handle == own))
+    });
+    for (mut transform, mut visibility, mut color) in markers.iter_mut() {
+        let Some((own, (_, target, shown))) = own.zip(display) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        visibility.set_if_neq(if *shown == Visibility::Hidden { Visibility::Hidden } else { Visibility::Inherited });
+        transform.translation = target.translation + Vec3::new(0.0, MARKER_HEIGHT, 10.0);
+        color.0 = player_color(own);
+    }
+}
+
+fn cleanup(mut commands: Commands, markers: Query<Entity, With<YouMarker>>) {
+    for entity in markers.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.insert_resource(MirroredView::default());
+}
@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+
+pub struct PoolPlugin;
+
+const MAX_PARTICLES: usize = 256;
+const PARTICLE_GRAVITY: f32 = -9.0;
+
+// A pre-spawned rollback entity that gets switched on and off instead of spawned/despawned,
+// so the snapshot always holds the same set of entities. Items are the only users: players,
+// the ball and level pieces are spawned once per match and live until it ends, so nothing
+// else churns through rollback entities yet. Anything that does later gets Pooled next to its
+// own marker and picks slots with first_free, the way match_schedule does for items
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Pooled {
+    index: u16,
+    pub active: bool,
+}
+
+// Visual-only, never part of the rollback state
+#[derive(Component)]
+struct Particle {
+    velocity: Vec2,
+    remaining: f32,
+    lifetime: f32,
+    color: Color,
+}
+
+#[derive(Resource, Default)]
+struct ParticlePool {
+    free: Vec<Entity>,
+    total: usize,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ParticleBurst {
+    pub position: Vec2,
+    pub color: Color,
+    pub count: u32,
+}
+
+impl Pooled {
+    pub fn new(index: u16) -> Self {
+        Self { index, active: false }
+    }
+}
+
+// Picks the free slot with the lowest index, so every peer (and every resimulation) reuses
+// slots in the same order regardless of query iteration order
+pub fn first_free<'a, T>(slots: impl Iterator<Item = (T, &'a Pooled)>) -> Option<T> {
+    slots
+        .filter(|(_, pooled)| !pooled.active)
+        .min_by_key(|(_, pooled)| pooled.index)
+        .map(|(item, _)| item)
+}
+
+impl Plugin for PoolPlugin {
+    fn build(&self, app: &mut App) {
+        app.rollback_component_with_copy::<Pooled>()
+            .init_resource::<ParticlePool>()
+            .add_event::<ParticleBurst>()
+            .add_systems(
+                Update,
+                (sync_pooled_visibility, spawn_particle_bursts, update_particles)
+                    .chain()
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_particles);
+    }
+}
+
+fn sync_pooled_visibility(mut pooled: Query<(&Pooled, &mut Visibility), Changed<Pooled>>) {
+    for (pooled, mut visibility) in pooled.iter_mut() {
+        *visibility = if pooled.active { Visibility::Inherited } else { Visibility::Hidden };
+    }
+}
+
+fn spawn_particle_bursts(
+    mut commands: Commands,
+    mut bursts: EventReader<ParticleBurst>,
+    mut pool: ResMut<ParticlePool>,
+    mut particles: Query<(&mut Particle, &mut Transform, &mut Visibility)>,
+) {
+    for burst in bursts.read() {
+        for i in 0..burst.count {
+            // Spread evenly in a fan, no randomness needed for a small poof
+            let angle = std::f32::consts::PI * (0.15 + 0.7 * i as f32 / burst.count.max(1) as f32);
+            let velocity = Vec2::from_angle(angle) * 3.0;
+            let particle = Particle {
+                velocity,
+                remaining: 0.5,
+                lifetime: 0.5,
+                color: burst.color,
+            };
+            let transform = Transform::from_translation(burst.position.extend(1.0));
+
+            if let Some(entity) = pool.free.pop() {
+                if let Ok((mut existing, mut existing_transform, mut visibility)) = particles.get_mut(entity) {
+                    *existing = particle;
+                    *existing_transform = transform;
+                    *visibility = Visibility::Inherited;
+                }
+            } else if pool.total < MAX_PARTICLES {
+                pool.total += 1;
+                commands.spawn((
+                    Sprite {
+                        color: burst.color,
+                        custom_size: Some(Vec2::splat(0.1)),
+                        ..default()
+                    },
+                    transform,
+                    particle,
+                ));
+            }
+        }
+    }
+}
+
+fn update_particles(
+    time: Res<Time>,
+    mut pool: ResMut<ParticlePool>,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite, &mut Visibility)>,
+) {
+    let dt = time.delta_secs();
+    for (entity, mut particle, mut transform, mut sprite, mut visibility) in particles.iter_mut() {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        particle.remaining -= dt;
+        if particle.remaining <= 0.0 {
+            *visibility = Visibility::Hidden;
+            pool.free.push(entity);
+            continue;
+        }
+
+        particle.velocity.y += PARTICLE_GRAVITY * dt;
+        transform.translation += (particle.velocity * dt).extend(0.0);
+        sprite.color = particle.color.with_alpha(particle.remaining / particle.lifetime);
+    }
+}
+
+fn cleanup_particles(
+    mut commands: Commands,
+    mut pool: ResMut<ParticlePool>,
+    particles: Query<Entity, With<Particle>>,
+) {
+    for entity in particles.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    *pool = ParticlePool::default();
+}
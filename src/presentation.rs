@@ -0,0 +1,122 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::rollback_budget::presenting;
+
+pub struct PresentationPlugin;
+
+// How quickly display entities catch up with their simulation entity, per second
+const SMOOTHING_RATE: f32 = 25.0;
+// Bigger jumps than this are teleports (respawns, rollbacks) and shouldn't be smoothed
+const SNAP_DISTANCE: f32 = 1.5;
+
+// What a simulation entity should look like. Simulation entities never carry a Sprite
+// themselves, so nothing visual ends up in the rollback snapshot
+#[derive(Component, Clone)]
+pub struct Appearance {
+    pub sprite: Sprite,
+    // Drawn by a material of its own instead of a Sprite, the sprite only describes the look.
+    // See character_material.rs
+    pub material: bool,
+    // The entity's own units per texel of the sprite, for art drawn at another resolution.
+    // Only read for a material, a plain sprite is sized by custom_size
+    pub texel_scale: f32,
+}
+
+// While present, displays hold where they are. The simulation carries on underneath and they
+// catch up once it's removed
+#[derive(Resource)]
+pub struct FreezeFrame;
+
+// Lives on the display entity and points back at the simulation entity it mirrors
+#[derive(Component)]
+pub struct Mirrors(pub Entity);
+
+// Team colors, used anywhere a player needs to be told apart at a glance
+pub fn player_color(handle: usize) -> Color {
+    if handle.is_multiple_of(2) {
+        Color::srgb(0.2, 0.4, 1.0)
+    } else {
+        Color::srgb(1.0, 0.3, 0.2)
+    }
+}
+
+impl Appearance {
+    pub fn new(sprite: Sprite) -> Self {
+        Self { sprite, material: false, texel_scale: 1.0 }
+    }
+
+    pub fn material(sprite: Sprite) -> Self {
+        Self { sprite, material: true, texel_scale: 1.0 }
+    }
+}
+
+impl Plugin for PresentationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (spawn_displays, sync_displays.run_if(presenting))
+                .chain()
+                .before(TransformSystem::TransformPropagate)
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), cleanup_displays);
+    }
+}
+
+fn spawn_displays(
+    mut commands: Commands,
+    simulated: Query<(Entity, &Appearance, &Transform), Added<Appearance>>,
+) {
+    for (entity, appearance, transform) in simulated.iter() {
+        let mut display = commands.spawn((*transform, Visibility::default(), Mirrors(entity)));
+        if !appearance.material {
+            display.insert(appearance.sprite.clone());
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn sync_displays(
+    mut commands: Commands,
+    time: Res<Time>,
+    freeze: Option<Res<FreezeFrame>>,
+    simulated: Query<(Ref<Appearance>, &Transform, Option<&Visibility>), Without<Mirrors>>,
+    mut displays: Query<(Entity, &Mirrors, Option<&mut Sprite>, &mut Transform, &mut Visibility)>,
+) {
+    let blend = 1.0 - (-SMOOTHING_RATE * time.delta_secs()).exp();
+
+    for (display, mirrors, sprite, mut transform, mut display_visibility) in displays.iter_mut() {
+        let Ok((appearance, target, visibility)) = simulated.get(mirrors.0) else {
+            // Simulation entity is gone (despawned or rolled back past its spawn)
+            commands.entity(display).despawn_recursive();
+            continue;
+        };
+
+        if appearance.is_changed()
+            && let Some(mut sprite) = sprite
+        {
+            *sprite = appearance.sprite.clone();
+        }
+        if let Some(visibility) = visibility {
+            display_visibility.set_if_neq(*visibility);
+        }
+
+        if freeze.is_some() {
+            continue;
+        }
+        if transform.translation.distance(target.translation) > SNAP_DISTANCE || *display_visibility == Visibility::Hidden {
+            transform.translation = target.translation;
+        } else {
+            transform.translation = transform.translation.lerp(target.translation, blend);
+        }
+        transform.rotation = target.rotation;
+        transform.scale = target.scale;
+    }
+}
+
+fn cleanup_displays(mut commands: Commands, displays: Query<Entity, With<Mirrors>>) {
+    for entity in displays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<FreezeFrame>();
+}
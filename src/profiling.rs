@@ -0,0 +1,130 @@
+use std::time::Duration;
+use bevy::prelude::*;
+use crate::GameState;
+use crate::locale::Locale;
+use crate::rollback_budget::RollbackBudget;
+use crate::settings::Settings;
+use crate::shared_channel::SharedChannel;
+use crate::ui_layout::SafeArea;
+
+pub struct ProfilingPlugin;
+
+const TOGGLE_KEY: KeyCode = KeyCode::F3;
+const BANDWIDTH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Counters for chasing hitches, hidden until F3. Not saved, it's a debugging aid
+#[derive(Component)]
+struct ProfilingOverlay;
+
+// Bytes per second through the GGRS channel over the last second, online only
+#[derive(Resource)]
+struct Bandwidth {
+    sent: u64,
+    received: u64,
+    copies: usize,
+}
+
+impl Plugin for ProfilingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), spawn_overlay)
+            .add_systems(
+                Update,
+                (toggle_overlay, sample_bandwidth.run_if(resource_exists::<SharedChannel>), update_overlay)
+                    .chain()
+                    .after(crate::rollback_budget::judge_frame)
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_overlay);
+    }
+}
+
+fn spawn_overlay(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(100.0),
+            left: Val::Px(20.0),
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.6, 1.0, 0.6)),
+        Visibility::Hidden,
+        ProfilingOverlay,
+    ));
+}
+
+fn toggle_overlay(keys: Res<ButtonInput<KeyCode>>, mut overlays: Query<&mut Visibility, With<ProfilingOverlay>>) {
+    if !keys.just_pressed(TOGGLE_KEY) {
+        return;
+    }
+    for mut visibility in overlays.iter_mut() {
+        *visibility = if *visibility == Visibility::Hidden { Visibility::Inherited } else { Visibility::Hidden };
+    }
+}
+
+fn sample_bandwidth(
+    mut commands: Commands,
+    channel: Res<SharedChannel>,
+    time: Res<Time<Real>>,
+    mut since_last: Local<Duration>,
+) {
+    *since_last += time.delta();
+    if *since_last < BANDWIDTH_INTERVAL {
+        return;
+    }
+    let secs = since_last.as_secs_f32();
+    *since_last = Duration::ZERO;
+    let traffic = channel.take_traffic();
+    commands.insert_resource(Bandwidth {
+        sent: (traffic.sent as f32 / secs).round() as u64,
+        received: (traffic.received as f32 / secs).round() as u64,
+        copies: channel.copies(),
+    });
+}
+
+fn update_overlay(
+    budget: Res<RollbackBudget>,
+    bandwidth: Option<Res<Bandwidth>>,
+    settings: Res<Settings>,
+    locale: Res<Locale>,
+    mut overlays: Query<&mut Text, With<ProfilingOverlay>>,
+) {
+    let bandwidth_changed = bandwidth.as_ref().is_some_and(|bandwidth| bandwidth.is_changed());
+    if !budget.is_changed() && !bandwidth_changed && !locale.is_changed() {
+        return;
+    }
+    let mut label = locale.format(
+        "profiling.rollbacks",
+        &[
+            ("rollbacks", &budget.rollbacks),
+            ("deepest", &budget.deepest),
+            ("budget", &settings.rollback_budget),
+            ("over", &budget.over_budget),
+            ("skipped", &budget.skipped),
+        ],
+    );
+    if let Some(bandwidth) = bandwidth {
+        label.push('\n');
+        label.push_str(&locale.format(
+            "profiling.bandwidth",
+            &[("sent", &bandwidth.sent), ("received", &bandwidth.received), ("copies", &bandwidth.copies)],
+        ));
+    }
+    for mut text in overlays.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+fn cleanup_overlay(mut commands: Commands, overlays: Query<Entity, With<ProfilingOverlay>>) {
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<Bandwidth>();
+}
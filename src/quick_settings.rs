@@ -0,0 +1,245 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{FOCUSED_BUTTON, NORMAL_BUTTON};
+use crate::settings::{adjust, Settings, SettingsEntry};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct QuickSettingsPlugin;
+
+// What the wheel offers, clockwise from the top. There's no screen shake in the game yet to
+// put here too
+const ENTRIES: [SettingsEntry; 2] = [SettingsEntry::Volume, SettingsEntry::UiScale];
+// Distance of each entry from the middle of the screen
+const RADIUS: f32 = 140.0;
+const ENTRY_WIDTH: f32 = 260.0;
+const ENTRY_HEIGHT: f32 = 56.0;
+// Fraction of full stick travel before the stick picks an entry
+const STICK_THRESHOLD: f32 = 0.5;
+
+// Open while the wheel is up, in place of the pause menu. The match stays paused under it
+// and the pause menu comes back once it's removed
+#[derive(Resource, Default)]
+pub struct QuickSettingsOpen {
+    selected: usize,
+}
+
+#[derive(Component)]
+struct QuickSettingsWheel;
+
+#[derive(Component)]
+struct WheelEntry(usize);
+
+#[derive(Component)]
+struct WheelLabel(usize);
+
+impl Plugin for QuickSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                spawn_wheel.run_if(resource_added::<QuickSettingsOpen>),
+                (wheel_input, update_wheel).chain().run_if(resource_exists::<QuickSettingsOpen>),
+            )
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), close_on_exit);
+    }
+}
+
+// Left edge and top of an entry, its center on the circle around the middle of the screen
+fn entry_position(index: usize) -> (Val, Val) {
+    let angle = std::f32::consts::TAU * index as f32 / ENTRIES.len() as f32;
+    let (x, y) = (angle.sin() * RADIUS, -angle.cos() * RADIUS);
+    (Val::Px(x - ENTRY_WIDTH / 2.0), Val::Px(y - ENTRY_HEIGHT / 2.0))
+}
+
+// The entry closest to where the stick points, once it's pushed far enough
+fn entry_at(stick: Vec2) -> Option<usize> {
+    if stick.length() < STICK_THRESHOLD {
+        return None;
+    }
+    // Clockwise from straight up, like the entries
+    let angle = stick.x.atan2(stick.y).rem_euclid(std::f32::consts::TAU);
+    let slice = std::f32::consts::TAU / ENTRIES.len() as f32;
+    Some((angle / slice).round() as usize % ENTRIES.len())
+}
+
+fn spawn_wheel(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..full_screen()
+            },
+            SafeArea,
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            QuickSettingsWheel,
+        ))
+        .with_children(|parent| {
+            // Zero-sized anchor in the middle, the entries hang off it
+            parent
+                .spawn(Node {
+                    width: Val::Px(0.0),
+                    height: Val::Px(0.0),
+                    ..default()
+                })
+                .with_children(|center| {
+                    center.spawn((
+                        Node {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(-ENTRY_WIDTH / 2.0),
+                            top: Val::Px(-20.0),
+                            width: Val::Px(ENTRY_WIDTH),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        Text::default(),
+                        Localized("quick_settings.title"),
+                        TextFont {
+                            font_size: 32.0,
+                            ..default()
+                        },
+                        TextColor(Color::WHITE),
+                    ));
+                    for index in 0..ENTRIES.len() {
+                        let (left, top) = entry_position(index);
+                        center
+                            .spawn((
+                                Node {
+                                    position_type: PositionType::Absolute,
+                                    left,
+                                    top,
+                                    width: Val::Px(ENTRY_WIDTH),
+                                    height: Val::Px(ENTRY_HEIGHT),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                BackgroundColor(NORMAL_BUTTON),
+                                WheelEntry(index),
+                            ))
+                            .with_children(|entry| {
+                                entry.spawn((
+                                    Text::new(""),
+                                    TextFont {
+                                        font_size: 24.0,
+                                        ..default()
+                                    },
+                                    TextColor(Color::WHITE),
+                                    WheelLabel(index),
+                                ));
+                            });
+                    }
+                });
+            parent.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(40.0),
+                    ..default()
+                },
+                Text::default(),
+                Localized("quick_settings.hint"),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.8, 0.8, 0.8)),
+            ));
+        });
+}
+
+// The stick or up and down pick an entry, the shoulder buttons, left and right or A change
+// it, B, Start or Esc go back to the pause menu
+fn wheel_input(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    mut open: ResMut<QuickSettingsOpen>,
+    mut settings: ResMut<Settings>,
+    wheels: Query<Entity, With<QuickSettingsWheel>>,
+) {
+    let mut by = 0;
+    let mut step_selection = 0;
+    let mut back = keys.just_pressed(KeyCode::Escape) || keys.just_pressed(KeyCode::Backspace);
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        step_selection -= 1;
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        step_selection += 1;
+    }
+    if keys.just_pressed(KeyCode::ArrowLeft) {
+        by -= 1;
+    }
+    if keys.just_pressed(KeyCode::ArrowRight) || keys.just_pressed(KeyCode::Enter) {
+        by += 1;
+    }
+    for gamepad in gamepads.iter() {
+        if let Some(index) = entry_at(gamepad.left_stick())
+            && open.selected != index
+        {
+            open.selected = index;
+        }
+        if gamepad.just_pressed(GamepadButton::DPadUp) {
+            step_selection -= 1;
+        }
+        if gamepad.just_pressed(GamepadButton::DPadDown) {
+            step_selection += 1;
+        }
+        for button in [GamepadButton::LeftTrigger, GamepadButton::LeftTrigger2, GamepadButton::DPadLeft] {
+            if gamepad.just_pressed(button) {
+                by -= 1;
+            }
+        }
+        for button in [GamepadButton::RightTrigger, GamepadButton::RightTrigger2, GamepadButton::DPadRight, GamepadButton::South] {
+            if gamepad.just_pressed(button) {
+                by += 1;
+            }
+        }
+        back |= gamepad.just_pressed(GamepadButton::East) || gamepad.just_pressed(GamepadButton::Start);
+    }
+    if step_selection != 0 {
+        open.selected = (open.selected as i32 + step_selection).rem_euclid(ENTRIES.len() as i32) as usize;
+    }
+    if by != 0 {
+        adjust(&mut settings, ENTRIES[open.selected], by);
+    }
+    if back {
+        for entity in wheels.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        commands.remove_resource::<QuickSettingsOpen>();
+        if let Err(err) = settings.save() {
+            warn!("can't save settings: {err}");
+        }
+    }
+}
+
+fn update_wheel(
+    open: Res<QuickSettingsOpen>,
+    (settings, locale): (Res<Settings>, Res<Locale>),
+    mut entries: Query<(&WheelEntry, &mut BackgroundColor)>,
+    mut labels: Query<(&WheelLabel, &mut Text)>,
+) {
+    for (entry, mut color) in entries.iter_mut() {
+        let wanted = if entry.0 == open.selected { FOCUSED_BUTTON } else { NORMAL_BUTTON };
+        if color.0 != wanted {
+            color.0 = wanted;
+        }
+    }
+    for (label, mut text) in labels.iter_mut() {
+        let wanted = ENTRIES[label.0].label(&settings, &locale);
+        if text.0 != wanted {
+            text.0 = wanted;
+        }
+    }
+}
+
+fn close_on_exit(mut commands: Commands, wheels: Query<Entity, With<QuickSettingsWheel>>) {
+    for entity in wheels.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<QuickSettingsOpen>();
+}
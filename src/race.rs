@@ -0,0 +1,501 @@
+use std::collections::BTreeMap;
+use bevy::prelude::*;
+use bevy_ggrs::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::build_info::BuildInfo;
+use crate::game::{Player, SessionMode};
+use crate::input::{Config, PlayerInput};
+use crate::level::ActiveLevel;
+use crate::locale::Locale;
+use crate::notifications::Notify;
+use crate::presentation::Appearance;
+use crate::rules::{GameModeDef, GameRulesApp};
+use crate::settings::Settings;
+use crate::ui_layout::SafeArea;
+
+pub struct RacePlugin;
+
+const LEADERBOARD_PATH: &str = "race_times.ron";
+#[cfg(not(target_arch = "wasm32"))]
+const GHOST_DIR: &str = "ghosts";
+const LEADERBOARD_SIZE: usize = 10;
+const RESTART_KEY: KeyCode = KeyCode::KeyR;
+const EXPORT_KEY: KeyCode = KeyCode::KeyE;
+// Plays back the best run, it never touches the racer
+pub const GHOST_HANDLE: usize = 1;
+const GHOST_ALPHA: f32 = 0.4;
+
+// Where both handles are on the course. Rollback state, so a checkpoint passed on a
+// mispredicted frame is taken back along with it
+#[derive(Resource, Clone, Default)]
+pub struct RaceProgress {
+    next_checkpoint: [usize; 2],
+    // Frames from the start to the finish line
+    finished: [Option<u32>; 2],
+}
+
+// The racer's inputs, one per frame from the start. Saved as the ghost if it's a new best
+#[derive(Resource, Default)]
+pub struct RaceRun {
+    inputs: Vec<PlayerInput>,
+    submitted: bool,
+    new_best: bool,
+}
+
+// The run being raced against, fed to the ghost handle a frame at a time
+#[derive(Resource)]
+pub struct Ghost {
+    inputs: Vec<PlayerInput>,
+    next: usize,
+}
+
+// On the ghost's player entity
+#[derive(Component)]
+pub struct RaceGhost;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RaceTime {
+    pub name: String,
+    pub frames: u32,
+    // Only the best time of a course keeps its inputs, that's the one raced against
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ghost: Vec<PlayerInput>,
+}
+
+// Best times on this machine for every course raced, by level hash so an edited course starts
+// a fresh board
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct Leaderboards {
+    courses: BTreeMap<u64, Vec<RaceTime>>,
+    // Ghosts from friends, the latest one for each course. Raced against when they're faster
+    // than the best time here, never put on the board
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    imported: BTreeMap<u64, RaceTime>,
+}
+
+#[derive(Component)]
+struct RaceHud;
+
+#[derive(Component)]
+struct RaceBoard;
+
+impl Ghost {
+    pub fn new(inputs: Vec<PlayerInput>) -> Self {
+        Self { inputs, next: 0 }
+    }
+
+    // Standing still once the recorded run is over
+    pub fn next_input(&mut self) -> PlayerInput {
+        let input = self.inputs.get(self.next).copied().unwrap_or_default();
+        self.next += 1;
+        input
+    }
+}
+
+impl RaceTime {
+    // Held inputs and for how many frames, a run only changes input every so often so this
+    // is what goes over the network
+    pub fn runs(&self) -> Vec<(PlayerInput, u32)> {
+        let mut runs: Vec<(PlayerInput, u32)> = Vec::new();
+        for input in &self.ghost {
+            match runs.last_mut() {
+                Some((held, frames)) if held == input => *frames += 1,
+                _ => runs.push((*input, 1)),
+            }
+        }
+        runs
+    }
+
+    pub fn from_runs(name: String, frames: u32, runs: &[(PlayerInput, u32)]) -> Result<Self, String> {
+        let length: u64 = runs.iter().map(|(_, frames)| u64::from(*frames)).sum();
+        if length != u64::from(frames) {
+            return Err(format!("ghost of {frames} frames has {length} frames of input"));
+        }
+        let ghost = runs.iter().flat_map(|(input, frames)| std::iter::repeat_n(*input, *frames as usize)).collect();
+        Ok(Self { name, frames, ghost })
+    }
+
+    // A header line for the course and one for the build, then the inputs in the format of a crash report's
+    // replay.txt, so a ghost can be audited like any other recording
+    pub fn to_ghost_file(&self, course: u64) -> String {
+        let mut text = format!("ghost {course:016x} {} {}\nbuild {}\n", self.frames, self.name, BuildInfo::CURRENT.label());
+        text.push_str("frame inputs (button bits per handle, then :axis when a stick was pushed)\n");
+        for (frame, input) in self.ghost.iter().enumerate() {
+            text.push_str(&format!("{frame} {}\n", input.to_field()));
+        }
+        text
+    }
+
+    pub fn parse_ghost_file(text: &str) -> Result<(u64, Self), String> {
+        let mut lines = text.lines();
+        let header = lines.next().unwrap_or_default();
+        let mut fields = header.splitn(4, ' ');
+        let (Some("ghost"), Some(course), Some(frames)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(format!("not a ghost file, it starts with {header:?}"));
+        };
+        let course = u64::from_str_radix(course, 16).map_err(|err| format!("course {course:?}: {err}"))?;
+        let frames = frames.parse::<u32>().map_err(|err| format!("frames {frames:?}: {err}"))?;
+        let name = fields.next().unwrap_or_default().to_string();
+        let mut ghost = Vec::new();
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            if fields.next().and_then(|frame| frame.parse::<u32>().ok()).is_none() {
+                continue;
+            }
+            let input = fields.next().unwrap_or_default();
+            ghost.push(PlayerInput::parse_field(input).map_err(|err| format!("{line:?}: {err}"))?);
+        }
+        if ghost.len() != frames as usize {
+            return Err(format!("ghost of {frames} frames has {} frames of input", ghost.len()));
+        }
+        Ok((course, Self { name, frames, ghost }))
+    }
+}
+
+impl Leaderboards {
+    pub fn load() -> Self {
+        let Ok(text) = std::fs::read_to_string(LEADERBOARD_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("ignoring broken {LEADERBOARD_PATH}: {err}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("leaderboards always serialize");
+        std::fs::write(LEADERBOARD_PATH, text)
+    }
+
+    pub fn times(&self, course: u64) -> &[RaceTime] {
+        self.courses.get(&course).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn best(&self, course: u64) -> Option<&RaceTime> {
+        self.times(course).first()
+    }
+
+    // The fastest run that can be raced against, from here or from a friend
+    pub fn ghost(&self, course: u64) -> Option<&RaceTime> {
+        let best = self.best(course).filter(|best| !best.ghost.is_empty());
+        match (best, self.imported.get(&course)) {
+            (Some(best), Some(imported)) if imported.frames < best.frames => Some(imported),
+            (None, imported) => imported,
+            (best, _) => best,
+        }
+    }
+
+    pub fn import(&mut self, course: u64, time: RaceTime) {
+        self.imported.insert(course, time);
+    }
+
+    // True if it's the new best. A tie doesn't take the ghost from the run that got there first
+    pub fn submit(&mut self, course: u64, time: RaceTime) -> bool {
+        let times = self.courses.entry(course).or_default();
+        let rank = times.iter().position(|other| time.frames < other.frames).unwrap_or(times.len());
+        times.insert(rank, time);
+        times.truncate(LEADERBOARD_SIZE);
+        for other in times.iter_mut().skip(1) {
+            other.ghost.clear();
+        }
+        rank == 0
+    }
+}
+
+// Minutes, seconds and milliseconds, from frames at 60 per second
+pub fn format_time(frames: u32) -> String {
+    let millis = u64::from(frames) * 1000 / 60;
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_game_mode(GameModeDef {
+            label_key: "menu.race",
+            session: SessionMode::Race,
+            mutators: &[],
+            unplayable: |level| (!level.is_race_course()).then_some("race.no_course"),
+        })
+        .rollback_resource_with_clone::<RaceProgress>()
+            .insert_resource(Leaderboards::load())
+            .add_systems(OnEnter(GameState::InGame), setup_race.run_if(resource_equals(SessionMode::Race)))
+            .add_systems(
+                Update,
+                (mark_ghost, submit_run, update_race_hud, restart_race, export_ghost)
+                    .chain()
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<RaceProgress>),
+            )
+            .add_systems(Update, import_dropped_ghosts)
+            .add_systems(OnExit(GameState::InGame), cleanup_race);
+    }
+}
+
+fn setup_race(mut commands: Commands, level: Res<ActiveLevel>, leaderboards: Res<Leaderboards>) {
+    commands.insert_resource(RaceProgress::default());
+    commands.insert_resource(RaceRun::default());
+    let ghost = leaderboards.ghost(level.0.content_hash());
+    commands.insert_resource(Ghost::new(ghost.map(|ghost| ghost.ghost.clone()).unwrap_or_default()));
+
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(60.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 32.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        TextLayout::new_with_justify(JustifyText::Center),
+        RaceHud,
+    ));
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(60.0),
+            right: Val::Px(20.0),
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+        RaceBoard,
+    ));
+}
+
+// Runs in the rollback schedule. A resimulated frame replaces what was recorded for it, and
+// nothing past the finish is kept
+pub fn record_run(
+    frame: Res<RollbackFrameCount>,
+    inputs: Res<PlayerInputs<Config>>,
+    progress: Res<RaceProgress>,
+    mut run: ResMut<RaceRun>,
+) {
+    if progress.finished[0].is_some() {
+        return;
+    }
+    run.inputs.truncate(frame.0.max(0) as usize);
+    run.inputs.push(inputs[0].0);
+}
+
+// Checkpoints count once the player's center is inside them, in the order the level lists
+// them. Crossing the last one on frame n is a time of n + 1 frames
+pub fn pass_checkpoints(
+    frame: Res<RollbackFrameCount>,
+    level: Res<ActiveLevel>,
+    mut progress: ResMut<RaceProgress>,
+    players: Query<(&Player, &Transform)>,
+) {
+    let checkpoints: Vec<_> = level.0.checkpoints().collect();
+    for (player, transform) in players.iter() {
+        let handle = player.handle.min(1);
+        if progress.finished[handle].is_some() {
+            continue;
+        }
+        let next = progress.next_checkpoint[handle];
+        if !checkpoints.get(next).is_some_and(|checkpoint| checkpoint.contains(transform.translation.truncate())) {
+            continue;
+        }
+        progress.next_checkpoint[handle] = next + 1;
+        if next + 1 == checkpoints.len() {
+            progress.finished[handle] = Some(frame.0.max(0) as u32 + 1);
+        }
+    }
+}
+
+// The ghost wears the racer's look, see-through, and isn't shown at all without a run to play
+fn mark_ghost(
+    mut commands: Commands,
+    ghost: Res<Ghost>,
+    mut players: Query<(Entity, &Player, &mut Appearance, Has<RaceGhost>)>,
+) {
+    let Some(racer) = players.iter().find(|(_, player, ..)| player.handle == 0).map(|(_, _, appearance, _)| appearance.clone()) else {
+        return;
+    };
+    let alpha = if ghost.inputs.is_empty() { 0.0 } else { GHOST_ALPHA };
+    for (entity, player, mut appearance, marked) in players.iter_mut() {
+        if player.handle != GHOST_HANDLE {
+            continue;
+        }
+        if !marked {
+            commands.entity(entity).insert(RaceGhost);
+        }
+        let color = racer.sprite.color.with_alpha(alpha);
+        if appearance.sprite.color != color || appearance.sprite.image != racer.sprite.image || appearance.texel_scale != racer.texel_scale {
+            appearance.sprite.color = color;
+            appearance.sprite.image = racer.sprite.image.clone();
+            appearance.sprite.anchor = racer.sprite.anchor;
+            appearance.texel_scale = racer.texel_scale;
+        }
+    }
+}
+
+fn submit_run(
+    level: Res<ActiveLevel>,
+    settings: Res<Settings>,
+    progress: Res<RaceProgress>,
+    mut run: ResMut<RaceRun>,
+    mut leaderboards: ResMut<Leaderboards>,
+) {
+    let Some(frames) = progress.finished[0] else {
+        return;
+    };
+    if run.submitted {
+        return;
+    }
+    run.submitted = true;
+    let time = RaceTime { name: settings.player_name.clone(), frames, ghost: run.inputs.clone() };
+    info!("race finished in {} ({frames} frames)", format_time(frames));
+    run.new_best = leaderboards.submit(level.0.content_hash(), time);
+    if let Err(err) = leaderboards.save() {
+        warn!("can't save {LEADERBOARD_PATH}: {err}");
+    }
+}
+
+fn update_race_hud(
+    level: Res<ActiveLevel>,
+    locale: Res<Locale>,
+    leaderboards: Res<Leaderboards>,
+    (progress, run, frame): (Res<RaceProgress>, Res<RaceRun>, Option<Res<RollbackFrameCount>>),
+    mut huds: Query<&mut Text, (With<RaceHud>, Without<RaceBoard>)>,
+    mut boards: Query<&mut Text, (With<RaceBoard>, Without<RaceHud>)>,
+) {
+    let course = level.0.content_hash();
+    let total = level.0.checkpoints().count();
+    let label = match progress.finished[0] {
+        Some(frames) => {
+            let key = if run.new_best { "race.new_best" } else { "race.finished" };
+            format!("{}\n{}", locale.format(key, &[("time", &format_time(frames))]), locale.get("race.restart"))
+        }
+        None => {
+            let frames = frame.map_or(0, |frame| frame.0.max(0) as u32);
+            let checkpoint = (progress.next_checkpoint[0] + 1).min(total);
+            format!(
+                "{}\n{}",
+                format_time(frames),
+                locale.format("race.checkpoint", &[("checkpoint", &checkpoint), ("total", &total)]),
+            )
+        }
+    };
+    for mut text in huds.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+
+    if !leaderboards.is_changed() && !locale.is_changed() && !boards.iter().any(|text| text.0.is_empty()) {
+        return;
+    }
+    let times = leaderboards.times(course);
+    let mut board = locale.get("race.leaderboard").to_string();
+    if times.is_empty() {
+        board.push('\n');
+        board.push_str(locale.get("race.no_times"));
+    }
+    for (rank, time) in times.iter().enumerate() {
+        board.push_str(&format!("\n{:>2}. {}  {}", rank + 1, format_time(time.frames), time.name));
+    }
+    for mut text in boards.iter_mut() {
+        text.0 = board.clone();
+    }
+}
+
+// Passing through NextMatch rebuilds the course, so every run starts from the same frame 0
+fn restart_race(keys: Res<ButtonInput<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keys.just_pressed(RESTART_KEY) {
+        next_state.set(GameState::NextMatch);
+    }
+}
+
+// Writes the ghost being raced against, or the one just set, to a file to pass on
+fn export_ghost(
+    keys: Res<ButtonInput<KeyCode>>,
+    level: Res<ActiveLevel>,
+    leaderboards: Res<Leaderboards>,
+    locale: Res<Locale>,
+    mut notify: EventWriter<Notify>,
+) {
+    if !keys.just_pressed(EXPORT_KEY) {
+        return;
+    }
+    let course = level.0.content_hash();
+    let Some(ghost) = leaderboards.ghost(course) else {
+        notify.send(Notify::warning(locale.get("toast.no_ghost")));
+        return;
+    };
+    let name = format!("{}-{}.ghost.txt", level.0.name, ghost.frames);
+    let text = ghost.to_ghost_file(course);
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = std::path::Path::new(GHOST_DIR).join(&name);
+        let written = std::fs::create_dir_all(GHOST_DIR).and_then(|()| std::fs::write(&path, text));
+        match written {
+            Ok(()) => notify.send(Notify::info(locale.format("toast.ghost_exported", &[("path", &path.display())]))),
+            Err(err) => notify.send(Notify::error(locale.format("toast.ghost_export_failed", &[("reason", &err)]))),
+        };
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        crate::web::save_file(&name, &text);
+        notify.send(Notify::info(locale.format("toast.ghost_exported", &[("path", &name)])));
+    }
+}
+
+// A ghost file dropped on the window is kept to race against, wherever the game is at
+fn import_dropped_ghosts(
+    mut drops: EventReader<FileDragAndDrop>,
+    mut leaderboards: ResMut<Leaderboards>,
+    locale: Res<Locale>,
+    mut notify: EventWriter<Notify>,
+) {
+    for drop in drops.read() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = drop else {
+            continue;
+        };
+        let parsed = std::fs::read_to_string(path_buf)
+            .map_err(|err| err.to_string())
+            .and_then(|text| RaceTime::parse_ghost_file(&text));
+        match parsed {
+            Ok((course, time)) => {
+                notify.send(Notify::info(ghost_received(&locale, &time)));
+                leaderboards.import(course, time);
+                if let Err(err) = leaderboards.save() {
+                    warn!("can't save {LEADERBOARD_PATH}: {err}");
+                }
+            }
+            Err(err) => {
+                warn!("can't import ghost {}: {err}", path_buf.display());
+                notify.send(Notify::error(locale.format("toast.ghost_import_failed", &[("reason", &err)])));
+            }
+        }
+    }
+}
+
+pub fn ghost_received(locale: &Locale, time: &RaceTime) -> String {
+    locale.format("toast.ghost_received", &[("name", &time.name), ("time", &format_time(time.frames))])
+}
+
+type RaceUi = Or<(With<RaceHud>, With<RaceBoard>)>;
+
+fn cleanup_race(mut commands: Commands, huds: Query<Entity, RaceUi>) {
+    for entity in huds.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<RaceProgress>();
+    commands.remove_resource::<RaceRun>();
+    commands.remove_resource::<Ghost>();
+}
@@ -0,0 +1,146 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::locale::{Locale, Localized};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct ReconnectPlugin;
+
+// How long a silent opponent gets to come back before the match is given up. GGRS holds the
+// simulation once it runs out of frames to predict, so nothing is lost while it waits
+pub const GRACE_SECS: u64 = 10;
+// Turns per second of the spinner
+const SPIN_SPEED: f32 = 1.5;
+
+// Up while the opponent's packets have stopped coming, see game::report_session_events
+#[derive(Resource)]
+pub struct Reconnecting {
+    // Seconds until GGRS declares the opponent gone
+    remaining: f32,
+}
+
+#[derive(Component)]
+struct ReconnectOverlay;
+
+#[derive(Component)]
+struct Spinner;
+
+#[derive(Component)]
+struct Countdown;
+
+impl Reconnecting {
+    // From the timeout GGRS reports with the interruption, in milliseconds
+    pub fn new(disconnect_timeout: u128) -> Self {
+        Self { remaining: disconnect_timeout as f32 / 1000.0 }
+    }
+}
+
+impl Plugin for ReconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                spawn_overlay.run_if(resource_added::<Reconnecting>),
+                update_overlay.run_if(resource_exists::<Reconnecting>),
+                despawn_overlay.run_if(resource_removed::<Reconnecting>),
+            )
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        )
+        .add_systems(OnExit(GameState::InGame), cleanup_overlay);
+    }
+}
+
+fn spawn_overlay(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(24.0),
+                ..full_screen()
+            },
+            SafeArea,
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.5)),
+            // Over the pause menu and everything else in the match
+            GlobalZIndex(10),
+            ReconnectOverlay,
+        ))
+        .with_children(|parent| {
+            // A dot going round the middle of its box
+            parent
+                .spawn((
+                    Node {
+                        width: Val::Px(48.0),
+                        height: Val::Px(48.0),
+                        ..default()
+                    },
+                    Spinner,
+                ))
+                .with_children(|spinner| {
+                    spinner.spawn((
+                        Node {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(19.0),
+                            top: Val::Px(0.0),
+                            width: Val::Px(10.0),
+                            height: Val::Px(10.0),
+                            ..default()
+                        },
+                        BackgroundColor(Color::WHITE),
+                        BorderRadius::MAX,
+                    ));
+                });
+            parent.spawn((
+                Text::default(),
+                Localized("reconnect.title"),
+                TextFont {
+                    font_size: 32.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 22.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                Countdown,
+            ));
+        });
+}
+
+// Real time, the match clock may be paused or stalled under it
+fn update_overlay(
+    time: Res<Time<Real>>,
+    mut reconnecting: ResMut<Reconnecting>,
+    locale: Res<Locale>,
+    mut spinners: Query<&mut Transform, With<Spinner>>,
+    mut countdowns: Query<&mut Text, With<Countdown>>,
+) {
+    reconnecting.remaining = (reconnecting.remaining - time.delta_secs()).max(0.0);
+    for mut transform in spinners.iter_mut() {
+        transform.rotate_z(-std::f32::consts::TAU * SPIN_SPEED * time.delta_secs());
+    }
+    let label = locale.format("reconnect.countdown", &[("seconds", &reconnecting.remaining.ceil())]);
+    for mut text in countdowns.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+fn despawn_overlay(mut commands: Commands, overlays: Query<Entity, With<ReconnectOverlay>>) {
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn cleanup_overlay(mut commands: Commands, overlays: Query<Entity, With<ReconnectOverlay>>) {
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<Reconnecting>();
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::game::NUM_PLAYERS;
+
+// Where a player looks for strangers first. Every region is a pairing room of its own on the
+// matchbox server, so players meet people close by before anyone further away
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Region {
+    #[default]
+    Europe,
+    NorthAmerica,
+    SouthAmerica,
+    Asia,
+    Oceania,
+}
+
+// Present from picking quick play until leaving the match, which region the socket is in
+#[derive(Resource, Debug)]
+pub struct QuickPlay {
+    // The home region and then its neighbours, nearest first
+    order: [Region; 5],
+    index: usize,
+    // The region that found an opponent has been announced
+    pub reported: bool,
+}
+
+impl Region {
+    pub const ALL: [Region; 5] =
+        [Region::Europe, Region::NorthAmerica, Region::SouthAmerica, Region::Asia, Region::Oceania];
+
+    fn id(self) -> &'static str {
+        match self {
+            Region::Europe => "eu",
+            Region::NorthAmerica => "na",
+            Region::SouthAmerica => "sa",
+            Region::Asia => "asia",
+            Region::Oceania => "oce",
+        }
+    }
+
+    pub fn label_key(self) -> &'static str {
+        match self {
+            Region::Europe => "region.eu",
+            Region::NorthAmerica => "region.na",
+            Region::SouthAmerica => "region.sa",
+            Region::Asia => "region.asia",
+            Region::Oceania => "region.oce",
+        }
+    }
+
+    // The other regions by how far their players tend to be, closest first
+    fn neighbours(self) -> [Region; 4] {
+        match self {
+            Region::Europe => [Region::NorthAmerica, Region::Asia, Region::SouthAmerica, Region::Oceania],
+            Region::NorthAmerica => [Region::SouthAmerica, Region::Europe, Region::Asia, Region::Oceania],
+            Region::SouthAmerica => [Region::NorthAmerica, Region::Europe, Region::Oceania, Region::Asia],
+            Region::Asia => [Region::Oceania, Region::Europe, Region::NorthAmerica, Region::SouthAmerica],
+            Region::Oceania => [Region::Asia, Region::NorthAmerica, Region::SouthAmerica, Region::Europe],
+        }
+    }
+
+    // The region's pairing room, two strangers at a time like the shared room
+    pub fn room_url(self, server: &str) -> String {
+        format!("{server}/extreme_bevy_{}?next={NUM_PLAYERS}", self.id())
+    }
+}
+
+impl QuickPlay {
+    pub fn new(home: Region) -> Self {
+        let [a, b, c, d] = home.neighbours();
+        Self { order: [home, a, b, c, d], index: 0, reported: false }
+    }
+
+    pub fn region(&self) -> Region {
+        self.order[self.index]
+    }
+
+    // On to the next region, back home after the furthest, so the search goes on until the
+    // player cancels
+    pub fn advance(&mut self) {
+        self.index = (self.index + 1) % self.order.len();
+    }
+}
@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::PeerId;
+
+// Seed shared by every peer in the match, used for anything random in the simulation
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MatchSeed(pub u64);
+
+impl MatchSeed {
+    // Every peer sees the same set of ids, so sorting and hashing them gives the
+    // same seed on both sides without an extra round trip
+    pub fn from_peers(peers: &[PeerId]) -> Self {
+        let mut sorted = peers.to_vec();
+        sorted.sort();
+
+        let bytes: Vec<u8> = sorted.iter().flat_map(|peer| *peer.0.as_bytes()).collect();
+        Self(fnv1a(&bytes))
+    }
+
+    // A fresh seed per set, derived from the match seed. Set 0 keeps the match seed as is
+    pub fn for_set(self, set: u32) -> Self {
+        Self(self.0 ^ (set as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+}
+
+// FNV-1a, so hashes don't depend on std's hasher implementation and match across builds
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+// SplitMix64. Only integer math internally, so it produces the same sequence on every platform
+#[derive(Clone, Copy, Debug)]
+pub struct DeterministicRng {
+    state: u64,
+}
+
+impl DeterministicRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Returns a value in [min, max)
+    pub fn range_u32(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+        min + (self.next_u64() % (max - min) as u64) as u32
+    }
+
+    // Floats are derived from a quantized integer so both peers get bit-identical values
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        let steps = 1000;
+        let t = self.range_u32(0, steps + 1) as f32 / steps as f32;
+        min + (max - min) * t
+    }
+
+    pub fn chance(&mut self, percent: u32) -> bool {
+        self.range_u32(0, 100) < percent
+    }
+}
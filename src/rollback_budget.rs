@@ -0,0 +1,81 @@
+use bevy::prelude::*;
+use bevy_ggrs::RollbackFrameCount;
+use crate::GameState;
+use crate::settings::Settings;
+
+pub struct RollbackBudgetPlugin;
+
+// Even past the budget every third frame is drawn, so a bad connection stutters instead of
+// freezing the picture
+const MAX_SKIPPED_IN_A_ROW: u32 = 2;
+
+// How deep rollbacks go, measured in the rollback schedule and judged once per frame against
+// Settings::rollback_budget. The totals are for the profiling overlay
+#[derive(Resource, Default)]
+pub struct RollbackBudget {
+    // Newest frame ever simulated, anything at or below it is being resimulated
+    newest_frame: Option<i32>,
+    // Deepest resimulation since the last judgement
+    depth: u32,
+    // What this frame's presentation does, see presenting
+    skipping: bool,
+    skipped_in_a_row: u32,
+    pub rollbacks: u32,
+    pub deepest: u32,
+    pub over_budget: u32,
+    pub skipped: u32,
+}
+
+impl Plugin for RollbackBudgetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RollbackBudget>()
+            .add_systems(OnEnter(GameState::InGame), reset_budget)
+            .add_systems(Update, judge_frame.run_if(in_state(GameState::InGame)));
+    }
+}
+
+fn reset_budget(mut budget: ResMut<RollbackBudget>) {
+    *budget = RollbackBudget::default();
+}
+
+// Runs early in the rollback schedule, every simulated frame passes through it
+pub fn measure_rollback(frame: Res<RollbackFrameCount>, mut budget: ResMut<RollbackBudget>) {
+    let newest = match budget.newest_frame {
+        Some(newest) if frame.0 <= newest => newest,
+        _ => {
+            budget.newest_frame = Some(frame.0);
+            return;
+        }
+    };
+    // The first frame after the one loaded is the deepest this rollback goes
+    let depth = (newest - frame.0 + 1) as u32;
+    if depth > budget.depth {
+        if budget.depth == 0 {
+            budget.rollbacks += 1;
+        }
+        budget.depth = depth;
+        budget.deepest = budget.deepest.max(depth);
+    }
+}
+
+// After a rollback deeper than the budget, this frame's presentation work waits for the next
+// one. The simulation already caught up, only the drawing of it is skipped
+pub fn judge_frame(settings: Res<Settings>, mut budget: ResMut<RollbackBudget>) {
+    let over = budget.depth > settings.rollback_budget;
+    budget.depth = 0;
+    if over {
+        budget.over_budget += 1;
+    }
+    budget.skipping = over && budget.skipped_in_a_row < MAX_SKIPPED_IN_A_ROW;
+    if budget.skipping {
+        budget.skipped += 1;
+        budget.skipped_in_a_row += 1;
+    } else {
+        budget.skipped_in_a_row = 0;
+    }
+}
+
+// Run condition for presentation work that can wait a frame
+pub fn presenting(budget: Res<RollbackBudget>) -> bool {
+    !budget.skipping
+}
@@ -0,0 +1,145 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::GgrsSchedule;
+use crate::GameState;
+use crate::game::{Player, SessionMode};
+use crate::level::LevelDef;
+use crate::skins::{CharacterInfo, CharacterRoster};
+
+pub struct RulesPlugin;
+
+// Players fall at this fraction of normal gravity in moon training, the ball as it always does
+const MOON_GRAVITY: f32 = 0.4;
+
+// A way to play offered on the main menu. It runs on one of the session kinds, with the
+// mutators listed switched on
+#[derive(Clone, Debug)]
+pub struct GameModeDef {
+    pub label_key: &'static str,
+    pub session: SessionMode,
+    pub mutators: &'static [&'static str],
+    // None if the level can be played this way, otherwise the locale key saying why not
+    pub unplayable: fn(&LevelDef) -> Option<&'static str>,
+}
+
+// In the order they were registered, which is the order of the main menu
+#[derive(Resource, Default)]
+pub struct GameModes(pub(crate) Vec<GameModeDef>);
+
+// Rule changes that run inside the rollback schedule, after players move and before speeds
+// are clamped. Off unless the mode being played lists them
+#[derive(Resource, Default)]
+pub struct Mutators(Vec<&'static str>);
+
+// The mutators on for this match. Only a mode picked on the main menu sets them, online
+// matches play without since nothing makes both peers agree on them
+#[derive(Resource, Default, Clone, Debug, PartialEq, Eq)]
+pub struct ActiveMutators(Vec<&'static str>);
+
+#[derive(SystemSet, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MutatorSet;
+
+// For downstream crates and forks to add content from their own plugins, without touching
+// the game's. Everything registered is read once the app starts
+pub trait GameRulesApp {
+    fn register_game_mode(&mut self, mode: GameModeDef) -> &mut Self;
+    fn register_mutator<M>(&mut self, id: &'static str, systems: impl IntoSystemConfigs<M>) -> &mut Self;
+    fn register_character(&mut self, character: CharacterInfo) -> &mut Self;
+}
+
+impl GameRulesApp for App {
+    fn register_game_mode(&mut self, mode: GameModeDef) -> &mut Self {
+        self.world_mut().get_resource_or_insert_with(GameModes::default).0.push(mode);
+        self
+    }
+
+    // The systems have to be deterministic like the rest of the rollback schedule, and keep
+    // all their state in rollback components or resources
+    fn register_mutator<M>(&mut self, id: &'static str, systems: impl IntoSystemConfigs<M>) -> &mut Self {
+        self.world_mut().get_resource_or_insert_with(Mutators::default).0.push(id);
+        self.add_systems(
+            GgrsSchedule,
+            systems.in_set(MutatorSet).run_if(move |active: Res<ActiveMutators>| active.0.contains(&id)),
+        )
+    }
+
+    fn register_character(&mut self, character: CharacterInfo) -> &mut Self {
+        self.world_mut().get_resource_or_insert_with(CharacterRoster::default).0.push(character);
+        self
+    }
+}
+
+impl ActiveMutators {
+    pub fn of(mode: &GameModeDef) -> Self {
+        Self(mode.mutators.to_vec())
+    }
+
+    pub fn from_ids(ids: Vec<&'static str>) -> Self {
+        Self(ids)
+    }
+}
+
+impl Mutators {
+    // In the order they were registered
+    pub fn ids(&self) -> &[&'static str] {
+        &self.0
+    }
+}
+
+impl GameModes {
+    pub fn iter(&self) -> impl Iterator<Item = &GameModeDef> {
+        self.0.iter()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&GameModeDef> {
+        self.0.get(index)
+    }
+}
+
+impl Plugin for RulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameModes>()
+            .init_resource::<Mutators>()
+            .init_resource::<ActiveMutators>()
+            // Built the way a downstream plugin would add one
+            .register_mutator("moon", moon_gravity)
+            .register_game_mode(GameModeDef {
+                label_key: "menu.moon_training",
+                session: SessionMode::Training,
+                mutators: &["moon"],
+                unplayable: |_| None,
+            })
+            .configure_sets(GgrsSchedule, MutatorSet.run_if(in_state(GameState::InGame)))
+            .add_systems(Startup, check_mutators)
+            .add_systems(OnEnter(GameState::MainMenu), reset_mutators)
+            .add_systems(OnEnter(GameState::InGame), log_mutators);
+    }
+}
+
+// A mode naming a mutator nobody registered would quietly play without it
+fn check_mutators(modes: Res<GameModes>, mutators: Res<Mutators>) {
+    for mode in modes.iter() {
+        for id in mode.mutators {
+            if !mutators.0.contains(id) {
+                warn!("game mode {} lists mutator {id}, which isn't registered", mode.label_key);
+            }
+        }
+    }
+}
+
+// Set every frame rather than once, so rollback and respawns can't bring normal gravity back
+fn moon_gravity(mut players: Query<&mut GravityScale, With<Player>>) {
+    for mut scale in players.iter_mut() {
+        scale.set_if_neq(GravityScale(MOON_GRAVITY));
+    }
+}
+
+fn reset_mutators(mut active: ResMut<ActiveMutators>) {
+    active.set_if_neq(ActiveMutators::default());
+}
+
+fn log_mutators(active: Res<ActiveMutators>) {
+    if !active.0.is_empty() {
+        info!("mutators on: {}", active.0.join(", "));
+    }
+}
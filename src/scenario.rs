@@ -0,0 +1,373 @@
+use std::path::PathBuf;
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::RollbackFrameCount;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::ball::Ball;
+use crate::confirmed::ConfirmedFrame;
+use crate::drill::{ActiveDrill, DummyBehavior, Goal};
+use crate::game::Player;
+use crate::locale::{Locale, Localized};
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated};
+use crate::scoring::Score;
+use crate::text_input::{spawn_text_input, InputFilter, TextInput};
+use crate::training::InputHistory;
+
+pub struct ScenarioPlugin;
+
+const SCENARIO_DIR: &str = "assets/scenarios";
+const MANIFEST_FILE: &str = "manifest.ron";
+const TOGGLE_KEY: KeyCode = KeyCode::F6;
+const NAME_MAX_LEN: usize = 24;
+
+// Where everything that moves is at one moment of a match
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct BodyState {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct GameSnapshot {
+    // By handle
+    pub players: [BodyState; 2],
+    pub ball: BodyState,
+}
+
+// A named training setup, one file per scenario in assets/scenarios. Saved ones only have
+// positions, drills written by hand can add what the dummy does and a goal
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    pub snapshot: GameSnapshot,
+    #[serde(default, skip_serializing_if = "is_idle")]
+    pub dummy: DummyBehavior,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<Goal>,
+}
+
+fn is_idle(dummy: &DummyBehavior) -> bool {
+    *dummy == DummyBehavior::Idle
+}
+
+// Lists the saved scenarios in the order they were saved, so the panel doesn't depend on
+// how the file system sorts names. Files dropped into the folder without an entry are listed
+// after them
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct ScenarioManifest {
+    entries: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ManifestEntry {
+    name: String,
+    file: String,
+}
+
+// Applied at the start of the next simulated frame. Training with network feel rolls back,
+// so it's kept and applied again whenever that frame is resimulated, until it's confirmed
+#[derive(Resource)]
+pub struct PendingScenario {
+    snapshot: GameSnapshot,
+    frame: Option<i32>,
+}
+
+// Open while the scenario panel is up. The keyboard belongs to the panel then, see input
+#[derive(Resource)]
+pub struct ScenarioMenuOpen;
+
+#[derive(Component)]
+struct ScenarioPanel;
+
+#[derive(Component)]
+struct ScenarioName;
+
+#[derive(Component)]
+enum ScenarioAction {
+    Save,
+    Load(String),
+    Close,
+}
+
+impl BodyState {
+    fn of(transform: &Transform, velocity: &LinearVelocity) -> Self {
+        Self { position: transform.translation.truncate().to_array(), velocity: velocity.0.to_array() }
+    }
+
+    fn apply(&self, transform: &mut Transform, velocity: &mut LinearVelocity) {
+        transform.translation = Vec2::from(self.position).extend(transform.translation.z);
+        velocity.0 = Vec2::from(self.velocity);
+    }
+}
+
+impl Scenario {
+    pub fn from_ron(text: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(text)
+    }
+
+    fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).expect("scenarios always serialize")
+    }
+}
+
+impl ScenarioManifest {
+    fn path() -> PathBuf {
+        PathBuf::from(SCENARIO_DIR).join(MANIFEST_FILE)
+    }
+
+    fn load() -> Self {
+        let mut manifest = match std::fs::read_to_string(Self::path()) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|err| {
+                warn!("ignoring broken scenario manifest: {err}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        manifest.add_unlisted();
+        manifest
+    }
+
+    fn add_unlisted(&mut self) {
+        let Ok(dir) = std::fs::read_dir(SCENARIO_DIR) else {
+            return;
+        };
+        let mut files: Vec<String> = dir
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|file| file.ends_with(".ron") && file != MANIFEST_FILE)
+            .filter(|file| !self.entries.iter().any(|entry| entry.file == *file))
+            .collect();
+        files.sort();
+        for file in files {
+            let path = PathBuf::from(SCENARIO_DIR).join(&file);
+            match std::fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|text| {
+                Scenario::from_ron(&text).map_err(|err| err.to_string())
+            }) {
+                Ok(scenario) => self.entries.push(ManifestEntry { name: scenario.name, file }),
+                Err(err) => warn!("skipping scenario {}: {err}", path.display()),
+            }
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        std::fs::create_dir_all(SCENARIO_DIR)?;
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("the manifest always serializes");
+        std::fs::write(Self::path(), text)
+    }
+
+    // Saving under a name that's taken replaces that scenario
+    fn add(&mut self, scenario: &Scenario) -> std::io::Result<()> {
+        let file = format!("{}.ron", file_stem(&scenario.name));
+        std::fs::create_dir_all(SCENARIO_DIR)?;
+        std::fs::write(PathBuf::from(SCENARIO_DIR).join(&file), scenario.to_ron())?;
+        self.entries.retain(|entry| entry.name != scenario.name);
+        self.entries.push(ManifestEntry { name: scenario.name.clone(), file });
+        self.save()
+    }
+
+    fn read(&self, name: &str) -> Result<Scenario, String> {
+        let entry = self.entries.iter().find(|entry| entry.name == name).ok_or_else(|| format!("no scenario {name:?}"))?;
+        let path = PathBuf::from(SCENARIO_DIR).join(&entry.file);
+        let text = std::fs::read_to_string(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+        let scenario = Scenario::from_ron(&text).map_err(|err| format!("{}: {err}", path.display()))?;
+        scenario.dummy.check().map_err(|err| format!("{}: {err}", path.display()))?;
+        Ok(scenario)
+    }
+}
+
+// "Net defense" is saved as net_defense.ron
+fn file_stem(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (toggle_panel, scenario_action)
+                .chain()
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<InputHistory>),
+        )
+        .add_systems(OnExit(GameState::InGame), close_panel);
+    }
+}
+
+impl PendingScenario {
+    pub fn new(snapshot: GameSnapshot) -> Self {
+        Self { snapshot, frame: None }
+    }
+}
+
+pub fn capture(
+    players: &Query<(&Player, &Transform, &LinearVelocity)>,
+    balls: &Query<(&Transform, &LinearVelocity), With<Ball>>,
+) -> GameSnapshot {
+    let mut snapshot = GameSnapshot::default();
+    for (player, transform, velocity) in players.iter() {
+        if let Some(state) = snapshot.players.get_mut(player.handle) {
+            *state = BodyState::of(transform, velocity);
+        }
+    }
+    if let Ok((transform, velocity)) = balls.get_single() {
+        snapshot.ball = BodyState::of(transform, velocity);
+    }
+    snapshot
+}
+
+// Runs in the rollback schedule before anything moves. The rally goes live right away, a
+// serve would snap the ball back over the server
+pub fn apply_scenario(
+    mut commands: Commands,
+    (frame, confirmed): (Res<RollbackFrameCount>, ConfirmedFrame),
+    mut pending: ResMut<PendingScenario>,
+    mut score: Option<ResMut<Score>>,
+    mut players: Query<(&Player, &mut Transform, &mut LinearVelocity), Without<Ball>>,
+    mut balls: Query<(&mut Transform, &mut LinearVelocity, &mut Ball)>,
+) {
+    let applied_on = *pending.frame.get_or_insert(frame.0);
+    if confirmed.get() >= applied_on {
+        commands.remove_resource::<PendingScenario>();
+    }
+    if frame.0 != applied_on {
+        return;
+    }
+    for (player, mut transform, mut velocity) in players.iter_mut() {
+        if let Some(state) = pending.snapshot.players.get(player.handle) {
+            state.apply(&mut transform, &mut velocity);
+        }
+    }
+    for (mut transform, mut velocity, mut ball) in balls.iter_mut() {
+        pending.snapshot.ball.apply(&mut transform, &mut velocity);
+        ball.touch(None);
+    }
+    if let Some(score) = score.as_mut() {
+        score.serve = None;
+        score.rally_pause = 0;
+        score.rally_frames = 0;
+    }
+}
+
+fn toggle_panel(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    open: Option<Res<ScenarioMenuOpen>>,
+    panels: Query<Entity, With<ScenarioPanel>>,
+    locale: Res<Locale>,
+) {
+    if !keys.just_pressed(TOGGLE_KEY) {
+        return;
+    }
+    if open.is_some() {
+        despawn_panel(&mut commands, &panels);
+    } else {
+        spawn_panel(&mut commands, &ScenarioManifest::load(), &locale);
+    }
+}
+
+fn spawn_panel(commands: &mut Commands, manifest: &ScenarioManifest, locale: &Locale) {
+    commands.insert_resource(ScenarioMenuOpen);
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                right: Val::Px(40.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7)),
+            ScenarioPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(locale.get("scenario.title")),
+                TextFont {
+                    font_size: 30.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            spawn_text_input(parent, 0, TextInput::new("", NAME_MAX_LEN, InputFilter::Any, "scenario.name")).insert(ScenarioName);
+            parent.spawn((menu_button(1), ScenarioAction::Save)).with_children(|parent| {
+                parent.spawn((button_text(""), Localized("scenario.save")));
+            });
+            for (order, entry) in manifest.entries.iter().enumerate() {
+                parent
+                    .spawn((menu_button(order as u32 + 2), ScenarioAction::Load(entry.name.clone())))
+                    .with_children(|parent| {
+                        parent.spawn(button_text(entry.name.clone()));
+                    });
+            }
+            parent
+                .spawn((menu_button(manifest.entries.len() as u32 + 2), ScenarioAction::Close, CancelButton))
+                .with_children(|parent| {
+                    parent.spawn((button_text(""), Localized("scenario.close")));
+                });
+        });
+}
+
+fn despawn_panel(commands: &mut Commands, panels: &Query<Entity, With<ScenarioPanel>>) {
+    for entity in panels.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ScenarioMenuOpen>();
+}
+
+#[allow(clippy::type_complexity)]
+fn scenario_action(
+    mut commands: Commands,
+    mut activated: EventReader<MenuActivated>,
+    (actions, names, panels): (Query<&ScenarioAction>, Query<&TextInput, With<ScenarioName>>, Query<Entity, With<ScenarioPanel>>),
+    players: Query<(&Player, &Transform, &LinearVelocity)>,
+    balls: Query<(&Transform, &LinearVelocity), With<Ball>>,
+    locale: Res<Locale>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        let mut manifest = ScenarioManifest::load();
+        match actions.get(*entity) {
+            Ok(ScenarioAction::Save) => {
+                let Some(name) = names.get_single().ok().map(|input| input.value.trim().to_string()) else {
+                    continue;
+                };
+                if name.is_empty() {
+                    continue;
+                }
+                let scenario = Scenario {
+                    name,
+                    snapshot: capture(&players, &balls),
+                    dummy: DummyBehavior::Idle,
+                    success: None,
+                };
+                if let Err(err) = manifest.add(&scenario) {
+                    warn!("failed to save scenario {:?}: {err}", scenario.name);
+                    continue;
+                }
+                info!("saved scenario {:?}", scenario.name);
+                // Rebuilt so the new scenario shows up in the list
+                despawn_panel(&mut commands, &panels);
+                spawn_panel(&mut commands, &manifest, &locale);
+            }
+            Ok(ScenarioAction::Load(name)) => match manifest.read(name) {
+                Ok(scenario) => {
+                    commands.insert_resource(PendingScenario::new(scenario.snapshot));
+                    commands.insert_resource(ActiveDrill::new(scenario));
+                    despawn_panel(&mut commands, &panels);
+                }
+                Err(err) => warn!("failed to load scenario: {err}"),
+            },
+            Ok(ScenarioAction::Close) => despawn_panel(&mut commands, &panels),
+            Err(_) => {}
+        }
+    }
+}
+
+fn close_panel(mut commands: Commands, panels: Query<Entity, With<ScenarioPanel>>) {
+    despawn_panel(&mut commands, &panels);
+    commands.remove_resource::<PendingScenario>();
+}
@@ -0,0 +1,557 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+use crate::ball::{Ball, BALL_RADIUS};
+use crate::confirmed::{ConfirmedEventPlugin, ConfirmedWriter};
+use crate::determinism::debug_assert_strictly_ordered;
+use crate::game::{Player, SessionMode};
+use crate::handicap::Handicaps;
+use crate::level::{ActiveLevel, LevelDef, PieceKind};
+use crate::locale::Locale;
+use crate::match_schedule::MatchSchedule;
+use crate::perspective::MirroredView;
+use crate::rng::MatchSeed;
+use crate::serve::Serve;
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct ScoringPlugin;
+
+// How long the ball stays dead after a point before the next rally starts
+const RALLY_PAUSE_FRAMES: u32 = 90;
+// Height above the server's spawn point the ball is put back at
+const SERVE_HEIGHT: f32 = 2.0;
+// Slack for the floor contact test, the ball rests slightly above the surface
+const FLOOR_TOLERANCE: f32 = 0.05;
+// Break between two sets, long enough to read the intermission screen
+const INTERMISSION_FRAMES: u32 = 60 * 4;
+// In sudden death the ball drops from this fraction of the way up to the ceiling
+const SUDDEN_DEATH_DROP_HEIGHT: f32 = 0.7;
+const SUDDEN_DEATH_DROP_SPEED: f32 = 2.0;
+
+// A rally ended with the ball on the floor, sent once its frame is confirmed
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PointScored {
+    pub winner: usize,
+    pub landed_at: Vec2,
+    // How long the ball was in play after the serve
+    pub rally_frames: u32,
+    // This point decided the match
+    pub won_match: bool,
+}
+
+// Rules both peers play by for the whole match
+#[derive(Resource, Clone, Debug)]
+pub struct MatchRules {
+    // Players swap sides every this many points, 0 turns it off
+    pub switch_sides_every: u32,
+    // Points needed to take a set
+    pub target_points: u32,
+    // Past the target, keep playing until someone leads by two
+    pub win_by_two: bool,
+    // Sets needed to take the match, 2 makes it best of 3
+    pub sets_to_win: u32,
+    // Re-roll the stage's items and hazards at the start of every set
+    pub rerandomize_stage: bool,
+    // A win-by-two deuce tied at this many points each goes to sudden death, 0 turns it off
+    pub sudden_death_at: u32,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            switch_sides_every: 5,
+            target_points: 11,
+            win_by_two: true,
+            sets_to_win: 2,
+            rerandomize_stage: false,
+            sudden_death_at: 14,
+        }
+    }
+}
+
+impl MatchRules {
+    pub fn set_winner(&self, points: [u32; 2]) -> Option<usize> {
+        let (leader, trailer) = if points[0] >= points[1] { (0, 1) } else { (1, 0) };
+        let lead = points[leader] - points[trailer];
+        let required_lead = if self.win_by_two { 2 } else { 1 };
+        (points[leader] >= self.target_points && lead >= required_lead).then_some(leader)
+    }
+
+    // Who is one point away from winning a deuce, only meaningful with win_by_two
+    pub fn advantage(&self, points: [u32; 2]) -> Option<usize> {
+        if !self.win_by_two || self.set_winner(points).is_some() {
+            return None;
+        }
+        let deuce = points[0].min(points[1]) + 1 >= self.target_points;
+        match points[0].abs_diff(points[1]) {
+            1 if deuce => Some(usize::from(points[1] > points[0])),
+            _ => None,
+        }
+    }
+
+    pub fn goes_to_sudden_death(&self, points: [u32; 2]) -> bool {
+        self.win_by_two && self.sudden_death_at > 0 && points[0] == points[1] && points[0] >= self.sudden_death_at
+    }
+}
+
+// Where a set stands between points. Sudden death rallies start from a neutral ball drop
+// and the next point takes the set
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoundPhase {
+    #[default]
+    Regular,
+    SuddenDeath,
+}
+
+// Rollback state for points and rally sequencing. Side 0 is left of the net, side 1 right
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Score {
+    // Head starts are put on the board once this is set
+    pub started: bool,
+    // Points in the current set
+    pub points: [u32; 2],
+    pub sets: [u32; 2],
+    // Zero based index of the set being played
+    pub set: u32,
+    // The pause running is the break between two sets
+    pub intermission: bool,
+    pub sides_swapped: bool,
+    pub server: usize,
+    // Frames left until the next rally, 0 while the ball is live
+    pub rally_pause: u32,
+    // Set when the last point means a side switch once the pause ends
+    pub switch_pending: bool,
+    pub phase: RoundPhase,
+    // The server is aiming, the ball is held until they let go
+    pub serve: Option<Serve>,
+    // Frames the ball has been in play since the serve
+    pub rally_frames: u32,
+    pub winner: Option<usize>,
+}
+
+#[derive(Component)]
+struct ScoreText;
+
+#[derive(Component)]
+struct IntermissionScreen;
+
+// Line under the score for side switches, advantage and the final result
+#[derive(Component)]
+struct ScoreBanner;
+
+impl Score {
+    pub fn side_of(&self, handle: usize) -> usize {
+        (handle % 2) ^ usize::from(self.sides_swapped)
+    }
+
+    pub fn handle_on_side(&self, side: usize) -> usize {
+        side ^ usize::from(self.sides_swapped)
+    }
+
+    // Where the ball waits for the next serve, or drops from in sudden death
+    pub fn serve_position(&self, level: &LevelDef) -> Vec2 {
+        match self.phase {
+            RoundPhase::Regular => level.spawn_point(self.side_of(self.server)) + Vec2::new(0.0, SERVE_HEIGHT),
+            RoundPhase::SuddenDeath => Vec2::new(0.0, level.bounds().max.y * SUDDEN_DEATH_DROP_HEIGHT),
+        }
+    }
+
+    // Sudden death mirrors side 0's spawn so neither player starts closer to the drop
+    pub fn start_position(&self, level: &LevelDef, handle: usize) -> Vec2 {
+        let side = self.side_of(handle);
+        match self.phase {
+            RoundPhase::Regular => level.spawn_point(side),
+            RoundPhase::SuddenDeath => {
+                let left = level.spawn_point(0);
+                Vec2::new(if side == 0 { left.x } else { -left.x }, left.y)
+            }
+        }
+    }
+
+    pub fn rally_live(&self) -> bool {
+        self.rally_pause == 0
+    }
+
+    pub fn award(&mut self, handle: usize, rules: &MatchRules) {
+        self.points[handle] += 1;
+        self.server = handle;
+        self.rally_pause = RALLY_PAUSE_FRAMES;
+
+        let set_winner = match self.phase {
+            RoundPhase::Regular => rules.set_winner(self.points),
+            RoundPhase::SuddenDeath => Some(handle),
+        };
+        if let Some(set_winner) = set_winner {
+            self.phase = RoundPhase::Regular;
+            self.sets[set_winner] += 1;
+            if self.sets[set_winner] >= rules.sets_to_win {
+                self.winner = Some(set_winner);
+            } else {
+                // Teams change ends between sets, the set's loser serves first
+                self.intermission = true;
+                self.switch_pending = true;
+                self.server = 1 - set_winner;
+                self.rally_pause = INTERMISSION_FRAMES;
+            }
+            return;
+        }
+
+        if rules.goes_to_sudden_death(self.points) {
+            self.phase = RoundPhase::SuddenDeath;
+        }
+
+        let total = self.points.iter().sum::<u32>();
+        if rules.switch_sides_every > 0 && total.is_multiple_of(rules.switch_sides_every) {
+            self.switch_pending = true;
+        }
+    }
+}
+
+impl Plugin for ScoringPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ConfirmedEventPlugin::<PointScored>::default())
+            .rollback_resource_with_clone::<Score>()
+            .init_resource::<MatchRules>()
+            // A race has no points to play for
+            .add_systems(OnEnter(GameState::InGame), setup_scoring.run_if(not(resource_equals(SessionMode::Race))))
+            .add_systems(Update, update_score_hud.run_if(in_state(GameState::InGame)))
+            .add_systems(OnExit(GameState::InGame), cleanup_scoring);
+    }
+}
+
+fn setup_scoring(mut commands: Commands) {
+    // The opening rally is served like every other one
+    commands.insert_resource(Score { serve: Some(Serve::default()), ..default() });
+
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(60.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 40.0,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        ScoreText,
+    ));
+
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(110.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 28.0,
+            ..default()
+        },
+        TextColor(Color::srgb(1.0, 0.9, 0.4)),
+        ScoreBanner,
+    ));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..full_screen()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7)),
+            Visibility::Hidden,
+            IntermissionScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 36.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                TextLayout::new_with_justify(JustifyText::Center),
+            ));
+        });
+}
+
+// True if the ball is resting on (or sunk into) a floor piece. Platforms don't count,
+// and this only looks at rollback state so every peer sees the same landing frame
+fn ball_on_floor(level: &ActiveLevel, position: Vec2) -> bool {
+    let bottom = position - Vec2::new(0.0, BALL_RADIUS + FLOOR_TOLERANCE);
+    level
+        .0
+        .pieces
+        .iter()
+        .any(|piece| piece.kind == PieceKind::Ground && piece.contains(bottom))
+}
+
+// Scores a landed ball, then restarts the rally once the pause runs out. Side switches only
+// ever happen here, at the boundary between two rallies
+pub fn update_rally(
+    mut score: ResMut<Score>,
+    rules: Res<MatchRules>,
+    level: Res<ActiveLevel>,
+    handicaps: Res<Handicaps>,
+    mut players: Query<(&mut Transform, &mut LinearVelocity, &mut Player), Without<Ball>>,
+    mut balls: Query<(&mut Transform, &mut LinearVelocity, &mut Ball)>,
+    mut points: ConfirmedWriter<PointScored>,
+) {
+    let Ok((mut ball_transform, mut ball_velocity, mut ball)) = balls.get_single_mut() else {
+        return;
+    };
+    // Nothing restarts once the match is decided
+    if score.winner.is_some() {
+        return;
+    }
+    // First simulated frame, the handicaps are settled by the time the session runs
+    if !score.started {
+        score.started = true;
+        score.points = handicaps.head_starts();
+    }
+
+    if score.rally_live() {
+        if score.serve.is_none() {
+            score.rally_frames += 1;
+        }
+        let position = ball_transform.translation.truncate();
+        if ball_on_floor(&level, position) {
+            let landed_side = usize::from(position.x >= 0.0);
+            let winner = score.handle_on_side(1 - landed_side);
+            score.award(winner, &rules);
+            info!("Point to player {winner}, score {:?}", score.points);
+            points.send(PointScored {
+                winner,
+                landed_at: position,
+                rally_frames: score.rally_frames,
+                won_match: score.winner.is_some(),
+            });
+            score.rally_frames = 0;
+        }
+        return;
+    }
+
+    score.rally_pause -= 1;
+    if score.rally_pause > 0 {
+        return;
+    }
+
+    if score.intermission {
+        score.intermission = false;
+        score.points = handicaps.head_starts();
+        score.set += 1;
+    }
+
+    if score.switch_pending {
+        score.switch_pending = false;
+        score.sides_swapped = !score.sides_swapped;
+    }
+
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(_, _, player)| player.handle);
+    debug_assert_strictly_ordered("update_rally", players.iter().map(|(_, _, player)| player.handle));
+
+    for (transform, velocity, player) in players.iter_mut() {
+        let side = score.side_of(player.handle);
+        transform.translation = score.start_position(&level.0, player.handle).extend(transform.translation.z);
+        velocity.0 = Vec2::ZERO;
+        player.facing_left = side == 1;
+        player.stun_frames = 0;
+        player.strike_cooldown = 0;
+    }
+
+    ball_transform.translation = score.serve_position(&level.0).extend(ball_transform.translation.z);
+    ball_velocity.0 = match score.phase {
+        RoundPhase::Regular => Vec2::ZERO,
+        RoundPhase::SuddenDeath => Vec2::new(0.0, -SUDDEN_DEATH_DROP_SPEED),
+    };
+    *ball = Ball::default();
+    score.serve = (score.phase == RoundPhase::Regular).then(Serve::default);
+}
+
+// Keeps the items and hazards in step with the set being played. The schedule is a pure
+// function of seed and set, so resimulating across a set boundary just regenerates it
+pub fn reroll_stage(
+    score: Res<Score>,
+    rules: Res<MatchRules>,
+    seed: Res<MatchSeed>,
+    mut schedule: ResMut<MatchSchedule>,
+) {
+    let set = if rules.rerandomize_stage { score.set } else { 0 };
+    if schedule.set != set {
+        *schedule = MatchSchedule::generate(seed.for_set(set));
+        schedule.set = set;
+    }
+}
+
+fn update_score_hud(
+    (score, mirrored): (Option<Res<Score>>, Res<MirroredView>),
+    rules: Res<MatchRules>,
+    locale: Res<Locale>,
+    mut score_text: Query<&mut Text, (With<ScoreText>, Without<ScoreBanner>)>,
+    mut banner_text: Query<&mut Text, (With<ScoreBanner>, Without<ScoreText>)>,
+    mut intermission: Query<(&mut Visibility, &Children), With<IntermissionScreen>>,
+    mut intermission_text: Query<&mut Text, (Without<ScoreText>, Without<ScoreBanner>)>,
+) {
+    let Some(score) = score else {
+        return;
+    };
+    if !score.is_changed() && !locale.is_changed() && !mirrored.is_changed() {
+        return;
+    }
+
+    // Scores are shown on the side each player is currently standing on, as it's drawn
+    let left = score.handle_on_side(usize::from(mirrored.0));
+    let right = score.handle_on_side(usize::from(!mirrored.0));
+    let label = locale.format(
+        "score.line",
+        &[
+            ("left", &(left + 1)),
+            ("left_sets", &score.sets[left]),
+            ("left_points", &score.points[left]),
+            ("right_points", &score.points[right]),
+            ("right_sets", &score.sets[right]),
+            ("right", &(right + 1)),
+        ],
+    );
+    for mut text in score_text.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+
+    let banner = if let Some(winner) = score.winner {
+        locale.format("score.match_won", &[("player", &(winner + 1))])
+    } else if score.intermission {
+        String::new()
+    } else if score.phase == RoundPhase::SuddenDeath {
+        locale.get("score.sudden_death").to_string()
+    } else if let Some(leader) = rules.advantage(score.points) {
+        locale.format("score.advantage", &[("player", &(leader + 1))])
+    } else if score.switch_pending {
+        locale.get("score.switch_sides").to_string()
+    } else {
+        String::new()
+    };
+    for mut text in banner_text.iter_mut() {
+        if text.0 != banner {
+            text.0 = banner.clone();
+        }
+    }
+
+    for (mut visibility, children) in intermission.iter_mut() {
+        visibility.set_if_neq(if score.intermission { Visibility::Inherited } else { Visibility::Hidden });
+        if !score.intermission {
+            continue;
+        }
+
+        // The loser of a set serves the next one, so the winner is the other handle
+        let just_won = 1 - score.server;
+        let summary = locale.format(
+            "score.intermission",
+            &[
+                ("set", &(score.set + 1)),
+                ("player", &(just_won + 1)),
+                ("left", &score.sets[0]),
+                ("right", &score.sets[1]),
+                ("next", &(score.set + 2)),
+            ],
+        );
+        for child in children.iter() {
+            if let Ok(mut text) = intermission_text.get_mut(*child) {
+                text.0 = summary.clone();
+            }
+        }
+    }
+}
+
+type ScoringUi = Or<(With<ScoreText>, With<ScoreBanner>, With<IntermissionScreen>)>;
+
+fn cleanup_scoring(
+    mut commands: Commands,
+    query: Query<Entity, ScoringUi>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<Score>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_winner() {
+        let win_by_two = MatchRules::default();
+        let win_by_one = MatchRules { win_by_two: false, ..default() };
+        let cases = [
+            (&win_by_two, [0, 0], None),
+            (&win_by_two, [10, 5], None),
+            (&win_by_two, [11, 9], Some(0)),
+            (&win_by_two, [9, 11], Some(1)),
+            (&win_by_two, [11, 10], None),
+            (&win_by_two, [10, 11], None),
+            (&win_by_two, [12, 10], Some(0)),
+            (&win_by_two, [10, 12], Some(1)),
+            (&win_by_two, [15, 14], None),
+            (&win_by_one, [11, 10], Some(0)),
+            (&win_by_one, [10, 11], Some(1)),
+            (&win_by_one, [10, 10], None),
+        ];
+        for (rules, points, expected) in cases {
+            assert_eq!(rules.set_winner(points), expected, "{points:?} win_by_two {}", rules.win_by_two);
+        }
+    }
+
+    #[test]
+    fn advantage() {
+        let win_by_two = MatchRules::default();
+        let win_by_one = MatchRules { win_by_two: false, ..default() };
+        let cases = [
+            (&win_by_two, [10, 9], None),
+            (&win_by_two, [10, 10], None),
+            (&win_by_two, [11, 10], Some(0)),
+            (&win_by_two, [10, 11], Some(1)),
+            (&win_by_two, [13, 12], Some(0)),
+            (&win_by_two, [12, 13], Some(1)),
+            (&win_by_two, [12, 10], None),
+            (&win_by_one, [11, 10], None),
+            (&win_by_one, [10, 11], None),
+        ];
+        for (rules, points, expected) in cases {
+            assert_eq!(rules.advantage(points), expected, "{points:?} win_by_two {}", rules.win_by_two);
+        }
+    }
+
+    #[test]
+    fn sudden_death_cap() {
+        let capped = MatchRules::default();
+        let uncapped = MatchRules { sudden_death_at: 0, ..default() };
+        let win_by_one = MatchRules { win_by_two: false, ..default() };
+        let cases = [
+            (&capped, [13, 13], false),
+            (&capped, [14, 14], true),
+            (&capped, [15, 15], true),
+            (&capped, [14, 13], false),
+            (&capped, [13, 14], false),
+            (&uncapped, [14, 14], false),
+            (&uncapped, [20, 20], false),
+            (&win_by_one, [14, 14], false),
+        ];
+        for (rules, points, expected) in cases {
+            assert_eq!(rules.goes_to_sudden_death(points), expected, "{points:?} {rules:?}");
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::*;
+use crate::GameState;
+use crate::ball::{Ball, BALL_RADIUS};
+use crate::game::Player;
+use crate::input::{Config, INPUT_LEFT, INPUT_RIGHT, INPUT_STRIKE};
+use crate::level::ActiveLevel;
+use crate::presentation::{player_color, Mirrors};
+use crate::scoring::Score;
+
+pub struct ServePlugin;
+
+// Three seconds to aim before the ball goes on its own
+pub const SERVE_FRAMES: u32 = 60 * 3;
+const SERVE_SPEED: f32 = 9.0;
+const ARROW_LENGTH: f32 = 1.5;
+const TIMER_RADIUS: f32 = BALL_RADIUS + 0.3;
+
+// Serve directions for side 0 from flat to steep, 30 to 70 degrees in 10 degree steps.
+// Written out rather than computed because sin and cos aren't guaranteed to round the same
+// on every platform, and the serve has to come out identical on both peers
+const SERVE_DIRECTIONS: [Vec2; 5] = [
+    Vec2::new(0.866_025_4, 0.5),
+    Vec2::new(0.766_044_4, 0.642_787_6),
+    Vec2::new(0.642_787_6, 0.766_044_4),
+    Vec2::new(0.5, 0.866_025_4),
+    Vec2::new(0.342_020_1, 0.939_692_6),
+];
+
+// Rollback state while the server is aiming, lives on Score
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Serve {
+    pub frames_left: u32,
+    // Index into SERVE_DIRECTIONS
+    pub aim: usize,
+    // Strike was pressed during this serve, letting go of it serves
+    pub armed: bool,
+}
+
+impl Default for Serve {
+    fn default() -> Self {
+        Self {
+            frames_left: SERVE_FRAMES,
+            aim: SERVE_DIRECTIONS.len() / 2,
+            armed: false,
+        }
+    }
+}
+
+impl Serve {
+    // Mirrored for side 1, which serves to the left
+    pub fn direction(&self, side: usize) -> Vec2 {
+        let direction = SERVE_DIRECTIONS[self.aim];
+        if side == 0 { direction } else { Vec2::new(-direction.x, direction.y) }
+    }
+
+    fn rotate(&mut self, steps_up: i32) {
+        self.aim = (self.aim as i32 + steps_up).clamp(0, SERVE_DIRECTIONS.len() as i32 - 1) as usize;
+    }
+}
+
+impl Plugin for ServePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            draw_serve_indicator
+                .run_if(in_state(GameState::InGame))
+                .run_if(resource_exists::<Score>),
+        );
+    }
+}
+
+// Holds the ball above the server until they serve. Left and right rotate the aim the way
+// the arrow turns on screen, pressing and letting go of strike sends the ball
+pub fn update_serve(
+    mut score: ResMut<Score>,
+    level: Res<ActiveLevel>,
+    inputs: Res<PlayerInputs<Config>>,
+    mut players: Query<(&Player, &mut LinearVelocity), Without<Ball>>,
+    mut balls: Query<(&mut Transform, &mut LinearVelocity, &mut Ball)>,
+) {
+    let Some(mut serve) = score.serve else {
+        return;
+    };
+    let Ok((mut transform, mut velocity, mut ball)) = balls.get_single_mut() else {
+        return;
+    };
+    // The server jumped up and struck it instead, that counts as the serve
+    if ball.last_touch.is_some() {
+        score.serve = None;
+        return;
+    }
+
+    let server = score.server;
+    let side = score.side_of(server);
+    let input = inputs[server].0;
+    let Some((player, mut player_velocity)) = players.iter_mut().find(|(player, _)| player.handle == server) else {
+        return;
+    };
+
+    // Right turns the arrow clockwise, which is flatter when serving to the right
+    let clockwise = if side == 0 { -1 } else { 1 };
+    if player.just_pressed(input, INPUT_RIGHT) {
+        serve.rotate(clockwise);
+    }
+    if player.just_pressed(input, INPUT_LEFT) {
+        serve.rotate(-clockwise);
+    }
+    if player.just_pressed(input, INPUT_STRIKE) {
+        serve.armed = true;
+    }
+
+    // Left and right aim, so the server doesn't walk while serving
+    player_velocity.0.x = 0.0;
+
+    serve.frames_left = serve.frames_left.saturating_sub(1);
+    if (serve.armed && player.just_released(input, INPUT_STRIKE)) || serve.frames_left == 0 {
+        velocity.0 = serve.direction(side) * SERVE_SPEED;
+        ball.touch(Some(server));
+        score.serve = None;
+        return;
+    }
+
+    transform.translation = score.serve_position(&level.0).extend(transform.translation.z);
+    velocity.0 = Vec2::ZERO;
+    score.serve = Some(serve);
+}
+
+// Aim arrow out of the ball and a ring around it that empties as the serve timer runs down
+fn draw_serve_indicator(
+    mut gizmos: Gizmos,
+    score: Res<Score>,
+    balls: Query<Entity, With<Ball>>,
+    displays: Query<(&Mirrors, &Transform)>,
+) {
+    let Some(serve) = score.serve else {
+        return;
+    };
+    let Ok(ball) = balls.get_single() else {
+        return;
+    };
+    let Some((_, transform)) = displays.iter().find(|(mirrors, _)| mirrors.0 == ball) else {
+        return;
+    };
+
+    let center = transform.translation.truncate();
+    let color = player_color(score.server);
+    let direction = serve.direction(score.side_of(score.server));
+    gizmos.arrow_2d(center + direction * TIMER_RADIUS, center + direction * (TIMER_RADIUS + ARROW_LENGTH), color);
+
+    let remaining = serve.frames_left as f32 / SERVE_FRAMES as f32;
+    gizmos.arc_2d(
+        Isometry2d::from_translation(center),
+        std::f32::consts::TAU * remaining,
+        TIMER_RADIUS,
+        color,
+    );
+}
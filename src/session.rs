@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use bevy_ggrs::ggrs::{P2PSession, PlayerType, SessionBuilder, SpectatorSession};
+use bevy_matchbox::prelude::PeerId;
+use crate::game::NUM_PLAYERS;
+use crate::input::Config;
+use crate::lobby::Lineup;
+use crate::network_feel::LoopbackConditions;
+use crate::reconnect::GRACE_SECS;
+use crate::shared_channel::SharedChannel;
+
+// Every kind of match the game plays, and what its GGRS session is built from
+#[derive(Clone)]
+pub enum SessionSpec {
+    // This machine drives both handles. A check distance above 0 rolls back that many frames
+    // every frame to check the simulation comes out the same, 0 plays like a normal local game
+    Local { check_distance: usize },
+    // Training against the dummy on the far end of a loopback, see network_feel
+    Loopback { input_delay: usize, conditions: LoopbackConditions },
+    // One of the two players of an online match. The host also carries the spectators
+    Online { channel: SharedChannel, local: PeerId, lineup: Lineup, input_delay: usize },
+    // Watching the lineup's host
+    Spectator { channel: SharedChannel, lineup: Lineup },
+}
+
+// The one place GGRS sessions are built and torn down. Matches start one on entering the
+// game and it goes with them on leaving, see game::cleanup_game
+#[derive(Resource, Default)]
+pub struct SessionManager {
+    // What the running session was built from, None with no session
+    running: Option<SessionSpec>,
+}
+
+impl SessionManager {
+    pub fn start(&mut self, commands: &mut Commands, spec: SessionSpec) -> Result<(), String> {
+        let session = match &spec {
+            SessionSpec::Local { check_distance } => SessionBuilder::<Config>::new()
+                .with_num_players(NUM_PLAYERS)
+                .with_check_distance(*check_distance)
+                .start_synctest_session()
+                .map(Session::SyncTest)
+                .map_err(|err| err.to_string())?,
+            SessionSpec::Loopback { input_delay, conditions } => {
+                Session::P2P(crate::network_feel::start_session(commands, *input_delay, *conditions)?)
+            }
+            SessionSpec::Online { channel, local, lineup, input_delay } => {
+                Session::P2P(start_p2p_session(channel.clone(), *local, lineup, *input_delay)?)
+            }
+            SessionSpec::Spectator { channel, lineup } => Session::Spectator(start_spectator_session(channel.clone(), lineup)),
+        };
+        commands.insert_resource(session);
+        self.running = Some(spec);
+        Ok(())
+    }
+
+    // The input delay the running session was built with, for the players of a networked one
+    pub fn input_delay(&self) -> Option<usize> {
+        match self.running.as_ref()? {
+            SessionSpec::Loopback { input_delay, .. } | SessionSpec::Online { input_delay, .. } => Some(*input_delay),
+            SessionSpec::Local { .. } | SessionSpec::Spectator { .. } => None,
+        }
+    }
+
+    pub fn stop(&mut self, commands: &mut Commands) {
+        if self.running.take().is_some() {
+            commands.remove_resource::<Session<Config>>();
+        }
+    }
+}
+
+fn start_p2p_session(
+    channel: SharedChannel,
+    local: PeerId,
+    lineup: &Lineup,
+    delay: usize,
+) -> Result<P2PSession<Config>, String> {
+    info!("input delay {delay} frames");
+    // create a GGRS P2P session
+    let mut session_builder = SessionBuilder::<Config>::new()
+        .with_num_players(NUM_PLAYERS)
+        .with_input_delay(delay)
+        .with_disconnect_timeout(std::time::Duration::from_secs(GRACE_SECS));
+
+    for (handle, peer) in lineup.players.iter().enumerate() {
+        let player = if *peer == local { PlayerType::Local } else { PlayerType::Remote(*peer) };
+        session_builder = session_builder
+            .add_player(player, handle)
+            .map_err(|err| err.to_string())?;
+    }
+    // Spectators all watch through the handle 0 player, they take the handles after the players
+    if lineup.session_host() == local {
+        for (i, spectator) in lineup.queue.iter().enumerate() {
+            session_builder = session_builder
+                .add_player(PlayerType::Spectator(*spectator), NUM_PLAYERS + i)
+                .map_err(|err| err.to_string())?;
+        }
+    }
+
+    // start the GGRS session
+    session_builder.start_p2p_session(channel).map_err(|err| err.to_string())
+}
+
+fn start_spectator_session(channel: SharedChannel, lineup: &Lineup) -> SpectatorSession<Config> {
+    SessionBuilder::<Config>::new()
+        .with_num_players(NUM_PLAYERS)
+        .start_spectator_session(lineup.session_host(), channel)
+}
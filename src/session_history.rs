@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use crate::GameState;
+use crate::lobby::Lobby;
+use crate::locale::Locale;
+use crate::scoring::PointScored;
+use crate::ui_layout::SafeArea;
+
+pub struct SessionHistoryPlugin;
+
+// Matches won against each other tonight, for every pair who played. Kept until the game
+// closes, by name since a peer who drops and rejoins comes back with a new id
+#[derive(Resource, Default)]
+pub struct SessionHistory {
+    // Names in sorted order, wins in the same order
+    wins: HashMap<(String, String), [u32; 2]>,
+}
+
+// The running tally under the match banner once the winning point is confirmed
+#[derive(Component)]
+struct TallyText;
+
+impl SessionHistory {
+    fn record(&mut self, winner: &str, loser: &str) {
+        let (key, index) = if winner <= loser {
+            ((winner.to_string(), loser.to_string()), 0)
+        } else {
+            ((loser.to_string(), winner.to_string()), 1)
+        };
+        self.wins.entry(key).or_default()[index] += 1;
+    }
+
+    // Wins of each side against the other, None if they haven't finished a match yet
+    pub fn tally(&self, first: &str, second: &str) -> Option<[u32; 2]> {
+        if first <= second {
+            self.wins.get(&(first.to_string(), second.to_string())).copied()
+        } else {
+            self.wins.get(&(second.to_string(), first.to_string())).map(|[a, b]| [*b, *a])
+        }
+    }
+}
+
+impl Plugin for SessionHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SessionHistory>()
+            .add_systems(OnEnter(GameState::InGame), spawn_tally)
+            .add_systems(
+                Update,
+                record_match
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<Lobby>),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_tally);
+    }
+}
+
+fn spawn_tally(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(150.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 24.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+        TallyText,
+    ));
+}
+
+// Everyone in the room counts it, spectators included, so the tally reads the same for all
+fn record_match(
+    mut history: ResMut<SessionHistory>,
+    mut points: EventReader<PointScored>,
+    lobby: Res<Lobby>,
+    locale: Res<Locale>,
+    mut tallies: Query<&mut Text, With<TallyText>>,
+) {
+    let Some(point) = points.read().find(|point| point.won_match) else {
+        return;
+    };
+    let Some(lineup) = &lobby.lineup else {
+        return;
+    };
+    let winner = lobby.name(lineup.players[point.winner]);
+    let loser = lobby.name(lineup.players[1 - point.winner]);
+    history.record(&winner, &loser);
+
+    let [winner_wins, loser_wins] = history.tally(&winner, &loser).unwrap_or_default();
+    let label = locale.format(
+        "history.tonight",
+        &[("winner", &winner), ("winner_wins", &winner_wins), ("loser_wins", &loser_wins), ("loser", &loser)],
+    );
+    for mut text in tallies.iter_mut() {
+        text.0 = label.clone();
+    }
+}
+
+fn cleanup_tally(mut commands: Commands, tallies: Query<Entity, With<TallyText>>) {
+    for entity in tallies.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,441 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::GameState;
+use crate::game::{FRAME_MS, MAX_INPUT_DELAY};
+use crate::gameplay_constants::PhysicsQuality;
+use crate::handicap::Handicap;
+use crate::regions::Region;
+use crate::skins::Character;
+use crate::locale::{Locale, LANGUAGES};
+use crate::notifications::Notify;
+use crate::menu_nav::{button_text, menu_button, CancelButton, MenuActivated, MenuAdjusted};
+use crate::ui_layout::{full_screen, SafeArea};
+
+pub struct SettingsPlugin;
+
+const SETTINGS_PATH: &str = "settings.ron";
+const UI_SCALE_STEPS: [f32; 4] = [0.75, 1.0, 1.25, 1.5];
+const VOLUME_STEPS: [f32; 5] = [0.0, 0.25, 0.5, 0.75, 1.0];
+// Percent of the screen kept clear on every side
+const SAFE_AREA_STEPS: [f32; 4] = [0.0, 2.5, 5.0, 7.5];
+// Fractions of full stick travel
+const STICK_DEADZONE_STEPS: [f32; 6] = [0.05, 0.1, 0.15, 0.2, 0.3, 0.4];
+const STICK_SATURATION_STEPS: [f32; 5] = [0.7, 0.8, 0.85, 0.9, 1.0];
+const STICK_THRESHOLD_STEPS: [f32; 5] = [0.2, 0.3, 0.4, 0.5, 0.6];
+// As much as an online match ever picks
+const MAX_TRAINING_INPUT_DELAY: usize = MAX_INPUT_DELAY;
+const MAX_PACKET_COPIES: usize = 3;
+const DEFAULT_MATCHBOX_SERVER: &str = "ws://ec2-54-67-37-240.us-west-1.compute.amazonaws.com:3536";
+// Milliseconds added to every packet of the training loopback
+const TRAINING_LATENCY_STEPS: [f32; 5] = [0.0, 25.0, 50.0, 100.0, 150.0];
+// Percent of training loopback packets lost. Never all of them, the session has to sync
+const TRAINING_LOSS_STEPS: [f32; 5] = [0.0, 5.0, 10.0, 25.0, 50.0];
+
+// Player preferences, kept in settings.ron next to the executable
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub ui_scale: f32,
+    pub safe_area: f32,
+    // Of every sound and the music, 0 to 1
+    pub volume: f32,
+    // Name of a file in assets/locale
+    pub language: String,
+    pub player_name: String,
+    // Keeps debug level gameplay logs as well, takes effect on the next start
+    pub verbose_logging: bool,
+    // Offered to the peer when hosting, the host's choice is what both play with
+    pub physics_quality: PhysicsQuality,
+    // What this player takes on in online matches, picked in the join screen
+    pub handicap: Handicap,
+    pub character: Character,
+    // Where quick play looks first, picked in the join screen
+    pub region: Region,
+    // Team colored rim around each player, for busy backgrounds
+    pub player_outlines: bool,
+    // Live stats strip at the bottom of the match, Tab flips it in game
+    pub stats_overlay: bool,
+    // Knockback and launch speeds floating up from every hit, for tuning strikes in playtests
+    pub hit_numbers: bool,
+    // One half of the screen per player when they're far apart in a big arena, local play only
+    pub split_screen: bool,
+    // Draws the match mirrored whenever this player's handle plays from the right, so they're
+    // always on the left of the screen. Online and against the dummy only
+    pub own_side_left: bool,
+    // Nearest neighbour textures and a match camera that lands on whole screen pixels, for
+    // crisp pixel art. The textures only change on the next start
+    pub pixel_art: bool,
+    // Stick travel ignored around the center, so a drifting stick reads as neutral
+    pub stick_deadzone: f32,
+    // Stick travel that already counts as fully pushed, worn sticks rarely reach the rim
+    pub stick_saturation: f32,
+    // How far the stick has to be pushed, after the two above, before it's a direction
+    pub stick_threshold: f32,
+    // Training plays against the dummy as if it were online, with this much input delay
+    pub training_input_delay: usize,
+    // and its inputs arriving late by a few frames at random, so rollbacks happen
+    pub training_rollbacks: bool,
+    // on top of a steady delay on every packet, in milliseconds
+    pub training_latency_ms: f32,
+    // and some of them never arriving, in percent
+    pub training_packet_loss: f32,
+    // Input delay to ask the opponent for before an online match, None for the one derived
+    // from the round trip
+    pub online_input_delay: Option<usize>,
+    // Lets a match that keeps lagging move both players to more input delay at the next rally,
+    // asked for by either of them and taken only if the other allows it too
+    pub auto_delay_bump: bool,
+    // Times each online packet is sent, more for lossy connections. Taken up on the next
+    // connection, see shared_channel
+    pub packet_copies: usize,
+    // Rollbacks deeper than this many frames skip drawing a frame, only set in settings.ron
+    pub rollback_budget: u32,
+    // Matchbox signaling server the room codes are rooms on, only set in settings.ron
+    pub matchbox_server: String,
+}
+
+#[derive(Component)]
+struct SettingsMenu;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsEntry {
+    UiScale,
+    SafeArea,
+    Volume,
+    Language,
+    VerboseLogging,
+    PhysicsQuality,
+    PlayerOutlines,
+    StatsOverlay,
+    HitNumbers,
+    SplitScreen,
+    OwnSideLeft,
+    PixelArt,
+    StickDeadzone,
+    StickSaturation,
+    StickThreshold,
+    TrainingInputDelay,
+    TrainingRollbacks,
+    TrainingLatency,
+    TrainingPacketLoss,
+    OnlineInputDelay,
+    AutoDelayBump,
+    PacketCopies,
+    Controls,
+    Back,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ui_scale: 1.0,
+            safe_area: 0.0,
+            volume: 1.0,
+            language: "en".to_string(),
+            player_name: "Player".to_string(),
+            verbose_logging: false,
+            physics_quality: PhysicsQuality::default(),
+            handicap: Handicap::default(),
+            character: Character::default(),
+            region: Region::default(),
+            player_outlines: true,
+            stats_overlay: false,
+            hit_numbers: false,
+            split_screen: false,
+            own_side_left: false,
+            pixel_art: false,
+            stick_deadzone: 0.15,
+            stick_saturation: 0.9,
+            stick_threshold: 0.4,
+            training_input_delay: 0,
+            training_rollbacks: false,
+            training_latency_ms: 0.0,
+            training_packet_loss: 0.0,
+            online_input_delay: None,
+            auto_delay_bump: true,
+            packet_copies: 1,
+            rollback_budget: 15,
+            matchbox_server: DEFAULT_MATCHBOX_SERVER.to_string(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let Ok(text) = std::fs::read_to_string(SETTINGS_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("ignoring broken {SETTINGS_PATH}: {err}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("settings always serialize");
+        std::fs::write(SETTINGS_PATH, text)
+    }
+}
+
+impl SettingsEntry {
+    pub fn label(self, settings: &Settings, locale: &Locale) -> String {
+        match self {
+            SettingsEntry::UiScale => locale.format("settings.ui_scale", &[("value", &(settings.ui_scale * 100.0).round())]),
+            SettingsEntry::SafeArea => locale.format("settings.safe_area", &[("value", &settings.safe_area)]),
+            SettingsEntry::Volume => locale.format("settings.volume", &[("value", &(settings.volume * 100.0).round())]),
+            SettingsEntry::Language => locale.format("settings.language", &[("value", &locale.get("language.name"))]),
+            SettingsEntry::VerboseLogging => {
+                let value = locale.get(if settings.verbose_logging { "settings.on" } else { "settings.off" });
+                locale.format("settings.verbose_logging", &[("value", &value)])
+            }
+            SettingsEntry::PhysicsQuality => locale.format(
+                "settings.physics_quality",
+                &[("value", &locale.get(settings.physics_quality.label_key()))],
+            ),
+            SettingsEntry::PlayerOutlines => {
+                let value = locale.get(if settings.player_outlines { "settings.on" } else { "settings.off" });
+                locale.format("settings.player_outlines", &[("value", &value)])
+            }
+            SettingsEntry::StatsOverlay => {
+                let value = locale.get(if settings.stats_overlay { "settings.on" } else { "settings.off" });
+                locale.format("settings.stats_overlay", &[("value", &value)])
+            }
+            SettingsEntry::HitNumbers => {
+                let value = locale.get(if settings.hit_numbers { "settings.on" } else { "settings.off" });
+                locale.format("settings.hit_numbers", &[("value", &value)])
+            }
+            SettingsEntry::SplitScreen => {
+                let value = locale.get(if settings.split_screen { "settings.on" } else { "settings.off" });
+                locale.format("settings.split_screen", &[("value", &value)])
+            }
+            SettingsEntry::OwnSideLeft => {
+                let value = locale.get(if settings.own_side_left { "settings.on" } else { "settings.off" });
+                locale.format("settings.own_side_left", &[("value", &value)])
+            }
+            SettingsEntry::PixelArt => {
+                let value = locale.get(if settings.pixel_art { "settings.on" } else { "settings.off" });
+                locale.format("settings.pixel_art", &[("value", &value)])
+            }
+            SettingsEntry::StickDeadzone => {
+                locale.format("settings.stick_deadzone", &[("value", &(settings.stick_deadzone * 100.0).round())])
+            }
+            SettingsEntry::StickSaturation => {
+                locale.format("settings.stick_saturation", &[("value", &(settings.stick_saturation * 100.0).round())])
+            }
+            SettingsEntry::StickThreshold => {
+                locale.format("settings.stick_threshold", &[("value", &(settings.stick_threshold * 100.0).round())])
+            }
+            SettingsEntry::TrainingInputDelay => locale.format(
+                "settings.training_input_delay",
+                &[("value", &settings.training_input_delay), ("ms", &(settings.training_input_delay as f32 * FRAME_MS).round())],
+            ),
+            SettingsEntry::TrainingRollbacks => {
+                let value = locale.get(if settings.training_rollbacks { "settings.on" } else { "settings.off" });
+                locale.format("settings.training_rollbacks", &[("value", &value)])
+            }
+            SettingsEntry::TrainingLatency => locale.format("settings.training_latency", &[("value", &settings.training_latency_ms)]),
+            SettingsEntry::TrainingPacketLoss => {
+                locale.format("settings.training_packet_loss", &[("value", &settings.training_packet_loss)])
+            }
+            SettingsEntry::OnlineInputDelay => match settings.online_input_delay {
+                Some(frames) => locale.format(
+                    "settings.online_input_delay",
+                    &[("value", &frames), ("ms", &(frames as f32 * FRAME_MS).round())],
+                ),
+                None => locale.get("settings.online_input_delay_auto").to_string(),
+            },
+            SettingsEntry::AutoDelayBump => {
+                let value = locale.get(if settings.auto_delay_bump { "settings.on" } else { "settings.off" });
+                locale.format("settings.auto_delay_bump", &[("value", &value)])
+            }
+            SettingsEntry::PacketCopies => locale.format("settings.packet_copies", &[("value", &settings.packet_copies)]),
+            SettingsEntry::Controls => locale.get("settings.controls").to_string(),
+            SettingsEntry::Back => locale.get("settings.back").to_string(),
+        }
+    }
+}
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world().contains_resource::<Settings>() {
+            app.insert_resource(Settings::load());
+        }
+        app
+            .add_systems(OnEnter(GameState::Settings), setup_settings_menu)
+            .add_systems(
+                Update,
+                (settings_menu_input, update_settings_labels)
+                    .chain()
+                    .run_if(in_state(GameState::Settings)),
+            )
+            .add_systems(OnExit(GameState::Settings), (save_settings, cleanup_settings_menu));
+    }
+}
+
+fn setup_settings_menu(mut commands: Commands, settings: Res<Settings>, locale: Res<Locale>) {
+    commands.spawn((Camera2d, SettingsMenu));
+
+    commands
+        .spawn((
+            Node {
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                ..full_screen()
+            },
+            SafeArea,
+            SettingsMenu,
+        ))
+        .with_children(|parent| {
+            let entries = [
+                SettingsEntry::UiScale,
+                SettingsEntry::SafeArea,
+                SettingsEntry::Volume,
+                SettingsEntry::Language,
+                SettingsEntry::VerboseLogging,
+                SettingsEntry::PhysicsQuality,
+                SettingsEntry::PlayerOutlines,
+                SettingsEntry::StatsOverlay,
+                SettingsEntry::HitNumbers,
+                SettingsEntry::SplitScreen,
+                SettingsEntry::OwnSideLeft,
+                SettingsEntry::PixelArt,
+                SettingsEntry::StickDeadzone,
+                SettingsEntry::StickSaturation,
+                SettingsEntry::StickThreshold,
+                SettingsEntry::TrainingInputDelay,
+                SettingsEntry::TrainingRollbacks,
+                SettingsEntry::TrainingLatency,
+                SettingsEntry::TrainingPacketLoss,
+                SettingsEntry::OnlineInputDelay,
+                SettingsEntry::AutoDelayBump,
+                SettingsEntry::PacketCopies,
+                SettingsEntry::Controls,
+                SettingsEntry::Back,
+            ];
+            for (order, entry) in entries.into_iter().enumerate() {
+                let mut button = parent.spawn((menu_button(order as u32), entry));
+                if entry == SettingsEntry::Back {
+                    button.insert(CancelButton);
+                }
+                button.with_children(|parent| {
+                    parent.spawn((button_text(entry.label(&settings, &locale)), entry));
+                });
+            }
+        });
+}
+
+// Moves to the neighbouring step, wrapping at the ends so confirm alone can cycle through
+fn step(steps: &[f32], current: f32, by: i32) -> f32 {
+    let index = steps
+        .iter()
+        .position(|value| (*value - current).abs() < 0.01)
+        .unwrap_or(0) as i32;
+    steps[(index + by).rem_euclid(steps.len() as i32) as usize]
+}
+
+pub fn adjust(settings: &mut Settings, entry: SettingsEntry, by: i32) {
+    match entry {
+        SettingsEntry::UiScale => settings.ui_scale = step(&UI_SCALE_STEPS, settings.ui_scale, by),
+        SettingsEntry::SafeArea => settings.safe_area = step(&SAFE_AREA_STEPS, settings.safe_area, by),
+        SettingsEntry::Volume => settings.volume = step(&VOLUME_STEPS, settings.volume, by),
+        SettingsEntry::Language => {
+            let index = LANGUAGES.iter().position(|language| *language == settings.language).unwrap_or(0) as i32;
+            let next = LANGUAGES[(index + by).rem_euclid(LANGUAGES.len() as i32) as usize];
+            settings.language = next.to_string();
+        }
+        SettingsEntry::VerboseLogging => settings.verbose_logging = !settings.verbose_logging,
+        SettingsEntry::PhysicsQuality => {
+            let qualities = PhysicsQuality::ALL;
+            let index = qualities.iter().position(|quality| *quality == settings.physics_quality).unwrap_or(0) as i32;
+            settings.physics_quality = qualities[(index + by).rem_euclid(qualities.len() as i32) as usize];
+        }
+        SettingsEntry::PlayerOutlines => settings.player_outlines = !settings.player_outlines,
+        SettingsEntry::StatsOverlay => settings.stats_overlay = !settings.stats_overlay,
+        SettingsEntry::HitNumbers => settings.hit_numbers = !settings.hit_numbers,
+        SettingsEntry::SplitScreen => settings.split_screen = !settings.split_screen,
+        SettingsEntry::OwnSideLeft => settings.own_side_left = !settings.own_side_left,
+        SettingsEntry::PixelArt => settings.pixel_art = !settings.pixel_art,
+        SettingsEntry::StickDeadzone => {
+            settings.stick_deadzone = step(&STICK_DEADZONE_STEPS, settings.stick_deadzone, by)
+        }
+        SettingsEntry::StickSaturation => {
+            settings.stick_saturation = step(&STICK_SATURATION_STEPS, settings.stick_saturation, by)
+        }
+        SettingsEntry::StickThreshold => {
+            settings.stick_threshold = step(&STICK_THRESHOLD_STEPS, settings.stick_threshold, by)
+        }
+        SettingsEntry::TrainingInputDelay => {
+            let steps = MAX_TRAINING_INPUT_DELAY as i32 + 1;
+            settings.training_input_delay = (settings.training_input_delay as i32 + by).rem_euclid(steps) as usize;
+        }
+        SettingsEntry::TrainingRollbacks => settings.training_rollbacks = !settings.training_rollbacks,
+        SettingsEntry::TrainingLatency => {
+            settings.training_latency_ms = step(&TRAINING_LATENCY_STEPS, settings.training_latency_ms, by)
+        }
+        SettingsEntry::TrainingPacketLoss => {
+            settings.training_packet_loss = step(&TRAINING_LOSS_STEPS, settings.training_packet_loss, by)
+        }
+        // Auto sits before 1 frame
+        SettingsEntry::OnlineInputDelay => {
+            let steps = MAX_INPUT_DELAY as i32 + 1;
+            let current = settings.online_input_delay.unwrap_or(0) as i32;
+            let next = (current + by).rem_euclid(steps) as usize;
+            settings.online_input_delay = (next > 0).then_some(next);
+        }
+        SettingsEntry::AutoDelayBump => settings.auto_delay_bump = !settings.auto_delay_bump,
+        SettingsEntry::PacketCopies => {
+            settings.packet_copies = (settings.packet_copies as i32 - 1 + by).rem_euclid(MAX_PACKET_COPIES as i32) as usize + 1;
+        }
+        SettingsEntry::Controls | SettingsEntry::Back => {}
+    }
+}
+
+fn settings_menu_input(
+    mut activated: EventReader<MenuActivated>,
+    mut adjusted: EventReader<MenuAdjusted>,
+    entries: Query<&SettingsEntry, With<Button>>,
+    mut settings: ResMut<Settings>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for MenuActivated(entity) in activated.read() {
+        match entries.get(*entity) {
+            Ok(SettingsEntry::Controls) => next_state.set(GameState::Controls),
+            Ok(SettingsEntry::Back) => next_state.set(GameState::MainMenu),
+            Ok(entry) => adjust(&mut settings, *entry, 1),
+            Err(_) => {}
+        }
+    }
+    for adjusted in adjusted.read() {
+        if let Ok(entry) = entries.get(adjusted.entity) {
+            adjust(&mut settings, *entry, adjusted.step);
+        }
+    }
+}
+
+fn update_settings_labels(
+    settings: Res<Settings>,
+    locale: Res<Locale>,
+    mut labels: Query<(&SettingsEntry, &mut Text)>,
+) {
+    if !settings.is_changed() && !locale.is_changed() {
+        return;
+    }
+    for (entry, mut text) in labels.iter_mut() {
+        text.0 = entry.label(&settings, &locale);
+    }
+}
+
+fn save_settings(settings: Res<Settings>, locale: Res<Locale>, mut notify: EventWriter<Notify>) {
+    match settings.save() {
+        Ok(()) => notify.send(Notify::info(locale.get("toast.settings_saved"))),
+        Err(err) => notify.send(Notify::error(locale.format("toast.settings_save_failed", &[("reason", &err)]))),
+    };
+}
+
+fn cleanup_settings_menu(mut commands: Commands, query: Query<Entity, With<SettingsMenu>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use bevy::prelude::*;
+use bevy_ggrs::ggrs::{Message, NonBlockingSocket};
+use bevy_matchbox::matchbox_socket::{Packet, WebRtcChannel};
+use bevy_matchbox::prelude::*;
+
+// GGRS keeps the socket it's given for the whole session and drops it with the session.
+// Handing it a shared handle instead of the channel itself keeps the channel alive with the
+// connection, so the next match of a rotation starts a new session on the same peers
+#[derive(Resource, Clone)]
+pub struct SharedChannel(Arc<Mutex<Link>>);
+
+struct Link {
+    channel: WebRtcChannel,
+    // Times every packet goes out. GGRS shrugs off the duplicates, and on a lossy connection
+    // one of them getting through beats waiting for a resend
+    copies: usize,
+    traffic: Traffic,
+}
+
+// Bytes through the channel since the last look, copies and all
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Traffic {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl SharedChannel {
+    pub fn new(channel: WebRtcChannel, copies: usize) -> Self {
+        Self(Arc::new(Mutex::new(Link { channel, copies: copies.max(1), traffic: Traffic::default() })))
+    }
+
+    fn link(&self) -> std::sync::MutexGuard<'_, Link> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn close(&self) {
+        self.link().channel.close();
+    }
+
+    pub fn copies(&self) -> usize {
+        self.link().copies
+    }
+
+    pub fn take_traffic(&self) -> Traffic {
+        std::mem::take(&mut self.link().traffic)
+    }
+}
+
+// Encoded the way matchbox's own GGRS socket does it, counting the bytes on the way
+impl NonBlockingSocket<PeerId> for SharedChannel {
+    fn send_to(&mut self, msg: &Message, addr: &PeerId) {
+        let packet: Packet = bincode::serialize(msg).expect("GGRS messages always serialize").into_boxed_slice();
+        let mut link = self.link();
+        link.traffic.sent += (packet.len() * link.copies) as u64;
+        for _ in 1..link.copies {
+            link.channel.send(packet.clone(), *addr);
+        }
+        link.channel.send(packet, *addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        let mut link = self.link();
+        let packets = link.channel.receive();
+        let mut messages = Vec::with_capacity(packets.len());
+        for (peer, packet) in packets {
+            link.traffic.received += packet.len() as u64;
+            match bincode::deserialize(&packet) {
+                Ok(msg) => messages.push((peer, msg)),
+                Err(err) => debug!("dropping a garbled packet from {peer}: {err}"),
+            }
+        }
+        messages
+    }
+}
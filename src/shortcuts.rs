@@ -0,0 +1,133 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::MatchboxSocket;
+use crate::GameState;
+use crate::game::SessionMode;
+use crate::input::Config;
+use crate::level::ActiveLevel;
+use crate::lobby::{request_rematch, send_ghost, Lobby};
+use crate::locale::Locale;
+use crate::menu_nav::{CancelButton, CapturesKeyboard, Focused};
+use crate::notifications::Notify;
+use crate::race::{format_time, Leaderboards};
+use crate::scoring::Score;
+
+pub struct ShortcutPlugin;
+
+const REMATCH_KEY: KeyCode = KeyCode::F5;
+const REQUEUE_KEY: KeyCode = KeyCode::F6;
+const SEND_GHOST_KEY: KeyCode = KeyCode::F7;
+
+// Keys that mean the same thing wherever they're pressed, each handled for the state it's
+// pressed in. Nothing fires while a text field has the keyboard
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shortcut {
+    // Same opponent again, once the match is decided
+    Rematch,
+    // Leave the room and matchmake again
+    Requeue,
+    // Esc with no menu open, brings up the pause menu in a match. A menu's own cancel button
+    // takes Esc first, see menu_nav
+    Back,
+    // Hand this level's time trial ghost to everyone in the room
+    SendGhost,
+}
+
+impl Plugin for ShortcutPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Shortcut>()
+            // Ahead of Update, so the Esc that closes a menu is seen with the menu still up
+            .add_systems(PreUpdate, read_shortcuts.after(bevy::input::InputSystem))
+            .add_systems(
+                Update,
+                (rematch, requeue, share_ghost).run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+fn read_shortcuts(
+    keys: Res<ButtonInput<KeyCode>>,
+    typing: Query<(), (With<Focused>, With<CapturesKeyboard>)>,
+    cancel_buttons: Query<(), With<CancelButton>>,
+    mut shortcuts: EventWriter<Shortcut>,
+) {
+    if !typing.is_empty() {
+        return;
+    }
+    if keys.just_pressed(REMATCH_KEY) {
+        shortcuts.send(Shortcut::Rematch);
+    }
+    if keys.just_pressed(REQUEUE_KEY) {
+        shortcuts.send(Shortcut::Requeue);
+    }
+    if keys.just_pressed(SEND_GHOST_KEY) {
+        shortcuts.send(Shortcut::SendGhost);
+    }
+    if keys.just_pressed(KeyCode::Escape) && cancel_buttons.is_empty() {
+        shortcuts.send(Shortcut::Back);
+    }
+}
+
+fn decided(score: &Option<Res<Score>>) -> bool {
+    score.as_ref().is_some_and(|score| score.winner.is_some())
+}
+
+// Locally there's nobody to ask, online the opponent has to press it too
+fn rematch(
+    mut shortcuts: EventReader<Shortcut>,
+    mode: Res<SessionMode>,
+    score: Option<Res<Score>>,
+    room: (Option<ResMut<MatchboxSocket>>, Option<ResMut<Lobby>>),
+    locale: Res<Locale>,
+    mut notify: EventWriter<Notify>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !shortcuts.read().any(|shortcut| *shortcut == Shortcut::Rematch) || !decided(&score) {
+        return;
+    }
+    if matches!(*mode, SessionMode::Local | SessionMode::SyncTest) {
+        next_state.set(GameState::NextMatch);
+    } else if let (SessionMode::Online, Some(mut socket), Some(mut lobby)) = (*mode, room.0, room.1)
+        && request_rematch(&mut socket, &mut lobby)
+    {
+        notify.send(Notify::info(locale.get("toast.rematch_sent")));
+    }
+}
+
+// Passing through NextMatch without keeping the connection closes the socket and opens a new
+// one, straight back into matchmaking
+fn requeue(
+    mut shortcuts: EventReader<Shortcut>,
+    mode: Res<SessionMode>,
+    score: Option<Res<Score>>,
+    session: Option<Res<bevy_ggrs::Session<Config>>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !shortcuts.read().any(|shortcut| *shortcut == Shortcut::Requeue) || *mode != SessionMode::Online {
+        return;
+    }
+    if session.is_none() || decided(&score) {
+        next_state.set(GameState::NextMatch);
+    }
+}
+
+fn share_ghost(
+    mut shortcuts: EventReader<Shortcut>,
+    socket: Option<ResMut<MatchboxSocket>>,
+    (level, leaderboards): (Res<ActiveLevel>, Res<Leaderboards>),
+    locale: Res<Locale>,
+    mut notify: EventWriter<Notify>,
+) {
+    if !shortcuts.read().any(|shortcut| *shortcut == Shortcut::SendGhost) {
+        return;
+    }
+    let Some(mut socket) = socket else {
+        return;
+    };
+    let course = level.0.content_hash();
+    let Some(ghost) = leaderboards.ghost(course) else {
+        notify.send(Notify::warning(locale.get("toast.no_ghost")));
+        return;
+    };
+    send_ghost(&mut socket, course, ghost);
+    notify.send(Notify::info(locale.format("toast.ghost_sent", &[("time", &format_time(ghost.frames))])));
+}
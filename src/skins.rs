@@ -0,0 +1,490 @@
+use std::path::PathBuf;
+use avian2d::prelude::*;
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use serde::{Deserialize, Serialize};
+use bevy_ggrs::LocalPlayers;
+use crate::GameState;
+use crate::achievements::Profile;
+use crate::game::{Player, SessionMode, PLAYER_SCALE};
+use crate::knockback::NORMAL_WEIGHT;
+use crate::locale::Locale;
+use crate::perspective::MirroredView;
+use crate::presentation::Appearance;
+use crate::rules::GameRulesApp;
+use crate::rng::fnv1a;
+use crate::scoring::Score;
+use crate::speed_limit::SpeedLimit;
+use crate::ui_layout::SafeArea;
+
+pub struct SkinPlugin;
+
+const CHARACTER_DIR: &str = "assets/characters";
+// What a run_speed and jump_height of 1 stand for
+const MOVE_SPEED: f32 = 7.0;
+const BOOSTED_MOVE_SPEED: f32 = 10.5;
+const JUMP_VELOCITY: f32 = 10.0;
+
+// Who a player is drawn as. The built-ins keep names of their own so saved settings still
+// read, everything else goes by its place in the roster
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Character {
+    #[default]
+    Ice,
+    Zapp,
+    Registered(u8),
+}
+
+// What the roster knows about a character, see rules::GameRulesApp::register_character
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CharacterInfo {
+    // File name of its definition in assets/characters
+    pub id: &'static str,
+    pub label_key: &'static str,
+    // Relative to assets
+    pub sprite: &'static str,
+}
+
+// Every character that can be picked. The built-ins are registered first, by SkinPlugin
+#[derive(Resource, Default)]
+pub struct CharacterRoster(pub(crate) Vec<CharacterInfo>);
+
+// Color variant a character is drawn in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Palette {
+    #[default]
+    Primary,
+    // Player 2's copy in a mirror match
+    Alternate,
+    // Earned through achievements, see achievements::Profile
+    Gold,
+    Frost,
+    Ember,
+}
+
+// Who plays which character, one per handle. Agreed during level sync online like handicaps
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlayerSkins(pub [Character; 2]);
+
+// What a character sounds like, from assets/characters/<id>.ron. Paths are relative to
+// assets, one is picked at random each time so repeats don't grate
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SoundSet {
+    pub jump: Vec<String>,
+    pub strike: Vec<String>,
+    // Getting knocked back by the opponent's strike
+    pub ko: Vec<String>,
+}
+
+// How a character's art sits on its body, so new art drops in without touching the code.
+// Pixels are the sprite's own. Missing entries are the built-ins' values
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct SpriteMetrics {
+    // Sprite pixels per world unit at normal size
+    pub pixels_per_unit: f32,
+    // The point of the sprite on the player's position, (0, 0) its bottom left and (1, 1) its
+    // top right
+    pub pivot: (f32, f32),
+    // The body the player collides with, in sprite pixels, and its center from the player's
+    // position. It isn't mirrored when the player turns around
+    pub collider_size: (f32, f32),
+    pub collider_offset: (f32, f32),
+}
+
+// How a character's body moves. The multipliers play like the original characters at 1
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Physique {
+    // Heavier characters are launched less far by the same hit
+    pub weight: f32,
+    // Scales gravity and terminal velocity together, so a fast faller drops sooner and harder
+    pub fall_speed: f32,
+    // Walking speed, boosted or not
+    pub run_speed: f32,
+    // How high a jump goes at normal gravity
+    pub jump_height: f32,
+    // Jumps before touching the ground again, the first one included
+    pub jumps: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CharacterDef {
+    pub sounds: SoundSet,
+    pub sprite: SpriteMetrics,
+    pub physique: Physique,
+}
+
+// Every character's definition, in roster order. Loaded once the roster is complete
+#[derive(Resource)]
+pub struct CharacterDefs(Vec<CharacterDef>);
+
+// Name of the character playing on one side of the arena, under the score
+#[derive(Component)]
+struct SkinTag {
+    side: usize,
+}
+
+impl Character {
+    fn index(self) -> usize {
+        match self {
+            Character::Ice => 0,
+            Character::Zapp => 1,
+            Character::Registered(index) => index as usize,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => Character::Ice,
+            1 => Character::Zapp,
+            _ => Character::Registered(index as u8),
+        }
+    }
+}
+
+impl CharacterRoster {
+    pub fn all(&self) -> Vec<Character> {
+        (0..self.0.len()).map(Character::from_index).collect()
+    }
+
+    // Ids have to be unique, they name the definition file. Sprites are only looked for
+    // natively, the web build fetches its assets
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (index, character) in self.0.iter().enumerate() {
+            if character.id.is_empty() {
+                problems.push(format!("character {index} has no id"));
+            } else if self.0[..index].iter().any(|earlier| earlier.id == character.id) {
+                problems.push(format!("two characters are called {}", character.id));
+            }
+            if !cfg!(target_arch = "wasm32") && !PathBuf::from("assets").join(character.sprite).is_file() {
+                problems.push(format!("{}'s sprite {} is missing", character.id, character.sprite));
+            }
+        }
+        problems
+    }
+
+    // One nobody here registered, picked by a peer with more installed, is drawn as the first
+    pub fn info(&self, character: Character) -> &CharacterInfo {
+        self.0.get(character.index()).unwrap_or(&self.0[0])
+    }
+}
+
+impl Default for SpriteMetrics {
+    fn default() -> Self {
+        Self {
+            pixels_per_unit: 1.0 / PLAYER_SCALE,
+            pivot: (0.5, 0.5),
+            collider_size: (200.0, 440.0),
+            collider_offset: (0.0, 0.0),
+        }
+    }
+}
+
+impl SpriteMetrics {
+    // The player's own units per sprite pixel. It's scaled by PLAYER_SCALE, so art at the
+    // built-ins' resolution is 1
+    pub fn texel_scale(&self) -> f32 {
+        1.0 / (self.pixels_per_unit * PLAYER_SCALE)
+    }
+
+    pub fn anchor(&self) -> Anchor {
+        Anchor::Custom(Vec2::new(self.pivot.0 - 0.5, self.pivot.1 - 0.5))
+    }
+
+    pub fn collider(&self) -> Collider {
+        let size = Vec2::from(self.collider_size) * self.texel_scale();
+        Collider::rectangle(size.x, size.y)
+    }
+
+    pub fn collider_translation(&self) -> Vec3 {
+        (Vec2::from(self.collider_offset) * self.texel_scale()).extend(0.0)
+    }
+
+    fn problems(&self) -> Vec<&'static str> {
+        let mut problems = Vec::new();
+        if !(self.pixels_per_unit.is_finite() && self.pixels_per_unit > 0.0) {
+            problems.push("pixels_per_unit has to be above 0");
+        }
+        let (width, height) = self.collider_size;
+        if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) {
+            problems.push("collider_size has to be above 0 both ways");
+        }
+        if ![self.pivot.0, self.pivot.1, self.collider_offset.0, self.collider_offset.1].iter().all(|value| value.is_finite()) {
+            problems.push("pivot and collider_offset have to be numbers");
+        }
+        problems
+    }
+}
+
+impl Default for Physique {
+    fn default() -> Self {
+        Self { weight: NORMAL_WEIGHT, fall_speed: 1.0, run_speed: 1.0, jump_height: 1.0, jumps: 2 }
+    }
+}
+
+impl Physique {
+    pub fn gravity_scale(&self) -> GravityScale {
+        GravityScale(self.fall_speed)
+    }
+
+    pub fn speed_limit(&self) -> SpeedLimit {
+        SpeedLimit { max_fall_speed: SpeedLimit::PLAYER.max_fall_speed * self.fall_speed, ..SpeedLimit::PLAYER }
+    }
+
+    // Walking speed in world units per second, with or without a speed boost
+    pub fn move_speed(&self, boosted: bool) -> f32 {
+        (if boosted { BOOSTED_MOVE_SPEED } else { MOVE_SPEED }) * self.run_speed
+    }
+
+    // Height goes with the square of the launch speed
+    pub fn jump_velocity(&self) -> f32 {
+        JUMP_VELOCITY * self.jump_height.sqrt()
+    }
+
+    fn problems(&self) -> Vec<&'static str> {
+        let mut problems = Vec::new();
+        if !(self.weight.is_finite() && self.weight > 0.0) {
+            problems.push("weight has to be above 0");
+        }
+        if !(self.fall_speed.is_finite() && self.fall_speed > 0.0) {
+            problems.push("fall_speed has to be above 0");
+        }
+        if !(self.run_speed.is_finite() && self.run_speed > 0.0) {
+            problems.push("run_speed has to be above 0");
+        }
+        if !(self.jump_height.is_finite() && self.jump_height > 0.0) {
+            problems.push("jump_height has to be above 0");
+        }
+        if self.jumps == 0 {
+            problems.push("jumps has to be at least 1");
+        }
+        problems
+    }
+}
+
+impl CharacterDef {
+    // A character without a definition is silent rather than missing
+    pub fn load(character: &CharacterInfo) -> Self {
+        let path = PathBuf::from(CHARACTER_DIR).join(format!("{}.ron", character.id));
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| ron::from_str::<Self>(&text).map_err(|err| err.to_string()));
+        let mut def = parsed.unwrap_or_else(|err| {
+            warn!("failed to load {}: {err}", path.display());
+            Self::default()
+        });
+        // Sounds that aren't on disk are dropped once here instead of failing on every play. The
+        // web build can't look, its assets are fetched
+        if cfg!(target_arch = "wasm32") {
+            return def;
+        }
+        for sounds in [&mut def.sounds.jump, &mut def.sounds.strike, &mut def.sounds.ko] {
+            sounds.retain(|sound| {
+                let found = PathBuf::from("assets").join(sound).is_file();
+                if !found {
+                    warn!("{}: no sound file {sound}", path.display());
+                }
+                found
+            });
+        }
+        def
+    }
+}
+
+impl CharacterDefs {
+    pub fn get(&self, character: Character) -> &CharacterDef {
+        self.0.get(character.index()).unwrap_or(&self.0[0])
+    }
+
+    pub fn problems(&self, roster: &CharacterRoster) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (info, def) in roster.0.iter().zip(&self.0) {
+            let own = def.sprite.problems().into_iter().chain(def.physique.problems());
+            problems.extend(own.map(|problem| format!("{}: {problem}", info.id)));
+        }
+        problems
+    }
+
+    // The bodies the picked characters collide with and how they fall come from each peer's
+    // own files, so peers compare this before playing
+    pub fn body_hash(&self, skins: &PlayerSkins) -> u64 {
+        let bodies: Vec<_> = skins.0.iter().map(|character| {
+            let def = self.get(*character);
+            let sprite = def.sprite;
+            (sprite.pixels_per_unit, sprite.collider_size, sprite.collider_offset, def.physique)
+        }).collect();
+        fnv1a(format!("{bodies:?}").as_bytes())
+    }
+}
+
+impl Palette {
+    // Multiplied into the sprite, white leaves it as drawn
+    pub fn tint(self) -> Color {
+        match self {
+            Palette::Primary => Color::WHITE,
+            Palette::Alternate => Color::srgb(1.0, 0.6, 0.45),
+            Palette::Gold => Color::srgb(1.0, 0.85, 0.35),
+            Palette::Frost => Color::srgb(0.6, 0.85, 1.0),
+            Palette::Ember => Color::srgb(1.0, 0.45, 0.3),
+        }
+    }
+
+    pub fn label_key(self) -> &'static str {
+        match self {
+            Palette::Primary => "palette.primary",
+            Palette::Alternate => "palette.alternate",
+            Palette::Gold => "palette.gold",
+            Palette::Frost => "palette.frost",
+            Palette::Ember => "palette.ember",
+        }
+    }
+}
+
+// The two characters the game always shipped with, one each
+impl Default for PlayerSkins {
+    fn default() -> Self {
+        Self([Character::Ice, Character::Zapp])
+    }
+}
+
+impl PlayerSkins {
+    pub fn character(&self, handle: usize) -> Character {
+        self.0.get(handle).copied().unwrap_or_default()
+    }
+
+    // Only depends on the picks, so every peer tints the same player
+    pub fn palette(&self, handle: usize) -> Palette {
+        if handle == 1 && self.0[0] == self.0[1] {
+            Palette::Alternate
+        } else {
+            Palette::Primary
+        }
+    }
+
+    // What this machine draws the handle in, its own player wearing the profile's colors
+    pub fn shown_palette(&self, handle: usize, own: Option<usize>, profile: &Profile) -> Palette {
+        match profile.worn_palette() {
+            Palette::Primary => self.palette(handle),
+            worn if own == Some(handle) => worn,
+            _ => self.palette(handle),
+        }
+    }
+}
+
+impl Plugin for SkinPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerSkins>()
+            .register_character(CharacterInfo { id: "ice", label_key: "character.ice", sprite: "sprites/ice3.png" })
+            .register_character(CharacterInfo { id: "zapp", label_key: "character.zapp", sprite: "sprites/zapp.png" })
+            .add_systems(Startup, load_character_defs)
+            .add_systems(OnEnter(GameState::InGame), (reset_skins, spawn_skin_tags))
+            .add_systems(Update, (apply_skins, update_skin_tags).run_if(in_state(GameState::InGame)))
+            .add_systems(OnExit(GameState::InGame), cleanup_skin_tags);
+    }
+}
+
+fn load_character_defs(mut commands: Commands, roster: Res<CharacterRoster>) {
+    commands.insert_resource(CharacterDefs(roster.0.iter().map(CharacterDef::load).collect()));
+}
+
+fn reset_skins(mut skins: ResMut<PlayerSkins>) {
+    skins.set_if_neq(PlayerSkins::default());
+}
+
+// The look is presentation only. The body is rollback state, but like handicaps skins only
+// change before the session starts, while level sync is still running
+fn apply_skins(
+    (skins, roster, defs): (Res<PlayerSkins>, Res<CharacterRoster>, Res<CharacterDefs>),
+    (mode, local_players, profile): (Res<SessionMode>, Option<Res<LocalPlayers>>, Res<Profile>),
+    asset_server: Res<AssetServer>,
+    mut players: Query<(&Player, &mut Appearance, &mut GravityScale, &mut SpeedLimit, &Children)>,
+    mut bodies: Query<(&mut Collider, &mut Transform), Without<Player>>,
+    added: Query<(), Added<Player>>,
+) {
+    // Online the own player is only known once the session starts
+    let own_known = local_players.as_ref().is_some_and(|local| local.is_changed());
+    if !skins.is_changed() && !profile.is_changed() && !own_known && added.is_empty() {
+        return;
+    }
+    let own = mode.own_handle(local_players.as_deref());
+    for (player, mut appearance, mut gravity, mut limit, children) in players.iter_mut() {
+        let character = skins.character(player.handle);
+        let metrics = defs.get(character).sprite;
+        let physique = defs.get(character).physique;
+        *gravity = physique.gravity_scale();
+        *limit = physique.speed_limit();
+        appearance.sprite.image = asset_server.load(roster.info(character).sprite);
+        appearance.sprite.color = skins.shown_palette(player.handle, own, &profile).tint();
+        appearance.sprite.anchor = metrics.anchor();
+        appearance.texel_scale = metrics.texel_scale();
+        let mut children = bodies.iter_many_mut(children);
+        while let Some((mut collider, mut transform)) = children.fetch_next() {
+            *collider = metrics.collider();
+            transform.translation = metrics.collider_translation();
+        }
+    }
+}
+
+fn spawn_skin_tags(mut commands: Commands) {
+    for side in 0..2 {
+        commands.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(20.0),
+                left: if side == 0 { Val::Px(20.0) } else { Val::Auto },
+                right: if side == 1 { Val::Px(20.0) } else { Val::Auto },
+                ..default()
+            },
+            SafeArea,
+            Text::new(""),
+            TextFont {
+                font_size: 24.0,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            SkinTag { side },
+        ));
+    }
+}
+
+// Tagged in the same palette as the sprite, so the orange player on the field is the
+// orange name on the HUD
+fn update_skin_tags(
+    (skins, roster): (Res<PlayerSkins>, Res<CharacterRoster>),
+    (mode, local_players, profile): (Res<SessionMode>, Option<Res<LocalPlayers>>, Res<Profile>),
+    (score, mirrored): (Option<Res<Score>>, Res<MirroredView>),
+    locale: Res<Locale>,
+    mut tags: Query<(&SkinTag, &mut Text, &mut TextColor)>,
+) {
+    let Some(score) = score else {
+        return;
+    };
+    let own_known = local_players.as_ref().is_some_and(|local| local.is_changed());
+    let unchanged = !skins.is_changed() && !score.is_changed() && !locale.is_changed() && !profile.is_changed();
+    if unchanged && !own_known && !mirrored.is_changed() {
+        return;
+    }
+    let own = mode.own_handle(local_players.as_deref());
+    for (tag, mut text, mut color) in tags.iter_mut() {
+        // A mirrored view has the simulation's right side on the left of the screen
+        let handle = score.handle_on_side(tag.side ^ usize::from(mirrored.0));
+        let label = locale.format(
+            "skins.tag",
+            &[("player", &(handle + 1)), ("character", &locale.get(roster.info(skins.character(handle)).label_key))],
+        );
+        if text.0 != label {
+            text.0 = label;
+        }
+        color.0 = skins.shown_palette(handle, own, &profile).tint();
+    }
+}
+
+fn cleanup_skin_tags(mut commands: Commands, tags: Query<Entity, With<SkinTag>>) {
+    for entity in tags.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
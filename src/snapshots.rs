@@ -0,0 +1,147 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_ggrs::{
+    GgrsComponentSnapshot, GgrsComponentSnapshots, LoadWorld, LoadWorldSet, Rollback, RollbackFrameCount, SaveWorld,
+    SaveWorldSet,
+};
+
+const MEMORY_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+// Snapshot storage for components that hardly ever change once spawned, like colliders.
+// Saved frames hold an Arc to the value, and a frame where it didn't change shares the
+// previous frame's Arc instead of cloning it again
+pub struct SharedSnapshotPlugin<C>(PhantomData<C>);
+
+// The Arc matching what each entity holds right now
+#[derive(Resource)]
+struct Latest<C>(HashMap<Rollback, Arc<C>>);
+
+// Shallow sizes, accumulated between log lines. Heap data behind the components isn't
+// counted, colliders already share their shapes
+#[derive(Resource, Default)]
+struct SnapshotMemory {
+    frames: usize,
+    // What cloning every shared component into every frame would have taken
+    cloned_bytes: usize,
+    // What was actually stored, one pointer per component plus the values that changed
+    stored_bytes: usize,
+}
+
+pub trait SharedSnapshotApp {
+    fn rollback_component_with_sharing<C: Component + Clone>(&mut self) -> &mut Self;
+}
+
+impl SharedSnapshotApp for App {
+    fn rollback_component_with_sharing<C: Component + Clone>(&mut self) -> &mut Self {
+        self.add_plugins(SharedSnapshotPlugin::<C>(PhantomData))
+    }
+}
+
+impl<C> Default for Latest<C> {
+    fn default() -> Self {
+        Self(HashMap::default())
+    }
+}
+
+impl<C: Component + Clone> Plugin for SharedSnapshotPlugin<C> {
+    fn build(&self, app: &mut App) {
+        if !app.world().contains_resource::<SnapshotMemory>() {
+            app.init_resource::<SnapshotMemory>()
+                .add_systems(SaveWorld, count_saved_frame.before(SaveWorldSet::Snapshot))
+                .add_systems(Update, log_snapshot_memory);
+        }
+        app.init_resource::<GgrsComponentSnapshots<C, Arc<C>>>()
+            .init_resource::<Latest<C>>()
+            .add_systems(
+                SaveWorld,
+                (GgrsComponentSnapshots::<C, Arc<C>>::discard_old_snapshots, save::<C>)
+                    .chain()
+                    .in_set(SaveWorldSet::Snapshot),
+            )
+            .add_systems(LoadWorld, load::<C>.in_set(LoadWorldSet::Data));
+    }
+}
+
+fn count_saved_frame(mut memory: ResMut<SnapshotMemory>) {
+    memory.frames += 1;
+}
+
+fn save<C: Component + Clone>(
+    mut snapshots: ResMut<GgrsComponentSnapshots<C, Arc<C>>>,
+    mut latest: ResMut<Latest<C>>,
+    mut memory: ResMut<SnapshotMemory>,
+    frame: Res<RollbackFrameCount>,
+    query: Query<(&Rollback, Ref<C>)>,
+) {
+    let mut current = HashMap::with_capacity(latest.0.len());
+    let mut fresh = 0;
+    for (rollback, component) in query.iter() {
+        let shared = match latest.0.get(rollback) {
+            Some(shared) if !component.is_changed() => shared.clone(),
+            _ => {
+                fresh += 1;
+                Arc::new(component.clone())
+            }
+        };
+        current.insert(*rollback, shared);
+    }
+
+    memory.cloned_bytes += current.len() * size_of::<C>();
+    memory.stored_bytes += current.len() * size_of::<Arc<C>>() + fresh * size_of::<C>();
+    let snapshot = GgrsComponentSnapshot::new(current.iter().map(|(rollback, shared)| (*rollback, shared.clone())));
+    snapshots.push(frame.0, snapshot);
+    latest.0 = current;
+}
+
+// Only writes components whose value actually differs, so a rollback doesn't make physics
+// rebuild every collider it passes over
+fn load<C: Component + Clone>(
+    mut commands: Commands,
+    mut snapshots: ResMut<GgrsComponentSnapshots<C, Arc<C>>>,
+    mut latest: ResMut<Latest<C>>,
+    frame: Res<RollbackFrameCount>,
+    mut query: Query<(Entity, &Rollback, Option<&mut C>)>,
+) {
+    let snapshot = snapshots.rollback(frame.0).get();
+    for (entity, rollback, component) in query.iter_mut() {
+        match (component, snapshot.get(rollback)) {
+            (Some(mut component), Some(stored)) => {
+                // Untouched since the last load, and every save since shared one value
+                let unchanged = !component.is_changed()
+                    && latest.0.get(rollback).is_some_and(|current| Arc::ptr_eq(current, stored));
+                if !unchanged {
+                    *component = C::clone(stored);
+                }
+                latest.0.insert(*rollback, stored.clone());
+            }
+            (Some(_), None) => {
+                commands.entity(entity).remove::<C>();
+                latest.0.remove(rollback);
+            }
+            (None, Some(stored)) => {
+                commands.entity(entity).insert(C::clone(stored));
+                latest.0.insert(*rollback, stored.clone());
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+fn log_snapshot_memory(mut memory: ResMut<SnapshotMemory>, time: Res<Time>, mut since_last: Local<Duration>) {
+    *since_last += time.delta();
+    if *since_last < MEMORY_LOG_INTERVAL || memory.frames == 0 {
+        return;
+    }
+    *since_last = Duration::ZERO;
+    info!(
+        target: "snapshots",
+        frames = memory.frames,
+        bytes_per_frame = memory.stored_bytes / memory.frames,
+        cloned_bytes_per_frame = memory.cloned_bytes / memory.frames,
+        "shared snapshot memory"
+    );
+    *memory = SnapshotMemory::default();
+}
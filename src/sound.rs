@@ -0,0 +1,81 @@
+use bevy::audio::Volume;
+use bevy::prelude::*;
+use crate::GameState;
+use crate::game::Jumped;
+use crate::rng::{DeterministicRng, MatchSeed};
+use crate::settings::Settings;
+use crate::skins::{CharacterDefs, PlayerSkins, SoundSet};
+use crate::strike::{PlayerHit, StrikeLanded};
+
+pub struct SoundPlugin;
+
+// Played slightly faster or slower each time, as a fraction of normal speed
+const PITCH_VARIATION: f32 = 0.06;
+
+// Picks the variant and pitch of every sound. Seeded from the match so a replay sounds like
+// the match did, but only ever advanced by presentation, the simulation never sees it
+#[derive(Resource)]
+struct SoundRng(DeterministicRng);
+
+#[derive(Component)]
+struct MatchSound;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SoundRng(DeterministicRng::new(0)))
+            .add_systems(
+                Update,
+                (seed_sounds.run_if(resource_added::<MatchSeed>), play_character_sounds)
+                    .chain()
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(Update, apply_volume.run_if(resource_changed::<Settings>))
+            .add_systems(OnExit(GameState::InGame), stop_sounds);
+    }
+}
+
+// Sounds take it up when they start playing
+fn apply_volume(settings: Res<Settings>, mut global: ResMut<GlobalVolume>) {
+    if global.volume.get() != settings.volume {
+        global.volume = Volume::new(settings.volume);
+    }
+}
+
+fn seed_sounds(seed: Res<MatchSeed>, mut rng: ResMut<SoundRng>) {
+    rng.0 = DeterministicRng::new(seed.0);
+}
+
+// Confirmed events only, so a strike that gets rolled back never made a sound
+fn play_character_sounds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    (defs, skins): (Res<CharacterDefs>, Res<PlayerSkins>),
+    mut rng: ResMut<SoundRng>,
+    mut jumps: EventReader<Jumped>,
+    mut strikes: EventReader<StrikeLanded>,
+    mut hits: EventReader<PlayerHit>,
+) {
+    let sounds = |handle: usize| -> &SoundSet { &defs.get(skins.character(handle)).sounds };
+    let mut queued: Vec<&[String]> = Vec::new();
+    queued.extend(jumps.read().map(|jump| sounds(jump.handle).jump.as_slice()));
+    queued.extend(strikes.read().map(|strike| sounds(strike.handle).strike.as_slice()));
+    queued.extend(hits.read().map(|hit| sounds(hit.target).ko.as_slice()));
+    for variants in queued {
+        if variants.is_empty() {
+            continue;
+        }
+        let path = &variants[rng.0.range_u32(0, variants.len() as u32) as usize];
+        let speed = rng.0.range_f32(1.0 - PITCH_VARIATION, 1.0 + PITCH_VARIATION);
+        commands.spawn((
+            AudioPlayer::new(asset_server.load(path.clone())),
+            PlaybackSettings::DESPAWN.with_speed(speed),
+            MatchSound,
+        ));
+    }
+}
+
+fn stop_sounds(mut commands: Commands, sounds: Query<Entity, With<MatchSound>>) {
+    for entity in sounds.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,38 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::*;
+
+pub struct SpeedLimitPlugin;
+
+// Caps on how fast a body may move, enforced once per rollback frame after every gameplay
+// impulse has been applied. Knockback, strikes and boosts can stack on the same frame, and
+// without a cap a rare combination sends something across the arena in one step
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SpeedLimit {
+    pub max_speed: f32,
+    // Terminal velocity, only limits downward motion
+    pub max_fall_speed: f32,
+}
+
+impl SpeedLimit {
+    pub const PLAYER: Self = Self { max_speed: 20.0, max_fall_speed: 14.0 };
+    pub const BALL: Self = Self { max_speed: 18.0, max_fall_speed: 18.0 };
+}
+
+impl Plugin for SpeedLimitPlugin {
+    fn build(&self, app: &mut App) {
+        app.rollback_component_with_copy::<SpeedLimit>();
+    }
+}
+
+// Fall speed first, then the overall cap, so a clamped fall never pushes the total past it
+pub fn clamp_speeds(mut bodies: Query<(&SpeedLimit, &mut LinearVelocity)>) {
+    for (limit, mut velocity) in bodies.iter_mut() {
+        let mut clamped = velocity.0;
+        clamped.y = clamped.y.max(-limit.max_fall_speed);
+        clamped = clamped.clamp_length_max(limit.max_speed);
+        if clamped != velocity.0 {
+            velocity.0 = clamped;
+        }
+    }
+}
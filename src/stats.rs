@@ -0,0 +1,284 @@
+use bevy::prelude::*;
+use bevy_ggrs::Session;
+use crate::GameState;
+use crate::game::Jumped;
+use crate::input::Config;
+use crate::locale::Locale;
+use crate::rollback_budget::RollbackBudget;
+use crate::scoring::PointScored;
+use crate::settings::Settings;
+use crate::strike::StrikeLanded;
+use crate::ui_layout::SafeArea;
+
+pub struct StatsPlugin;
+
+const TOGGLE_KEY: KeyCode = KeyCode::Tab;
+const SAMPLE_SECS: f32 = 0.5;
+// A minute of samples on the graph, the whole match stays in MatchStats
+const GRAPH_SAMPLES: usize = 120;
+// Frames ahead or behind at the top and bottom of the graph, and rollbacks per sample at the top
+const GRAPH_FRAMES: f32 = 8.0;
+const GRAPH_ROLLBACKS: f32 = 20.0;
+
+// Running totals for the match, only ever counted from confirmed events so both peers and
+// anyone watching a stream see the same numbers
+#[derive(Resource, Default)]
+struct MatchStats {
+    strikes: [u32; 2],
+    jumps: [u32; 2],
+    rallies: u32,
+    rally_frames: u64,
+    // How this machine kept time with the session, one sample every SAMPLE_SECS. Unlike the
+    // totals above these are local, a spectator sees its own lag behind the host
+    timing: Vec<TimingSample>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TimingSample {
+    // Ahead of the opponent for a player, behind the host as a negative for a spectator
+    frames_ahead: i32,
+    rollbacks: u32,
+}
+
+#[derive(Component)]
+struct StatsStrip;
+
+#[derive(Component)]
+struct TimingGraph;
+
+// The bars of one sample on the graph, the newest on the right
+#[derive(Component)]
+struct GraphAhead(usize);
+
+#[derive(Component)]
+struct GraphRollbacks(usize);
+
+impl MatchStats {
+    // Seconds of play per rally, 0 before the first point
+    fn average_rally_secs(&self) -> f32 {
+        if self.rallies == 0 {
+            return 0.0;
+        }
+        self.rally_frames as f32 / self.rallies as f32 / 60.0
+    }
+}
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), setup_stats)
+            .add_systems(
+                Update,
+                (
+                    toggle_stats,
+                    count_stats,
+                    sample_timing.run_if(resource_exists::<Session<Config>>),
+                    update_stats_strip,
+                    update_timing_graph,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_stats);
+    }
+}
+
+fn setup_stats(mut commands: Commands, locale: Res<Locale>) {
+    commands.insert_resource(MatchStats::default());
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(20.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 22.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.85, 0.85, 0.85)),
+        Visibility::Hidden,
+        StatsStrip,
+    ));
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(56.0),
+                right: Val::Px(20.0),
+                width: Val::Px(240.0),
+                height: Val::Px(64.0),
+                flex_direction: FlexDirection::Row,
+                ..default()
+            },
+            SafeArea,
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.5)),
+            Visibility::Hidden,
+            TimingGraph,
+        ))
+        .with_children(|parent| {
+            for index in 0..GRAPH_SAMPLES {
+                parent
+                    .spawn(Node { width: Val::Percent(100.0 / GRAPH_SAMPLES as f32), height: Val::Percent(100.0), ..default() })
+                    .with_children(|parent| {
+                        parent.spawn((
+                            Node { position_type: PositionType::Absolute, width: Val::Percent(100.0), ..default() },
+                            BackgroundColor(Color::srgb(0.35, 0.6, 1.0)),
+                            GraphAhead(index),
+                        ));
+                        parent.spawn((
+                            Node {
+                                position_type: PositionType::Absolute,
+                                width: Val::Percent(100.0),
+                                bottom: Val::ZERO,
+                                ..default()
+                            },
+                            BackgroundColor(Color::srgba(1.0, 0.3, 0.25, 0.8)),
+                            GraphRollbacks(index),
+                        ));
+                    });
+            }
+            parent.spawn((
+                Node { position_type: PositionType::Absolute, top: Val::Px(-20.0), left: Val::ZERO, ..default() },
+                Text::new(locale.get("stats.graph")),
+                TextFont {
+                    font_size: 14.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.85, 0.85, 0.85)),
+            ));
+        });
+}
+
+// Flipping it mid-match sticks for the next one too, the settings menu saves it
+fn toggle_stats(keys: Res<ButtonInput<KeyCode>>, mut settings: ResMut<Settings>) {
+    if keys.just_pressed(TOGGLE_KEY) {
+        settings.stats_overlay = !settings.stats_overlay;
+    }
+}
+
+fn count_stats(
+    mut stats: ResMut<MatchStats>,
+    mut strikes: EventReader<StrikeLanded>,
+    mut jumps: EventReader<Jumped>,
+    mut points: EventReader<PointScored>,
+) {
+    for strike in strikes.read() {
+        stats.strikes[strike.handle] += 1;
+    }
+    for jump in jumps.read() {
+        stats.jumps[jump.handle] += 1;
+    }
+    for point in points.read() {
+        stats.rallies += 1;
+        stats.rally_frames += u64::from(point.rally_frames);
+    }
+}
+
+fn update_stats_strip(
+    stats: Res<MatchStats>,
+    settings: Res<Settings>,
+    locale: Res<Locale>,
+    mut strips: Query<(&mut Text, &mut Visibility), With<StatsStrip>>,
+) {
+    if !stats.is_changed() && !settings.is_changed() && !locale.is_changed() {
+        return;
+    }
+    let label = locale.format(
+        "stats.line",
+        &[
+            ("strikes_1", &stats.strikes[0]),
+            ("jumps_1", &stats.jumps[0]),
+            ("rally", &format!("{:.1}", stats.average_rally_secs())),
+            ("strikes_2", &stats.strikes[1]),
+            ("jumps_2", &stats.jumps[1]),
+        ],
+    );
+    for (mut text, mut visibility) in strips.iter_mut() {
+        visibility.set_if_neq(if settings.stats_overlay { Visibility::Inherited } else { Visibility::Hidden });
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+// Frame advantage straight from GGRS, rollbacks as counted by the budget since the last sample.
+// A local session keeps no time with anyone, so there's nothing to sample
+fn sample_timing(
+    time: Res<Time<Real>>,
+    mut since_last: Local<f32>,
+    mut counted: Local<u32>,
+    session: Res<Session<Config>>,
+    budget: Res<RollbackBudget>,
+    mut stats: ResMut<MatchStats>,
+) {
+    *since_last += time.delta_secs();
+    if *since_last < SAMPLE_SECS {
+        return;
+    }
+    *since_last = 0.0;
+    let frames_ahead = match session.as_ref() {
+        Session::P2P(session) => session.frames_ahead(),
+        Session::Spectator(session) => -(session.frames_behind_host() as i32),
+        Session::SyncTest(_) => return,
+    };
+    // The budget starts over with each match
+    if budget.rollbacks < *counted {
+        *counted = 0;
+    }
+    let rollbacks = budget.rollbacks - *counted;
+    *counted = budget.rollbacks;
+    stats.timing.push(TimingSample { frames_ahead, rollbacks });
+}
+
+// Shown with the stats strip. Ahead grows up from the middle and behind down from it,
+// rollbacks grow up from the bottom
+fn update_timing_graph(
+    stats: Res<MatchStats>,
+    settings: Res<Settings>,
+    mut graphs: Query<&mut Visibility, With<TimingGraph>>,
+    mut ahead_bars: Query<(&GraphAhead, &mut Node), Without<GraphRollbacks>>,
+    mut rollback_bars: Query<(&GraphRollbacks, &mut Node), Without<GraphAhead>>,
+) {
+    if !stats.is_changed() && !settings.is_changed() {
+        return;
+    }
+    let shown = settings.stats_overlay && !stats.timing.is_empty();
+    for mut visibility in graphs.iter_mut() {
+        visibility.set_if_neq(if shown { Visibility::Inherited } else { Visibility::Hidden });
+    }
+    let recent = &stats.timing[stats.timing.len().saturating_sub(GRAPH_SAMPLES)..];
+    // Right aligned, columns before the first sample stay empty
+    let sample = |index: usize| (index + recent.len()).checked_sub(GRAPH_SAMPLES).map(|at| recent[at]);
+    for (GraphAhead(index), mut node) in ahead_bars.iter_mut() {
+        let frames = sample(*index).map_or(0, |sample| sample.frames_ahead);
+        let height = (frames.unsigned_abs() as f32 / GRAPH_FRAMES).min(1.0) * 50.0;
+        node.height = Val::Percent(height);
+        if frames >= 0 {
+            node.top = Val::Auto;
+            node.bottom = Val::Percent(50.0);
+        } else {
+            node.top = Val::Percent(50.0);
+            node.bottom = Val::Auto;
+        }
+    }
+    for (GraphRollbacks(index), mut node) in rollback_bars.iter_mut() {
+        let rollbacks = sample(*index).map_or(0, |sample| sample.rollbacks);
+        node.height = Val::Percent((rollbacks as f32 / GRAPH_ROLLBACKS).min(1.0) * 100.0);
+    }
+}
+
+type StatsUi = Or<(With<StatsStrip>, With<TimingGraph>)>;
+
+fn cleanup_stats(
+    mut commands: Commands,
+    strips: Query<Entity, StatsUi>,
+) {
+    for entity in strips.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<MatchStats>();
+}
@@ -0,0 +1,353 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use bevy_ggrs::*;
+use crate::ball::Ball;
+use crate::confirmed::ConfirmedWriter;
+use crate::determinism::debug_assert_strictly_ordered;
+use crate::game::{Player, SessionMode};
+use crate::input::{get_input_direction, Config, INPUT_STRIKE};
+use crate::knockback::{self, Hit};
+use crate::skins::{CharacterDefs, PlayerSkins};
+
+pub const STRIKE_RANGE: f32 = 1.2;
+const STRIKE_COOLDOWN_FRAMES: u8 = 20;
+const BALL_STRIKE_SPEED: f32 = 9.0;
+const CLASH_BALL_SPEED: f32 = 8.0;
+const PLAYER_KNOCKBACK: Vec2 = Vec2::new(6.0, 4.0);
+const CLASH_PUSHBACK: Vec2 = Vec2::new(5.0, 2.0);
+const STUN_FRAMES: u8 = 12;
+const METER_PER_HIT: u8 = 10;
+pub const METER_MAX: u8 = 100;
+
+// Frame counts of a move, the way fighting games list them. Startup counts the first active
+// frame, so a move that connects on the frame it's pressed has a startup of 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameData {
+    pub startup: u8,
+    pub active: u8,
+    pub recovery: u8,
+}
+
+// A strike connects on the frame it's pressed and can't be repeated until the cooldown is over
+pub const STRIKE_FRAME_DATA: FrameData = FrameData {
+    startup: 1,
+    active: 1,
+    recovery: STRIKE_COOLDOWN_FRAMES - 1,
+};
+
+// A strike landed on a player, sent once its frame is confirmed
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PlayerHit {
+    pub target: usize,
+    pub knockback: Vec2,
+    pub stun_frames: u8,
+}
+
+// A strike connected with the ball or the opponent, sent once its frame is confirmed
+#[derive(Event, Clone, Copy, Debug)]
+pub struct StrikeLanded {
+    pub handle: usize,
+    // The ball rather than the opponent
+    pub ball: bool,
+    // How fast it was sent off
+    pub speed: f32,
+}
+
+// Everything the clash rules need to know about one player's strike this frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrikeAttempt {
+    pub handle: usize,
+    pub x: f32,
+    pub facing: f32,
+    pub meter: u8,
+    pub reaches_ball: bool,
+    pub reaches_opponent: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StrikeOutcome {
+    BallHit { handle: usize, velocity: Vec2 },
+    // Both went for the ball with equal meter, neither gets to aim it
+    BallStraightUp,
+    // away is 1 to knock the target right, -1 left
+    PlayerHit { attacker: usize, target: usize, away: f32 },
+    PushBack { handle: usize, velocity: Vec2 },
+}
+
+// Clash rules, applied to every strike that starts on the same frame:
+// 1. Two players striking each other cancel out and both get pushed apart.
+// 2. The ball beats a body hit: a player in reach of the ball strikes the ball.
+// 3. Several players on the ball: the higher meter wins, a tie sends it straight up.
+// 4. Whatever is left lands on the opponent.
+// The result only depends on the attempts themselves, never on the order they're passed in.
+pub fn resolve_strikes(attempts: &[StrikeAttempt]) -> Vec<StrikeOutcome> {
+    let mut attempts = attempts.to_vec();
+    attempts.sort_by_key(|attempt| attempt.handle);
+
+    let mut outcomes = Vec::new();
+    let mut consumed = vec![false; attempts.len()];
+
+    for (i, a) in attempts.iter().enumerate() {
+        for (j, b) in attempts.iter().enumerate().skip(i + 1) {
+            if a.reaches_opponent == Some(b.handle) && b.reaches_opponent == Some(a.handle) {
+                let away = if a.x <= b.x { -1.0 } else { 1.0 };
+                outcomes.push(StrikeOutcome::PushBack {
+                    handle: a.handle,
+                    velocity: Vec2::new(CLASH_PUSHBACK.x * away, CLASH_PUSHBACK.y),
+                });
+                outcomes.push(StrikeOutcome::PushBack {
+                    handle: b.handle,
+                    velocity: Vec2::new(-CLASH_PUSHBACK.x * away, CLASH_PUSHBACK.y),
+                });
+                consumed[i] = true;
+                consumed[j] = true;
+            }
+        }
+    }
+
+    let on_ball: Vec<&StrikeAttempt> = attempts
+        .iter()
+        .enumerate()
+        .filter(|(i, attempt)| !consumed[*i] && attempt.reaches_ball)
+        .map(|(_, attempt)| attempt)
+        .collect();
+
+    if let Some(top_meter) = on_ball.iter().map(|attempt| attempt.meter).max() {
+        let leaders: Vec<_> = on_ball.iter().filter(|attempt| attempt.meter == top_meter).collect();
+        if let [winner] = leaders.as_slice() {
+            let direction = Vec2::new(winner.facing, 1.0).normalize();
+            outcomes.push(StrikeOutcome::BallHit {
+                handle: winner.handle,
+                velocity: direction * BALL_STRIKE_SPEED,
+            });
+        } else {
+            outcomes.push(StrikeOutcome::BallStraightUp);
+        }
+    }
+
+    for (i, attempt) in attempts.iter().enumerate() {
+        if consumed[i] || attempt.reaches_ball {
+            continue;
+        }
+        if let Some(target) = attempt.reaches_opponent {
+            outcomes.push(StrikeOutcome::PlayerHit {
+                attacker: attempt.handle,
+                target,
+                away: attempt.facing,
+            });
+        }
+    }
+
+    outcomes
+}
+
+// True on the frame a strike starts, once strike has run for it
+pub fn strike_started(player: &Player) -> bool {
+    player.strike_cooldown == STRIKE_COOLDOWN_FRAMES
+}
+
+// How far into its strike a player is, 1 on the frame it started. None once it can strike again
+pub fn strike_frame(player: &Player) -> Option<u8> {
+    (player.strike_cooldown > 0).then(|| STRIKE_COOLDOWN_FRAMES + 1 - player.strike_cooldown)
+}
+
+pub fn strike(
+    inputs: Res<PlayerInputs<Config>>,
+    mode: Res<SessionMode>,
+    (defs, skins): (Res<CharacterDefs>, Res<PlayerSkins>),
+    mut hits: ConfirmedWriter<PlayerHit>,
+    mut landed: ConfirmedWriter<StrikeLanded>,
+    mut players: Query<(&Transform, &mut LinearVelocity, &mut Player), Without<Ball>>,
+    mut balls: Query<(&Transform, &mut LinearVelocity, &mut Ball)>,
+) {
+    let ball_position = balls.get_single().ok().map(|(transform, ..)| transform.translation.truncate());
+
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(_, _, player)| player.handle);
+    debug_assert_strictly_ordered("strike", players.iter().map(|(_, _, player)| player.handle));
+
+    let positions: Vec<(usize, Vec2)> = players
+        .iter()
+        .map(|(transform, _, player)| (player.handle, transform.translation.truncate()))
+        .collect();
+
+    let mut attempts = Vec::new();
+    for (transform, _, player) in players.iter_mut() {
+        player.strike_cooldown = player.strike_cooldown.saturating_sub(1);
+
+        let (input, _) = inputs[player.handle];
+        if !player.just_pressed(input, INPUT_STRIKE) || player.strike_cooldown > 0 || player.stun_frames > 0 {
+            continue;
+        }
+        player.strike_cooldown = STRIKE_COOLDOWN_FRAMES;
+
+        let position = transform.translation.truncate();
+        attempts.push(StrikeAttempt {
+            handle: player.handle,
+            x: position.x,
+            facing: if player.facing_left { -1.0 } else { 1.0 },
+            meter: player.meter,
+            reaches_ball: ball_position.is_some_and(|ball| ball.distance(position) <= STRIKE_RANGE),
+            // A race ghost is only there to be chased
+            reaches_opponent: positions
+                .iter()
+                .find(|(handle, other)| *handle != player.handle && other.distance(position) <= STRIKE_RANGE)
+                .map(|(handle, _)| *handle)
+                .filter(|_| *mode != SessionMode::Race),
+        });
+    }
+
+    for outcome in resolve_strikes(&attempts) {
+        match outcome {
+            StrikeOutcome::BallHit { handle, velocity } => {
+                if let Ok((_, mut ball_velocity, mut ball)) = balls.get_single_mut() {
+                    ball_velocity.0 = velocity;
+                    ball.touch(Some(handle));
+                }
+                if let Some((_, _, player)) = players.iter_mut().find(|(_, _, player)| player.handle == handle) {
+                    player.meter = (player.meter + METER_PER_HIT).min(METER_MAX);
+                }
+                landed.send(StrikeLanded { handle, ball: true, speed: velocity.length() });
+            }
+            StrikeOutcome::BallStraightUp => {
+                if let Ok((_, mut ball_velocity, mut ball)) = balls.get_single_mut() {
+                    ball_velocity.0 = Vec2::new(0.0, CLASH_BALL_SPEED);
+                    ball.touch(None);
+                }
+            }
+            StrikeOutcome::PlayerHit { attacker, target, away } => {
+                let knockback = knockback::launch(Hit {
+                    base: PLAYER_KNOCKBACK,
+                    away,
+                    influence: get_input_direction(inputs[target].0).x,
+                    weight: defs.get(skins.character(target)).physique.weight,
+                });
+                for (_, velocity, player) in players.iter_mut() {
+                    if player.handle == target {
+                        velocity.0 = knockback;
+                        player.stun_frames = STUN_FRAMES;
+                        hits.send(PlayerHit { target, knockback, stun_frames: STUN_FRAMES });
+                    } else if player.handle == attacker {
+                        player.meter = (player.meter + METER_PER_HIT).min(METER_MAX);
+                    }
+                }
+                landed.send(StrikeLanded { handle: attacker, ball: false, speed: knockback.length() });
+            }
+            StrikeOutcome::PushBack { handle, velocity: push } => {
+                if let Some((_, velocity, player)) = players.iter_mut().find(|(_, _, player)| player.handle == handle) {
+                    velocity.0 = push;
+                    player.stun_frames = STUN_FRAMES / 2;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempt(handle: usize, x: f32, facing: f32) -> StrikeAttempt {
+        StrikeAttempt { handle, x, facing, meter: 0, reaches_ball: false, reaches_opponent: None }
+    }
+
+    fn reversed(attempts: &[StrikeAttempt]) -> Vec<StrikeAttempt> {
+        attempts.iter().rev().copied().collect()
+    }
+
+    #[test]
+    fn mutual_strikes_push_both_apart() {
+        let attempts = [
+            StrikeAttempt { reaches_opponent: Some(1), ..attempt(0, -0.5, 1.0) },
+            StrikeAttempt { reaches_opponent: Some(0), ..attempt(1, 0.5, -1.0) },
+        ];
+        assert_eq!(
+            resolve_strikes(&attempts),
+            vec![
+                StrikeOutcome::PushBack { handle: 0, velocity: Vec2::new(-CLASH_PUSHBACK.x, CLASH_PUSHBACK.y) },
+                StrikeOutcome::PushBack { handle: 1, velocity: Vec2::new(CLASH_PUSHBACK.x, CLASH_PUSHBACK.y) },
+            ]
+        );
+    }
+
+    #[test]
+    fn mutual_strikes_beat_the_ball() {
+        let attempts = [
+            StrikeAttempt { reaches_opponent: Some(1), reaches_ball: true, ..attempt(0, -0.5, 1.0) },
+            StrikeAttempt { reaches_opponent: Some(0), ..attempt(1, 0.5, -1.0) },
+        ];
+        let outcomes = resolve_strikes(&attempts);
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|outcome| matches!(outcome, StrikeOutcome::PushBack { .. })));
+    }
+
+    #[test]
+    fn meter_tie_on_the_ball_sends_it_straight_up() {
+        let attempts = [
+            StrikeAttempt { reaches_ball: true, meter: 30, ..attempt(0, -0.5, 1.0) },
+            StrikeAttempt { reaches_ball: true, meter: 30, ..attempt(1, 0.5, -1.0) },
+        ];
+        assert_eq!(resolve_strikes(&attempts), vec![StrikeOutcome::BallStraightUp]);
+    }
+
+    #[test]
+    fn higher_meter_takes_the_ball() {
+        let attempts = [
+            StrikeAttempt { reaches_ball: true, meter: 10, ..attempt(0, -0.5, 1.0) },
+            StrikeAttempt { reaches_ball: true, meter: 40, ..attempt(1, 0.5, -1.0) },
+        ];
+        assert_eq!(
+            resolve_strikes(&attempts),
+            vec![StrikeOutcome::BallHit { handle: 1, velocity: Vec2::new(-1.0, 1.0).normalize() * BALL_STRIKE_SPEED }]
+        );
+    }
+
+    #[test]
+    fn single_striker_on_the_ball_aims_it() {
+        let attempts = [StrikeAttempt { reaches_ball: true, ..attempt(0, -0.5, 1.0) }];
+        assert_eq!(
+            resolve_strikes(&attempts),
+            vec![StrikeOutcome::BallHit { handle: 0, velocity: Vec2::new(1.0, 1.0).normalize() * BALL_STRIKE_SPEED }]
+        );
+    }
+
+    #[test]
+    fn single_striker_on_the_opponent_hits_them() {
+        let attempts = [StrikeAttempt { reaches_opponent: Some(1), ..attempt(0, -0.5, 1.0) }];
+        assert_eq!(
+            resolve_strikes(&attempts),
+            vec![StrikeOutcome::PlayerHit { attacker: 0, target: 1, away: 1.0 }]
+        );
+    }
+
+    #[test]
+    fn striking_nothing_has_no_outcome() {
+        assert_eq!(resolve_strikes(&[attempt(0, 0.0, 1.0)]), Vec::new());
+        assert_eq!(resolve_strikes(&[]), Vec::new());
+    }
+
+    #[test]
+    fn order_of_attempts_does_not_matter() {
+        let cases = [
+            vec![
+                StrikeAttempt { reaches_opponent: Some(1), ..attempt(0, -0.5, 1.0) },
+                StrikeAttempt { reaches_opponent: Some(0), ..attempt(1, 0.5, -1.0) },
+            ],
+            vec![
+                StrikeAttempt { reaches_ball: true, meter: 30, ..attempt(0, -0.5, 1.0) },
+                StrikeAttempt { reaches_ball: true, meter: 30, ..attempt(1, 0.5, -1.0) },
+            ],
+            vec![
+                StrikeAttempt { reaches_ball: true, meter: 50, ..attempt(0, -0.5, 1.0) },
+                StrikeAttempt { reaches_ball: true, meter: 20, ..attempt(1, 0.5, -1.0) },
+            ],
+            vec![
+                StrikeAttempt { reaches_ball: true, ..attempt(0, -0.5, 1.0) },
+                StrikeAttempt { reaches_opponent: Some(0), ..attempt(1, 0.5, -1.0) },
+            ],
+        ];
+        for attempts in cases {
+            assert_eq!(resolve_strikes(&attempts), resolve_strikes(&reversed(&attempts)), "{attempts:?}");
+        }
+    }
+}
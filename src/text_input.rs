@@ -0,0 +1,316 @@
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use crate::locale::Locale;
+use crate::menu_nav::{CapturesKeyboard, Focusable, Focused, NORMAL_BUTTON};
+
+pub struct TextInputPlugin;
+
+const CARET_BLINK_SECONDS: f32 = 0.5;
+const ON_SCREEN_ROWS: [&str; 4] = ["1234567890", "QWERTYUIOP", "ASDFGHJKL", "ZXCVBNM"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFilter {
+    Any,
+    // Letters and digits only, upper-cased, so codes read out loud can't be mistyped
+    RoomCode,
+    // Letters, digits, dashes and underscores, so the text works as a file name everywhere
+    FileName,
+}
+
+// A single line text field. It takes keystrokes while it has menu focus
+#[derive(Component, Clone, Debug)]
+#[require(CapturesKeyboard)]
+pub struct TextInput {
+    pub value: String,
+    pub max_len: usize,
+    pub filter: InputFilter,
+    // Locale key shown while the field is empty
+    pub placeholder: &'static str,
+}
+
+#[derive(Component)]
+struct TextInputLabel;
+
+#[derive(Component)]
+struct OnScreenKeyboard;
+
+#[derive(Component, Clone, Copy)]
+enum OnScreenKey {
+    Char(char),
+    Space,
+    Backspace,
+    Done,
+}
+
+impl InputFilter {
+    fn apply(self, c: char) -> Option<char> {
+        match self {
+            InputFilter::Any => (!c.is_control()).then_some(c),
+            InputFilter::RoomCode => c.is_ascii_alphanumeric().then(|| c.to_ascii_uppercase()),
+            InputFilter::FileName => (c.is_ascii_alphanumeric() || c == '-' || c == '_').then_some(c),
+        }
+    }
+}
+
+impl TextInput {
+    pub fn new(value: impl Into<String>, max_len: usize, filter: InputFilter, placeholder: &'static str) -> Self {
+        Self {
+            value: value.into(),
+            max_len,
+            filter,
+            placeholder,
+        }
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        for c in text.chars().filter_map(|c| self.filter.apply(c)) {
+            if self.value.chars().count() >= self.max_len {
+                break;
+            }
+            self.value.push(c);
+        }
+    }
+}
+
+// Spawns the field with its label child. The returned commands are for the field itself
+pub fn spawn_text_input<'a>(parent: &'a mut ChildBuilder, order: u32, input: TextInput) -> EntityCommands<'a> {
+    let mut field = parent.spawn((
+        Button,
+        Node {
+            width: Val::Px(320.0),
+            height: Val::Px(55.0),
+            margin: UiRect::all(Val::Px(8.0)),
+            padding: UiRect::horizontal(Val::Px(12.0)),
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        Focusable::new(order),
+        input,
+    ));
+    field.with_children(|parent| {
+        parent.spawn((
+            Text::default(),
+            TextFont {
+                font_size: 24.0,
+                ..default()
+            },
+            TextColor(Color::srgb(0.9, 0.9, 0.9)),
+            TextInputLabel,
+        ));
+    });
+    field
+}
+
+impl Plugin for TextInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                type_into_focused,
+                open_on_screen_keyboard,
+                press_on_screen_keys,
+                render_text_inputs,
+            )
+                .chain(),
+        );
+    }
+}
+
+// Reads the system clipboard through whatever tool the platform ships with, there's no
+// clipboard crate in the dependency tree
+#[cfg(not(target_arch = "wasm32"))]
+fn read_clipboard() -> Option<String> {
+    let candidates: [(&str, &[&str]); 5] = [
+        ("wl-paste", &["--no-newline"]),
+        ("xclip", &["-selection", "clipboard", "-o"]),
+        ("xsel", &["--clipboard", "--output"]),
+        ("pbpaste", &[]),
+        ("powershell", &["-NoProfile", "-Command", "Get-Clipboard"]),
+    ];
+    candidates.iter().find_map(|(program, args)| {
+        let output = std::process::Command::new(program).args(*args).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_clipboard() -> Option<String> {
+    None
+}
+
+fn type_into_focused(
+    mut events: EventReader<KeyboardInput>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut inputs: Query<&mut TextInput, With<Focused>>,
+) {
+    let Ok(mut input) = inputs.get_single_mut() else {
+        events.clear();
+        return;
+    };
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight, KeyCode::SuperLeft, KeyCode::SuperRight]);
+
+    for event in events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Character(text) if ctrl => {
+                if text.eq_ignore_ascii_case("v")
+                    && let Some(pasted) = read_clipboard()
+                {
+                    input.insert(&pasted);
+                }
+            }
+            Key::Character(text) => input.insert(text),
+            Key::Space => input.insert(" "),
+            Key::Backspace => {
+                input.value.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Touch screens have no keyboard, so tapping a field brings up one made of buttons
+fn open_on_screen_keyboard(
+    mut commands: Commands,
+    touches: Res<Touches>,
+    tapped: Query<&Interaction, (Changed<Interaction>, With<TextInput>)>,
+    keyboards: Query<Entity, With<OnScreenKeyboard>>,
+    inputs: Query<(), With<TextInput>>,
+) {
+    // Leaving the screen takes the keyboard with it
+    if inputs.is_empty() {
+        for entity in keyboards.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+
+    let tapped = tapped.iter().any(|interaction| *interaction == Interaction::Pressed);
+    if !tapped || touches.iter_just_pressed().next().is_none() || !keyboards.is_empty() {
+        return;
+    }
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(0.0),
+                left: Val::Px(0.0),
+                right: Val::Px(0.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.85)),
+            OnScreenKeyboard,
+        ))
+        .with_children(|parent| {
+            let keys = ON_SCREEN_ROWS
+                .iter()
+                .map(|row| row.chars().map(OnScreenKey::Char).collect::<Vec<_>>())
+                .chain([vec![OnScreenKey::Space, OnScreenKey::Backspace, OnScreenKey::Done]]);
+            for row in keys {
+                parent
+                    .spawn(Node {
+                        flex_direction: FlexDirection::Row,
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        for key in row {
+                            let (label, width) = match key {
+                                OnScreenKey::Char(c) => (c.to_string(), 48.0),
+                                OnScreenKey::Space => ("Space".to_string(), 160.0),
+                                OnScreenKey::Backspace => ("<-".to_string(), 96.0),
+                                OnScreenKey::Done => ("OK".to_string(), 96.0),
+                            };
+                            parent
+                                .spawn((
+                                    Button,
+                                    Node {
+                                        width: Val::Px(width),
+                                        height: Val::Px(48.0),
+                                        margin: UiRect::all(Val::Px(3.0)),
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        ..default()
+                                    },
+                                    BackgroundColor(NORMAL_BUTTON),
+                                    key,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn((
+                                        Text::new(label),
+                                        TextFont {
+                                            font_size: 22.0,
+                                            ..default()
+                                        },
+                                    ));
+                                });
+                        }
+                    });
+            }
+        });
+}
+
+fn press_on_screen_keys(
+    mut commands: Commands,
+    pressed: Query<(&Interaction, &OnScreenKey), Changed<Interaction>>,
+    keyboards: Query<Entity, With<OnScreenKeyboard>>,
+    mut inputs: Query<&mut TextInput, With<Focused>>,
+) {
+    for (interaction, key) in pressed.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Ok(mut input) = inputs.get_single_mut() else {
+            continue;
+        };
+        match key {
+            OnScreenKey::Char(c) => input.insert(&c.to_string()),
+            OnScreenKey::Space => input.insert(" "),
+            OnScreenKey::Backspace => {
+                input.value.pop();
+            }
+            OnScreenKey::Done => {
+                for entity in keyboards.iter() {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+        }
+    }
+}
+
+fn render_text_inputs(
+    time: Res<Time>,
+    locale: Res<Locale>,
+    inputs: Query<(&TextInput, Has<Focused>, &Children)>,
+    mut labels: Query<(&mut Text, &mut TextColor), With<TextInputLabel>>,
+) {
+    let caret_visible = ((time.elapsed_secs() / CARET_BLINK_SECONDS) as u32).is_multiple_of(2);
+
+    for (input, focused, children) in inputs.iter() {
+        let (label, color) = if input.value.is_empty() && !focused {
+            (locale.get(input.placeholder).to_string(), Color::srgb(0.5, 0.5, 0.5))
+        } else {
+            let caret = if focused && caret_visible { "|" } else { "" };
+            (format!("{}{caret}", input.value), Color::srgb(0.9, 0.9, 0.9))
+        };
+
+        for child in children.iter() {
+            let Ok((mut text, mut text_color)) = labels.get_mut(*child) else {
+                continue;
+            };
+            if text.0 != label {
+                text.0 = label.clone();
+            }
+            if text_color.0 != color {
+                text_color.0 = color;
+            }
+        }
+    }
+}
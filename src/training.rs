@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use bevy_ggrs::{PlayerInputs, RollbackFrameCount};
+use crate::GameState;
+use crate::game::{Player, SessionMode};
+use crate::input::{Config, INPUT_LEFT, INPUT_RIGHT, INPUT_STRIKE, INPUT_UP};
+use crate::locale::Locale;
+use crate::rules::{GameModeDef, GameRulesApp};
+use crate::scoring::PointScored;
+use crate::strike::{strike_frame, PlayerHit, StrikeLanded, STRIKE_FRAME_DATA};
+use crate::ui_layout::SafeArea;
+
+pub struct TrainingPlugin;
+
+// Frames of history kept, a few seconds is plenty to read a sequence back
+const HISTORY_FRAMES: usize = 300;
+// Rows on the strip, one per change of input
+const STRIP_ROWS: usize = 18;
+
+// What the training player pressed on every simulated frame, newest at the back. Exists only
+// while training
+#[derive(Resource, Default)]
+pub struct InputHistory(VecDeque<(i32, u8)>);
+
+// Input history down the left edge, fighting game style. Each row is one held input and how
+// many frames it was held for, newest on top
+#[derive(Component)]
+struct InputStrip;
+
+// Strikes landed in a row by the training player, on the ball or the dummy. Dropped when the
+// ball reaches the floor or the player gets hit. Counted from confirmed events like the stats
+#[derive(Resource, Default)]
+pub struct Combo {
+    pub current: u32,
+    best: u32,
+}
+
+#[derive(Component)]
+struct ComboCounter;
+
+// The strike's frame data, with the frame the training player's strike is on while it plays out
+#[derive(Component)]
+struct FrameDataReadout;
+
+impl Plugin for TrainingPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_game_mode(GameModeDef {
+            label_key: "menu.training",
+            session: SessionMode::Training,
+            mutators: &[],
+            unplayable: |_| None,
+        })
+        .add_systems(OnEnter(GameState::InGame), setup_training.run_if(resource_equals(SessionMode::Training)))
+            .add_systems(
+                Update,
+                (update_input_strip, update_frame_data, (count_combo, update_combo_counter).chain())
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(resource_exists::<InputHistory>),
+            )
+            .add_systems(OnExit(GameState::InGame), cleanup_training);
+    }
+}
+
+fn setup_training(mut commands: Commands) {
+    commands.insert_resource(InputHistory::default());
+    commands.insert_resource(Combo::default());
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(100.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 28.0,
+            ..default()
+        },
+        TextColor(Color::srgb(1.0, 0.85, 0.3)),
+        ComboCounter,
+    ));
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(140.0),
+            left: Val::Px(20.0),
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+        InputStrip,
+    ));
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(140.0),
+            right: Val::Px(20.0),
+            ..default()
+        },
+        SafeArea,
+        Text::new(""),
+        TextFont {
+            font_size: 20.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+        FrameDataReadout,
+    ));
+}
+
+// Runs in the rollback schedule. A resimulated frame replaces what was recorded for it
+pub fn record_history(
+    frame: Res<RollbackFrameCount>,
+    inputs: Res<PlayerInputs<Config>>,
+    mut history: ResMut<InputHistory>,
+) {
+    while history.0.back().is_some_and(|(recorded, _)| *recorded >= frame.0) {
+        history.0.pop_back();
+    }
+    if history.0.len() == HISTORY_FRAMES {
+        history.0.pop_front();
+    }
+    history.0.push_back((frame.0, inputs[0].0.buttons));
+}
+
+fn input_glyphs(input: u8) -> String {
+    let mut glyphs = String::new();
+    for (bit, glyph) in [
+        (INPUT_LEFT, "<"),
+        (INPUT_RIGHT, ">"),
+        (INPUT_UP, "^"),
+        (INPUT_STRIKE, "X"),
+    ] {
+        if input & bit != 0 {
+            glyphs.push_str(glyph);
+            glyphs.push(' ');
+        }
+    }
+    if glyphs.is_empty() { "-".to_string() } else { glyphs }
+}
+
+fn update_input_strip(history: Res<InputHistory>, mut strips: Query<&mut Text, With<InputStrip>>) {
+    if !history.is_changed() {
+        return;
+    }
+    let mut rows: Vec<(u8, u32)> = Vec::new();
+    for (_, input) in history.0.iter().rev() {
+        if let Some((held, frames)) = rows.last_mut()
+            && held == input
+        {
+            *frames += 1;
+        } else if rows.len() == STRIP_ROWS {
+            break;
+        } else {
+            rows.push((*input, 1));
+        }
+    }
+    let label: String = rows
+        .iter()
+        .map(|(input, frames)| format!("{frames:>3}  {}\n", input_glyphs(*input)))
+        .collect();
+    for mut text in strips.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+// Read off the rollback state, so a resimulated frame shows what was settled on
+fn update_frame_data(
+    locale: Res<Locale>,
+    players: Query<&Player>,
+    mut readouts: Query<&mut Text, With<FrameDataReadout>>,
+) {
+    let Some(player) = players.iter().find(|player| player.handle == 0) else {
+        return;
+    };
+    let frame = strike_frame(player).map_or("-".to_string(), |frame| frame.to_string());
+    let label = locale.format(
+        "training.frame_data",
+        &[
+            ("startup", &STRIKE_FRAME_DATA.startup),
+            ("active", &STRIKE_FRAME_DATA.active),
+            ("recovery", &STRIKE_FRAME_DATA.recovery),
+            ("frame", &frame),
+        ],
+    );
+    for mut text in readouts.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+// Drops come first, a point and the strike that set up the next rally can be confirmed together
+pub fn count_combo(
+    mut combo: ResMut<Combo>,
+    mut strikes: EventReader<StrikeLanded>,
+    mut hits: EventReader<PlayerHit>,
+    mut points: EventReader<PointScored>,
+) {
+    let ball_down = points.read().count() > 0;
+    let player_hit = hits.read().any(|hit| hit.target == 0);
+    if ball_down || player_hit {
+        combo.current = 0;
+    }
+    for strike in strikes.read() {
+        if strike.handle == 0 {
+            combo.current += 1;
+            combo.best = combo.best.max(combo.current);
+        }
+    }
+}
+
+fn update_combo_counter(
+    combo: Res<Combo>,
+    locale: Res<Locale>,
+    mut counters: Query<&mut Text, With<ComboCounter>>,
+) {
+    if !combo.is_changed() && !locale.is_changed() {
+        return;
+    }
+    let label = locale.format("training.combo", &[("count", &combo.current), ("best", &combo.best)]);
+    for mut text in counters.iter_mut() {
+        text.0 = label.clone();
+    }
+}
+
+type TrainingUi = Or<(With<InputStrip>, With<ComboCounter>, With<FrameDataReadout>)>;
+
+fn cleanup_training(
+    mut commands: Commands,
+    strips: Query<Entity, TrainingUi>,
+) {
+    for entity in strips.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<InputHistory>();
+    commands.remove_resource::<Combo>();
+}
@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+use crate::settings::Settings;
+
+pub struct UiLayoutPlugin;
+
+// Marks a top-level HUD or menu node. Its margins follow the safe area setting, so anything
+// placed against the screen edges stays clear of TV overscan
+#[derive(Component)]
+pub struct SafeArea;
+
+// Absolutely positioned root covering the whole (safe) screen. Fill in alignment with
+// struct update syntax
+pub fn full_screen() -> Node {
+    Node {
+        position_type: PositionType::Absolute,
+        left: Val::Px(0.0),
+        right: Val::Px(0.0),
+        top: Val::Px(0.0),
+        bottom: Val::Px(0.0),
+        ..default()
+    }
+}
+
+impl Plugin for UiLayoutPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, apply_ui_layout);
+    }
+}
+
+fn apply_ui_layout(
+    settings: Res<Settings>,
+    mut ui_scale: ResMut<UiScale>,
+    mut roots: Query<(Ref<SafeArea>, &mut Node)>,
+) {
+    if settings.is_changed() {
+        ui_scale.0 = settings.ui_scale;
+    }
+
+    let margin = UiRect {
+        left: Val::Vw(settings.safe_area),
+        right: Val::Vw(settings.safe_area),
+        top: Val::Vh(settings.safe_area),
+        bottom: Val::Vh(settings.safe_area),
+    };
+    for (safe_area, mut node) in roots.iter_mut() {
+        if settings.is_changed() || safe_area.is_added() {
+            node.margin = margin;
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+use avian2d::prelude::*;
+use crate::ball::Ball;
+use crate::game::Player;
+use crate::level::ActiveLevel;
+use crate::scoring::Score;
+
+// How far past the arena edge something may get before it counts as escaped. Bodies pressed
+// into a wall sit slightly past the edge for a frame or two
+const ESCAPE_MARGIN: f32 = 1.0;
+
+fn is_lost(position: Vec2, velocity: Vec2, bounds: Rect) -> bool {
+    !position.is_finite() || !velocity.is_finite() || !bounds.contains(position)
+}
+
+// A physics glitch that launches a body out of the arena or turns its position into NaN
+// would otherwise soft-lock the match, since no rule ever brings it back. Only dynamic
+// bodies move on their own, so those are the ones watched. Everything here reads rollback
+// state only, so both peers recover on the same frame
+pub fn recover_out_of_bounds(
+    level: Res<ActiveLevel>,
+    score: Option<Res<Score>>,
+    mut players: Query<(&Player, &mut Transform, &mut LinearVelocity), Without<Ball>>,
+    mut balls: Query<(&mut Transform, &mut LinearVelocity), With<Ball>>,
+) {
+    let bounds = level.0.bounds().inflate(ESCAPE_MARGIN);
+
+    for (player, mut transform, mut velocity) in players.iter_mut() {
+        if !is_lost(transform.translation.truncate(), velocity.0, bounds) {
+            continue;
+        }
+        let safe = score.as_ref().map_or(level.0.spawn_point(player.handle % 2), |score| {
+            score.start_position(&level.0, player.handle)
+        });
+        warn!(
+            "player {} out of bounds at {} moving {}, reset to {safe}",
+            player.handle, transform.translation, velocity.0
+        );
+        transform.translation = safe.extend(0.0);
+        velocity.0 = Vec2::ZERO;
+    }
+
+    for (mut transform, mut velocity) in balls.iter_mut() {
+        if !is_lost(transform.translation.truncate(), velocity.0, bounds) {
+            continue;
+        }
+        let safe = score
+            .as_ref()
+            .map_or(level.0.spawn_point(0) + Vec2::Y * 2.0, |score| score.serve_position(&level.0));
+        warn!("ball out of bounds at {} moving {}, reset to {safe}", transform.translation, velocity.0);
+        transform.translation = safe.extend(0.0);
+        velocity.0 = Vec2::ZERO;
+    }
+}
@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy::winit::WakeUp;
+use bevy_ggrs::Session;
+use winit::event_loop::EventLoop;
+use winit::platform::web::{EventLoopExtWebSys, WaitUntilStrategy};
+use crate::GameState;
+use crate::input::Config;
+use crate::locale::Locale;
+use crate::notifications::Notify;
+
+// Browser-only glue, main only declares this module for wasm32
+pub struct WebPlugin;
+
+// Gaps between frames longer than this mean the browser throttled us anyway. Shorter ones
+// GGRS catches up from on its own
+const STALL_WARNING_SECS: f64 = 1.0;
+
+// The page side lives in plain JS, bevy has no hooks for tab visibility or page scrolling
+mod page {
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen(inline_js = r#"
+const audioContexts = [];
+let backgroundWarning = "";
+let pageTitle = document.title;
+let hiddenSince = null;
+let hiddenFor = 0;
+
+export function install_page_handlers() {
+    // Let the canvas fill the page, bevy keeps it sized to its parent
+    document.documentElement.style.height = "100%";
+    document.body.style.cssText += ";margin:0;height:100%;overflow:hidden";
+
+    // Remember every audio context bevy creates so they can be suspended with the tab
+    for (const name of ["AudioContext", "webkitAudioContext"]) {
+        const Original = window[name];
+        if (!Original) {
+            continue;
+        }
+        window[name] = class extends Original {
+            constructor(...args) {
+                super(...args);
+                audioContexts.push(this);
+            }
+        };
+    }
+
+    document.addEventListener("visibilitychange", () => {
+        if (document.hidden) {
+            hiddenSince = performance.now();
+            audioContexts.forEach((context) => context.suspend());
+            if (backgroundWarning) {
+                pageTitle = document.title;
+                document.title = backgroundWarning;
+            }
+        } else {
+            if (hiddenSince !== null) {
+                hiddenFor += performance.now() - hiddenSince;
+                hiddenSince = null;
+            }
+            if (backgroundWarning) {
+                document.title = pageTitle;
+            }
+            audioContexts.forEach((context) => context.resume());
+        }
+    });
+
+    // Arrow keys and space would scroll the page when the canvas doesn't have focus
+    const gameKeys = ["ArrowUp", "ArrowDown", "ArrowLeft", "ArrowRight", "Space"];
+    window.addEventListener("keydown", (event) => {
+        const typing = event.target instanceof HTMLInputElement || event.target instanceof HTMLTextAreaElement;
+        if (!typing && gameKeys.includes(event.code)) {
+            event.preventDefault();
+        }
+    }, { passive: false });
+}
+
+export function set_background_warning(text) {
+    backgroundWarning = text;
+}
+
+// Seconds the tab spent hidden since the last call, counted by the visibilitychange handler
+export function take_hidden_secs() {
+    if (hiddenSince !== null) {
+        const now = performance.now();
+        hiddenFor += now - hiddenSince;
+        hiddenSince = now;
+    }
+    const secs = hiddenFor / 1000;
+    hiddenFor = 0;
+    return secs;
+}
+
+// Blocking on purpose, the audit has to have its inputs before the app starts
+export function audit_source() {
+    const url = new URLSearchParams(window.location.search).get("audit");
+    if (!url) {
+        return "";
+    }
+    const request = new XMLHttpRequest();
+    request.open("GET", url, false);
+    request.send();
+    return request.status === 200 ? request.responseText : "";
+}
+
+export function audit_url() {
+    return new URLSearchParams(window.location.search).get("audit") ?? "";
+}
+
+export function save_file(name, contents) {
+    const link = document.createElement("a");
+    link.href = URL.createObjectURL(new Blob([contents], { type: "text/plain" }));
+    link.download = name.split("/").pop();
+    link.click();
+    setTimeout(() => URL.revokeObjectURL(link.href), 0);
+}
+"#)]
+    extern "C" {
+        pub fn install_page_handlers();
+        pub fn set_background_warning(text: &str);
+        pub fn take_hidden_secs() -> f64;
+        pub fn audit_source() -> String;
+        pub fn audit_url() -> String;
+        pub fn save_file(name: &str, contents: &str);
+    }
+}
+
+// The ?audit= url and what it points at, see audit::AuditRun
+pub fn audit_source() -> Option<(String, String)> {
+    let url = page::audit_url();
+    if url.is_empty() {
+        return None;
+    }
+    let text = page::audit_source();
+    if text.is_empty() {
+        error!("can't audit {url}: nothing could be fetched from it");
+        return None;
+    }
+    Some((url, text))
+}
+
+pub fn save_file(name: &str, contents: &str) {
+    page::save_file(name, contents);
+}
+
+// Has to run before the app is built, bevy creates its audio context while adding plugins
+pub fn install_page_handlers() {
+    page::install_page_handlers();
+}
+
+impl Plugin for WebPlugin {
+    fn build(&self, app: &mut App) {
+        // Bevy's default timer for unfocused windows gets throttled with the tab, one running
+        // in a worker doesn't
+        if let Some(event_loop) = app.world().get_non_send_resource::<EventLoop<WakeUp>>() {
+            event_loop.set_wait_until_strategy(WaitUntilStrategy::Worker);
+        }
+        app.add_systems(OnEnter(GameState::InGame), (arm_background_warning, hide_window))
+            .add_systems(OnExit(GameState::InGame), (disarm_background_warning, show_window))
+            .add_systems(Update, report_background_stall.run_if(in_state(GameState::InGame)));
+    }
+}
+
+// The tab title tells the player a match is still going on while they're in another tab
+fn arm_background_warning(locale: Res<Locale>) {
+    page::set_background_warning(locale.get("web.background_title"));
+}
+
+fn disarm_background_warning() {
+    page::set_background_warning("");
+}
+
+// Background tabs get no animation frames, and bevy normally only updates between those.
+// With the window marked invisible it updates from its wait timer instead, so the session
+// keeps ticking and sending inputs until the player comes back. This has to happen before
+// the tab is hidden: no system gets to run after that to do it, not even for WindowOccluded.
+// Winit ignores visibility on the web, so the canvas stays on screen and the animation
+// frames still pace the match while the tab is shown
+fn hide_window(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    for mut window in windows.iter_mut() {
+        window.visible = false;
+    }
+}
+
+fn show_window(mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    for mut window in windows.iter_mut() {
+        window.visible = true;
+    }
+}
+
+// Some browsers throttle workers too, so a long trip away can still stall the match. A long
+// frame with the tab shown the whole time is something else and isn't blamed on the tab
+fn report_background_stall(
+    time: Res<Time<Real>>,
+    session: Option<Res<Session<Config>>>,
+    locale: Res<Locale>,
+    mut notify: EventWriter<Notify>,
+) {
+    let stalled = time.delta_secs_f64();
+    let was_hidden = page::take_hidden_secs() > 0.0;
+    if !was_hidden || stalled < STALL_WARNING_SECS || session.is_none() {
+        return;
+    }
+    let seconds = stalled.round();
+    warn!("tab was in the background for {seconds}s, the session stalled");
+    notify.send(Notify::warning(locale.format("toast.tab_backgrounded", &[("seconds", &seconds)])));
+}